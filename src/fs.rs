@@ -0,0 +1,221 @@
+//! File system utilities shared by the file based sinks and stores of rama.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+    },
+    time::{Duration, Instant},
+};
+
+/// Replace the file at the given path atomically with the contents written by `write`,
+/// by writing them to a temporary file first, which is then renamed to the path.
+///
+/// Each call uses its own temporary file, such that concurrent writes to the same path
+/// never share one, the last rename winning.
+pub(crate) fn write_atomic(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> Result<(), io::Error>,
+) -> Result<(), io::Error> {
+    static TMP_FILES: AtomicU64 = AtomicU64::new(0);
+
+    let mut tmp_path = path.to_owned().into_os_string();
+    tmp_path.push(format!(
+        ".{}-{}.tmp",
+        std::process::id(),
+        TMP_FILES.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let result = (|| {
+        let mut file = BufWriter::new(File::create(&tmp_path)?);
+        write(&mut file)?;
+        file.into_inner()
+            .map_err(|err| err.into_error())?
+            .sync_all()?;
+        std::fs::rename(&tmp_path, path)
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// Run [`write_atomic`] on the blocking thread pool of tokio,
+/// such that it can be awaited from within async code.
+pub(crate) async fn spawn_write_atomic(
+    path: PathBuf,
+    write: impl FnOnce(&mut BufWriter<File>) -> Result<(), io::Error> + Send + 'static,
+) -> Result<(), io::Error> {
+    tokio::task::spawn_blocking(move || write_atomic(&path, write))
+        .await
+        .map_err(io::Error::other)?
+}
+
+/// The writer of a [`WriterThread`], which does the actual file I/O on that thread.
+pub(crate) trait BatchWriter: Send + 'static {
+    /// The items sent to the [`WriterThread`].
+    type Item: Send + 'static;
+
+    /// Write a single item, e.g. into a buffer.
+    fn write(&mut self, item: Self::Item);
+
+    /// Flush the items written so far, called once per batch of items.
+    fn flush(&mut self);
+}
+
+/// A dedicated thread owning a [`BatchWriter`], such that file based sinks and stores
+/// can hand off their items from within synchronous code, e.g. the poll of a response body,
+/// without ever blocking on file I/O.
+///
+/// The items are handled in batches: all items sent within the batch interval after
+/// the first item of a batch are written, after which the writer is flushed once.
+/// The thread stops once the [`WriterThread`] is dropped and all items are written.
+#[derive(Debug)]
+pub(crate) struct WriterThread<T> {
+    sender: SyncSender<Message<T>>,
+}
+
+#[derive(Debug)]
+enum Message<T> {
+    Item(T),
+    Flush(SyncSender<()>),
+}
+
+impl<T: Send + 'static> WriterThread<T> {
+    /// Spawn a new [`WriterThread`] with the given name, holding on to at most
+    /// `capacity` items while the writer is busy.
+    pub(crate) fn spawn<W>(name: &str, capacity: usize, batch_interval: Duration, writer: W) -> Self
+    where
+        W: BatchWriter<Item = T>,
+    {
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        if let Err(err) = std::thread::Builder::new()
+            .name(name.to_owned())
+            .spawn(move || run(writer, receiver, batch_interval))
+        {
+            tracing::error!(error = %err, name, "failed to spawn writer thread");
+        }
+        Self { sender }
+    }
+
+    /// Send the item to the writer, returning it back if the writer is too far behind,
+    /// or no longer running.
+    pub(crate) fn send(&self, item: T) -> Result<(), T> {
+        self.sender
+            .try_send(Message::Item(item))
+            .map_err(|err| match err {
+                TrySendError::Full(Message::Item(item))
+                | TrySendError::Disconnected(Message::Item(item)) => item,
+                _ => unreachable!("only items are tried to be sent"),
+            })
+    }
+
+    /// Block until all items sent so far are written and flushed.
+    pub(crate) fn flush(&self) {
+        let (done, flushed) = mpsc::sync_channel(1);
+        if self.sender.send(Message::Flush(done)).is_ok() {
+            let _ = flushed.recv();
+        }
+    }
+}
+
+fn run<W: BatchWriter>(
+    mut writer: W,
+    receiver: Receiver<Message<W::Item>>,
+    batch_interval: Duration,
+) {
+    while let Ok(message) = receiver.recv() {
+        let mut flushed = Vec::new();
+        match message {
+            Message::Item(item) => {
+                writer.write(item);
+                let deadline = Instant::now() + batch_interval;
+                while let Ok(message) =
+                    receiver.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                {
+                    match message {
+                        Message::Item(item) => writer.write(item),
+                        Message::Flush(done) => {
+                            flushed.push(done);
+                            break;
+                        }
+                    }
+                }
+            }
+            Message::Flush(done) => flushed.push(done),
+        }
+        writer.flush();
+        for done in flushed {
+            let _ = done.send(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_write_atomic() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+
+        write_atomic(&path, |file| file.write_all(b"one")).unwrap();
+        write_atomic(&path, |file| file.write_all(b"two")).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "two");
+
+        // a failed write leaves the file, and no temporary file, behind
+        let err = write_atomic(&path, |file| {
+            file.write_all(b"three")?;
+            Err(io::Error::other("failed"))
+        })
+        .unwrap_err();
+        assert_eq!(err.to_string(), "failed");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "two");
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[derive(Debug, Default)]
+    struct VecWriter {
+        pending: Vec<u32>,
+        batches: std::sync::Arc<std::sync::Mutex<Vec<Vec<u32>>>>,
+    }
+
+    impl BatchWriter for VecWriter {
+        type Item = u32;
+
+        fn write(&mut self, item: u32) {
+            self.pending.push(item);
+        }
+
+        fn flush(&mut self) {
+            if !self.pending.is_empty() {
+                self.batches
+                    .lock()
+                    .unwrap()
+                    .push(std::mem::take(&mut self.pending));
+            }
+        }
+    }
+
+    #[test]
+    fn test_writer_thread_batches() {
+        let writer = VecWriter::default();
+        let batches = writer.batches.clone();
+        let thread = WriterThread::spawn("test-writer", 2, Duration::from_secs(60), writer);
+
+        thread.send(1).unwrap();
+        thread.send(2).unwrap();
+        // a flush ends the batch, without waiting for the batch interval
+        thread.flush();
+        assert_eq!(*batches.lock().unwrap(), [vec![1, 2]]);
+
+        thread.send(3).unwrap();
+        thread.flush();
+        assert_eq!(*batches.lock().unwrap(), [vec![1, 2], vec![3]]);
+    }
+}
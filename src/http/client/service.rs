@@ -1,16 +1,17 @@
 use crate::{
     error::Error,
     http::{
-        layer::dns::DnsResolvedSocketAddresses,
+        layer::{dns::DnsResolvedSocketAddresses, trace_context::TraceContext},
         service::web::extract::{FromRequestParts, Host},
         Request, Response, Version,
     },
     service::{Context, Service},
+    stream::Stream,
+    tcp::client::TcpConnector,
 };
 use hyper_util::rt::TokioIo;
 
 #[derive(Debug, Clone)]
-#[non_exhaustive]
 /// An http client that can be used to serve HTTP/1.1 and H2 requests.
 ///
 /// This client is not intended to be used as a general purpose HTTP client, but rather as a
@@ -25,12 +26,29 @@ use hyper_util::rt::TokioIo;
 ///
 /// <https://docs.rs/hyper-util/latest/hyper_util/client/legacy/struct.Client.html>
 /// might serve for some inspiration for some of the above features.
-pub struct HttpClient;
+///
+/// The connection is established using a connector, which is a [`TcpConnector`] by default.
+pub struct HttpClient<C = TcpConnector> {
+    connector: C,
+}
 
 impl HttpClient {
     /// Create a new [`HttpClient`].
     pub fn new() -> Self {
-        HttpClient
+        HttpClient {
+            connector: TcpConnector::new(),
+        }
+    }
+}
+
+impl<C> HttpClient<C> {
+    /// Use the given connector to establish the connection to the target,
+    /// which receives the target address as a `host:port` [`String`],
+    /// the same as for the [`Forwarder`].
+    ///
+    /// [`Forwarder`]: crate::tcp::service::Forwarder
+    pub fn connector<T>(self, connector: T) -> HttpClient<T> {
+        HttpClient { connector }
     }
 }
 
@@ -110,9 +128,12 @@ impl std::error::Error for HttpClientError {
     }
 }
 
-impl<State, Body> Service<State, Request<Body>> for HttpClient
+impl<State, Body, C> Service<State, Request<Body>> for HttpClient<C>
 where
     State: Send + Sync + 'static,
+    C: Service<State, String>,
+    C::Response: Stream + Unpin,
+    C::Error: Into<std::io::Error>,
     Body: http_body::Body + Unpin + Send + 'static,
     Body::Data: Send + 'static,
     Body::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
@@ -128,7 +149,12 @@ where
         // TODO: should this service be able to support persistent connection?
         // TODO: should this service be able to support connection pooling?
 
-        let (parts, body) = req.into_parts();
+        let (mut parts, body) = req.into_parts();
+
+        // continue the trace at the upstream server
+        if let Some(trace_context) = ctx.get::<TraceContext>() {
+            trace_context.inject(&mut parts.headers);
+        }
 
         // get target address
         let address = if let Some(dns_info) = ctx.get::<DnsResolvedSocketAddresses>() {
@@ -160,21 +186,23 @@ where
 
         // TODO: should this client support upstream proxies?
 
-        // create the tcp connection
-        tokio::net::TcpStream::connect(&address).await?;
-
-        let tcp_stream = tokio::net::TcpStream::connect(address).await?;
+        // create the connection
+        let stream = self
+            .connector
+            .serve(ctx.clone(), address)
+            .await
+            .map_err(Into::into)?;
 
         // TODO: figure out how we wish to handle https here
 
-        let tcp_stream = TokioIo::new(Box::pin(tcp_stream));
+        let stream = TokioIo::new(Box::pin(stream));
 
         let req = Request::from_parts(parts, body);
         let resp = match req.version() {
             Version::HTTP_2 => {
                 let executor = ctx.executor().clone();
                 let (mut sender, conn) =
-                    hyper::client::conn::http2::handshake(executor, tcp_stream).await?;
+                    hyper::client::conn::http2::handshake(executor, stream).await?;
 
                 ctx.spawn(async move {
                     if let Err(err) = conn.await {
@@ -186,7 +214,7 @@ where
                 sender.send_request(req).await?
             }
             Version::HTTP_11 | Version::HTTP_10 | Version::HTTP_09 => {
-                let (mut sender, conn) = hyper::client::conn::http1::handshake(tcp_stream).await?;
+                let (mut sender, conn) = hyper::client::conn::http1::handshake(stream).await?;
 
                 ctx.spawn(async move {
                     if let Err(err) = conn.await {
@@ -0,0 +1,73 @@
+use super::{AccessLogger, Record};
+use bytes::{Buf, Bytes};
+use http_body::{Body, Frame, SizeHint};
+use pin_project_lite::pin_project;
+use std::{
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+pin_project! {
+    /// A body which counts the bytes of its data, and logs the access [`Record`]
+    /// once it reached its end or is dropped.
+    pub(super) struct AccessLogBody<B> {
+        #[pin]
+        inner: B,
+        pending: Option<(Record, AccessLogger)>,
+    }
+
+    impl<B> PinnedDrop for AccessLogBody<B> {
+        fn drop(this: Pin<&mut Self>) {
+            if let Some((record, logger)) = this.project().pending.take() {
+                logger.log(record);
+            }
+        }
+    }
+}
+
+impl<B> AccessLogBody<B> {
+    pub(super) fn new(inner: B, record: Record, logger: AccessLogger) -> Self {
+        Self {
+            inner,
+            pending: Some((record, logger)),
+        }
+    }
+}
+
+impl<B> Body for AccessLogBody<B>
+where
+    B: Body<Data = Bytes>,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let frame = ready!(this.inner.poll_frame(cx));
+        match &frame {
+            Some(Ok(frame)) => {
+                if let (Some(data), Some((record, _))) = (frame.data_ref(), this.pending.as_mut()) {
+                    record.bytes += data.remaining() as u64;
+                }
+            }
+            Some(Err(_)) => (),
+            None => {
+                if let Some((record, logger)) = this.pending.take() {
+                    logger.log(record);
+                }
+            }
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
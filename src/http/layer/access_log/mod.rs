@@ -0,0 +1,498 @@
+//! Middleware that writes an access log record for each request served.
+//!
+//! Where the [`TraceLayer`] is meant for debugging, the [`AccessLogLayer`] writes
+//! exactly one line per request, in a stable [`AccessLogFormat`], to an [`AccessLogSink`]:
+//!
+//! - [`TracingSink`]: an `INFO` [`tracing`] event with the `rama::access_log` target (default);
+//! - [`FileSink`]: a file, rotated once it reaches a maximum size;
+//! - a [`tokio::sync::mpsc`] (unbounded) sender of [`String`]s.
+//!
+//! The line is written once the response body is fully sent (or dropped),
+//! such that it contains the number of bytes of the response body,
+//! and the latency of the request up to that point.
+//!
+//! # Fields
+//!
+//! Next to the request and response itself, the fields are drawn from:
+//!
+//! - the [`SocketInfo`] in the [`Context`]: the peer address;
+//! - the [`RequestId`] in the request extensions, as set by the [`SetRequestIdLayer`];
+//! - the [`Basic`] credentials in the [`Context`], as inserted by the [`ProxyAuthLayer`]: the user;
+//! - the [`NegotiatedTlsParameters`] in the [`Context`]: the TLS SNI and version;
+//! - the [`DnsResolvedSocketAddresses`] in the [`Context`]: the upstream address.
+//!
+//! Fields found in the [`Context`] are only available to the [`AccessLogLayer`]
+//! when it is layered beneath the layers inserting them.
+//!
+//! The [`AccessLogFormat::Common`] and [`AccessLogFormat::Combined`] formats only contain
+//! the fields of their specification, while the [`AccessLogFormat::Json`] format contains
+//! all of them, as one object per line with the following keys,
+//! omitting the keys of fields which are not available:
+//!
+//! `timestamp`, `peer_addr`, `request_id`, `user`, `method`, `uri`, `version`, `status`,
+//! `bytes`, `referer`, `user_agent`, `tls_server_name`, `tls_version`, `upstream_addr`
+//! and `latency_s`, `latency_ms`, `latency_us` or `latency_ns`, depending on the [`LatencyUnit`].
+//!
+//! [`TraceLayer`]: crate::http::layer::trace::TraceLayer
+//! [`SocketInfo`]: crate::stream::SocketInfo
+//! [`RequestId`]: crate::http::layer::request_id::RequestId
+//! [`SetRequestIdLayer`]: crate::http::layer::request_id::SetRequestIdLayer
+//! [`Basic`]: crate::http::headers::authorization::Basic
+//! [`ProxyAuthLayer`]: crate::http::layer::proxy_auth::ProxyAuthLayer
+//! [`NegotiatedTlsParameters`]: crate::tls::rustls::server::NegotiatedTlsParameters
+//! [`DnsResolvedSocketAddresses`]: crate::http::layer::dns::DnsResolvedSocketAddresses
+//!
+//! # Example
+//!
+//! ```
+//! use rama::http::layer::access_log::{AccessLogFormat, AccessLogLayer};
+//! use rama::http::{Body, Request, Response};
+//! use rama::service::{Context, Layer, Service, service_fn};
+//! use rama::latency::LatencyUnit;
+//! use std::convert::Infallible;
+//!
+//! async fn hello(_req: Request) -> Result<Response, Infallible> {
+//!     Ok(Response::new(Body::from("hello")))
+//! }
+//!
+//! # #[tokio::main]
+//! # async fn main() {
+//! let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+//! let service = AccessLogLayer::new()
+//!     .format(AccessLogFormat::Json)
+//!     .latency_unit(LatencyUnit::Micros)
+//!     .sink(tx)
+//!     .layer(service_fn(hello));
+//!
+//! let response = service
+//!     .serve(Context::default(), Request::new(Body::empty()))
+//!     .await
+//!     .unwrap();
+//! drop(response);
+//!
+//! let line = rx.recv().await.unwrap();
+//! assert!(line.contains(r#""status":200"#));
+//! # }
+//! ```
+
+use crate::{
+    error::BoxError,
+    http::{
+        dep::http_body,
+        header,
+        headers::authorization::Basic,
+        layer::{dns::DnsResolvedSocketAddresses, request_id::RequestId, util::date::UtcDateTime},
+        Body, Method, Request, Response, StatusCode, Uri, Version,
+    },
+    latency::LatencyUnit,
+    service::{Context, Layer, Service},
+    stream::SocketInfo,
+    tls::rustls::{dep::rustls::ProtocolVersion, server::NegotiatedTlsParameters},
+};
+use bytes::Bytes;
+use std::{
+    fmt::{self, Write},
+    net::SocketAddr,
+    sync::Arc,
+    time::SystemTime,
+};
+use tokio::time::Instant;
+
+mod body;
+use body::AccessLogBody;
+
+mod sink;
+#[doc(inline)]
+pub use sink::{AccessLogSink, FileSink, TracingSink};
+
+/// The format of the lines written by the [`AccessLogLayer`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AccessLogFormat {
+    /// The [Common Log Format], as used by most web servers:
+    ///
+    /// `127.0.0.1 - john [10/Oct/2000:13:55:36 +0000] "GET /index.html HTTP/1.1" 200 2326`
+    ///
+    /// [Common Log Format]: https://en.wikipedia.org/wiki/Common_Log_Format
+    Common,
+    /// The Combined Log Format, which is the [`AccessLogFormat::Common`] format
+    /// followed by the quoted `Referer` and `User-Agent` headers.
+    #[default]
+    Combined,
+    /// A JSON object per line, containing all fields.
+    ///
+    /// See the [module docs](self) for its keys.
+    Json,
+}
+
+/// The fields of a single access log line.
+#[derive(Debug)]
+struct Record {
+    timestamp: SystemTime,
+    start: Instant,
+    peer_addr: Option<SocketAddr>,
+    request_id: Option<String>,
+    user: Option<String>,
+    method: Method,
+    uri: Uri,
+    version: Version,
+    status: Option<StatusCode>,
+    bytes: u64,
+    referer: Option<String>,
+    user_agent: Option<String>,
+    tls_server_name: Option<String>,
+    tls_version: Option<ProtocolVersion>,
+    upstream_addr: Option<SocketAddr>,
+}
+
+impl Record {
+    fn new<State, B>(ctx: &Context<State>, req: &Request<B>) -> Self {
+        let header_value = |name| {
+            req.headers()
+                .get(name)
+                .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+        };
+        let tls = ctx.get::<NegotiatedTlsParameters>();
+        Self {
+            timestamp: SystemTime::now(),
+            start: Instant::now(),
+            peer_addr: ctx.get::<SocketInfo>().map(|info| *info.peer_addr()),
+            request_id: req
+                .extensions()
+                .get::<RequestId>()
+                .map(|id| String::from_utf8_lossy(id.header_value().as_bytes()).into_owned()),
+            user: ctx.get::<Basic>().map(|basic| basic.username().to_owned()),
+            method: req.method().clone(),
+            uri: req.uri().clone(),
+            version: req.version(),
+            status: None,
+            bytes: 0,
+            referer: header_value(header::REFERER),
+            user_agent: header_value(header::USER_AGENT),
+            tls_server_name: tls.and_then(|tls| tls.server_name.clone()),
+            tls_version: tls.and_then(|tls| tls.protocol_version),
+            upstream_addr: ctx
+                .get::<DnsResolvedSocketAddresses>()
+                .map(|addresses| *addresses.address()),
+        }
+    }
+
+    fn format(&self, format: AccessLogFormat, latency_unit: LatencyUnit) -> String {
+        match format {
+            AccessLogFormat::Common => self.format_common(),
+            AccessLogFormat::Combined => {
+                let mut line = self.format_common();
+                let _ = write!(
+                    line,
+                    " \"{}\" \"{}\"",
+                    Escaped(self.referer.as_deref().unwrap_or("-")),
+                    Escaped(self.user_agent.as_deref().unwrap_or("-")),
+                );
+                line
+            }
+            AccessLogFormat::Json => self.format_json(latency_unit),
+        }
+    }
+
+    fn format_common(&self) -> String {
+        let date = UtcDateTime::new(self.timestamp);
+        format!(
+            "{} - {} [{:02}/{}/{}:{:02}:{:02}:{:02} +0000] \"{} {} {:?}\" {} {}",
+            self.peer_addr
+                .map_or_else(|| "-".to_owned(), |addr| addr.ip().to_string()),
+            self.user.as_deref().map_or_else(
+                || "-".to_owned(),
+                |user| Escaped(user).to_string().replace(' ', "%20")
+            ),
+            date.day,
+            date.month_name(),
+            date.year,
+            date.hour,
+            date.minute,
+            date.second,
+            self.method,
+            Escaped(&self.uri.to_string()),
+            self.version,
+            self.status
+                .map_or_else(|| "-".to_owned(), |status| status.as_u16().to_string()),
+            self.bytes,
+        )
+    }
+
+    fn format_json(&self, latency_unit: LatencyUnit) -> String {
+        let mut object = serde_json::Map::new();
+        let mut insert = |key: &str, value: serde_json::Value| {
+            object.insert(key.to_owned(), value);
+        };
+        insert(
+            "timestamp",
+            UtcDateTime::new(self.timestamp).rfc3339().into(),
+        );
+        if let Some(addr) = self.peer_addr {
+            insert("peer_addr", addr.to_string().into());
+        }
+        if let Some(request_id) = &self.request_id {
+            insert("request_id", request_id.as_str().into());
+        }
+        if let Some(user) = &self.user {
+            insert("user", user.as_str().into());
+        }
+        insert("method", self.method.as_str().into());
+        insert("uri", self.uri.to_string().into());
+        insert("version", format!("{:?}", self.version).into());
+        if let Some(status) = self.status {
+            insert("status", status.as_u16().into());
+        }
+        insert("bytes", self.bytes.into());
+        if let Some(referer) = &self.referer {
+            insert("referer", referer.as_str().into());
+        }
+        if let Some(user_agent) = &self.user_agent {
+            insert("user_agent", user_agent.as_str().into());
+        }
+        if let Some(server_name) = &self.tls_server_name {
+            insert("tls_server_name", server_name.as_str().into());
+        }
+        if let Some(version) = self.tls_version {
+            insert("tls_version", tls_version_str(version).into());
+        }
+        if let Some(addr) = self.upstream_addr {
+            insert("upstream_addr", addr.to_string().into());
+        }
+        let latency = self.start.elapsed();
+        match latency_unit {
+            LatencyUnit::Seconds => insert("latency_s", latency.as_secs_f64().into()),
+            LatencyUnit::Millis => insert("latency_ms", (latency.as_millis() as u64).into()),
+            LatencyUnit::Micros => insert("latency_us", (latency.as_micros() as u64).into()),
+            LatencyUnit::Nanos => insert("latency_ns", (latency.as_nanos() as u64).into()),
+        }
+        serde_json::Value::Object(object).to_string()
+    }
+}
+
+fn tls_version_str(version: ProtocolVersion) -> String {
+    match version {
+        ProtocolVersion::TLSv1_0 => "TLSv1.0".to_owned(),
+        ProtocolVersion::TLSv1_1 => "TLSv1.1".to_owned(),
+        ProtocolVersion::TLSv1_2 => "TLSv1.2".to_owned(),
+        ProtocolVersion::TLSv1_3 => "TLSv1.3".to_owned(),
+        version => format!("{version:?}"),
+    }
+}
+
+/// Escapes quotes, backslashes and control characters,
+/// such that a value cannot break the line, or its quoting.
+struct Escaped<'a>(&'a str);
+
+impl fmt::Display for Escaped<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                c if c.is_control() => write!(f, "\\x{:02x}", c as u32)?,
+                c => f.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Formats records and writes them to the [`AccessLogSink`].
+#[derive(Clone)]
+struct AccessLogger {
+    format: AccessLogFormat,
+    latency_unit: LatencyUnit,
+    sink: Arc<dyn AccessLogSink>,
+}
+
+impl AccessLogger {
+    fn log(&self, record: Record) {
+        self.sink
+            .write(record.format(self.format, self.latency_unit));
+    }
+}
+
+impl fmt::Debug for AccessLogger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AccessLogger")
+            .field("format", &self.format)
+            .field("latency_unit", &self.latency_unit)
+            .finish()
+    }
+}
+
+/// A [`Service`] which writes an access log line for each request it serves,
+/// created using the [`AccessLogLayer`].
+#[derive(Debug, Clone)]
+pub struct AccessLogService<S> {
+    inner: S,
+    logger: AccessLogger,
+}
+
+impl<S> AccessLogService<S> {
+    define_inner_service_accessors!();
+}
+
+impl<State, S, ReqBody, ResBody> Service<State, Request<ReqBody>> for AccessLogService<S>
+where
+    State: Send + Sync + 'static,
+    S: Service<State, Request<ReqBody>, Response = Response<ResBody>>,
+    ReqBody: Send + 'static,
+    ResBody: http_body::Body<Data = Bytes> + Send + Sync + 'static,
+    ResBody::Error: Into<BoxError>,
+{
+    type Response = Response;
+    type Error = S::Error;
+
+    async fn serve(
+        &self,
+        ctx: Context<State>,
+        req: Request<ReqBody>,
+    ) -> Result<Self::Response, Self::Error> {
+        let mut record = Record::new(&ctx, &req);
+        match self.inner.serve(ctx, req).await {
+            Ok(res) => {
+                record.status = Some(res.status());
+                let logger = self.logger.clone();
+                Ok(res.map(|body| Body::new(AccessLogBody::new(body, record, logger))))
+            }
+            Err(err) => {
+                self.logger.log(record);
+                Err(err)
+            }
+        }
+    }
+}
+
+/// A [`Layer`] that writes an access log line for each request.
+///
+/// By default lines are written in the [`AccessLogFormat::Combined`] format
+/// to the [`TracingSink`], with latencies in milliseconds.
+///
+/// See the [module docs](self) for more details.
+#[derive(Debug, Clone)]
+pub struct AccessLogLayer {
+    logger: AccessLogger,
+}
+
+impl AccessLogLayer {
+    /// Create a new [`AccessLogLayer`].
+    pub fn new() -> Self {
+        Self {
+            logger: AccessLogger {
+                format: AccessLogFormat::default(),
+                latency_unit: LatencyUnit::Millis,
+                sink: Arc::new(TracingSink),
+            },
+        }
+    }
+
+    /// Set the [`AccessLogFormat`] of the written lines.
+    pub fn format(mut self, format: AccessLogFormat) -> Self {
+        self.logger.format = format;
+        self
+    }
+
+    /// Set the [`LatencyUnit`] of the latency, as written in the [`AccessLogFormat::Json`] format.
+    pub fn latency_unit(mut self, latency_unit: LatencyUnit) -> Self {
+        self.logger.latency_unit = latency_unit;
+        self
+    }
+
+    /// Set the [`AccessLogSink`] the lines are written to.
+    pub fn sink(mut self, sink: impl AccessLogSink) -> Self {
+        self.logger.sink = Arc::new(sink);
+        self
+    }
+}
+
+impl Default for AccessLogLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> Layer<S> for AccessLogLayer {
+    type Service = AccessLogService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AccessLogService {
+            inner,
+            logger: self.logger.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        http::{dep::http_body_util::BodyExt, headers::Authorization},
+        service::service_fn,
+    };
+    use std::convert::Infallible;
+    use tokio::sync::mpsc;
+
+    async fn hello(_req: Request) -> Result<Response, Infallible> {
+        Ok(Response::new(Body::from("hello")))
+    }
+
+    fn context() -> Context<()> {
+        let mut ctx = Context::default();
+        ctx.insert(SocketInfo::new(None, "10.0.0.1:4321".parse().unwrap()));
+        ctx.insert(Authorization::basic("john", "secret").0);
+        ctx
+    }
+
+    fn request() -> Request {
+        Request::builder()
+            .uri("http://example.com/index.html?q=x")
+            .header("user-agent", "curl/8.0 \"quoted\"")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_access_log_combined() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let service = AccessLogLayer::new().sink(tx).layer(service_fn(hello));
+
+        let res = service.serve(context(), request()).await.unwrap();
+        assert!(rx.try_recv().is_err(), "logged before the body is sent");
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "hello");
+
+        let line = rx.recv().await.unwrap();
+        let (start, rest) = line.split_once(" [").unwrap();
+        assert_eq!(start, "10.0.0.1 - john");
+        let (_date, rest) = rest.split_once("] ").unwrap();
+        assert_eq!(
+            rest,
+            r#""GET http://example.com/index.html?q=x HTTP/1.1" 200 5 "-" "curl/8.0 \"quoted\"""#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_access_log_json() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let service = AccessLogLayer::new()
+            .format(AccessLogFormat::Json)
+            .latency_unit(LatencyUnit::Nanos)
+            .sink(tx)
+            .layer(service_fn(hello));
+
+        // dropping the body logs the bytes sent so far
+        drop(service.serve(context(), request()).await.unwrap());
+
+        let line = rx.recv().await.unwrap();
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["peer_addr"], "10.0.0.1:4321");
+        assert_eq!(value["user"], "john");
+        assert_eq!(value["method"], "GET");
+        assert_eq!(value["status"], 200);
+        assert_eq!(value["bytes"], 0);
+        assert_eq!(value["user_agent"], "curl/8.0 \"quoted\"");
+        assert!(value["latency_ns"].is_u64());
+        assert!(value.get("tls_version").is_none());
+    }
+}
@@ -0,0 +1,234 @@
+use crate::fs::{BatchWriter, WriterThread};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+
+/// A sink to which the [`AccessLogLayer`] writes its access log lines.
+///
+/// A line is written as soon as the response body ends, from within its poll,
+/// such that [`AccessLogSink::write`] is expected to return right away.
+///
+/// [`AccessLogLayer`]: super::AccessLogLayer
+pub trait AccessLogSink: Send + Sync + 'static {
+    /// Write a single access log line, without trailing newline.
+    fn write(&self, line: String);
+}
+
+/// An [`AccessLogSink`] which emits each line as an `INFO` [`tracing`] event,
+/// with the `rama::access_log` target.
+///
+/// This is the default sink of the [`AccessLogLayer`].
+///
+/// [`AccessLogLayer`]: super::AccessLogLayer
+#[derive(Debug, Clone, Default)]
+pub struct TracingSink;
+
+impl AccessLogSink for TracingSink {
+    fn write(&self, line: String) {
+        tracing::info!(target: "rama::access_log", "{line}");
+    }
+}
+
+/// Sends each line over the channel, dropping the lines the receiver is not interested in.
+impl AccessLogSink for tokio::sync::mpsc::UnboundedSender<String> {
+    fn write(&self, line: String) {
+        let _ = self.send(line);
+    }
+}
+
+/// Sends each line over the channel, dropping the lines for which the channel has no capacity.
+impl AccessLogSink for tokio::sync::mpsc::Sender<String> {
+    fn write(&self, line: String) {
+        if let Err(tokio::sync::mpsc::error::TrySendError::Full(_)) = self.try_send(line) {
+            tracing::warn!("access log channel is full: dropping line");
+        }
+    }
+}
+
+/// The default number of rotated files kept by the [`FileSink`].
+const DEFAULT_MAX_FILES: usize = 5;
+
+/// The number of lines the [`FileSink`] holds on to while its writer thread is busy,
+/// after which lines are dropped.
+const MAX_PENDING_LINES: usize = 4096;
+
+/// An [`AccessLogSink`] which appends each line to a file,
+/// rotating the file once it reached a maximum size.
+///
+/// The file is written by a dedicated thread, started on the first line.
+/// Lines are dropped, with a warning, when that thread falls too far behind.
+/// Use [`FileSink::flush`] to wait until all lines are written, e.g. before exiting.
+///
+/// Rotated files are renamed by appending a number to the path,
+/// `<path>.1` being the most recent one.
+#[derive(Debug)]
+pub struct FileSink {
+    path: PathBuf,
+    max_size: Option<u64>,
+    max_files: usize,
+    file: Mutex<Option<File>>,
+    writer: OnceLock<WriterThread<String>>,
+}
+
+impl FileSink {
+    /// Open the [`FileSink`] at the given path, creating the file if it does not exist yet.
+    ///
+    /// By default the file is never rotated.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, io::Error> {
+        let path = path.as_ref().to_owned();
+        let file = open_append(&path)?;
+        Ok(Self {
+            path,
+            max_size: None,
+            max_files: DEFAULT_MAX_FILES,
+            file: Mutex::new(Some(file)),
+            writer: OnceLock::new(),
+        })
+    }
+
+    /// Rotate the file once writing a line would make it exceed the given size in bytes.
+    pub fn max_size(mut self, max_size: u64) -> Self {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    /// Set the number of rotated files to keep, by default 5,
+    /// removing the oldest file on rotation.
+    pub fn max_files(mut self, max_files: usize) -> Self {
+        self.max_files = max_files;
+        self
+    }
+
+    /// Get the path of the file of this sink.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Block until all lines written so far are written to the file.
+    pub fn flush(&self) {
+        if let Some(writer) = self.writer.get() {
+            writer.flush();
+        }
+    }
+
+    fn spawn(&self) -> WriterThread<String> {
+        let file = self
+            .file
+            .lock()
+            .unwrap()
+            .take()
+            .expect("file sink writer is only spawned once");
+        let writer = Writer {
+            path: self.path.clone(),
+            max_size: self.max_size,
+            max_files: self.max_files,
+            size: file.metadata().map(|metadata| metadata.len()).unwrap_or(0),
+            file: BufWriter::new(file),
+        };
+        WriterThread::spawn("rama-access-log", MAX_PENDING_LINES, Duration::ZERO, writer)
+    }
+}
+
+impl AccessLogSink for FileSink {
+    fn write(&self, line: String) {
+        if self.writer.get_or_init(|| self.spawn()).send(line).is_err() {
+            tracing::warn!(path = ?self.path, "access log writer is behind: dropping line");
+        }
+    }
+}
+
+/// The writer of a [`FileSink`], which owns the file on its [`WriterThread`].
+struct Writer {
+    path: PathBuf,
+    max_size: Option<u64>,
+    max_files: usize,
+    file: BufWriter<File>,
+    size: u64,
+}
+
+impl BatchWriter for Writer {
+    type Item = String;
+
+    fn write(&mut self, line: String) {
+        if let Err(err) = self.append(&line) {
+            tracing::error!(error = %err, path = ?self.path, "failed to write access log line");
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Err(err) = self.file.flush() {
+            tracing::error!(error = %err, path = ?self.path, "failed to flush access log file");
+        }
+    }
+}
+
+impl Writer {
+    fn rotate(&mut self) -> Result<(), io::Error> {
+        self.file.flush()?;
+        if self.max_files == 0 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            for n in (1..self.max_files).rev() {
+                let from = rotated_path(&self.path, n);
+                if from.exists() {
+                    std::fs::rename(from, rotated_path(&self.path, n + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        }
+        self.file = BufWriter::new(open_append(&self.path)?);
+        self.size = 0;
+        Ok(())
+    }
+
+    fn append(&mut self, line: &str) -> Result<(), io::Error> {
+        let len = line.len() as u64 + 1;
+        if self
+            .max_size
+            .is_some_and(|max_size| self.size > 0 && self.size + len > max_size)
+        {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.file.write_all(b"\n")?;
+        self.size += len;
+        Ok(())
+    }
+}
+
+fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let mut path = path.to_owned().into_os_string();
+    path.push(format!(".{n}"));
+    path.into()
+}
+
+fn open_append(path: &Path) -> Result<File, io::Error> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_sink_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("access.log");
+
+        let sink = FileSink::open(&path).unwrap().max_size(10).max_files(2);
+        for line in ["one", "two", "three", "four", "five", "six"] {
+            sink.write(line.to_owned());
+        }
+        sink.flush();
+
+        let read = |path: &Path| std::fs::read_to_string(path).unwrap();
+        assert_eq!(read(&path), "six\n");
+        assert_eq!(read(&rotated_path(&path, 1)), "four\nfive\n");
+        assert_eq!(read(&rotated_path(&path, 2)), "three\n");
+        assert!(!rotated_path(&path, 3).exists());
+    }
+}
@@ -0,0 +1,119 @@
+use crate::error::BoxError;
+use bytes::{Buf, Bytes};
+use http_body::{Body, Frame, SizeHint};
+use pin_project_lite::pin_project;
+use std::{
+    fmt,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+pin_project! {
+    /// A response body which ends, or fails, after a number of bytes.
+    pub(super) struct FaultBody<B> {
+        #[pin]
+        inner: B,
+        remaining: usize,
+        abort: bool,
+        exceeded: bool,
+        done: bool,
+    }
+}
+
+impl<B> FaultBody<B> {
+    /// Truncate the body after the given number of bytes.
+    pub(super) fn truncate(inner: B, after: usize) -> Self {
+        Self {
+            inner,
+            remaining: after,
+            abort: false,
+            exceeded: false,
+            done: false,
+        }
+    }
+
+    /// Fail the body after the given number of bytes.
+    pub(super) fn abort(inner: B, after: usize) -> Self {
+        Self {
+            inner,
+            remaining: after,
+            abort: true,
+            exceeded: false,
+            done: false,
+        }
+    }
+}
+
+impl<B> Body for FaultBody<B>
+where
+    B: Body<Data = Bytes>,
+    B::Error: Into<BoxError>,
+{
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        if *this.done {
+            return Poll::Ready(None);
+        }
+        // the body is only cut once more data than the limit follows, such that
+        // a body of exactly the limit, and its trailers, are passed through as is
+        if *this.exceeded {
+            *this.done = true;
+            return Poll::Ready(this.abort.then(|| Err(BodyAborted.into())));
+        }
+
+        match ready!(this.inner.poll_frame(cx)) {
+            Some(Ok(frame)) => match frame.into_data() {
+                Ok(mut data) => {
+                    if data.remaining() > *this.remaining {
+                        data = data.slice(..*this.remaining);
+                        *this.exceeded = true;
+                        if data.is_empty() {
+                            *this.done = true;
+                            return Poll::Ready(this.abort.then(|| Err(BodyAborted.into())));
+                        }
+                    }
+                    *this.remaining -= data.len();
+                    Poll::Ready(Some(Ok(Frame::data(data))))
+                }
+                // the body ends without exceeding the limit, so trailers are passed through
+                Err(frame) => {
+                    *this.done = true;
+                    Poll::Ready(Some(Ok(frame)))
+                }
+            },
+            Some(Err(err)) => Poll::Ready(Some(Err(err.into()))),
+            None => {
+                *this.done = true;
+                Poll::Ready(None)
+            }
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let mut hint = SizeHint::new();
+        if let Some(upper) = self.inner.size_hint().upper() {
+            hint.set_upper(upper.min(self.remaining as u64));
+        }
+        hint
+    }
+}
+
+/// The error returned by a response body aborted by the [`FaultInjectionService`].
+///
+/// [`FaultInjectionService`]: super::FaultInjectionService
+#[derive(Debug)]
+struct BodyAborted;
+
+impl fmt::Display for BodyAborted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "response body aborted by fault injection")
+    }
+}
+
+impl std::error::Error for BodyAborted {}
@@ -0,0 +1,682 @@
+//! Middleware that injects faults into http requests, e.g. for chaos testing
+//! how clients cope with a misbehaving server or proxy.
+//!
+//! For each request accepted by the [`Matcher`] of the [`FaultInjectionLayer`],
+//! a delay is added and at most one [`Fault`] is injected, each with its configured percentage.
+//! The [`FaultDecision`] made is inserted into the [`Context`] of the request.
+//!
+//! Optionally the faults can also be driven by a request header, in which case the
+//! faults it describes are injected instead. The header is a url encoded config
+//! (similar to the [`HeaderConfigLayer`]), with the following optional fields:
+//!
+//! - `delay_ms`: the delay in milliseconds;
+//! - `status`: the status code to respond with;
+//! - `abort_after`: the number of response body bytes after which to abort the body;
+//! - `truncate_after`: the number of response body bytes after which to end the body;
+//! - `corrupt_headers`: `true` to corrupt the response headers;
+//! - `drop`: `true` to drop the connection.
+//!
+//! [`HeaderConfigLayer`]: crate::http::layer::header_config::HeaderConfigLayer
+//!
+//! # Example
+//!
+//! ```
+//! use rama::http::layer::fault_injection::FaultInjectionLayer;
+//! use rama::http::{matcher::HttpMatcher, Body, Request, Response, StatusCode};
+//! use rama::service::{Context, Layer, Service, service_fn};
+//! use std::{convert::Infallible, time::Duration};
+//!
+//! # #[tokio::main]
+//! # async fn main() {
+//! let service = FaultInjectionLayer::new()
+//!     .matcher(HttpMatcher::path("/api/*"))
+//!     .delay(25.0, Duration::from_millis(10))
+//!     .status(100.0, StatusCode::SERVICE_UNAVAILABLE)
+//!     .header("x-fault-injection")
+//!     .layer(service_fn(|_req: Request| async move {
+//!         Ok::<_, Infallible>(Response::new(Body::empty()))
+//!     }));
+//!
+//! let req = Request::builder().uri("/api/users").body(Body::empty()).unwrap();
+//! let resp = service.serve(Context::default(), req).await.unwrap();
+//! assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+//!
+//! // requests not matched pass through as is
+//! let req = Request::builder().uri("/health").body(Body::empty()).unwrap();
+//! let resp = service.serve(Context::default(), req).await.unwrap();
+//! assert_eq!(resp.status(), StatusCode::OK);
+//! # }
+//! ```
+
+use crate::{
+    error::{BoxError, Error},
+    http::{
+        dep::http_body,
+        header::{CONNECTION, CONTENT_LENGTH, TRANSFER_ENCODING},
+        layer::header_config::extract_header_config,
+        utils::HeaderValueErr,
+        Body, HeaderName, HeaderValue, Request, Response, StatusCode,
+    },
+    service::{
+        context::Extensions,
+        matcher::Always,
+        util::rng::{HasherRng, Rng},
+        Context, Layer, Matcher, Service,
+    },
+};
+use bytes::Bytes;
+use serde::Deserialize;
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+mod body;
+use body::FaultBody;
+
+/// A fault injected by the [`FaultInjectionService`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fault {
+    /// Respond with the given status code and an empty body,
+    /// without calling the inner service.
+    Status(StatusCode),
+    /// Abort the response body with an error after the given number of bytes.
+    AbortBody {
+        /// The number of bytes sent before the body is aborted.
+        after: usize,
+    },
+    /// End the response body early after the given number of bytes.
+    TruncateBody {
+        /// The number of bytes sent before the body ends.
+        after: usize,
+    },
+    /// Corrupt the values of the response headers.
+    ///
+    /// Headers required to frame the response are left untouched.
+    CorruptHeaders,
+    /// Drop the connection, by failing with a [`ConnectionDropped`] error,
+    /// without calling the inner service.
+    DropConnection,
+}
+
+/// The faults injected for a request by the [`FaultInjectionService`],
+/// inserted into the [`Context`] of that request.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FaultDecision {
+    delay: Option<Duration>,
+    fault: Option<Fault>,
+}
+
+impl FaultDecision {
+    /// Get the delay added before the request is served, if any.
+    pub fn delay(&self) -> Option<Duration> {
+        self.delay
+    }
+
+    /// Get the [`Fault`] injected for the request, if any.
+    pub fn fault(&self) -> Option<&Fault> {
+        self.fault.as_ref()
+    }
+}
+
+/// The error returned by the [`FaultInjectionService`]
+/// in case a [`Fault::DropConnection`] is injected.
+#[derive(Debug)]
+pub struct ConnectionDropped;
+
+impl fmt::Display for ConnectionDropped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "connection dropped by fault injection")
+    }
+}
+
+impl std::error::Error for ConnectionDropped {}
+
+/// The config of the faults to inject, read from the request header.
+#[derive(Debug, Clone, Deserialize)]
+struct HeaderFaults {
+    delay_ms: Option<u64>,
+    status: Option<u16>,
+    abort_after: Option<usize>,
+    truncate_after: Option<usize>,
+    corrupt_headers: Option<bool>,
+    drop: Option<bool>,
+}
+
+impl HeaderFaults {
+    fn into_decision(self) -> Result<FaultDecision, HeaderValueErr> {
+        let fault = if self.drop.unwrap_or_default() {
+            Some(Fault::DropConnection)
+        } else if let Some(status) = self.status {
+            let status = StatusCode::from_u16(status)
+                .map_err(|_| HeaderValueErr::HeaderInvalid("status".to_owned()))?;
+            Some(Fault::Status(status))
+        } else if let Some(after) = self.abort_after {
+            Some(Fault::AbortBody { after })
+        } else if let Some(after) = self.truncate_after {
+            Some(Fault::TruncateBody { after })
+        } else if self.corrupt_headers.unwrap_or_default() {
+            Some(Fault::CorruptHeaders)
+        } else {
+            None
+        };
+        Ok(FaultDecision {
+            delay: self.delay_ms.map(Duration::from_millis),
+            fault,
+        })
+    }
+}
+
+/// The faults configured for a [`FaultInjectionLayer`].
+#[derive(Debug, Clone, Default)]
+struct Faults {
+    delay: Option<(f64, Duration)>,
+    faults: Vec<(f64, Fault)>,
+    header: Option<HeaderName>,
+}
+
+impl Faults {
+    fn push(&mut self, percentage: f64, fault: Fault) {
+        assert_percentage(percentage);
+        let total: f64 = self.faults.iter().map(|(p, _)| p).sum::<f64>() + percentage;
+        assert!(
+            total <= 100.0,
+            "the percentages of all faults together must not exceed 100"
+        );
+        self.faults.push((percentage, fault));
+    }
+
+    /// Roll the dice for a single request.
+    fn roll(&self, rng: &mut dyn Rng) -> FaultDecision {
+        let delay = self
+            .delay
+            .and_then(|(percentage, delay)| (rng.next_f64() * 100.0 < percentage).then_some(delay));
+
+        let mut roll = rng.next_f64() * 100.0;
+        let mut fault = None;
+        for (percentage, candidate) in &self.faults {
+            if roll < *percentage {
+                fault = Some(candidate.clone());
+                break;
+            }
+            roll -= percentage;
+        }
+
+        FaultDecision { delay, fault }
+    }
+}
+
+fn assert_percentage(percentage: f64) {
+    assert!(
+        (0.0..=100.0).contains(&percentage),
+        "percentage must be within [0, 100]"
+    );
+}
+
+/// A [`Service`] which injects faults into the requests accepted by its [`Matcher`],
+/// created using the [`FaultInjectionLayer`].
+///
+/// See the [module docs](self) for more details.
+pub struct FaultInjectionService<S, M = Always> {
+    inner: S,
+    faults: Faults,
+    matcher: M,
+    rng: Arc<Mutex<Box<dyn Rng>>>,
+}
+
+impl<S, M> FaultInjectionService<S, M> {
+    define_inner_service_accessors!();
+}
+
+impl<S: fmt::Debug, M: fmt::Debug> fmt::Debug for FaultInjectionService<S, M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FaultInjectionService")
+            .field("inner", &self.inner)
+            .field("faults", &self.faults)
+            .field("matcher", &self.matcher)
+            .finish()
+    }
+}
+
+impl<S: Clone, M: Clone> Clone for FaultInjectionService<S, M> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            faults: self.faults.clone(),
+            matcher: self.matcher.clone(),
+            rng: self.rng.clone(),
+        }
+    }
+}
+
+impl<S, M> FaultInjectionService<S, M> {
+    /// Decide on the faults to inject for the given request,
+    /// removing the fault injection header from it, if any.
+    fn decide<ReqBody>(&self, req: &mut Request<ReqBody>) -> FaultDecision {
+        if let Some(header) = &self.faults.header {
+            let decision = extract_header_config::<_, HeaderFaults, _>(&*req, header)
+                .and_then(HeaderFaults::into_decision);
+            req.headers_mut().remove(header);
+            match decision {
+                Ok(decision) => return decision,
+                Err(HeaderValueErr::HeaderMissing(_)) => (),
+                Err(err) => {
+                    tracing::debug!(error = %err, "ignore invalid fault injection header");
+                }
+            }
+        }
+        let mut rng = self.rng.lock().unwrap();
+        self.faults.roll(rng.as_mut())
+    }
+}
+
+impl<State, S, M, ReqBody, ResBody> Service<State, Request<ReqBody>> for FaultInjectionService<S, M>
+where
+    State: Send + Sync + 'static,
+    S: Service<State, Request<ReqBody>, Response = Response<ResBody>>,
+    S::Error: Into<BoxError>,
+    M: Matcher<State, Request<ReqBody>>,
+    ReqBody: Send + 'static,
+    ResBody: http_body::Body<Data = Bytes> + Send + Sync + 'static,
+    ResBody::Error: Into<BoxError>,
+{
+    type Response = Response;
+    type Error = Error;
+
+    async fn serve(
+        &self,
+        mut ctx: Context<State>,
+        mut req: Request<ReqBody>,
+    ) -> Result<Self::Response, Self::Error> {
+        let mut ext = Extensions::new();
+        if !self.matcher.matches(Some(&mut ext), &ctx, &req) {
+            return match self.inner.serve(ctx, req).await {
+                Ok(res) => Ok(res.map(Body::new)),
+                Err(err) => Err(Error::new(err)),
+            };
+        }
+        ctx.extend(ext);
+
+        let decision = self.decide(&mut req);
+        ctx.insert(decision.clone());
+
+        if let Some(delay) = decision.delay {
+            tokio::time::sleep(delay).await;
+        }
+
+        match decision.fault {
+            Some(Fault::DropConnection) => return Err(ConnectionDropped.into()),
+            Some(Fault::Status(status)) => {
+                let mut res = Response::new(Body::empty());
+                *res.status_mut() = status;
+                return Ok(res);
+            }
+            _ => (),
+        }
+
+        let res = self.inner.serve(ctx, req).await.map_err(Error::new)?;
+        Ok(match decision.fault {
+            Some(Fault::AbortBody { after }) => {
+                let mut res = res.map(|body| Body::new(FaultBody::abort(body, after)));
+                // the body might end short of the upstream length, which would fail it early
+                res.headers_mut().remove(CONTENT_LENGTH);
+                res
+            }
+            Some(Fault::TruncateBody { after }) => {
+                let mut res = res.map(|body| Body::new(FaultBody::truncate(body, after)));
+                res.headers_mut().remove(CONTENT_LENGTH);
+                res
+            }
+            Some(Fault::CorruptHeaders) => {
+                let mut res = res.map(Body::new);
+                corrupt_headers(&mut res);
+                res
+            }
+            _ => res.map(Body::new),
+        })
+    }
+}
+
+/// Corrupt the header values of the given response by reversing them,
+/// except for those required to frame the response.
+fn corrupt_headers(res: &mut Response) {
+    for (name, value) in res.headers_mut().iter_mut() {
+        if name == CONTENT_LENGTH || name == TRANSFER_ENCODING || name == CONNECTION {
+            continue;
+        }
+        let mut bytes = value.as_bytes().to_vec();
+        bytes.reverse();
+        if let Ok(corrupted) = HeaderValue::from_bytes(&bytes) {
+            *value = corrupted;
+        }
+    }
+}
+
+/// A [`Layer`] that injects faults into http requests.
+///
+/// See the [module docs](self) for more details.
+#[derive(Debug, Clone)]
+pub struct FaultInjectionLayer<M = Always> {
+    faults: Faults,
+    matcher: M,
+    seed: Option<u64>,
+}
+
+impl FaultInjectionLayer {
+    /// Create a new [`FaultInjectionLayer`], which matches all requests
+    /// but injects no faults until configured to do so.
+    pub fn new() -> Self {
+        Self {
+            faults: Faults::default(),
+            matcher: Always::new(),
+            seed: None,
+        }
+    }
+}
+
+impl Default for FaultInjectionLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M> FaultInjectionLayer<M> {
+    /// Only inject faults into the requests accepted by the given [`Matcher`],
+    /// e.g. a [`HttpMatcher`], passing through all other requests as is.
+    ///
+    /// [`HttpMatcher`]: crate::http::matcher::HttpMatcher
+    pub fn matcher<T>(self, matcher: T) -> FaultInjectionLayer<T> {
+        FaultInjectionLayer {
+            faults: self.faults,
+            matcher,
+            seed: self.seed,
+        }
+    }
+
+    /// Delay the given percentage of requests with the given duration.
+    ///
+    /// The delay is decided independently of the other faults.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the percentage is not within `[0, 100]`.
+    pub fn delay(mut self, percentage: f64, delay: Duration) -> Self {
+        assert_percentage(percentage);
+        self.faults.delay = Some((percentage, delay));
+        self
+    }
+
+    /// Respond to the given percentage of requests with the given status code.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the percentage is not within `[0, 100]`,
+    /// or if the percentages of all faults together exceed 100.
+    pub fn status(mut self, percentage: f64, status: StatusCode) -> Self {
+        self.faults.push(percentage, Fault::Status(status));
+        self
+    }
+
+    /// Abort the response body of the given percentage of requests after the given number of bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the percentage is not within `[0, 100]`,
+    /// or if the percentages of all faults together exceed 100.
+    pub fn abort_body(mut self, percentage: f64, after: usize) -> Self {
+        self.faults.push(percentage, Fault::AbortBody { after });
+        self
+    }
+
+    /// Truncate the response body of the given percentage of requests after the given number of bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the percentage is not within `[0, 100]`,
+    /// or if the percentages of all faults together exceed 100.
+    pub fn truncate_body(mut self, percentage: f64, after: usize) -> Self {
+        self.faults.push(percentage, Fault::TruncateBody { after });
+        self
+    }
+
+    /// Corrupt the response headers of the given percentage of requests.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the percentage is not within `[0, 100]`,
+    /// or if the percentages of all faults together exceed 100.
+    pub fn corrupt_headers(mut self, percentage: f64) -> Self {
+        self.faults.push(percentage, Fault::CorruptHeaders);
+        self
+    }
+
+    /// Drop the connection of the given percentage of requests.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the percentage is not within `[0, 100]`,
+    /// or if the percentages of all faults together exceed 100.
+    pub fn drop_connection(mut self, percentage: f64) -> Self {
+        self.faults.push(percentage, Fault::DropConnection);
+        self
+    }
+
+    /// Let the faults of a request be driven by the given request header, when present.
+    ///
+    /// The header is removed from the request before it is served.
+    /// See the [module docs](self) for the format of the header.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the name is not a valid header name.
+    pub fn header(mut self, name: impl AsRef<str>) -> Self {
+        let name = HeaderName::try_from(name.as_ref()).expect("valid header name");
+        self.faults.header = Some(name);
+        self
+    }
+
+    /// Make the random faults reproducible, given the same seed
+    /// and the same order of requests.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+}
+
+impl<S, M> Layer<S> for FaultInjectionLayer<M>
+where
+    M: Clone,
+{
+    type Service = FaultInjectionService<S, M>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        let rng: Box<dyn Rng> = match self.seed {
+            Some(seed) => Box::new(HasherRng::with_seed(seed)),
+            None => Box::new(HasherRng::new()),
+        };
+        FaultInjectionService {
+            inner,
+            faults: self.faults.clone(),
+            matcher: self.matcher.clone(),
+            rng: Arc::new(Mutex::new(rng)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        http::{dep::http_body_util::BodyExt, matcher::HttpMatcher},
+        service::service_fn,
+    };
+    use std::convert::Infallible;
+
+    fn fault_service(
+        layer: FaultInjectionLayer<impl Matcher<(), Request> + Clone>,
+    ) -> impl Service<(), Request, Response = Response, Error = Error> {
+        layer.layer(service_fn(|ctx: Context<()>, req: Request| async move {
+            assert!(!req.headers().contains_key("x-fault"));
+            let fault = ctx
+                .get::<FaultDecision>()
+                .and_then(|decision| decision.fault().cloned());
+            Ok::<_, Infallible>(
+                Response::builder()
+                    .header("x-fault", format!("{fault:?}"))
+                    .header(CONTENT_LENGTH, "11")
+                    .body(Body::from("hello world"))
+                    .unwrap(),
+            )
+        }))
+    }
+
+    fn request(path: &str) -> Request {
+        Request::builder().uri(path).body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_fault_injection_status() {
+        let service = fault_service(
+            FaultInjectionLayer::new()
+                .matcher(HttpMatcher::path("/faulty"))
+                .status(100.0, StatusCode::BAD_GATEWAY),
+        );
+
+        let res = service
+            .serve(Context::default(), request("/faulty"))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+
+        let res = service
+            .serve(Context::default(), request("/other"))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["x-fault"], "None");
+    }
+
+    #[tokio::test]
+    async fn test_fault_injection_header() {
+        let service = fault_service(FaultInjectionLayer::new().header("x-fault"));
+
+        let mut req = request("/");
+        req.headers_mut()
+            .insert("x-fault", HeaderValue::from_static("truncate_after=5"));
+        let res = service.serve(Context::default(), req).await.unwrap();
+        assert_eq!(res.headers()["x-fault"], "Some(TruncateBody { after: 5 })");
+        assert!(!res.headers().contains_key(CONTENT_LENGTH));
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "hello");
+
+        let mut req = request("/");
+        req.headers_mut()
+            .insert("x-fault", HeaderValue::from_static("abort_after=5"));
+        let res = service.serve(Context::default(), req).await.unwrap();
+        assert!(!res.headers().contains_key(CONTENT_LENGTH));
+        assert!(res.into_body().collect().await.is_err());
+
+        // a body of exactly the limit is not aborted
+        let mut req = request("/");
+        req.headers_mut()
+            .insert("x-fault", HeaderValue::from_static("abort_after=11"));
+        let res = service.serve(Context::default(), req).await.unwrap();
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "hello world");
+
+        let mut req = request("/");
+        req.headers_mut()
+            .insert("x-fault", HeaderValue::from_static("drop=true"));
+        let err = service.serve(Context::default(), req).await.unwrap_err();
+        assert!(err.downcast_ref::<ConnectionDropped>().is_some());
+
+        // an invalid header is ignored
+        let mut req = request("/");
+        req.headers_mut()
+            .insert("x-fault", HeaderValue::from_static("status=1000"));
+        let res = service.serve(Context::default(), req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_fault_body_trailers() {
+        use crate::http::dep::http_body::Frame;
+        use crate::http::{dep::http_body_util::StreamBody, HeaderMap};
+
+        fn body() -> StreamBody<impl futures::Stream<Item = Result<Frame<Bytes>, Infallible>>> {
+            let mut trailers = HeaderMap::new();
+            trailers.insert("x-checksum", HeaderValue::from_static("abc"));
+            StreamBody::new(futures::stream::iter([
+                Ok(Frame::data(Bytes::from_static(b"hello"))),
+                Ok(Frame::trailers(trailers)),
+            ]))
+        }
+
+        // the limit is never hit, so the trailers are kept
+        let collected = body::FaultBody::abort(body(), 10).collect().await.unwrap();
+        assert_eq!(collected.trailers().unwrap()["x-checksum"], "abc");
+        assert_eq!(collected.to_bytes(), "hello");
+
+        // the limit is hit exactly, but no more data follows, so the trailers are kept
+        let collected = body::FaultBody::abort(body(), 5).collect().await.unwrap();
+        assert_eq!(collected.trailers().unwrap()["x-checksum"], "abc");
+        assert_eq!(collected.to_bytes(), "hello");
+
+        // the body is cut short, so the trailers are never sent
+        let collected = body::FaultBody::truncate(body(), 3)
+            .collect()
+            .await
+            .unwrap();
+        assert!(collected.trailers().is_none());
+        assert_eq!(collected.to_bytes(), "hel");
+        assert!(body::FaultBody::abort(body(), 3).collect().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fault_injection_corrupt_headers() {
+        let service = fault_service(FaultInjectionLayer::new().corrupt_headers(100.0));
+        let res = service
+            .serve(Context::default(), request("/"))
+            .await
+            .unwrap();
+        assert_eq!(res.headers()["x-fault"], ")sredaeHtpurroC(emoS");
+    }
+
+    #[tokio::test]
+    async fn test_fault_injection_percentages_reproducible() {
+        async fn faults(seed: u64) -> Vec<String> {
+            let service = fault_service(
+                FaultInjectionLayer::new()
+                    .status(30.0, StatusCode::INTERNAL_SERVER_ERROR)
+                    .truncate_body(30.0, 1)
+                    .seed(seed),
+            );
+            let mut faults = Vec::new();
+            for _ in 0..100 {
+                let res = service
+                    .serve(Context::default(), request("/"))
+                    .await
+                    .unwrap();
+                faults.push(match res.headers().get("x-fault") {
+                    Some(fault) => fault.to_str().unwrap().to_owned(),
+                    None => res.status().to_string(),
+                });
+            }
+            faults
+        }
+
+        let first = faults(42).await;
+        assert_eq!(first, faults(42).await);
+        let passed = first.iter().filter(|fault| *fault == "None").count();
+        assert!((20..=60).contains(&passed), "{passed}");
+    }
+
+    #[test]
+    #[should_panic(expected = "must not exceed 100")]
+    fn test_fault_injection_percentages_exceeded() {
+        let _ = FaultInjectionLayer::new()
+            .status(60.0, StatusCode::BAD_GATEWAY)
+            .drop_connection(50.0);
+    }
+}
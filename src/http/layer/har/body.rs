@@ -0,0 +1,110 @@
+use super::PendingEntry;
+use bytes::{Buf, Bytes};
+use http_body::{Body, Frame, SizeHint};
+use pin_project_lite::pin_project;
+use std::{
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{ready, Context, Poll},
+};
+
+/// The captured data of a body, up to a maximum size.
+#[derive(Debug, Default)]
+pub(super) struct Captured {
+    pub(super) data: Vec<u8>,
+    pub(super) size: u64,
+}
+
+impl Captured {
+    fn push(&mut self, mut data: impl Buf, max_size: usize) {
+        self.size += data.remaining() as u64;
+        while data.has_remaining() && self.data.len() < max_size {
+            let chunk = data.chunk();
+            let n = chunk.len().min(max_size - self.data.len());
+            self.data.extend_from_slice(&chunk[..n]);
+            data.advance(n);
+        }
+    }
+
+    pub(super) fn truncated(&self) -> bool {
+        self.size > self.data.len() as u64
+    }
+}
+
+pin_project! {
+    /// A body which captures its data, up to a maximum size,
+    /// finishing the [`PendingEntry`], if any, once it reached its end or is dropped.
+    pub(super) struct CaptureBody<B> {
+        #[pin]
+        inner: B,
+        captured: Arc<Mutex<Captured>>,
+        max_size: usize,
+        pending: Option<PendingEntry>,
+    }
+
+    impl<B> PinnedDrop for CaptureBody<B> {
+        fn drop(this: Pin<&mut Self>) {
+            let this = this.project();
+            if let Some(pending) = this.pending.take() {
+                pending.finish(&this.captured.lock().unwrap());
+            }
+        }
+    }
+}
+
+impl<B> CaptureBody<B> {
+    pub(super) fn new(
+        inner: B,
+        captured: Arc<Mutex<Captured>>,
+        max_size: usize,
+        pending: Option<PendingEntry>,
+    ) -> Self {
+        Self {
+            inner,
+            captured,
+            max_size,
+            pending,
+        }
+    }
+}
+
+impl<B> Body for CaptureBody<B>
+where
+    B: Body<Data = Bytes>,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let frame = ready!(this.inner.poll_frame(cx));
+        match &frame {
+            Some(Ok(frame)) => {
+                if let Some(data) = frame.data_ref() {
+                    this.captured
+                        .lock()
+                        .unwrap()
+                        .push(data.clone(), *this.max_size);
+                }
+            }
+            Some(Err(_)) => (),
+            None => {
+                if let Some(pending) = this.pending.take() {
+                    pending.finish(&this.captured.lock().unwrap());
+                }
+            }
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
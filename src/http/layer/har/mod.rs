@@ -0,0 +1,666 @@
+//! Middleware that records the requests served, and their responses,
+//! as an HTTP Archive ([HAR 1.2]).
+//!
+//! The [`HarRecorderLayer`] records a [`HarEntry`] for each request, containing
+//! its headers, timings and bodies, up to a maximum size, into a [`HarStore`],
+//! per session. By default the session of a request is the username of its
+//! [`Basic`] proxy credentials, falling back to the `anonymous` session.
+//!
+//! The entries can be stored:
+//!
+//! - in memory, using the [`MemoryHarStore`], such that they can be downloaded
+//!   from an admin endpoint, served by its [`MemoryHarStore::web_service`];
+//! - to a file per session, using the [`FileHarStore`].
+//!
+//! An entry is recorded once the response body is fully sent (or dropped).
+//!
+//! # Sensitive headers
+//!
+//! The values of headers marked as [sensitive] are redacted, such as those marked
+//! by the [`SetSensitiveHeadersLayer`], in which case the [`HarRecorderLayer`]
+//! is to be layered beneath it. As the [`SetSensitiveHeadersLayer`] only marks
+//! response headers once the response is returned, the response headers named
+//! as a sensitive request header are redacted as well.
+//!
+//! [HAR 1.2]: http://www.softwareishard.com/blog/har-12-spec/
+//! [`Basic`]: crate::http::headers::authorization::Basic
+//! [sensitive]: https://docs.rs/http/latest/http/header/struct.HeaderValue.html#method.set_sensitive
+//! [`SetSensitiveHeadersLayer`]: crate::http::layer::sensitive_headers::SetSensitiveHeadersLayer
+//!
+//! # Example
+//!
+//! ```
+//! use rama::http::layer::har::{HarRecorderLayer, MemoryHarStore};
+//! use rama::http::layer::sensitive_headers::SetSensitiveHeadersLayer;
+//! use rama::http::{header::AUTHORIZATION, Body, Request, Response};
+//! use rama::service::{Context, Service, ServiceBuilder};
+//! use std::{convert::Infallible, iter::once};
+//!
+//! async fn hello(_req: Request) -> Result<Response, Infallible> {
+//!     Ok(Response::new(Body::from("hello")))
+//! }
+//!
+//! # #[tokio::main]
+//! # async fn main() {
+//! let store = MemoryHarStore::new();
+//! let service = ServiceBuilder::new()
+//!     .layer(SetSensitiveHeadersLayer::new(once(AUTHORIZATION)))
+//!     .layer(HarRecorderLayer::new(store.clone()).max_body_size(1024))
+//!     .service_fn(hello);
+//!
+//! let request = Request::builder()
+//!     .uri("http://example.com/")
+//!     .header(AUTHORIZATION, "Bearer secret")
+//!     .body(Body::empty())
+//!     .unwrap();
+//! let response = service.serve(Context::default(), request).await.unwrap();
+//! drop(response);
+//!
+//! let har = store.har("anonymous").unwrap();
+//! let entry = &har.log.entries[0];
+//! assert_eq!(entry.request.headers[0].value, "[REDACTED]");
+//! assert_eq!(entry.response.status, 200);
+//!
+//! // serve the recorded sessions, e.g. nested under `/har` in an admin web service
+//! let admin = rama::http::service::web::WebService::<()>::default()
+//!     .nest("/har", store.web_service());
+//! # }
+//! ```
+
+use crate::{
+    error::BoxError,
+    http::{
+        dep::http_body,
+        header,
+        layer::util::{
+            date::UtcDateTime,
+            record::{header_value, BASE64},
+        },
+        Body, HeaderMap, HeaderName, Request, Response, Version,
+    },
+    service::{
+        layer::limit::policy::{KeyExtractor, ProxyUsernameKey},
+        Context, Layer, Service,
+    },
+};
+use base64::Engine as _;
+use bytes::Bytes;
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+use tokio::time::Instant;
+
+mod model;
+#[doc(inline)]
+pub use model::{
+    Har, HarCache, HarContent, HarCreator, HarEntry, HarLog, HarNameValue, HarPostData, HarRequest,
+    HarResponse, HarTimings,
+};
+
+mod store;
+#[doc(inline)]
+pub use store::{FileHarStore, HarStore, MemoryHarStore};
+
+mod body;
+use body::{CaptureBody, Captured};
+
+/// The session of requests of which no session can be identified.
+const ANONYMOUS_SESSION: &str = "anonymous";
+
+/// The default maximum size of the recorded bodies, in bytes.
+const DEFAULT_MAX_BODY_SIZE: usize = 64 * 1024;
+
+/// A [`HarEntry`] of which the bodies are still being captured.
+struct PendingEntry {
+    store: Arc<dyn HarStore>,
+    session: String,
+    entry: HarEntry,
+    start: Instant,
+    wait: Duration,
+    request_body: Arc<Mutex<Captured>>,
+}
+
+impl PendingEntry {
+    /// Complete the entry with the captured bodies, and record it.
+    fn finish(mut self, response_body: &Captured) {
+        let receive = self.start.elapsed().saturating_sub(self.wait);
+        self.entry.timings = HarTimings {
+            send: 0.0,
+            wait: millis(self.wait),
+            receive: millis(receive),
+        };
+        self.entry.time = millis(self.wait + receive);
+
+        let request_body = self.request_body.lock().unwrap();
+        self.entry.request.body_size = request_body.size as i64;
+        if request_body.size > 0 {
+            let (text, encoding) = body_text(&request_body);
+            self.entry.request.post_data = Some(HarPostData {
+                mime_type: mime_type(&self.entry.request.headers),
+                text,
+                comment: body_comment(&request_body, encoding),
+            });
+        }
+        drop(request_body);
+
+        let content = &mut self.entry.response.content;
+        self.entry.response.body_size = response_body.size as i64;
+        content.size = response_body.size as i64;
+        if response_body.size > 0 {
+            let (text, encoding) = body_text(response_body);
+            content.text = Some(text);
+            content.encoding = encoding.map(ToOwned::to_owned);
+            content.comment = body_comment(response_body, None);
+        }
+
+        self.store.record(&self.session, self.entry);
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// The captured body as text, base64 encoded if it is not valid UTF-8.
+fn body_text(captured: &Captured) -> (String, Option<&'static str>) {
+    match std::str::from_utf8(&captured.data) {
+        Ok(text) => (text.to_owned(), None),
+        // the body may be truncated in the middle of a character
+        Err(err) if captured.truncated() && err.error_len().is_none() => (
+            String::from_utf8_lossy(&captured.data[..err.valid_up_to()]).into_owned(),
+            None,
+        ),
+        Err(_) => (BASE64.encode(&captured.data), Some("base64")),
+    }
+}
+
+fn body_comment(captured: &Captured, encoding: Option<&str>) -> Option<String> {
+    let truncated = captured
+        .truncated()
+        .then(|| format!("truncated to {} bytes", captured.data.len()));
+    match (truncated, encoding) {
+        (Some(truncated), Some(encoding)) => Some(format!("{truncated}, {encoding} encoded")),
+        (Some(truncated), None) => Some(truncated),
+        (None, Some(encoding)) => Some(format!("{encoding} encoded")),
+        (None, None) => None,
+    }
+}
+
+fn mime_type(headers: &[HarNameValue]) -> String {
+    headers
+        .iter()
+        .find(|header| {
+            header
+                .name
+                .eq_ignore_ascii_case(header::CONTENT_TYPE.as_str())
+        })
+        .map_or_else(|| "x-unknown".to_owned(), |header| header.value.clone())
+}
+
+fn http_version(version: Version) -> String {
+    format!("{version:?}")
+}
+
+fn har_headers(headers: &HeaderMap, sensitive: &[HeaderName]) -> Vec<HarNameValue> {
+    headers
+        .iter()
+        .map(|(name, value)| HarNameValue {
+            name: name.to_string(),
+            value: header_value(value, value.is_sensitive() || sensitive.contains(name)),
+        })
+        .collect()
+}
+
+fn har_request<B>(req: &Request<B>) -> HarRequest {
+    let uri = req.uri();
+    let url = match (uri.authority(), req.headers().get(header::HOST)) {
+        (None, Some(host)) => format!("http://{}{}", String::from_utf8_lossy(host.as_bytes()), uri),
+        _ => uri.to_string(),
+    };
+    let query_string = uri
+        .query()
+        .and_then(|query| serde_urlencoded::from_str::<Vec<(String, String)>>(query).ok())
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(name, value)| HarNameValue { name, value })
+        .collect();
+
+    HarRequest {
+        method: req.method().to_string(),
+        url,
+        http_version: http_version(req.version()),
+        cookies: Vec::new(),
+        headers: har_headers(req.headers(), &[]),
+        query_string,
+        post_data: None,
+        headers_size: -1,
+        body_size: 0,
+    }
+}
+
+fn har_response<B>(res: Option<&Response<B>>, sensitive: &[HeaderName]) -> HarResponse {
+    let Some(res) = res else {
+        return HarResponse {
+            status: 0,
+            status_text: String::new(),
+            http_version: String::new(),
+            cookies: Vec::new(),
+            headers: Vec::new(),
+            content: HarContent {
+                size: 0,
+                mime_type: "x-unknown".to_owned(),
+                text: None,
+                encoding: None,
+                comment: Some("request failed".to_owned()),
+            },
+            redirect_url: String::new(),
+            headers_size: -1,
+            body_size: 0,
+        };
+    };
+
+    let headers = har_headers(res.headers(), sensitive);
+    HarResponse {
+        status: res.status().as_u16(),
+        status_text: res
+            .status()
+            .canonical_reason()
+            .unwrap_or_default()
+            .to_owned(),
+        http_version: http_version(res.version()),
+        cookies: Vec::new(),
+        content: HarContent {
+            size: 0,
+            mime_type: mime_type(&headers),
+            text: None,
+            encoding: None,
+            comment: None,
+        },
+        redirect_url: res
+            .headers()
+            .get(header::LOCATION)
+            .map(|location| String::from_utf8_lossy(location.as_bytes()).into_owned())
+            .unwrap_or_default(),
+        headers,
+        headers_size: -1,
+        body_size: 0,
+    }
+}
+
+/// A [`Service`] which records the requests it serves as [`HarEntry`]s,
+/// created using the [`HarRecorderLayer`].
+pub struct HarRecorderService<S, K = ProxyUsernameKey> {
+    inner: S,
+    store: Arc<dyn HarStore>,
+    key: K,
+    max_body_size: usize,
+}
+
+impl<S, K> HarRecorderService<S, K> {
+    define_inner_service_accessors!();
+}
+
+impl<S: fmt::Debug, K: fmt::Debug> fmt::Debug for HarRecorderService<S, K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HarRecorderService")
+            .field("inner", &self.inner)
+            .field("key", &self.key)
+            .field("max_body_size", &self.max_body_size)
+            .finish()
+    }
+}
+
+impl<S: Clone, K: Clone> Clone for HarRecorderService<S, K> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            store: self.store.clone(),
+            key: self.key.clone(),
+            max_body_size: self.max_body_size,
+        }
+    }
+}
+
+impl<State, S, K, ReqBody, ResBody> Service<State, Request<ReqBody>> for HarRecorderService<S, K>
+where
+    State: Send + Sync + 'static,
+    S: Service<State, Request, Response = Response<ResBody>>,
+    K: KeyExtractor<State, Request<ReqBody>, Key = String>,
+    ReqBody: http_body::Body<Data = Bytes> + Send + Sync + 'static,
+    ReqBody::Error: Into<BoxError>,
+    ResBody: http_body::Body<Data = Bytes> + Send + Sync + 'static,
+    ResBody::Error: Into<BoxError>,
+{
+    type Response = Response;
+    type Error = S::Error;
+
+    async fn serve(
+        &self,
+        ctx: Context<State>,
+        req: Request<ReqBody>,
+    ) -> Result<Self::Response, Self::Error> {
+        let session = self
+            .key
+            .extract(&ctx, &req)
+            .unwrap_or_else(|| ANONYMOUS_SESSION.to_owned());
+        let started = SystemTime::now();
+        let start = Instant::now();
+
+        let sensitive: Vec<_> = req
+            .headers()
+            .iter()
+            .filter(|(_, value)| value.is_sensitive())
+            .map(|(name, _)| name.clone())
+            .collect();
+        let request = har_request(&req);
+        let request_body = Arc::new(Mutex::new(Captured::default()));
+        let req = req.map(|body| {
+            Body::new(CaptureBody::new(
+                body,
+                request_body.clone(),
+                self.max_body_size,
+                None,
+            ))
+        });
+
+        let result = self.inner.serve(ctx, req).await;
+        let pending = PendingEntry {
+            store: self.store.clone(),
+            session,
+            entry: HarEntry {
+                started_date_time: UtcDateTime::new(started).rfc3339(),
+                time: 0.0,
+                request,
+                response: har_response(result.as_ref().ok(), &sensitive),
+                cache: HarCache::default(),
+                timings: HarTimings {
+                    send: 0.0,
+                    wait: 0.0,
+                    receive: 0.0,
+                },
+            },
+            start,
+            wait: start.elapsed(),
+            request_body,
+        };
+
+        match result {
+            Ok(res) => Ok(res.map(|body| {
+                Body::new(CaptureBody::new(
+                    body,
+                    Default::default(),
+                    self.max_body_size,
+                    Some(pending),
+                ))
+            })),
+            Err(err) => {
+                pending.finish(&Captured::default());
+                Err(err)
+            }
+        }
+    }
+}
+
+/// A [`Layer`] that records requests, and their responses, as [`HarEntry`]s.
+///
+/// See the [module docs](self) for more details.
+pub struct HarRecorderLayer<K = ProxyUsernameKey> {
+    store: Arc<dyn HarStore>,
+    key: K,
+    max_body_size: usize,
+}
+
+impl HarRecorderLayer {
+    /// Create a new [`HarRecorderLayer`], recording into the given [`HarStore`].
+    pub fn new(store: impl HarStore) -> Self {
+        Self {
+            store: Arc::new(store),
+            key: ProxyUsernameKey,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+        }
+    }
+}
+
+impl<K> HarRecorderLayer<K> {
+    /// Identify the session of a request using the given [`KeyExtractor`].
+    pub fn key<K2>(self, key: K2) -> HarRecorderLayer<K2> {
+        HarRecorderLayer {
+            store: self.store,
+            key,
+            max_body_size: self.max_body_size,
+        }
+    }
+
+    /// Set the maximum size of the recorded request and response bodies, by default 64 KiB,
+    /// truncating the bodies exceeding it.
+    pub fn max_body_size(mut self, max_body_size: usize) -> Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+}
+
+impl<K: fmt::Debug> fmt::Debug for HarRecorderLayer<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HarRecorderLayer")
+            .field("key", &self.key)
+            .field("max_body_size", &self.max_body_size)
+            .finish()
+    }
+}
+
+impl<K: Clone> Clone for HarRecorderLayer<K> {
+    fn clone(&self) -> Self {
+        Self {
+            store: self.store.clone(),
+            key: self.key.clone(),
+            max_body_size: self.max_body_size,
+        }
+    }
+}
+
+impl<S, K: Clone> Layer<S> for HarRecorderLayer<K> {
+    type Service = HarRecorderService<S, K>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        HarRecorderService {
+            inner,
+            store: self.store.clone(),
+            key: self.key.clone(),
+            max_body_size: self.max_body_size,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        http::{
+            dep::http_body_util::BodyExt, headers::Authorization, layer::util::record::REDACTED,
+            HeaderValue, StatusCode,
+        },
+        service::service_fn,
+    };
+    use std::convert::Infallible;
+
+    async fn echo(req: Request) -> Result<Response, Infallible> {
+        let body = req.into_body().collect().await.unwrap().to_bytes();
+        let mut secret = HeaderValue::from_static("secret");
+        secret.set_sensitive(true);
+        Ok(Response::builder()
+            .header("content-type", "text/plain")
+            .header("x-token", secret)
+            .body(Body::from(body))
+            .unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_har_recorder() {
+        let store = MemoryHarStore::new();
+        let service = HarRecorderLayer::new(store.clone())
+            .max_body_size(8)
+            .layer(service_fn(echo));
+
+        let mut ctx = Context::default();
+        ctx.insert(Authorization::basic("john", "secret").0);
+        let mut cookie = HeaderValue::from_static("session=abc");
+        cookie.set_sensitive(true);
+        let req = Request::builder()
+            .method("POST")
+            .uri("/echo?a=1&b=two")
+            .header("host", "example.com")
+            .header("content-type", "text/plain")
+            .header("cookie", cookie)
+            .body(Body::from("hello, world"))
+            .unwrap();
+
+        let res = service.serve(ctx, req).await.unwrap();
+        assert!(
+            store.har("john").is_none(),
+            "recorded before the body is sent"
+        );
+        res.into_body().collect().await.unwrap();
+
+        let har = store.har("john").unwrap();
+        assert_eq!(har.log.version, "1.2");
+        let entry = &har.log.entries[0];
+        assert_eq!(entry.request.method, "POST");
+        assert_eq!(entry.request.url, "http://example.com/echo?a=1&b=two");
+        assert_eq!(entry.request.query_string[1].value, "two");
+        let cookie = entry
+            .request
+            .headers
+            .iter()
+            .find(|header| header.name == "cookie")
+            .unwrap();
+        assert_eq!(cookie.value, REDACTED);
+        assert_eq!(entry.request.body_size, 12);
+        let post_data = entry.request.post_data.as_ref().unwrap();
+        assert_eq!(post_data.text, "hello, w");
+        assert_eq!(post_data.mime_type, "text/plain");
+        assert_eq!(post_data.comment.as_deref(), Some("truncated to 8 bytes"));
+
+        assert_eq!(entry.response.status, 200);
+        assert_eq!(entry.response.status_text, "OK");
+        let token = entry
+            .response
+            .headers
+            .iter()
+            .find(|header| header.name == "x-token")
+            .unwrap();
+        assert_eq!(token.value, REDACTED);
+        assert_eq!(entry.response.content.size, 12);
+        assert_eq!(entry.response.content.text.as_deref(), Some("hello, w"));
+        assert!(entry.time >= entry.timings.wait);
+    }
+
+    #[tokio::test]
+    async fn test_har_web_service() {
+        let store = MemoryHarStore::new();
+        let service = HarRecorderLayer::new(store.clone()).layer(service_fn(echo));
+        let req = Request::builder()
+            .uri("http://example.com/")
+            .body(Body::from(vec![0xff, 0xfe]))
+            .unwrap();
+        drop(service.serve(Context::default(), req).await.unwrap());
+
+        let web = store.web_service::<()>();
+        let res = web
+            .serve(
+                Context::default(),
+                Request::get("/").body(Body::empty()).unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, r#"["anonymous"]"#);
+
+        let res = web
+            .serve(
+                Context::default(),
+                Request::get("/anonymous").body(Body::empty()).unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers()["content-disposition"],
+            "attachment; filename=\"anonymous.har\""
+        );
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        let har: Har = serde_json::from_slice(&body).unwrap();
+        let post_data = har.log.entries[0].request.post_data.as_ref().unwrap();
+        assert_eq!(post_data.text, "//4=");
+        assert_eq!(post_data.comment.as_deref(), Some("base64 encoded"));
+
+        let res = web
+            .serve(
+                Context::default(),
+                Request::delete("/anonymous").body(Body::empty()).unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert!(store.sessions().is_empty());
+    }
+
+    fn har_entry(url: &str) -> HarEntry {
+        HarEntry {
+            started_date_time: "1994-11-06T08:49:37.000Z".to_owned(),
+            time: 0.0,
+            request: HarRequest {
+                url: url.to_owned(),
+                ..har_request(&Request::new(()))
+            },
+            response: har_response::<()>(None, &[]),
+            cache: HarCache::default(),
+            timings: HarTimings {
+                send: 0.0,
+                wait: 0.0,
+                receive: 0.0,
+            },
+        }
+    }
+
+    #[test]
+    fn test_memory_har_store_max_sessions() {
+        let store = MemoryHarStore::new().max_sessions(2);
+        store.record("john", har_entry("http://a"));
+        store.record("jane", har_entry("http://b"));
+        store.record("john", har_entry("http://c"));
+        store.record("joe", har_entry("http://d"));
+
+        // jane is dropped, as an entry of john was recorded more recently
+        assert_eq!(store.sessions(), ["joe", "john"]);
+        assert_eq!(store.har("john").unwrap().log.entries.len(), 2);
+    }
+
+    #[test]
+    fn test_file_har_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileHarStore::new(dir.path()).unwrap().max_entries(1);
+        store.record("../jane doe", har_entry("http://a"));
+        store.record("../jane doe", har_entry("http://b"));
+        store.record("jane doe", har_entry("http://c"));
+        store.record("jane_doe", har_entry("http://d"));
+        store.flush();
+
+        let read = |session: &str| -> Har {
+            serde_json::from_slice(&std::fs::read(store.path(session)).unwrap()).unwrap()
+        };
+        assert_eq!(
+            store.path("../jane doe"),
+            dir.path().join("%2E%2E%2Fjane%20doe.har")
+        );
+        let har = read("../jane doe");
+        assert_eq!(har.log.entries.len(), 1);
+        assert_eq!(har.log.entries[0].request.url, "http://b");
+
+        // distinct sessions never share a file
+        assert_eq!(store.path("jane_doe"), dir.path().join("jane_doe.har"));
+        assert_eq!(read("jane doe").log.entries[0].request.url, "http://c");
+        assert_eq!(read("jane_doe").log.entries[0].request.url, "http://d");
+    }
+}
@@ -0,0 +1,173 @@
+//! The [HAR 1.2] data model, as far as recorded by the [`HarRecorderLayer`].
+//!
+//! [HAR 1.2]: http://www.softwareishard.com/blog/har-12-spec/
+//! [`HarRecorderLayer`]: super::HarRecorderLayer
+
+use serde::{Deserialize, Serialize};
+
+/// The root of an HTTP Archive.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Har {
+    /// The log of the archive.
+    pub log: HarLog,
+}
+
+impl Har {
+    /// Create a new [`Har`], created by rama, containing the given entries.
+    pub fn new(entries: Vec<HarEntry>) -> Self {
+        Self {
+            log: HarLog {
+                version: "1.2".to_owned(),
+                creator: HarCreator {
+                    name: "rama".to_owned(),
+                    version: env!("CARGO_PKG_VERSION").to_owned(),
+                },
+                entries,
+            },
+        }
+    }
+}
+
+/// The log of an HTTP Archive.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HarLog {
+    /// The version of the format, `1.2`.
+    pub version: String,
+    /// The application which created the log.
+    pub creator: HarCreator,
+    /// The recorded requests, in order of recording.
+    pub entries: Vec<HarEntry>,
+}
+
+/// The application which created an HTTP Archive.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HarCreator {
+    /// The name of the application.
+    pub name: String,
+    /// The version of the application.
+    pub version: String,
+}
+
+/// A single recorded request and its response.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarEntry {
+    /// The start of the request, as an RFC 3339 timestamp.
+    pub started_date_time: String,
+    /// The total time of the request in milliseconds, i.e. the sum of its timings.
+    pub time: f64,
+    /// The recorded request.
+    pub request: HarRequest,
+    /// The recorded response.
+    pub response: HarResponse,
+    /// The cache info, which is never recorded.
+    pub cache: HarCache,
+    /// The timings of the request.
+    pub timings: HarTimings,
+}
+
+/// A recorded request.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarRequest {
+    /// The method of the request.
+    pub method: String,
+    /// The absolute url of the request.
+    pub url: String,
+    /// The http version of the request, e.g. `HTTP/1.1`.
+    pub http_version: String,
+    /// The cookies of the request, which are only recorded as part of the headers.
+    pub cookies: Vec<HarNameValue>,
+    /// The headers of the request.
+    pub headers: Vec<HarNameValue>,
+    /// The parameters of the query string of the request.
+    pub query_string: Vec<HarNameValue>,
+    /// The recorded body of the request, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_data: Option<HarPostData>,
+    /// The size of the headers, which is not recorded: `-1`.
+    pub headers_size: i64,
+    /// The size of the body in bytes.
+    pub body_size: i64,
+}
+
+/// A recorded response.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarResponse {
+    /// The status code of the response, `0` if the request failed.
+    pub status: u16,
+    /// The canonical reason of the status code.
+    pub status_text: String,
+    /// The http version of the response, e.g. `HTTP/1.1`.
+    pub http_version: String,
+    /// The cookies of the response, which are only recorded as part of the headers.
+    pub cookies: Vec<HarNameValue>,
+    /// The headers of the response.
+    pub headers: Vec<HarNameValue>,
+    /// The recorded body of the response.
+    pub content: HarContent,
+    /// The value of the `Location` header, if any.
+    #[serde(rename = "redirectURL")]
+    pub redirect_url: String,
+    /// The size of the headers, which is not recorded: `-1`.
+    pub headers_size: i64,
+    /// The size of the body in bytes.
+    pub body_size: i64,
+}
+
+/// A name-value pair, such as a header or query string parameter.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HarNameValue {
+    /// The name of the pair.
+    pub name: String,
+    /// The value of the pair.
+    pub value: String,
+}
+
+/// The recorded body of a request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarPostData {
+    /// The mime type of the body.
+    pub mime_type: String,
+    /// The body as text, which is base64 encoded if not valid UTF-8.
+    pub text: String,
+    /// A comment on the recording of the body, e.g. when it is truncated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+/// The recorded body of a response.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarContent {
+    /// The size of the body in bytes.
+    pub size: i64,
+    /// The mime type of the body.
+    pub mime_type: String,
+    /// The body as text, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    /// The encoding of the text, `base64` if the body is not valid UTF-8.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+    /// A comment on the recording of the body, e.g. when it is truncated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+/// The cache info of an entry, which is never recorded.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HarCache {}
+
+/// The timings of a request, in milliseconds.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HarTimings {
+    /// The time to send the request, which is not recorded: `0`.
+    pub send: f64,
+    /// The time waiting for the response head.
+    pub wait: f64,
+    /// The time receiving the response body.
+    pub receive: f64,
+}
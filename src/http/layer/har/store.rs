@@ -0,0 +1,354 @@
+use super::{Har, HarEntry};
+use crate::{
+    fs::{write_atomic, BatchWriter, WriterThread},
+    http::{
+        header,
+        response::Json,
+        service::web::{extract::Path, WebService},
+        Body, IntoResponse, Response, StatusCode,
+    },
+};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io,
+    path::{Path as FsPath, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+    time::Duration,
+};
+
+/// The default maximum number of entries kept per session.
+const DEFAULT_MAX_ENTRIES: usize = 1000;
+
+/// The default maximum number of sessions kept.
+const DEFAULT_MAX_SESSIONS: usize = 100;
+
+/// The default interval within which the entries recorded by a [`FileHarStore`]
+/// are written at once.
+const DEFAULT_WRITE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A store in which the [`HarRecorderLayer`] records its [`HarEntry`]s, per session.
+///
+/// Entries are recorded from within the response body, see the [`FileHarStore`]
+/// for a store which keeps its file I/O off that path.
+///
+/// [`HarRecorderLayer`]: super::HarRecorderLayer
+pub trait HarStore: Send + Sync + 'static {
+    /// Record the [`HarEntry`] of a request within the given session.
+    fn record(&self, session: &str, entry: HarEntry);
+}
+
+/// A [`HarStore`] which keeps the last entries of the most recent sessions in memory,
+/// such that they can be downloaded as a [`Har`], e.g. using its [`MemoryHarStore::web_service`].
+#[derive(Debug, Clone)]
+pub struct MemoryHarStore {
+    sessions: Arc<Mutex<Sessions>>,
+    max_entries: usize,
+    max_sessions: usize,
+}
+
+#[derive(Debug, Default)]
+struct Sessions {
+    sessions: HashMap<String, Session>,
+    recorded: u64,
+}
+
+#[derive(Debug)]
+struct Session {
+    entries: VecDeque<HarEntry>,
+    last_recorded: u64,
+}
+
+impl MemoryHarStore {
+    /// Create a new, empty, [`MemoryHarStore`].
+    pub fn new() -> Self {
+        Self {
+            sessions: Default::default(),
+            max_entries: DEFAULT_MAX_ENTRIES,
+            max_sessions: DEFAULT_MAX_SESSIONS,
+        }
+    }
+
+    /// Set the maximum number of entries kept per session, by default 1000,
+    /// dropping the oldest entry once exceeded.
+    pub fn max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+
+    /// Set the maximum number of sessions kept, by default 100,
+    /// dropping the session of which an entry was recorded the longest ago once exceeded.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the maximum number of sessions is 0.
+    pub fn max_sessions(mut self, max_sessions: usize) -> Self {
+        assert!(max_sessions > 0, "max_sessions must be at least 1");
+        self.max_sessions = max_sessions;
+        self
+    }
+
+    /// Get the names of the sessions with recorded entries, sorted.
+    pub fn sessions(&self) -> Vec<String> {
+        let mut sessions: Vec<_> = self
+            .sessions
+            .lock()
+            .unwrap()
+            .sessions
+            .keys()
+            .cloned()
+            .collect();
+        sessions.sort();
+        sessions
+    }
+
+    /// Get the [`Har`] of the given session, if it has recorded entries.
+    pub fn har(&self, session: &str) -> Option<Har> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .sessions
+            .get(session)
+            .map(|session| Har::new(session.entries.iter().cloned().collect()))
+    }
+
+    /// Remove the given session, returning its [`Har`], if it had recorded entries.
+    pub fn remove(&self, session: &str) -> Option<Har> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .sessions
+            .remove(session)
+            .map(|session| Har::new(session.entries.into()))
+    }
+
+    /// Create a [`WebService`] to download the recorded sessions,
+    /// e.g. to be nested in an admin web service:
+    ///
+    /// - `GET /`: the names of the sessions, as a JSON array;
+    /// - `GET /:session`: the [`Har`] of the session, as a `.har` attachment;
+    /// - `DELETE /:session`: remove the session.
+    pub fn web_service<State>(&self) -> WebService<State>
+    where
+        State: Send + Sync + 'static,
+    {
+        #[derive(Debug, serde::Deserialize)]
+        struct Params {
+            session: String,
+        }
+
+        let list = self.clone();
+        let download = self.clone();
+        let remove = self.clone();
+        WebService::default()
+            .get("/", move || {
+                let store = list.clone();
+                async move { Json(store.sessions()) }
+            })
+            .get("/:session", move |Path(params): Path<Params>| {
+                let store = download.clone();
+                async move {
+                    match store.har(&params.session) {
+                        Some(har) => har_response(&params.session, &har),
+                        None => StatusCode::NOT_FOUND.into_response(),
+                    }
+                }
+            })
+            .delete("/:session", move |Path(params): Path<Params>| {
+                let store = remove.clone();
+                async move {
+                    match store.remove(&params.session) {
+                        Some(_) => StatusCode::NO_CONTENT,
+                        None => StatusCode::NOT_FOUND,
+                    }
+                }
+            })
+    }
+}
+
+impl Default for MemoryHarStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HarStore for MemoryHarStore {
+    fn record(&self, session: &str, entry: HarEntry) {
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.recorded += 1;
+        let recorded = sessions.recorded;
+        if !sessions.sessions.contains_key(session) && sessions.sessions.len() >= self.max_sessions
+        {
+            let oldest = sessions
+                .sessions
+                .iter()
+                .min_by_key(|(_, session)| session.last_recorded)
+                .map(|(name, _)| name.clone());
+            if let Some(oldest) = oldest {
+                sessions.sessions.remove(&oldest);
+            }
+        }
+
+        let session = sessions
+            .sessions
+            .entry(session.to_owned())
+            .or_insert_with(|| Session {
+                entries: VecDeque::new(),
+                last_recorded: recorded,
+            });
+        session.last_recorded = recorded;
+        session.entries.push_back(entry);
+        while session.entries.len() > self.max_entries {
+            session.entries.pop_front();
+        }
+    }
+}
+
+fn har_response(session: &str, har: &Har) -> Response {
+    match serde_json::to_vec(har) {
+        Ok(body) => Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}.har\"", file_name(session)),
+            )
+            .body(Body::from(body))
+            .unwrap(),
+        Err(err) => Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Body::from(format!("failed to encode har: {err}")))
+            .unwrap(),
+    }
+}
+
+/// The characters of a session which are percent-encoded in its file name,
+/// such that distinct sessions never share a file.
+const FILE_NAME_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'_');
+
+fn file_name(session: &str) -> String {
+    utf8_percent_encode(session, FILE_NAME_ENCODE_SET).to_string()
+}
+
+/// A [`HarStore`] which writes the [`Har`] of each session to a file in a directory,
+/// named after the percent-encoded session, e.g. `<dir>/jane%20doe.har`.
+///
+/// The files are written by a dedicated thread, started on the first recorded entry,
+/// which rewrites the file of a session, replacing it atomically, at most once per
+/// write interval. Use [`FileHarStore::flush`] to wait until all entries are written.
+///
+/// The last entries of the most recent sessions are kept in memory as a [`MemoryHarStore`]
+/// does, such that the file of a dropped session only holds the entries recorded after.
+#[derive(Debug, Clone)]
+pub struct FileHarStore {
+    dir: PathBuf,
+    memory: MemoryHarStore,
+    write_interval: Duration,
+    dirty: Arc<Mutex<HashSet<String>>>,
+    writer: Arc<OnceLock<WriterThread<()>>>,
+}
+
+impl FileHarStore {
+    /// Create a new [`FileHarStore`] writing to the given directory,
+    /// creating it if it does not exist yet.
+    pub fn new(dir: impl AsRef<FsPath>) -> Result<Self, io::Error> {
+        let dir = dir.as_ref().to_owned();
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            memory: MemoryHarStore::new(),
+            write_interval: DEFAULT_WRITE_INTERVAL,
+            dirty: Default::default(),
+            writer: Default::default(),
+        })
+    }
+
+    /// Set the maximum number of entries written per session, by default 1000,
+    /// dropping the oldest entry once exceeded.
+    pub fn max_entries(mut self, max_entries: usize) -> Self {
+        self.memory = self.memory.max_entries(max_entries);
+        self
+    }
+
+    /// Set the maximum number of sessions kept in memory, by default 100.
+    ///
+    /// See [`MemoryHarStore::max_sessions`] for more details.
+    pub fn max_sessions(mut self, max_sessions: usize) -> Self {
+        self.memory = self.memory.max_sessions(max_sessions);
+        self
+    }
+
+    /// Set the interval within which the recorded entries are written at once, by default 1 second.
+    pub fn write_interval(mut self, write_interval: Duration) -> Self {
+        self.write_interval = write_interval;
+        self
+    }
+
+    /// Get the path of the file of the given session.
+    pub fn path(&self, session: &str) -> PathBuf {
+        session_path(&self.dir, session)
+    }
+
+    /// Block until all entries recorded so far are written to their files.
+    pub fn flush(&self) {
+        if let Some(writer) = self.writer.get() {
+            writer.flush();
+        }
+    }
+
+    fn spawn(&self) -> WriterThread<()> {
+        let writer = Writer {
+            dir: self.dir.clone(),
+            memory: self.memory.clone(),
+            dirty: self.dirty.clone(),
+        };
+        WriterThread::spawn("rama-har-store", 1, self.write_interval, writer)
+    }
+}
+
+impl HarStore for FileHarStore {
+    fn record(&self, session: &str, entry: HarEntry) {
+        self.memory.record(session, entry);
+        self.dirty.lock().unwrap().insert(session.to_owned());
+        // a single pending notification suffices, as the writer writes all sessions recorded since
+        let _ = self.writer.get_or_init(|| self.spawn()).send(());
+    }
+}
+
+fn session_path(dir: &FsPath, session: &str) -> PathBuf {
+    dir.join(format!("{}.har", file_name(session)))
+}
+
+/// The writer of a [`FileHarStore`], which writes the files of the recorded sessions
+/// on its [`WriterThread`], notified for each recorded entry.
+struct Writer {
+    dir: PathBuf,
+    memory: MemoryHarStore,
+    dirty: Arc<Mutex<HashSet<String>>>,
+}
+
+impl BatchWriter for Writer {
+    type Item = ();
+
+    fn write(&mut self, _: ()) {}
+
+    fn flush(&mut self) {
+        let dirty = std::mem::take(&mut *self.dirty.lock().unwrap());
+        for session in dirty {
+            if let Err(err) = self.write_session(&session) {
+                tracing::error!(error = %err, session, "failed to write har file");
+            }
+        }
+    }
+}
+
+impl Writer {
+    fn write_session(&self, session: &str) -> Result<(), io::Error> {
+        let Some(har) = self.memory.har(session) else {
+            return Ok(());
+        };
+        write_atomic(&session_path(&self.dir, session), |file| {
+            Ok(serde_json::to_writer_pretty(file, &har)?)
+        })
+    }
+}
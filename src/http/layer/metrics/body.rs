@@ -0,0 +1,76 @@
+use bytes::{Buf, Bytes};
+use http_body::{Body, Frame, SizeHint};
+use pin_project_lite::pin_project;
+use prometheus::Histogram;
+use std::{
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+pin_project! {
+    /// A body which observes its size in a [`Histogram`],
+    /// once it reached its end or is dropped.
+    pub(super) struct SizeBody<B> {
+        #[pin]
+        inner: B,
+        size: u64,
+        histogram: Option<Histogram>,
+    }
+
+    impl<B> PinnedDrop for SizeBody<B> {
+        fn drop(this: Pin<&mut Self>) {
+            let this = this.project();
+            if let Some(histogram) = this.histogram.take() {
+                histogram.observe(*this.size as f64);
+            }
+        }
+    }
+}
+
+impl<B> SizeBody<B> {
+    pub(super) fn new(inner: B, histogram: Histogram) -> Self {
+        Self {
+            inner,
+            size: 0,
+            histogram: Some(histogram),
+        }
+    }
+}
+
+impl<B> Body for SizeBody<B>
+where
+    B: Body<Data = Bytes>,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let frame = ready!(this.inner.poll_frame(cx));
+        match &frame {
+            Some(Ok(frame)) => {
+                if let Some(data) = frame.data_ref() {
+                    *this.size += data.remaining() as u64;
+                }
+            }
+            Some(Err(_)) => (),
+            None => {
+                if let Some(histogram) = this.histogram.take() {
+                    histogram.observe(*this.size as f64);
+                }
+            }
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
@@ -0,0 +1,513 @@
+//! Middleware that records [`prometheus`] metrics of http requests.
+//!
+//! The [`HttpMetricsLayer`] records the following metrics:
+//!
+//! - `http_server_requests_total`: the number of requests served;
+//! - `http_server_request_duration_seconds`: the time until the response is returned;
+//! - `http_server_requests_in_flight`: the number of requests being served;
+//! - `http_server_request_body_size_bytes`: the size of the request bodies;
+//! - `http_server_response_body_size_bytes`: the size of the response bodies.
+//!
+//! All metrics are labelled with the `method` and `route` of the request,
+//! while the metrics known once served are also labelled with the `status` class
+//! of the response (e.g. `2xx`), or `error` in case the inner service failed.
+//! The route is the name of the first route matched by the request,
+//! or `unmatched` in case none of the routes match.
+//!
+//! To keep the number of time series bounded, the standard methods are recorded as is,
+//! while the number of distinct extension methods and `route` values is limited,
+//! recording any other value as `other`.
+//!
+//! The metrics can be exposed using the [`prometheus_metrics`] endpoint.
+//!
+//! [`prometheus`]: https://crates.io/crates/prometheus
+//! [`prometheus_metrics`]: crate::http::service::web::prometheus_metrics
+//!
+//! # Example
+//!
+//! ```
+//! use rama::http::layer::metrics::HttpMetricsLayer;
+//! use rama::http::{matcher::HttpMatcher, Body, Request, Response};
+//! use rama::service::{Context, Layer, Service, service_fn};
+//! use prometheus::Registry;
+//! use std::convert::Infallible;
+//!
+//! # #[tokio::main]
+//! # async fn main() {
+//! let registry = Registry::new();
+//! let service = HttpMetricsLayer::with_registry(&registry)
+//!     .unwrap()
+//!     .route("users", HttpMatcher::path("/users/*"))
+//!     .layer(service_fn(|_req: Request| async move {
+//!         Ok::<_, Infallible>(Response::new(Body::from("hello")))
+//!     }));
+//!
+//! let req = Request::builder().uri("/users/42").body(Body::empty()).unwrap();
+//! service.serve(Context::default(), req).await.unwrap();
+//!
+//! let requests = registry
+//!     .gather()
+//!     .into_iter()
+//!     .find(|family| family.get_name() == "http_server_requests_total")
+//!     .unwrap();
+//! assert_eq!(requests.get_metric()[0].get_counter().get_value(), 1.0);
+//! # }
+//! ```
+
+use crate::{
+    error::BoxError,
+    http::{dep::http_body, matcher::HttpMatcher, Body, Method, Request, Response, StatusCode},
+    service::{Context, Layer, Matcher, Service},
+};
+use bytes::Bytes;
+use prometheus::{
+    exponential_buckets, Histogram, HistogramOpts, HistogramVec, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry,
+};
+use std::{
+    collections::HashSet,
+    fmt,
+    sync::{Arc, Mutex, OnceLock},
+};
+use tokio::time::Instant;
+
+mod body;
+use body::SizeBody;
+
+/// The default maximum of distinct extension methods and `route` values.
+const DEFAULT_MAX_LABEL_VALUES: usize = 100;
+
+/// The label value recorded when a label exceeds its maximum of distinct values.
+const OTHER: &str = "other";
+
+/// The label value recorded when a request matches none of the routes.
+const UNMATCHED: &str = "unmatched";
+
+/// The [`prometheus`] metrics recorded by the [`HttpMetricsLayer`].
+///
+/// See the [module docs](self) for the recorded metrics.
+///
+/// [`prometheus`]: https://crates.io/crates/prometheus
+#[derive(Debug, Clone)]
+pub struct HttpMetrics {
+    requests: IntCounterVec,
+    duration: HistogramVec,
+    in_flight: IntGaugeVec,
+    request_body_size: HistogramVec,
+    response_body_size: HistogramVec,
+}
+
+impl HttpMetrics {
+    /// Create the [`HttpMetrics`] and register them in the given [`Registry`].
+    pub fn register(registry: &Registry) -> Result<Self, prometheus::Error> {
+        let size_buckets = exponential_buckets(64.0, 4.0, 10)?;
+        let metrics = Self {
+            requests: IntCounterVec::new(
+                Opts::new(
+                    "http_server_requests_total",
+                    "Number of http requests served.",
+                ),
+                &["method", "status", "route"],
+            )?,
+            duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "http_server_request_duration_seconds",
+                    "Duration of serving http requests, until the response is returned.",
+                ),
+                &["method", "status", "route"],
+            )?,
+            in_flight: IntGaugeVec::new(
+                Opts::new(
+                    "http_server_requests_in_flight",
+                    "Number of http requests being served.",
+                ),
+                &["method", "route"],
+            )?,
+            request_body_size: HistogramVec::new(
+                HistogramOpts::new(
+                    "http_server_request_body_size_bytes",
+                    "Size of the http request bodies.",
+                )
+                .buckets(size_buckets.clone()),
+                &["method", "route"],
+            )?,
+            response_body_size: HistogramVec::new(
+                HistogramOpts::new(
+                    "http_server_response_body_size_bytes",
+                    "Size of the http response bodies.",
+                )
+                .buckets(size_buckets),
+                &["method", "status", "route"],
+            )?,
+        };
+        registry.register(Box::new(metrics.requests.clone()))?;
+        registry.register(Box::new(metrics.duration.clone()))?;
+        registry.register(Box::new(metrics.in_flight.clone()))?;
+        registry.register(Box::new(metrics.request_body_size.clone()))?;
+        registry.register(Box::new(metrics.response_body_size.clone()))?;
+        Ok(metrics)
+    }
+
+    /// Get the [`HttpMetrics`] registered in the default [`Registry`],
+    /// registering them on first use.
+    ///
+    /// # Panics
+    ///
+    /// Panics if metrics with the same names are already registered in the default registry.
+    pub fn global() -> Self {
+        static METRICS: OnceLock<HttpMetrics> = OnceLock::new();
+        METRICS
+            .get_or_init(|| {
+                Self::register(prometheus::default_registry())
+                    .expect("register http metrics in default registry")
+            })
+            .clone()
+    }
+}
+
+/// Limits the number of distinct values recorded for a label.
+#[derive(Debug)]
+struct LabelLimiter {
+    max: usize,
+    seen: Mutex<HashSet<String>>,
+}
+
+impl LabelLimiter {
+    fn new(max: usize) -> Self {
+        Self {
+            max,
+            seen: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Get the value to record for the given label value.
+    fn limit<'a>(&self, value: &'a str) -> &'a str {
+        let mut seen = self.seen.lock().unwrap();
+        if seen.contains(value) {
+            value
+        } else if seen.len() < self.max {
+            seen.insert(value.to_owned());
+            value
+        } else {
+            OTHER
+        }
+    }
+}
+
+/// Get the `method` label of the given method, limiting only the distinct extension methods,
+/// such that these cannot take up the budget of the standard methods.
+fn method_label<'a>(method: &'a Method, extensions: &LabelLimiter) -> &'a str {
+    match *method {
+        Method::GET
+        | Method::HEAD
+        | Method::POST
+        | Method::PUT
+        | Method::DELETE
+        | Method::CONNECT
+        | Method::OPTIONS
+        | Method::TRACE
+        | Method::PATCH => method.as_str(),
+        _ => extensions.limit(method.as_str()),
+    }
+}
+
+/// Get the class of the given status code, e.g. `2xx`.
+fn status_class(status: StatusCode) -> &'static str {
+    match status.as_u16() {
+        100..=199 => "1xx",
+        200..=299 => "2xx",
+        300..=399 => "3xx",
+        400..=499 => "4xx",
+        _ => "5xx",
+    }
+}
+
+/// Decrements the in-flight gauge once the request is served, or cancelled.
+struct InFlightGuard(IntGauge);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+/// A [`Service`] which records [`HttpMetrics`] of the requests it serves,
+/// created using the [`HttpMetricsLayer`].
+///
+/// See the [module docs](self) for more details.
+pub struct HttpMetricsService<S> {
+    inner: S,
+    metrics: HttpMetrics,
+    routes: Arc<Vec<(String, HttpMatcher)>>,
+    methods: Arc<LabelLimiter>,
+    route_names: Arc<LabelLimiter>,
+}
+
+impl<S> HttpMetricsService<S> {
+    define_inner_service_accessors!();
+}
+
+impl<S: fmt::Debug> fmt::Debug for HttpMetricsService<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HttpMetricsService")
+            .field("inner", &self.inner)
+            .field("metrics", &self.metrics)
+            .field("routes", &self.routes)
+            .finish()
+    }
+}
+
+impl<S: Clone> Clone for HttpMetricsService<S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            metrics: self.metrics.clone(),
+            routes: self.routes.clone(),
+            methods: self.methods.clone(),
+            route_names: self.route_names.clone(),
+        }
+    }
+}
+
+impl<State, S, ReqBody, ResBody> Service<State, Request<ReqBody>> for HttpMetricsService<S>
+where
+    State: Send + Sync + 'static,
+    S: Service<State, Request, Response = Response<ResBody>>,
+    ReqBody: http_body::Body<Data = Bytes> + Send + Sync + 'static,
+    ReqBody::Error: Into<BoxError>,
+    ResBody: http_body::Body<Data = Bytes> + Send + Sync + 'static,
+    ResBody::Error: Into<BoxError>,
+{
+    type Response = Response;
+    type Error = S::Error;
+
+    async fn serve(
+        &self,
+        ctx: Context<State>,
+        req: Request<ReqBody>,
+    ) -> Result<Self::Response, Self::Error> {
+        let method = method_label(req.method(), &self.methods).to_owned();
+        let route = self
+            .routes
+            .iter()
+            .find(|(_, matcher)| matcher.matches(None, &ctx, &req))
+            .map_or(UNMATCHED, |(name, _)| self.route_names.limit(name))
+            .to_owned();
+
+        let in_flight = self.metrics.in_flight.with_label_values(&[&method, &route]);
+        in_flight.inc();
+        let _guard = InFlightGuard(in_flight);
+
+        let request_body_size = self
+            .metrics
+            .request_body_size
+            .with_label_values(&[&method, &route]);
+        let req = req.map(|body| Body::new(SizeBody::new(body, request_body_size)));
+
+        let start = Instant::now();
+        let result = self.inner.serve(ctx, req).await;
+        let status = match &result {
+            Ok(res) => status_class(res.status()),
+            Err(_) => "error",
+        };
+        let labels = [method.as_str(), status, route.as_str()];
+        self.metrics.requests.with_label_values(&labels).inc();
+        self.metrics
+            .duration
+            .with_label_values(&labels)
+            .observe(start.elapsed().as_secs_f64());
+
+        let response_body_size: Histogram =
+            self.metrics.response_body_size.with_label_values(&labels);
+        result.map(|res| res.map(|body| Body::new(SizeBody::new(body, response_body_size))))
+    }
+}
+
+/// A [`Layer`] that records [`HttpMetrics`] of http requests.
+///
+/// See the [module docs](self) for more details.
+#[derive(Debug, Clone)]
+pub struct HttpMetricsLayer {
+    metrics: HttpMetrics,
+    routes: Vec<(String, HttpMatcher)>,
+    max_label_values: usize,
+}
+
+impl HttpMetricsLayer {
+    /// Create a new [`HttpMetricsLayer`], recording the [`HttpMetrics`]
+    /// registered in the default [`Registry`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if metrics with the same names are already registered in the default registry,
+    /// other than by this layer.
+    pub fn new() -> Self {
+        Self::with_metrics(HttpMetrics::global())
+    }
+
+    /// Create a new [`HttpMetricsLayer`], recording [`HttpMetrics`]
+    /// registered in the given [`Registry`].
+    pub fn with_registry(registry: &Registry) -> Result<Self, prometheus::Error> {
+        Ok(Self::with_metrics(HttpMetrics::register(registry)?))
+    }
+
+    /// Create a new [`HttpMetricsLayer`], recording the given [`HttpMetrics`],
+    /// e.g. to share them between multiple layers.
+    pub fn with_metrics(metrics: HttpMetrics) -> Self {
+        Self {
+            metrics,
+            routes: Vec::new(),
+            max_label_values: DEFAULT_MAX_LABEL_VALUES,
+        }
+    }
+
+    /// Add a route with the given name, recorded as the `route` label
+    /// of the requests matched by the given [`HttpMatcher`].
+    ///
+    /// Routes are tried in the order they are added.
+    pub fn route(mut self, name: impl Into<String>, matcher: HttpMatcher) -> Self {
+        self.routes.push((name.into(), matcher));
+        self
+    }
+
+    /// Set the maximum of distinct extension methods recorded for the `method` label,
+    /// and of distinct values recorded for the `route` label, by default 100 each.
+    pub fn max_label_values(mut self, max: usize) -> Self {
+        self.max_label_values = max;
+        self
+    }
+}
+
+impl Default for HttpMetricsLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> Layer<S> for HttpMetricsLayer {
+    type Service = HttpMetricsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        HttpMetricsService {
+            inner,
+            metrics: self.metrics.clone(),
+            routes: Arc::new(self.routes.clone()),
+            methods: Arc::new(LabelLimiter::new(self.max_label_values)),
+            route_names: Arc::new(LabelLimiter::new(self.max_label_values)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        http::{dep::http_body_util::BodyExt, Method},
+        service::service_fn,
+    };
+
+    fn value(registry: &Registry, name: &str, labels: &[(&str, &str)]) -> Option<f64> {
+        let family = registry
+            .gather()
+            .into_iter()
+            .find(|family| family.get_name() == name)?;
+        let metric = family.get_metric().iter().find(|metric| {
+            labels.iter().all(|(name, value)| {
+                metric
+                    .get_label()
+                    .iter()
+                    .any(|label| label.get_name() == *name && label.get_value() == *value)
+            })
+        })?;
+        Some(match family.get_field_type() {
+            prometheus::proto::MetricType::COUNTER => metric.get_counter().get_value(),
+            prometheus::proto::MetricType::GAUGE => metric.get_gauge().get_value(),
+            _ => metric.get_histogram().get_sample_sum(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_http_metrics() {
+        let registry = Registry::new();
+        let service = HttpMetricsLayer::with_registry(&registry)
+            .unwrap()
+            .route("echo", HttpMatcher::path("/echo"))
+            .max_label_values(2)
+            .layer(service_fn(|req: Request| async move {
+                if req.method() == Method::DELETE {
+                    return Err("not allowed");
+                }
+                let body = req.into_body().collect().await.unwrap().to_bytes();
+                Ok(Response::new(Body::from(body)))
+            }));
+
+        let req = Request::post("/echo").body(Body::from("hello")).unwrap();
+        let res = service.serve(Context::default(), req).await.unwrap();
+        assert_eq!(
+            value(
+                &registry,
+                "http_server_requests_in_flight",
+                &[("route", "echo")]
+            ),
+            Some(0.0)
+        );
+        res.into_body().collect().await.unwrap();
+
+        let labels = [("method", "POST"), ("status", "2xx"), ("route", "echo")];
+        assert_eq!(
+            value(&registry, "http_server_requests_total", &labels),
+            Some(1.0)
+        );
+        assert_eq!(
+            value(
+                &registry,
+                "http_server_request_body_size_bytes",
+                &labels[..1]
+            ),
+            Some(5.0)
+        );
+        assert_eq!(
+            value(&registry, "http_server_response_body_size_bytes", &labels),
+            Some(5.0)
+        );
+
+        let req = Request::delete("/other").body(Body::empty()).unwrap();
+        service.serve(Context::default(), req).await.unwrap_err();
+        let labels = [
+            ("method", "DELETE"),
+            ("status", "error"),
+            ("route", UNMATCHED),
+        ];
+        assert_eq!(
+            value(&registry, "http_server_requests_total", &labels),
+            Some(1.0)
+        );
+
+        // standard methods are always recorded, unlike extension methods
+        for method in ["FOO", "BAR", "BAZ", "GET"] {
+            let req = Request::builder()
+                .method(method)
+                .uri("/echo")
+                .body(Body::empty())
+                .unwrap();
+            service.serve(Context::default(), req).await.unwrap();
+        }
+        for (method, total) in [("FOO", 1.0), ("BAR", 1.0), (OTHER, 1.0), ("GET", 1.0)] {
+            assert_eq!(
+                value(
+                    &registry,
+                    "http_server_requests_total",
+                    &[("method", method)]
+                ),
+                Some(total)
+            );
+        }
+    }
+
+    #[test]
+    fn test_http_metrics_register_twice() {
+        let registry = Registry::new();
+        HttpMetrics::register(&registry).unwrap();
+        assert!(HttpMetrics::register(&registry).is_err());
+    }
+}
@@ -17,24 +17,30 @@
 //! [`Layer`]: crate::service::Layer
 //! [`Service`]: crate::service::Service
 
+pub mod access_log;
 pub mod auth;
 pub mod body_limit;
 pub mod catch_panic;
 pub mod classify;
 pub mod cors;
 pub mod dns;
+pub mod fault_injection;
+pub mod har;
 pub mod header_config;
 pub mod map_request_body;
 pub mod map_response_body;
+pub mod metrics;
 pub mod normalize_path;
 pub mod propagate_headers;
 pub mod proxy_auth;
+pub mod replay;
 pub mod request_id;
 pub mod sensitive_headers;
 pub mod set_header;
 pub mod set_status;
 pub mod timeout;
 pub mod trace;
+pub mod trace_context;
 pub mod upgrade;
 pub mod validate_request;
 
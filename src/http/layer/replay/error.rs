@@ -0,0 +1,30 @@
+//! Error type for the ReplayLayer middleware.
+
+use std::{error, fmt};
+
+/// No recording was found for the request,
+/// returned by the [`ReplayService`] unless it falls through on misses.
+///
+/// [`ReplayService`]: super::ReplayService
+#[derive(Debug, Clone)]
+pub struct RecordingNotFound(String);
+
+impl RecordingNotFound {
+    /// Construct a new recording not found error
+    pub(crate) fn new(signature: String) -> Self {
+        Self(signature)
+    }
+
+    /// The normalized signature of the request for which no recording was found.
+    pub fn signature(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for RecordingNotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "no recording found for request: {}", self.0)
+    }
+}
+
+impl error::Error for RecordingNotFound {}
@@ -0,0 +1,500 @@
+//! Middleware to record the responses of an upstream, such as the [`HttpClient`],
+//! and to replay them later, e.g. to run integration tests without network access.
+//!
+//! The [`RecordLayer`] stores each request, and the response it received, as a [`Recording`]
+//! into a [`RecordingStore`], keyed by the normalized signature of the request.
+//! The [`ReplayLayer`] serves these recordings back for requests with the same signature.
+//! On a miss it either fails with [`RecordingNotFound`], the default, or falls through
+//! to its inner service.
+//!
+//! The signature of a request is computed using a [`RequestSignature`],
+//! which defines which parts of the request are to match. The same [`RequestSignature`]
+//! is to be used for recording and replaying.
+//!
+//! The recordings can be stored:
+//!
+//! - in memory, using the [`MemoryRecordingStore`];
+//! - as a file per recording, using the [`FileRecordingStore`],
+//!   to be committed alongside the tests replaying them.
+//!
+//! Layering a [`ReplayLayer`] that falls through on misses on top of a [`RecordLayer`]
+//! only records the requests which were not recorded yet.
+//!
+//! Request and response bodies are buffered in full, such that these layers
+//! are not suited for streaming bodies.
+//!
+//! [`HttpClient`]: crate::http::client::HttpClient
+//!
+//! # Example
+//!
+//! ```
+//! use rama::http::client::HttpClient;
+//! use rama::http::layer::replay::{
+//!     MemoryRecordingStore, RecordLayer, RecordingNotFound, ReplayLayer,
+//! };
+//! use rama::http::{dep::http_body_util::BodyExt, Body, Request, Response};
+//! use rama::service::{service_fn, Context, Layer, Service};
+//! use std::convert::Infallible;
+//!
+//! # #[tokio::main]
+//! # async fn main() {
+//! let store = MemoryRecordingStore::new();
+//! let request = |path: &str| {
+//!     Request::builder()
+//!         .uri(format!("http://example.com{path}"))
+//!         .body(Body::empty())
+//!         .unwrap()
+//! };
+//!
+//! // record the responses of the upstream, here a fake one
+//! let upstream = service_fn(|_req: Request| async {
+//!     Ok::<_, Infallible>(Response::new(Body::from("hello")))
+//! });
+//! let client = RecordLayer::new(store.clone()).layer(upstream);
+//! client.serve(Context::default(), request("/")).await.unwrap();
+//!
+//! // replay them, without ever calling the actual client
+//! let client = ReplayLayer::new(store).layer(HttpClient::new());
+//! let response = client.serve(Context::default(), request("/")).await.unwrap();
+//! let body = response.into_body().collect().await.unwrap().to_bytes();
+//! assert_eq!(body, "hello");
+//!
+//! let err = client.serve(Context::default(), request("/missing")).await.unwrap_err();
+//! assert!(err.downcast_ref::<RecordingNotFound>().is_some());
+//! # }
+//! ```
+
+use crate::{
+    error::BoxError,
+    http::{
+        dep::{http_body, http_body_util::BodyExt},
+        Body, Request, Response,
+    },
+    service::{Context, Layer, Service},
+};
+use bytes::Bytes;
+use std::{fmt, sync::Arc};
+
+mod error;
+#[doc(inline)]
+pub use error::RecordingNotFound;
+
+mod recording;
+use recording::{recorded_headers, recording_key};
+#[doc(inline)]
+pub use recording::{RecordedBody, RecordedRequest, RecordedResponse, Recording};
+
+mod signature;
+#[doc(inline)]
+pub use signature::RequestSignature;
+
+mod store;
+#[doc(inline)]
+pub use store::{FileRecordingStore, MemoryRecordingStore, RecordingStore};
+
+/// A [`Service`] which records the responses of its inner service,
+/// created using the [`RecordLayer`].
+pub struct RecordService<S, R> {
+    inner: S,
+    store: Arc<R>,
+    signature: RequestSignature,
+}
+
+impl<S, R> RecordService<S, R> {
+    define_inner_service_accessors!();
+}
+
+impl<S: fmt::Debug, R> fmt::Debug for RecordService<S, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RecordService")
+            .field("inner", &self.inner)
+            .field("signature", &self.signature)
+            .finish()
+    }
+}
+
+impl<S: Clone, R> Clone for RecordService<S, R> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            store: self.store.clone(),
+            signature: self.signature.clone(),
+        }
+    }
+}
+
+impl<State, S, R, ReqBody, ResBody> Service<State, Request<ReqBody>> for RecordService<S, R>
+where
+    State: Send + Sync + 'static,
+    R: RecordingStore,
+    S: Service<State, Request, Response = Response<ResBody>>,
+    S::Error: Into<BoxError>,
+    ReqBody: http_body::Body<Data = Bytes> + Send + 'static,
+    ReqBody::Error: Into<BoxError>,
+    ResBody: http_body::Body<Data = Bytes> + Send + 'static,
+    ResBody::Error: Into<BoxError>,
+{
+    type Response = Response;
+    type Error = BoxError;
+
+    async fn serve(
+        &self,
+        ctx: Context<State>,
+        req: Request<ReqBody>,
+    ) -> Result<Self::Response, Self::Error> {
+        let (parts, body) = req.into_parts();
+        let body = body.collect().await.map_err(Into::into)?.to_bytes();
+        let signature = self.signature.sign(&parts, &body);
+        let request = RecordedRequest {
+            method: parts.method.to_string(),
+            uri: parts.uri.to_string(),
+            headers: recorded_headers(&parts.headers, false),
+            body: RecordedBody::new(&body),
+        };
+
+        let res = self
+            .inner
+            .serve(ctx, Request::from_parts(parts, Body::from(body)))
+            .await
+            .map_err(Into::into)?;
+        let (parts, body) = res.into_parts();
+        let body = body.collect().await.map_err(Into::into)?.to_bytes();
+
+        self.store
+            .save(Recording {
+                signature,
+                request,
+                response: RecordedResponse {
+                    status: parts.status.as_u16(),
+                    headers: recorded_headers(&parts.headers, true),
+                    body: RecordedBody::new(&body),
+                },
+            })
+            .await?;
+
+        Ok(Response::from_parts(parts, Body::from(body)))
+    }
+}
+
+/// A [`Layer`] that records the responses of its inner service as [`Recording`]s.
+///
+/// See the [module docs](self) for more details.
+pub struct RecordLayer<R> {
+    store: Arc<R>,
+    signature: RequestSignature,
+}
+
+impl<R> RecordLayer<R> {
+    /// Create a new [`RecordLayer`], saving the recordings into the given [`RecordingStore`].
+    pub fn new(store: R) -> Self {
+        Self {
+            store: Arc::new(store),
+            signature: RequestSignature::default(),
+        }
+    }
+
+    /// Set the [`RequestSignature`] used to compute the signature of a request.
+    pub fn signature(mut self, signature: RequestSignature) -> Self {
+        self.signature = signature;
+        self
+    }
+}
+
+impl<R> fmt::Debug for RecordLayer<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RecordLayer")
+            .field("signature", &self.signature)
+            .finish()
+    }
+}
+
+impl<R> Clone for RecordLayer<R> {
+    fn clone(&self) -> Self {
+        Self {
+            store: self.store.clone(),
+            signature: self.signature.clone(),
+        }
+    }
+}
+
+impl<S, R> Layer<S> for RecordLayer<R> {
+    type Service = RecordService<S, R>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RecordService {
+            inner,
+            store: self.store.clone(),
+            signature: self.signature.clone(),
+        }
+    }
+}
+
+/// A [`Service`] which serves the recorded responses,
+/// created using the [`ReplayLayer`].
+pub struct ReplayService<S, R> {
+    inner: S,
+    store: Arc<R>,
+    signature: RequestSignature,
+    fall_through: bool,
+}
+
+impl<S, R> ReplayService<S, R> {
+    define_inner_service_accessors!();
+}
+
+impl<S: fmt::Debug, R> fmt::Debug for ReplayService<S, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReplayService")
+            .field("inner", &self.inner)
+            .field("signature", &self.signature)
+            .field("fall_through", &self.fall_through)
+            .finish()
+    }
+}
+
+impl<S: Clone, R> Clone for ReplayService<S, R> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            store: self.store.clone(),
+            signature: self.signature.clone(),
+            fall_through: self.fall_through,
+        }
+    }
+}
+
+impl<State, S, R, ReqBody> Service<State, Request<ReqBody>> for ReplayService<S, R>
+where
+    State: Send + Sync + 'static,
+    R: RecordingStore,
+    S: Service<State, Request, Response = Response>,
+    S::Error: Into<BoxError>,
+    ReqBody: http_body::Body<Data = Bytes> + Send + 'static,
+    ReqBody::Error: Into<BoxError>,
+{
+    type Response = Response;
+    type Error = BoxError;
+
+    async fn serve(
+        &self,
+        ctx: Context<State>,
+        req: Request<ReqBody>,
+    ) -> Result<Self::Response, Self::Error> {
+        let (parts, body) = req.into_parts();
+        let body = body.collect().await.map_err(Into::into)?.to_bytes();
+        let signature = self.signature.sign(&parts, &body);
+
+        // the key is only a hash of the signature, so compare the full signature as well
+        if let Some(recording) = self.store.load(&recording_key(&signature)).await? {
+            if recording.signature == signature {
+                return recording.response.to_response();
+            }
+        }
+
+        if self.fall_through {
+            self.inner
+                .serve(ctx, Request::from_parts(parts, Body::from(body)))
+                .await
+                .map_err(Into::into)
+        } else {
+            Err(RecordingNotFound::new(signature).into())
+        }
+    }
+}
+
+/// A [`Layer`] that serves the recorded responses instead of calling its inner service.
+///
+/// See the [module docs](self) for more details.
+pub struct ReplayLayer<R> {
+    store: Arc<R>,
+    signature: RequestSignature,
+    fall_through: bool,
+}
+
+impl<R> ReplayLayer<R> {
+    /// Create a new [`ReplayLayer`], loading the recordings from the given [`RecordingStore`].
+    pub fn new(store: R) -> Self {
+        Self {
+            store: Arc::new(store),
+            signature: RequestSignature::default(),
+            fall_through: false,
+        }
+    }
+
+    /// Set the [`RequestSignature`] used to compute the signature of a request.
+    pub fn signature(mut self, signature: RequestSignature) -> Self {
+        self.signature = signature;
+        self
+    }
+
+    /// Call the inner service for requests without a recording,
+    /// instead of failing with [`RecordingNotFound`], the default.
+    pub fn fall_through(mut self, fall_through: bool) -> Self {
+        self.fall_through = fall_through;
+        self
+    }
+}
+
+impl<R> fmt::Debug for ReplayLayer<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReplayLayer")
+            .field("signature", &self.signature)
+            .field("fall_through", &self.fall_through)
+            .finish()
+    }
+}
+
+impl<R> Clone for ReplayLayer<R> {
+    fn clone(&self) -> Self {
+        Self {
+            store: self.store.clone(),
+            signature: self.signature.clone(),
+            fall_through: self.fall_through,
+        }
+    }
+}
+
+impl<S, R> Layer<S> for ReplayLayer<R> {
+    type Service = ReplayService<S, R>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ReplayService {
+            inner,
+            store: self.store.clone(),
+            signature: self.signature.clone(),
+            fall_through: self.fall_through,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        http::{
+            client::HttpClient, header, layer::util::record::REDACTED, HeaderValue, StatusCode,
+        },
+        service::{service_fn, ServiceBuilder},
+    };
+    use std::{
+        convert::Infallible,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    fn upstream(
+        calls: Arc<AtomicUsize>,
+    ) -> impl Service<(), Request, Response = Response, Error = Infallible> + Clone {
+        service_fn(move |req: Request| {
+            let calls = calls.clone();
+            async move {
+                calls.fetch_add(1, Ordering::SeqCst);
+                let body = req.into_body().collect().await.unwrap().to_bytes();
+                Ok(Response::builder()
+                    .status(StatusCode::CREATED)
+                    .header(header::CONTENT_TYPE, "application/octet-stream")
+                    .header(header::TRANSFER_ENCODING, "chunked")
+                    .body(Body::from([&body[..], &[0xff]].concat()))
+                    .unwrap())
+            }
+        })
+    }
+
+    fn request(body: &'static str) -> Request {
+        let mut token = HeaderValue::from_static("Bearer secret");
+        token.set_sensitive(true);
+        Request::post("http://example.com/items?b=2&a=1")
+            .header(header::AUTHORIZATION, token)
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_record_and_replay() {
+        let store = MemoryRecordingStore::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let client = RecordLayer::new(store.clone()).layer(upstream(calls.clone()));
+        let res = client
+            .serve(Context::default(), request("a"))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(
+            res.into_body().collect().await.unwrap().to_bytes(),
+            &b"a\xff"[..]
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(store.len(), 1);
+
+        let signature = RequestSignature::new().sign(&request("a").into_parts().0, b"a");
+        let recording = store
+            .load(&recording_key(&signature))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(recording.signature, signature);
+        assert_eq!(
+            recording.request.headers,
+            vec![("authorization".to_owned(), REDACTED.to_owned())]
+        );
+        assert_eq!(recording.response.body.encoding.as_deref(), Some("base64"));
+        assert!(recording
+            .response
+            .headers
+            .iter()
+            .all(|(name, _)| name != "transfer-encoding"));
+
+        // replay without network, the http client is never called
+        let client = ReplayLayer::new(store).layer(HttpClient::new());
+        let res = client
+            .serve(Context::default(), request("a"))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(
+            res.headers()[header::CONTENT_TYPE],
+            "application/octet-stream"
+        );
+        assert_eq!(
+            res.into_body().collect().await.unwrap().to_bytes(),
+            &b"a\xff"[..]
+        );
+
+        let err = client
+            .serve(Context::default(), request("b"))
+            .await
+            .unwrap_err();
+        let err = err.downcast_ref::<RecordingNotFound>().unwrap();
+        assert_eq!(
+            err.signature(),
+            RequestSignature::new().sign(&request("b").into_parts().0, b"b")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replay_fall_through_records_misses() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileRecordingStore::new(dir.path().join("recordings"));
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let client = ServiceBuilder::new()
+            .layer(ReplayLayer::new(store.clone()).fall_through(true))
+            .layer(RecordLayer::new(store.clone()))
+            .service(upstream(calls.clone()));
+
+        for body in ["a", "b", "a"] {
+            let res = client
+                .serve(Context::default(), request(body))
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::CREATED);
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(
+            std::fs::read_dir(dir.path().join("recordings"))
+                .unwrap()
+                .count(),
+            2
+        );
+    }
+}
@@ -0,0 +1,132 @@
+use crate::http::{
+    header,
+    layer::util::record::{header_value, BASE64},
+    HeaderMap, HeaderName, HeaderValue, Response, StatusCode,
+};
+use base64::Engine as _;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+
+/// A recorded request and the response it received from upstream,
+/// as stored by the [`RecordLayer`] and served back by the [`ReplayLayer`].
+///
+/// [`RecordLayer`]: super::RecordLayer
+/// [`ReplayLayer`]: super::ReplayLayer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Recording {
+    /// The normalized signature of the request, see [`RequestSignature`].
+    ///
+    /// [`RequestSignature`]: super::RequestSignature
+    pub signature: String,
+    /// The recorded request, kept for reference only.
+    pub request: RecordedRequest,
+    /// The recorded response.
+    pub response: RecordedResponse,
+}
+
+impl Recording {
+    /// The key under which the recording is stored, derived from its signature.
+    pub fn key(&self) -> String {
+        recording_key(&self.signature)
+    }
+}
+
+/// Get the key under which the recording of a request with the given signature is stored.
+pub(super) fn recording_key(signature: &str) -> String {
+    format!("{:016x}", super::signature::fnv1a(signature.as_bytes()))
+}
+
+/// A recorded request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedRequest {
+    /// The method of the request.
+    pub method: String,
+    /// The uri of the request.
+    pub uri: String,
+    /// The headers of the request, with the values of sensitive headers redacted.
+    pub headers: Vec<(String, String)>,
+    /// The body of the request.
+    pub body: RecordedBody,
+}
+
+/// A recorded response.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedResponse {
+    /// The status code of the response.
+    pub status: u16,
+    /// The headers of the response.
+    pub headers: Vec<(String, String)>,
+    /// The body of the response.
+    pub body: RecordedBody,
+}
+
+impl RecordedResponse {
+    /// Create the [`Response`] as recorded.
+    pub(super) fn to_response(&self) -> Result<Response, crate::error::BoxError> {
+        let mut headers = HeaderMap::with_capacity(self.headers.len());
+        for (name, value) in &self.headers {
+            headers.append(
+                HeaderName::try_from(name.as_str())?,
+                HeaderValue::try_from(value.as_str())?,
+            );
+        }
+        let mut response = Response::new(self.body.to_bytes()?.into());
+        *response.status_mut() = StatusCode::from_u16(self.status)?;
+        *response.headers_mut() = headers;
+        Ok(response)
+    }
+}
+
+/// A recorded body, as text if it is valid UTF-8 and base64 encoded otherwise.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedBody {
+    /// The body as text.
+    pub text: String,
+    /// The encoding of the text, `base64` if the body is not valid UTF-8.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+}
+
+impl RecordedBody {
+    /// Create a new [`RecordedBody`] for the given data.
+    pub fn new(data: &[u8]) -> Self {
+        match std::str::from_utf8(data) {
+            Ok(text) => Self {
+                text: text.to_owned(),
+                encoding: None,
+            },
+            Err(_) => Self {
+                text: BASE64.encode(data),
+                encoding: Some("base64".to_owned()),
+            },
+        }
+    }
+
+    /// Get the data of the body, decoding it if needed.
+    pub fn to_bytes(&self) -> Result<Bytes, base64::DecodeError> {
+        match self.encoding.as_deref() {
+            Some("base64") => BASE64.decode(&self.text).map(Into::into),
+            _ => Ok(Bytes::copy_from_slice(self.text.as_bytes())),
+        }
+    }
+}
+
+/// Record the given headers, leaving out the connection specific ones.
+///
+/// The values of sensitive headers are redacted, unless `keep_sensitive` is set.
+pub(super) fn recorded_headers(headers: &HeaderMap, keep_sensitive: bool) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .filter(|(name, _)| {
+            *name != header::CONNECTION
+                && *name != header::TRANSFER_ENCODING
+                && name.as_str() != "keep-alive"
+        })
+        .map(|(name, value)| {
+            (
+                name.to_string(),
+                header_value(value, value.is_sensitive() && !keep_sensitive),
+            )
+        })
+        .collect()
+}
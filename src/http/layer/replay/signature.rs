@@ -0,0 +1,159 @@
+use crate::http::{
+    dep::http::{request::Parts, uri::Authority},
+    header, HeaderName,
+};
+use std::fmt::Write as _;
+
+/// The normalization rules used to compute the signature of a request,
+/// under which its recording is stored and looked up.
+///
+/// By default the signature consists of:
+///
+/// - the method of the request;
+/// - its url, with the scheme and host lowercased, the default port removed
+///   and the query string parameters sorted;
+/// - a hash of its body.
+///
+/// No headers are part of the signature, unless added using [`RequestSignature::header`].
+#[derive(Debug, Clone, Default)]
+pub struct RequestSignature {
+    headers: Vec<HeaderName>,
+    ignored_query_params: Vec<String>,
+    ignore_body: bool,
+}
+
+impl RequestSignature {
+    /// Create a new [`RequestSignature`], using the default rules.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Include the values of the given header in the signature.
+    pub fn header(mut self, name: HeaderName) -> Self {
+        self.headers.push(name);
+        self
+    }
+
+    /// Leave the given query string parameter out of the signature,
+    /// e.g. a timestamp or nonce which differs for each request.
+    pub fn ignore_query_param(mut self, name: impl Into<String>) -> Self {
+        self.ignored_query_params.push(name.into());
+        self
+    }
+
+    /// Leave the body of the request out of the signature, by default it is included.
+    pub fn ignore_body(mut self, ignore_body: bool) -> Self {
+        self.ignore_body = ignore_body;
+        self
+    }
+
+    /// Compute the normalized signature of the request with the given head and body.
+    pub fn sign(&self, parts: &Parts, body: &[u8]) -> String {
+        let uri = &parts.uri;
+        let scheme = uri.scheme_str().unwrap_or("http").to_ascii_lowercase();
+        let authority = uri.authority().cloned().or_else(|| {
+            parts
+                .headers
+                .get(header::HOST)
+                .and_then(|host| Authority::try_from(host.as_bytes()).ok())
+        });
+        let host = authority
+            .as_ref()
+            .map(|authority| authority.host().to_ascii_lowercase())
+            .unwrap_or_default();
+        let port = authority.as_ref().and_then(Authority::port_u16);
+        let default_port = if scheme == "https" { 443 } else { 80 };
+        let path = match uri.path() {
+            "" => "/",
+            path => path,
+        };
+
+        let mut signature = format!("{} {scheme}://{host}", parts.method);
+        if let Some(port) = port.filter(|port| *port != default_port) {
+            let _ = write!(signature, ":{port}");
+        }
+        signature.push_str(path);
+
+        let mut query: Vec<(String, String)> = uri
+            .query()
+            .and_then(|query| serde_urlencoded::from_str(query).ok())
+            .unwrap_or_default();
+        query.retain(|(name, _)| !self.ignored_query_params.contains(name));
+        query.sort();
+        if !query.is_empty() {
+            signature.push('?');
+            signature.push_str(&serde_urlencoded::to_string(&query).unwrap_or_default());
+        }
+
+        let mut headers: Vec<_> = self.headers.iter().collect();
+        headers.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+        headers.dedup();
+        for name in headers {
+            for value in parts.headers.get_all(name) {
+                let _ = write!(
+                    signature,
+                    "\n{name}: {}",
+                    String::from_utf8_lossy(value.as_bytes()).trim()
+                );
+            }
+        }
+
+        if !self.ignore_body && !body.is_empty() {
+            let _ = write!(signature, "\nbody: {:016x}", fnv1a(body));
+        }
+
+        signature
+    }
+}
+
+/// The [64 bit FNV-1a](http://www.isthe.com/chongo/tech/comp/fnv/) hash of the given data,
+/// which, unlike the std hashers, is stable across releases and platforms.
+pub(super) fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::Request;
+
+    fn sign(signature: &RequestSignature, req: Request<&'static str>) -> String {
+        let (parts, body) = req.into_parts();
+        signature.sign(&parts, body.as_bytes())
+    }
+
+    #[test]
+    fn test_request_signature() {
+        let signature = RequestSignature::new()
+            .header(HeaderName::from_static("x-api-version"))
+            .ignore_query_param("ts");
+
+        let a = Request::post("http://Example.com:80/search?q=rama&page=2&ts=1")
+            .header("x-api-version", "2 ")
+            .header("user-agent", "a")
+            .body("{}")
+            .unwrap();
+        let b = Request::post("/search?page=2&ts=2&q=rama")
+            .header("host", "example.com")
+            .header("x-api-version", "2")
+            .header("user-agent", "b")
+            .body("{}")
+            .unwrap();
+        let expected = format!(
+            "POST http://example.com/search?page=2&q=rama\nx-api-version: 2\nbody: {:016x}",
+            fnv1a(b"{}")
+        );
+        assert_eq!(sign(&signature, a), expected);
+        assert_eq!(sign(&signature, b), expected);
+
+        let c = Request::post("https://example.com:8443/search")
+            .body("{\"q\":1}")
+            .unwrap();
+        assert_eq!(
+            sign(&signature.clone().ignore_body(true), c),
+            "POST https://example.com:8443/search"
+        );
+    }
+}
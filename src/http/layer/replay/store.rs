@@ -0,0 +1,108 @@
+use super::Recording;
+use crate::fs::spawn_write_atomic;
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    future::Future,
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+/// A store of [`Recording`]s, by their [`Recording::key`].
+pub trait RecordingStore: Send + Sync + 'static {
+    /// The error returned in case the store fails.
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    /// Load the recording stored under the given key, if any.
+    fn load(
+        &self,
+        key: &str,
+    ) -> impl Future<Output = Result<Option<Recording>, Self::Error>> + Send;
+
+    /// Save the given recording, replacing the recording stored under the same key, if any.
+    fn save(&self, recording: Recording) -> impl Future<Output = Result<(), Self::Error>> + Send;
+}
+
+/// A [`RecordingStore`] which keeps the recordings in memory.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryRecordingStore {
+    recordings: Arc<Mutex<HashMap<String, Recording>>>,
+}
+
+impl MemoryRecordingStore {
+    /// Create a new, empty, [`MemoryRecordingStore`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the number of stored recordings.
+    pub fn len(&self) -> usize {
+        self.recordings.lock().unwrap().len()
+    }
+
+    /// Returns `true` if no recordings are stored.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl RecordingStore for MemoryRecordingStore {
+    type Error = Infallible;
+
+    async fn load(&self, key: &str) -> Result<Option<Recording>, Self::Error> {
+        Ok(self.recordings.lock().unwrap().get(key).cloned())
+    }
+
+    async fn save(&self, recording: Recording) -> Result<(), Self::Error> {
+        self.recordings
+            .lock()
+            .unwrap()
+            .insert(recording.key(), recording);
+        Ok(())
+    }
+}
+
+/// A [`RecordingStore`] which stores each recording as a JSON file in a directory,
+/// named after its key, e.g. `<dir>/9a4c1f0e5b7d2c38.json`.
+///
+/// The files are meant to be committed alongside the tests replaying them.
+#[derive(Debug, Clone)]
+pub struct FileRecordingStore {
+    dir: PathBuf,
+}
+
+impl FileRecordingStore {
+    /// Create a new [`FileRecordingStore`] using the given directory,
+    /// which is created once the first recording is saved.
+    pub fn new(dir: impl AsRef<Path>) -> Self {
+        Self {
+            dir: dir.as_ref().to_owned(),
+        }
+    }
+
+    /// Get the path of the file of the recording stored under the given key.
+    pub fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{key}.json"))
+    }
+}
+
+impl RecordingStore for FileRecordingStore {
+    type Error = io::Error;
+
+    async fn load(&self, key: &str) -> Result<Option<Recording>, Self::Error> {
+        match tokio::fs::read(self.path(key)).await {
+            Ok(data) => Ok(Some(serde_json::from_slice(&data)?)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn save(&self, recording: Recording) -> Result<(), Self::Error> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        spawn_write_atomic(self.path(&recording.key()), move |file| {
+            Ok(serde_json::to_writer_pretty(file, &recording)?)
+        })
+        .await
+    }
+}
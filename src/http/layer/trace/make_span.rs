@@ -88,6 +88,8 @@ impl<B> MakeSpan<B> for DefaultMakeSpan {
                         method = %request.method(),
                         uri = %request.uri(),
                         version = ?request.version(),
+                        trace_id = tracing::field::Empty,
+                        span_id = tracing::field::Empty,
                         headers = ?request.headers(),
                     )
                 } else {
@@ -97,6 +99,8 @@ impl<B> MakeSpan<B> for DefaultMakeSpan {
                         method = %request.method(),
                         uri = %request.uri(),
                         version = ?request.version(),
+                        trace_id = tracing::field::Empty,
+                        span_id = tracing::field::Empty,
                     )
                 }
             }
@@ -8,6 +8,7 @@ use crate::http::layer::classify::{
     ClassifiedResponse, ClassifyResponse, GrpcErrorsAsFailures, MakeClassifier,
     ServerErrorsAsFailures, SharedClassifier,
 };
+use crate::http::layer::trace_context::link_span;
 use crate::http::{Request, Response};
 use crate::service::{Context, Service};
 use std::{fmt, time::Instant};
@@ -293,12 +294,13 @@ where
 
     async fn serve(
         &self,
-        ctx: Context<State>,
+        mut ctx: Context<State>,
         req: Request<ReqBody>,
     ) -> Result<Self::Response, Self::Error> {
         let start = Instant::now();
 
         let span = self.make_span.make_span(&req);
+        link_span(&mut ctx, &span);
 
         let classifier = self.make_classifier.make_classifier(&req);
 
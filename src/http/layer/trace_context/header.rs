@@ -0,0 +1,242 @@
+//! Parsing and formatting of the W3C Trace Context and B3 propagation headers.
+
+use super::TraceContext;
+use crate::http::{HeaderMap, HeaderName, HeaderValue};
+
+const TRACEPARENT: HeaderName = HeaderName::from_static("traceparent");
+const TRACESTATE: HeaderName = HeaderName::from_static("tracestate");
+const B3: HeaderName = HeaderName::from_static("b3");
+const X_B3_TRACE_ID: HeaderName = HeaderName::from_static("x-b3-traceid");
+const X_B3_SPAN_ID: HeaderName = HeaderName::from_static("x-b3-spanid");
+const X_B3_PARENT_SPAN_ID: HeaderName = HeaderName::from_static("x-b3-parentspanid");
+const X_B3_SAMPLED: HeaderName = HeaderName::from_static("x-b3-sampled");
+const X_B3_FLAGS: HeaderName = HeaderName::from_static("x-b3-flags");
+
+/// Parse a non-zero id of exactly `len` lowercase hex digits.
+fn parse_id(s: &str, len: usize) -> Option<u128> {
+    if s.len() != len || !s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')) {
+        return None;
+    }
+    u128::from_str_radix(s, 16).ok().filter(|id| *id != 0)
+}
+
+/// Parse a 64-bit B3 trace id, or a 128-bit B3 or W3C trace id.
+fn parse_trace_id(s: &str) -> Option<u128> {
+    match s.len() {
+        16 => parse_id(s, 16),
+        _ => parse_id(s, 32),
+    }
+}
+
+fn parse_span_id(s: &str) -> Option<u64> {
+    parse_id(s, 16).map(|id| id as u64)
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &HeaderName) -> Option<&'a str> {
+    headers.get(name)?.to_str().ok().map(str::trim)
+}
+
+/// Parse the `traceparent` and `tracestate` headers.
+pub(super) fn parse_w3c(headers: &HeaderMap) -> Option<TraceContext> {
+    let value = header_str(headers, &TRACEPARENT)?;
+    let mut parts = value.split('-');
+    let version = parts.next()?;
+    let trace_id = parts.next()?;
+    let span_id = parts.next()?;
+    let flags = parts.next()?;
+    // future versions may append fields, while version 00 may not
+    if version.len() != 2
+        || version == "ff"
+        || u8::from_str_radix(version, 16).is_err()
+        || (version == "00" && parts.next().is_some())
+        || flags.len() != 2
+    {
+        return None;
+    }
+    let flags = u8::from_str_radix(flags, 16).ok()?;
+
+    let trace_state = headers
+        .get_all(&TRACESTATE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .collect::<Vec<_>>()
+        .join(",");
+
+    Some(TraceContext {
+        trace_id: parse_id(trace_id, 32)?,
+        span_id: parse_span_id(span_id)?,
+        parent_span_id: None,
+        sampled: flags & 0x01 == 0x01,
+        trace_state: (!trace_state.is_empty()).then_some(trace_state),
+        b3: false,
+    })
+}
+
+/// Parse the single `b3` header, or the multiple `x-b3-*` headers.
+pub(super) fn parse_b3(headers: &HeaderMap) -> Option<TraceContext> {
+    let (trace_id, span_id, sampled) = if let Some(value) = header_str(headers, &B3) {
+        let mut parts = value.split('-');
+        let trace_id = parts.next()?;
+        let span_id = parts.next()?;
+        let sampled = matches!(parts.next(), Some("1" | "d"));
+        (parse_trace_id(trace_id)?, parse_span_id(span_id)?, sampled)
+    } else {
+        let trace_id = header_str(headers, &X_B3_TRACE_ID)?;
+        let span_id = header_str(headers, &X_B3_SPAN_ID)?;
+        let sampled = matches!(header_str(headers, &X_B3_SAMPLED), Some("1" | "true"))
+            || header_str(headers, &X_B3_FLAGS) == Some("1");
+        (parse_trace_id(trace_id)?, parse_span_id(span_id)?, sampled)
+    };
+
+    Some(TraceContext {
+        trace_id,
+        span_id,
+        parent_span_id: None,
+        sampled,
+        trace_state: None,
+        b3: true,
+    })
+}
+
+/// Set the propagation headers of the given [`TraceContext`],
+/// replacing any existing ones.
+pub(super) fn inject(context: &TraceContext, headers: &mut HeaderMap) {
+    let traceparent = context.traceparent();
+    headers.insert(
+        TRACEPARENT,
+        HeaderValue::from_str(&traceparent).expect("valid traceparent header"),
+    );
+    headers.remove(&TRACESTATE);
+    if let Some(value) = context
+        .trace_state
+        .as_deref()
+        .and_then(|state| HeaderValue::from_str(state).ok())
+    {
+        headers.insert(TRACESTATE, value);
+    }
+
+    if context.b3 {
+        for name in [
+            X_B3_TRACE_ID,
+            X_B3_SPAN_ID,
+            X_B3_PARENT_SPAN_ID,
+            X_B3_SAMPLED,
+            X_B3_FLAGS,
+        ] {
+            headers.remove(name);
+        }
+        let b3 = format!(
+            "{:032x}-{:016x}-{}",
+            context.trace_id,
+            context.span_id,
+            if context.sampled { "1" } else { "0" }
+        );
+        headers.insert(B3, HeaderValue::from_str(&b3).expect("valid b3 header"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| {
+                (
+                    HeaderName::from_static(name),
+                    HeaderValue::from_static(value),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_w3c() {
+        let ctx = parse_w3c(&headers(&[
+            (
+                "traceparent",
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            ),
+            ("tracestate", "congo=t61rcWkgMzE"),
+        ]))
+        .unwrap();
+        assert_eq!(ctx.trace_id(), 0x4bf92f3577b34da6a3ce929d0e0e4736);
+        assert_eq!(ctx.span_id(), 0x00f067aa0ba902b7);
+        assert!(ctx.sampled());
+        assert_eq!(ctx.trace_state(), Some("congo=t61rcWkgMzE"));
+
+        // future versions may append fields
+        assert!(parse_w3c(&headers(&[(
+            "traceparent",
+            "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00-extra",
+        )]))
+        .is_some());
+
+        for invalid in [
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+        ] {
+            assert!(
+                parse_w3c(&headers(&[("traceparent", invalid)])).is_none(),
+                "{invalid}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_b3() {
+        let ctx = parse_b3(&headers(&[(
+            "b3",
+            "80f198ee56343ba864fe8b2a57d3eff7-e457b5a2e4d86bd1-1-05e3ac9a4f6e3b90",
+        )]))
+        .unwrap();
+        assert_eq!(ctx.trace_id(), 0x80f198ee56343ba864fe8b2a57d3eff7);
+        assert_eq!(ctx.span_id(), 0xe457b5a2e4d86bd1);
+        assert!(ctx.sampled());
+
+        let ctx = parse_b3(&headers(&[
+            ("x-b3-traceid", "a3ce929d0e0e4736"),
+            ("x-b3-spanid", "00f067aa0ba902b7"),
+            ("x-b3-sampled", "0"),
+        ]))
+        .unwrap();
+        assert_eq!(ctx.trace_id(), 0xa3ce929d0e0e4736);
+        assert!(!ctx.sampled());
+
+        assert!(parse_b3(&headers(&[("b3", "0")])).is_none());
+    }
+
+    #[test]
+    fn test_inject() {
+        let ctx = parse_b3(&headers(&[(
+            "b3",
+            "80f198ee56343ba864fe8b2a57d3eff7-e457b5a2e4d86bd1-1",
+        )]))
+        .unwrap()
+        .child();
+
+        let mut headers = headers(&[("x-b3-sampled", "1"), ("tracestate", "old=1")]);
+        inject(&ctx, &mut headers);
+        assert_eq!(
+            headers["traceparent"],
+            format!(
+                "00-80f198ee56343ba864fe8b2a57d3eff7-{:016x}-01",
+                ctx.span_id()
+            )
+        );
+        assert_eq!(
+            headers["b3"],
+            format!("80f198ee56343ba864fe8b2a57d3eff7-{:016x}-1", ctx.span_id())
+        );
+        assert!(!headers.contains_key("tracestate"));
+        assert!(!headers.contains_key("x-b3-sampled"));
+        assert_eq!(parse_w3c(&headers).unwrap().span_id(), ctx.span_id());
+    }
+}
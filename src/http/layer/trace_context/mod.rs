@@ -0,0 +1,299 @@
+//! Middleware that propagates the [W3C Trace Context], and optionally [B3],
+//! of the requests served.
+//!
+//! The [`TraceContextLayer`] parses the `traceparent` and `tracestate` headers
+//! of incoming requests (falling back to the `b3` or `x-b3-*` headers if enabled),
+//! starting a new trace if the request has none. The [`TraceContext`] of the
+//! current hop is then inserted into the [`Context`], such that:
+//!
+//! - the [`TraceLayer`] records its `trace_id` and `span_id` on the request span,
+//!   and parents the span on the remote span when the `opentelemetry` feature is enabled;
+//! - the [`HttpClient`] injects it into the outgoing requests,
+//!   continuing the trace at the upstream server.
+//!
+//! The [`TraceContextLayer`] is therefore to be layered on top of the [`TraceLayer`].
+//!
+//! # OpenTelemetry
+//!
+//! When the `opentelemetry` feature is enabled, the [`TraceLayer`] spans are
+//! linked to their remote parents using [`tracing-opentelemetry`], and the
+//! [`TraceContext`] follows the trace and span ids assigned by OpenTelemetry.
+//! Exporting these spans, e.g. to an OTLP collector, is a matter of installing
+//! a [`tracing_opentelemetry::layer`] with a tracer of the exporter of choice,
+//! such as the one of the [`opentelemetry-otlp`] crate.
+//!
+//! [W3C Trace Context]: https://www.w3.org/TR/trace-context/
+//! [B3]: https://github.com/openzipkin/b3-propagation
+//! [`TraceLayer`]: crate::http::layer::trace::TraceLayer
+//! [`HttpClient`]: crate::http::client::HttpClient
+//! [`tracing-opentelemetry`]: https://crates.io/crates/tracing-opentelemetry
+//! [`tracing_opentelemetry::layer`]: https://docs.rs/tracing-opentelemetry/latest/tracing_opentelemetry/fn.layer.html
+//! [`opentelemetry-otlp`]: https://crates.io/crates/opentelemetry-otlp
+//!
+//! # Example
+//!
+//! ```
+//! use rama::http::layer::{trace::TraceLayer, trace_context::{TraceContext, TraceContextLayer}};
+//! use rama::http::{Body, Request, Response};
+//! use rama::service::{Context, Service, ServiceBuilder};
+//! use std::convert::Infallible;
+//!
+//! async fn handle<State>(ctx: Context<State>, _req: Request) -> Result<Response, Infallible> {
+//!     let trace_context = ctx.get::<TraceContext>().unwrap();
+//!     assert_eq!(trace_context.trace_id(), 0x4bf92f3577b34da6a3ce929d0e0e4736);
+//!     assert_eq!(trace_context.parent_span_id(), Some(0x00f067aa0ba902b7));
+//!     Ok(Response::new(Body::empty()))
+//! }
+//!
+//! # #[tokio::main]
+//! # async fn main() {
+//! let service = ServiceBuilder::new()
+//!     .layer(TraceContextLayer::new().b3(true))
+//!     .layer(TraceLayer::new_for_http())
+//!     .service_fn(handle);
+//!
+//! let request = Request::builder()
+//!     .header("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")
+//!     .body(Body::empty())
+//!     .unwrap();
+//! service.serve(Context::default(), request).await.unwrap();
+//! # }
+//! ```
+
+use crate::{
+    http::{HeaderMap, Request},
+    service::{
+        util::rng::{HasherRng, Rng},
+        Context, Layer, Service,
+    },
+};
+use std::future::Future;
+use tracing::Span;
+
+mod header;
+
+#[cfg(feature = "opentelemetry")]
+mod otel;
+
+/// The trace context of a request, as propagated between services
+/// by the [`TraceContextLayer`] and [`HttpClient`].
+///
+/// [`HttpClient`]: crate::http::client::HttpClient
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceContext {
+    trace_id: u128,
+    span_id: u64,
+    parent_span_id: Option<u64>,
+    sampled: bool,
+    trace_state: Option<String>,
+    b3: bool,
+}
+
+impl TraceContext {
+    /// Start a new trace, of which the [`TraceContext`] is the root.
+    pub fn new(sampled: bool) -> Self {
+        let mut rng = HasherRng::new();
+        let trace_id = ((rng.next_u64() as u128) << 64) | rng.next_u64() as u128;
+        Self {
+            trace_id: trace_id.max(1),
+            span_id: random_span_id(&mut rng),
+            parent_span_id: None,
+            sampled,
+            trace_state: None,
+            b3: false,
+        }
+    }
+
+    /// Parse the [`TraceContext`] from the `traceparent` and `tracestate` headers.
+    pub fn from_w3c_headers(headers: &HeaderMap) -> Option<Self> {
+        header::parse_w3c(headers)
+    }
+
+    /// Parse the [`TraceContext`] from the `b3` header, or the `x-b3-*` headers.
+    ///
+    /// The [`TraceContext`] is injected with the `b3` header,
+    /// next to the W3C headers, from then on.
+    pub fn from_b3_headers(headers: &HeaderMap) -> Option<Self> {
+        header::parse_b3(headers)
+    }
+
+    /// Create the [`TraceContext`] of a new span within this trace,
+    /// of which the span of this [`TraceContext`] is the parent.
+    pub fn child(&self) -> Self {
+        Self {
+            span_id: random_span_id(&mut HasherRng::new()),
+            parent_span_id: Some(self.span_id),
+            ..self.clone()
+        }
+    }
+
+    /// Get the id of the trace.
+    pub fn trace_id(&self) -> u128 {
+        self.trace_id
+    }
+
+    /// Get the id of the span.
+    pub fn span_id(&self) -> u64 {
+        self.span_id
+    }
+
+    /// Get the id of the parent span, if any.
+    pub fn parent_span_id(&self) -> Option<u64> {
+        self.parent_span_id
+    }
+
+    /// Returns `true` if the trace is sampled, i.e. recorded.
+    pub fn sampled(&self) -> bool {
+        self.sampled
+    }
+
+    /// Get the vendor specific `tracestate`, if any.
+    pub fn trace_state(&self) -> Option<&str> {
+        self.trace_state.as_deref()
+    }
+
+    /// Format the [`TraceContext`] as a `traceparent` header value.
+    pub fn traceparent(&self) -> String {
+        format!(
+            "00-{:032x}-{:016x}-{:02x}",
+            self.trace_id,
+            self.span_id,
+            u8::from(self.sampled)
+        )
+    }
+
+    /// Inject the [`TraceContext`] into the given headers,
+    /// replacing any existing trace context headers.
+    pub fn inject(&self, headers: &mut HeaderMap) {
+        header::inject(self, headers)
+    }
+}
+
+fn random_span_id(rng: &mut impl Rng) -> u64 {
+    rng.next_u64().max(1)
+}
+
+/// Record the [`TraceContext`] of the [`Context`], if any, on the given span,
+/// linking the span to its remote parent when the `opentelemetry` feature is enabled.
+pub(crate) fn link_span<State>(ctx: &mut Context<State>, span: &Span) {
+    #[cfg(feature = "opentelemetry")]
+    otel::link_span(ctx, span);
+
+    if let Some(trace_context) = ctx.get::<TraceContext>() {
+        span.record(
+            "trace_id",
+            tracing::field::display(format_args!("{:032x}", trace_context.trace_id)),
+        );
+        span.record(
+            "span_id",
+            tracing::field::display(format_args!("{:016x}", trace_context.span_id)),
+        );
+    }
+}
+
+/// A [`Service`] which propagates the [`TraceContext`] of the requests it serves,
+/// created using the [`TraceContextLayer`].
+#[derive(Debug, Clone)]
+pub struct TraceContextService<S> {
+    inner: S,
+    b3: bool,
+}
+
+impl<S> TraceContextService<S> {
+    define_inner_service_accessors!();
+}
+
+impl<State, S, Body> Service<State, Request<Body>> for TraceContextService<S>
+where
+    State: Send + Sync + 'static,
+    S: Service<State, Request<Body>>,
+    Body: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+
+    fn serve(
+        &self,
+        mut ctx: Context<State>,
+        req: Request<Body>,
+    ) -> impl Future<Output = Result<Self::Response, Self::Error>> + Send + '_ {
+        let trace_context = TraceContext::from_w3c_headers(req.headers())
+            .or_else(|| {
+                self.b3
+                    .then(|| TraceContext::from_b3_headers(req.headers()))
+                    .flatten()
+            })
+            .map(|remote| remote.child())
+            .unwrap_or_else(|| TraceContext::new(true));
+        ctx.insert(trace_context);
+        self.inner.serve(ctx, req)
+    }
+}
+
+/// A [`Layer`] which propagates the [`TraceContext`] of requests.
+///
+/// See the [module docs](self) for more details.
+#[derive(Debug, Clone, Default)]
+pub struct TraceContextLayer {
+    b3: bool,
+}
+
+impl TraceContextLayer {
+    /// Create a new [`TraceContextLayer`], propagating the W3C trace context only.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also parse the B3 headers of requests without W3C trace context,
+    /// by default disabled.
+    pub fn b3(mut self, b3: bool) -> Self {
+        self.b3 = b3;
+        self
+    }
+}
+
+impl<S> Layer<S> for TraceContextLayer {
+    type Service = TraceContextService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        TraceContextService { inner, b3: self.b3 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{http::Body, service::service_fn};
+    use std::convert::Infallible;
+
+    #[tokio::test]
+    async fn test_trace_context_layer() {
+        let service = TraceContextLayer::new().b3(true).layer(service_fn(
+            |ctx: Context<()>, _req: Request| async move {
+                let trace_context = ctx.get::<TraceContext>().unwrap().clone();
+                Ok::<_, Infallible>(trace_context)
+            },
+        ));
+
+        let req = Request::builder()
+            .header("b3", "a3ce929d0e0e4736-00f067aa0ba902b7-1")
+            .body(Body::empty())
+            .unwrap();
+        let trace_context = service.serve(Context::default(), req).await.unwrap();
+        assert_eq!(trace_context.trace_id(), 0xa3ce929d0e0e4736);
+        assert_eq!(trace_context.parent_span_id(), Some(0x00f067aa0ba902b7));
+        assert_ne!(trace_context.span_id(), 0x00f067aa0ba902b7);
+        assert!(trace_context.sampled());
+
+        let mut headers = HeaderMap::new();
+        trace_context.inject(&mut headers);
+        assert_eq!(headers["traceparent"], trace_context.traceparent());
+        assert!(headers.contains_key("b3"));
+
+        // requests without trace context start a new trace
+        let req = Request::new(Body::empty());
+        let trace_context = service.serve(Context::default(), req).await.unwrap();
+        assert_eq!(trace_context.parent_span_id(), None);
+        assert_ne!(trace_context.trace_id(), 0);
+    }
+}
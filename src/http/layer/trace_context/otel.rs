@@ -0,0 +1,129 @@
+//! Linking of [`tracing`] spans to their remote parent using [`tracing_opentelemetry`].
+
+use super::TraceContext;
+use crate::service::Context;
+use opentelemetry::trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId};
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Parent the given span on the remote span of the [`TraceContext`] in the [`Context`],
+/// and update the [`TraceContext`] to the ids OpenTelemetry assigned to the span.
+pub(super) fn link_span<State>(ctx: &mut Context<State>, span: &Span) {
+    let Some(mut trace_context) = ctx.get::<TraceContext>().cloned() else {
+        return;
+    };
+
+    if let Some(parent_span_id) = trace_context.parent_span_id {
+        let remote = SpanContext::new(
+            TraceId::from(trace_context.trace_id),
+            SpanId::from(parent_span_id),
+            if trace_context.sampled {
+                TraceFlags::SAMPLED
+            } else {
+                TraceFlags::default()
+            },
+            true,
+            trace_context
+                .trace_state
+                .as_deref()
+                .and_then(|state| state.parse().ok())
+                .unwrap_or_default(),
+        );
+        span.set_parent(opentelemetry::Context::new().with_remote_span_context(remote));
+    }
+
+    // without an OpenTelemetry subscriber layer the span context is invalid,
+    // in which case the ids of the trace context are kept as is
+    let otel_context = span.context();
+    let span_context = otel_context.span().span_context().clone();
+    if span_context.is_valid() {
+        trace_context.trace_id = u128::from_be_bytes(span_context.trace_id().to_bytes());
+        trace_context.span_id = u64::from_be_bytes(span_context.span_id().to_bytes());
+        trace_context.sampled = span_context.is_sampled();
+        ctx.insert(trace_context);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::HeaderMap;
+    use futures::future::BoxFuture;
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_sdk::{
+        export::trace::{ExportResult, SpanData, SpanExporter},
+        trace::TracerProvider,
+    };
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[derive(Debug, Clone, Default)]
+    struct CollectExporter(Arc<Mutex<Vec<SpanData>>>);
+
+    impl SpanExporter for CollectExporter {
+        fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+            self.0.lock().unwrap().extend(batch);
+            Box::pin(std::future::ready(Ok(())))
+        }
+    }
+
+    #[test]
+    fn test_link_span() {
+        let exporter = CollectExporter::default();
+        let provider = TracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("rama")));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "traceparent",
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01"
+                .parse()
+                .unwrap(),
+        );
+        let remote = TraceContext::from_w3c_headers(&headers).unwrap();
+        let mut ctx = Context::default();
+        ctx.insert(remote.child());
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("request");
+            link_span(&mut ctx, &span);
+        });
+        provider.force_flush();
+
+        // the trace context picks up the ids OpenTelemetry assigned to the span
+        let trace_context = ctx.get::<TraceContext>().unwrap();
+        assert_eq!(trace_context.trace_id(), remote.trace_id());
+        assert_eq!(trace_context.parent_span_id(), Some(remote.span_id()));
+        assert!(trace_context.sampled());
+
+        // the exported span is parented on the remote span
+        let spans = exporter.0.lock().unwrap();
+        assert_eq!(spans.len(), 1);
+        let span = &spans[0];
+        assert_eq!(
+            span.span_context.trace_id(),
+            TraceId::from(remote.trace_id())
+        );
+        assert_eq!(
+            span.span_context.span_id(),
+            SpanId::from(trace_context.span_id())
+        );
+        assert_eq!(span.parent_span_id, SpanId::from(remote.span_id()));
+    }
+
+    #[test]
+    fn test_link_span_without_otel_layer() {
+        let mut ctx = Context::default();
+        let trace_context = TraceContext::new(true).child();
+        ctx.insert(trace_context.clone());
+
+        let span = tracing::info_span!("request");
+        link_span(&mut ctx, &span);
+
+        // without an OpenTelemetry layer the ids are kept as is
+        assert_eq!(ctx.get::<TraceContext>(), Some(&trace_context));
+    }
+}
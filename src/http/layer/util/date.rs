@@ -0,0 +1,84 @@
+//! UTC date and time utilities, as used to format the timestamps of logs and recordings.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// A [`SystemTime`] broken down into its UTC calendar date and time of day,
+/// with millisecond precision.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct UtcDateTime {
+    pub(crate) year: i64,
+    pub(crate) month: u32,
+    pub(crate) day: u32,
+    pub(crate) hour: u32,
+    pub(crate) minute: u32,
+    pub(crate) second: u32,
+    pub(crate) millis: u32,
+}
+
+impl UtcDateTime {
+    /// Break down the given [`SystemTime`], clamping times before the unix epoch to it.
+    pub(crate) fn new(time: SystemTime) -> Self {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let secs = since_epoch.as_secs();
+        let (days, secs_of_day) = ((secs / 86400) as i64, (secs % 86400) as u32);
+
+        // civil from days, see http://howardhinnant.github.io/date_algorithms.html
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z.rem_euclid(146097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + i64::from(month <= 2);
+
+        Self {
+            year,
+            month,
+            day,
+            hour: secs_of_day / 3600,
+            minute: secs_of_day / 60 % 60,
+            second: secs_of_day % 60,
+            millis: since_epoch.subsec_millis(),
+        }
+    }
+
+    /// The abbreviated English name of the month, e.g. `Nov`.
+    pub(crate) fn month_name(&self) -> &'static str {
+        MONTHS[self.month as usize - 1]
+    }
+
+    /// Format as an RFC 3339 timestamp, e.g. `1994-11-06T08:49:37.000Z`.
+    pub(crate) fn rfc3339(&self) -> String {
+        format!(
+            "{}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second, self.millis
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_utc_date_time() {
+        let date = UtcDateTime::new(UNIX_EPOCH + Duration::from_millis(784111777123));
+        assert_eq!(date.rfc3339(), "1994-11-06T08:49:37.123Z");
+        assert_eq!(date.month_name(), "Nov");
+
+        let date = UtcDateTime::new(UNIX_EPOCH + Duration::from_secs(951782400));
+        assert_eq!(date.rfc3339(), "2000-02-29T00:00:00.000Z");
+
+        assert_eq!(
+            UtcDateTime::new(UNIX_EPOCH).rfc3339(),
+            "1970-01-01T00:00:00.000Z"
+        );
+    }
+}
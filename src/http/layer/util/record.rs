@@ -0,0 +1,22 @@
+//! Utilities shared by the layers recording requests and their responses,
+//! such as the [`HarRecorderLayer`] and the [`RecordLayer`].
+//!
+//! [`HarRecorderLayer`]: crate::http::layer::har::HarRecorderLayer
+//! [`RecordLayer`]: crate::http::layer::replay::RecordLayer
+
+use crate::http::HeaderValue;
+
+/// The value recorded in place of the value of a sensitive header.
+pub(crate) const REDACTED: &str = "[REDACTED]";
+
+/// The engine used to encode recorded bodies which are not valid UTF-8.
+pub(crate) const BASE64: base64::engine::GeneralPurpose = base64::engine::general_purpose::STANDARD;
+
+/// Record the given header value as text, or [`REDACTED`] if it is to be redacted.
+pub(crate) fn header_value(value: &HeaderValue, redact: bool) -> String {
+    if redact {
+        REDACTED.to_owned()
+    } else {
+        String::from_utf8_lossy(value.as_bytes()).into_owned()
+    }
+}
@@ -40,14 +40,56 @@ impl MethodMatcher {
         Self(bits)
     }
 
-    pub(crate) const fn contains(&self, other: Self) -> bool {
+    /// Create a [`MethodMatcher`] which matches no methods at all.
+    pub(crate) const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Returns true if all methods of `other` are also matched by `self`.
+    pub const fn contains(&self, other: Self) -> bool {
         self.bits() & other.bits() == other.bits()
     }
 
+    /// Returns true if no method is matched by this [`MethodMatcher`].
+    pub const fn is_empty(&self) -> bool {
+        self.bits() == 0
+    }
+
     /// Performs the OR operation between the [`MethodMatcher`] in `self` with `other`.
     pub const fn or(self, other: Self) -> Self {
         Self(self.0 | other.0)
     }
+
+    /// Iterate over all [`Method`]s matched by this [`MethodMatcher`].
+    pub fn iter(&self) -> impl Iterator<Item = Method> + '_ {
+        [
+            (Self::CONNECT, Method::CONNECT),
+            (Self::DELETE, Method::DELETE),
+            (Self::GET, Method::GET),
+            (Self::HEAD, Method::HEAD),
+            (Self::OPTIONS, Method::OPTIONS),
+            (Self::PATCH, Method::PATCH),
+            (Self::POST, Method::POST),
+            (Self::PUT, Method::PUT),
+            (Self::TRACE, Method::TRACE),
+        ]
+        .into_iter()
+        .filter_map(|(matcher, method)| self.contains(matcher).then_some(method))
+    }
+}
+
+impl fmt::Display for MethodMatcher {
+    /// Formats the matched methods as a comma-separated list,
+    /// as used for example in the `Allow` header.
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (index, method) in self.iter().enumerate() {
+            if index > 0 {
+                f.write_str(", ")?;
+            }
+            f.write_str(method.as_str())?;
+        }
+        Ok(())
+    }
 }
 
 impl<State, Body> crate::service::Matcher<State, Request<Body>> for MethodMatcher {
@@ -158,4 +200,20 @@ mod tests {
             MethodMatcher::TRACE
         );
     }
+
+    #[test]
+    fn iter_and_display() {
+        let matcher = MethodMatcher::POST
+            .or(MethodMatcher::GET)
+            .or(MethodMatcher::OPTIONS);
+        assert_eq!(
+            matcher.iter().collect::<Vec<_>>(),
+            vec![Method::GET, Method::OPTIONS, Method::POST]
+        );
+        assert_eq!(matcher.to_string(), "GET, OPTIONS, POST");
+
+        assert!(MethodMatcher::empty().is_empty());
+        assert_eq!(MethodMatcher::empty().iter().count(), 0);
+        assert_eq!(MethodMatcher::empty().to_string(), "");
+    }
 }
@@ -22,6 +22,7 @@ mod version;
 pub use version::VersionMatcher;
 
 mod path;
+pub(crate) use path::ParamSegment;
 #[doc(inline)]
 pub use path::{PathMatcher, UriParams, UriParamsDeserializeError};
 
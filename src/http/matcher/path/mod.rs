@@ -2,6 +2,7 @@ use crate::{
     http::Request,
     service::{context::Extensions, Context},
 };
+use regex::Regex;
 use std::collections::HashMap;
 
 mod de;
@@ -82,10 +83,174 @@ impl std::error::Error for UriParamsDeserializeError {}
 #[derive(Debug, Clone)]
 enum PathFragment {
     Literal(String),
-    Param(String),
+    Param(PathParam),
     Glob,
 }
 
+#[derive(Debug, Clone)]
+struct PathParam {
+    name: String,
+    constraint: Option<ParamConstraint>,
+    suffix: Option<String>,
+    optional: bool,
+}
+
+impl PathParam {
+    fn new(segment: &str) -> Self {
+        let segment = ParamSegment::parse(segment);
+        Self {
+            name: segment.name.to_lowercase(),
+            constraint: segment.constraint.map(ParamConstraint::new),
+            suffix: segment.suffix.map(str::to_lowercase),
+            optional: segment.optional,
+        }
+    }
+
+    /// Returns the (percent-decoded) value of the param
+    /// in case the given segment matches this param.
+    fn match_segment(&self, segment: &str) -> Option<String> {
+        let value = match &self.suffix {
+            Some(suffix) => {
+                let index = segment.len().checked_sub(suffix.len())?;
+                if !segment.is_char_boundary(index)
+                    || !segment[index..].eq_ignore_ascii_case(suffix)
+                {
+                    return None;
+                }
+                &segment[..index]
+            }
+            None => segment,
+        };
+        if value.is_empty() {
+            return None;
+        }
+        let value = percent_encoding::percent_decode(value.as_bytes())
+            .decode_utf8()
+            .map(|s| s.to_string())
+            .unwrap_or_else(|_| value.to_owned());
+        match &self.constraint {
+            Some(constraint) if !constraint.matches(&value) => None,
+            _ => Some(value),
+        }
+    }
+}
+
+/// A param segment of a path, split in its raw parts.
+///
+/// Syntax: `:name{constraint}suffix?`, where only the name is required.
+/// The name runs up to the constraint, such that a suffix requires either a constraint
+/// or a name in braces, e.g. `:{name}suffix`.
+pub(crate) struct ParamSegment<'a> {
+    pub(crate) name: &'a str,
+    pub(crate) constraint: Option<&'a str>,
+    pub(crate) suffix: Option<&'a str>,
+    pub(crate) optional: bool,
+}
+
+impl<'a> ParamSegment<'a> {
+    /// Parse a param segment, with or without the leading `:`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a brace of the name or constraint is not closed.
+    pub(crate) fn parse(segment: &'a str) -> Self {
+        let raw = segment;
+        let segment = segment.strip_prefix(':').unwrap_or(segment);
+        let (segment, optional) = match segment.strip_suffix('?') {
+            Some(segment) => (segment, true),
+            None => (segment, false),
+        };
+
+        let (name, mut rest) = match segment.strip_prefix('{') {
+            Some(segment) => {
+                let end = segment
+                    .find('}')
+                    .unwrap_or_else(|| panic!("unclosed brace in path param: {raw}"));
+                (&segment[..end], &segment[end + 1..])
+            }
+            None => segment.split_at(segment.find('{').unwrap_or(segment.len())),
+        };
+
+        let mut constraint = None;
+        if rest.starts_with('{') {
+            // find the matching closing brace, allowing nested braces (e.g. regex quantifiers)
+            let mut depth = 0;
+            for (index, c) in rest.char_indices() {
+                match c {
+                    '{' => depth += 1,
+                    '}' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            constraint = Some(&rest[1..index]);
+                            rest = &rest[index + 1..];
+                            break;
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            if constraint.is_none() {
+                panic!("unclosed brace in path param: {raw}");
+            }
+        }
+
+        Self {
+            name,
+            constraint,
+            suffix: (!rest.is_empty()).then_some(rest),
+            optional,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum ParamConstraint {
+    Int,
+    Uint,
+    Alpha,
+    Alnum,
+    Hex,
+    Uuid,
+    Regex(Regex),
+}
+
+impl ParamConstraint {
+    fn new(constraint: &str) -> Self {
+        match constraint {
+            "int" => Self::Int,
+            "uint" => Self::Uint,
+            "alpha" => Self::Alpha,
+            "alnum" => Self::Alnum,
+            "hex" => Self::Hex,
+            "uuid" => Self::Uuid,
+            re => Self::Regex(
+                Regex::new(&format!("^(?:{})$", re)).expect("valid path param constraint regex"),
+            ),
+        }
+    }
+
+    fn matches(&self, value: &str) -> bool {
+        match self {
+            Self::Int => {
+                let digits = value.strip_prefix('-').unwrap_or(value);
+                !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit())
+            }
+            Self::Uint => value.bytes().all(|b| b.is_ascii_digit()),
+            Self::Alpha => value.chars().all(|c| c.is_alphabetic()),
+            Self::Alnum => value.chars().all(|c| c.is_alphanumeric()),
+            Self::Hex => value.bytes().all(|b| b.is_ascii_hexdigit()),
+            Self::Uuid => {
+                value.len() == 36
+                    && value.bytes().enumerate().all(|(index, b)| match index {
+                        8 | 13 | 18 | 23 => b == b'-',
+                        _ => b.is_ascii_hexdigit(),
+                    })
+            }
+            Self::Regex(re) => re.is_match(value),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 enum PathMatcherKind {
     Literal(String),
@@ -94,12 +259,42 @@ enum PathMatcherKind {
 
 #[derive(Debug, Clone)]
 /// Matcher based on the URI path.
+///
+/// The path is matched segment per segment, case-insensitive, where each segment is one of:
+///
+/// - a literal, e.g. `users`, which has to match exactly;
+/// - a param, e.g. `:id`, which matches any non-empty segment and is inserted
+///   as part of the [`UriParams`] under the given name;
+/// - a glob `*`, only allowed as the last segment, which matches the remainder of the path.
+///
+/// A param can be further restricted by a constraint, a suffix and can be marked as optional,
+/// using the syntax `:name{constraint}suffix?`:
+///
+/// - the constraint is either one of the built-in constraints
+///   `int`, `uint`, `alpha`, `alnum`, `hex` and `uuid`, or a regex pattern which has to match
+///   the entire (percent-decoded) value, e.g. `:slug{[a-z-]+}`;
+/// - the suffix is a literal which has to end the segment and is not part of the value,
+///   e.g. `:{file}.json` which captures `report` as `file` for the segment `report.json`;
+///   without a constraint the name has to be put in braces, as `:file.json` is a param
+///   named `file.json`;
+/// - the trailing `?` marks the param as optional, which means that the path still matches
+///   in case it ends right before this segment.
+///
+/// A segment that does not satisfy the constraints of a param results in no match,
+/// such that `/users/:id{int}` can be routed differently from `/users/me`.
+///
+/// Constraints cannot contain a `/`, as a param is always matched against a single segment.
 pub struct PathMatcher {
     kind: PathMatcherKind,
 }
 
 impl PathMatcher {
     /// Create a new [`PathMatcher`] for the given path.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a param constraint is an invalid regex pattern,
+    /// or if a brace of a param is not closed.
     pub fn new(path: impl AsRef<str>) -> Self {
         let path = path.as_ref();
         let path = path.trim().trim_matches('/');
@@ -126,9 +321,7 @@ impl PathMatcher {
                     return None;
                 }
                 if s.starts_with(':') {
-                    Some(PathFragment::Param(
-                        s.trim_start_matches(':').to_lowercase(),
-                    ))
+                    Some(PathFragment::Param(PathParam::new(s)))
                 } else if s == "*" && index == fragment_length - 1 {
                     Some(PathFragment::Glob)
                 } else {
@@ -168,15 +361,13 @@ impl PathMatcher {
                                     return None;
                                 }
                             }
-                            PathFragment::Param(name) => {
-                                if segment.is_empty() {
-                                    return None;
+                            PathFragment::Param(param) => {
+                                // an empty segment is only possible for an empty path
+                                if segment.is_empty() && param.optional {
+                                    continue;
                                 }
-                                let segment = percent_encoding::percent_decode(segment.as_bytes())
-                                    .decode_utf8()
-                                    .map(|s| s.to_string())
-                                    .unwrap_or_else(|_| segment.to_owned());
-                                params.insert(name.to_owned(), segment);
+                                let value = param.match_segment(segment)?;
+                                params.insert(param.name.clone(), value);
                             }
                             PathFragment::Glob => {
                                 params.append_glob(segment);
@@ -189,6 +380,7 @@ impl PathMatcher {
                             params.glob()?;
                             params.append_glob(segment);
                         }
+                        (None, Some(PathFragment::Param(param))) if param.optional => (),
                         _ => {
                             return None;
                         }
@@ -342,6 +534,76 @@ mod test {
                 params.glob = Some("/reset.css".to_owned());
                 params
             }),
+            TestCase::some("/users/123", "/users/:id{int}", {
+                let mut params = UriParams::default();
+                params.insert("id".to_owned(), "123".to_owned());
+                params
+            }),
+            TestCase::some("/users/-7", "/users/:id{int}", {
+                let mut params = UriParams::default();
+                params.insert("id".to_owned(), "-7".to_owned());
+                params
+            }),
+            TestCase::none("/users/me", "/users/:id{int}"),
+            TestCase::none("/users/-7", "/users/:id{uint}"),
+            TestCase::none("/users/12a", "/users/:id{uint}"),
+            TestCase::some("/blog/hello-world", "/blog/:slug{[a-z-]+}", {
+                let mut params = UriParams::default();
+                params.insert("slug".to_owned(), "hello-world".to_owned());
+                params
+            }),
+            TestCase::none("/blog/hello_world", "/blog/:slug{[a-z-]+}"),
+            TestCase::some("/code/abc", "/code/:code{[a-z]{3}}", {
+                let mut params = UriParams::default();
+                params.insert("code".to_owned(), "abc".to_owned());
+                params
+            }),
+            TestCase::none("/code/abcd", "/code/:code{[a-z]{3}}"),
+            TestCase::some(
+                "/items/67e55044-10b1-426f-9247-bb680e5fe0c8",
+                "/items/:id{uuid}",
+                {
+                    let mut params = UriParams::default();
+                    params.insert(
+                        "id".to_owned(),
+                        "67e55044-10b1-426f-9247-bb680e5fe0c8".to_owned(),
+                    );
+                    params
+                },
+            ),
+            TestCase::none("/items/67e55044-10b1-426f-9247", "/items/:id{uuid}"),
+            TestCase::some("/reports/q1%20sales.json", "/reports/:{file}.json", {
+                let mut params = UriParams::default();
+                params.insert("file".to_owned(), "q1 sales".to_owned());
+                params
+            }),
+            TestCase::some("/reports/2024.JSON", "/reports/:year{int}.json", {
+                let mut params = UriParams::default();
+                params.insert("year".to_owned(), "2024".to_owned());
+                params
+            }),
+            TestCase::none("/reports/q1.json", "/reports/:year{int}.json"),
+            TestCase::none("/reports/.json", "/reports/:{file}.json"),
+            TestCase::none("/reports/q1.xml", "/reports/:{file}.json"),
+            TestCase::some("/users", "/users/:id?", UriParams::default()),
+            TestCase::some("/users/42", "/users/:id{int}?", {
+                let mut params = UriParams::default();
+                params.insert("id".to_owned(), "42".to_owned());
+                params
+            }),
+            TestCase::none("/users/me", "/users/:id{int}?"),
+            TestCase::some("/", "/:lang?", UriParams::default()),
+            TestCase::none("/users", "/users/:id?/posts"),
+            TestCase::some("/users/42/posts", "/users/:user-id/posts", {
+                let mut params = UriParams::default();
+                params.insert("user-id".to_owned(), "42".to_owned());
+                params
+            }),
+            TestCase::some("/users/42-id", "/users/:{user}-id", {
+                let mut params = UriParams::default();
+                params.insert("user".to_owned(), "42".to_owned());
+                params
+            }),
         ];
         for test_case in test_cases.into_iter() {
             let matcher = PathMatcher::new(test_case.matcher_path);
@@ -374,6 +636,45 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_param_segment_parse() {
+        for (segment, name, constraint, suffix, optional) in [
+            (":id", "id", None, None, false),
+            (":id?", "id", None, None, true),
+            (":id{int}", "id", Some("int"), None, false),
+            (":code{[a-z]{3}}?", "code", Some("[a-z]{3}"), None, true),
+            (":file.json", "file.json", None, None, false),
+            (":user-id", "user-id", None, None, false),
+            (":{file}.json", "file", None, Some(".json"), false),
+            (":{id}{int}?", "id", Some("int"), None, true),
+            (":year{int}.json?", "year", Some("int"), Some(".json"), true),
+        ] {
+            let parsed = ParamSegment::parse(segment);
+            assert_eq!(parsed.name, name, "segment: {}", segment);
+            assert_eq!(parsed.constraint, constraint, "segment: {}", segment);
+            assert_eq!(parsed.suffix, suffix, "segment: {}", segment);
+            assert_eq!(parsed.optional, optional, "segment: {}", segment);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_path_matcher_invalid_constraint() {
+        PathMatcher::new("/users/:id{[a-z}");
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_path_matcher_unclosed_constraint() {
+        PathMatcher::new("/users/:id{int");
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_path_matcher_unclosed_name() {
+        PathMatcher::new("/reports/:{file.json");
+    }
+
     #[test]
     fn test_deserialize_uri_params() {
         let params = UriParams {
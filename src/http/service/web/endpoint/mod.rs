@@ -1,5 +1,6 @@
+use super::openapi::Operation;
 use crate::{
-    http::matcher::HttpMatcher,
+    http::matcher::{HttpMatcher, MethodMatcher, PathMatcher},
     http::{IntoResponse, Request, Response},
     service::{BoxService, Context, Service, ServiceBuilder},
 };
@@ -10,9 +11,20 @@ pub mod extract;
 
 pub(crate) struct Endpoint<State> {
     pub(crate) matcher: HttpMatcher,
+    pub(crate) route: Option<EndpointRoute>,
     pub(crate) service: BoxService<State, Request, Response, Infallible>,
 }
 
+/// The path and methods an [`Endpoint`] was registered for,
+/// used to find out which methods are allowed for a path that did match,
+/// and to document the endpoint in case an [`Operation`] is attached to it.
+pub(crate) struct EndpointRoute {
+    pub(crate) pattern: String,
+    pub(crate) path: PathMatcher,
+    pub(crate) methods: MethodMatcher,
+    pub(crate) operation: Option<Operation>,
+}
+
 /// utility trait to accept multiple types as an endpoint service for [`super::WebService`]
 pub trait IntoEndpointService<State, T>: private::Sealed<T> {
     /// convert the type into a [`crate::service::Service`].
@@ -8,6 +8,8 @@ mod endpoint;
 #[doc(inline)]
 pub use endpoint::{extract, EndpointServiceFn, IntoEndpointService};
 
+pub mod openapi;
+
 pub mod k8s;
 #[doc(inline)]
 pub use k8s::{k8s_health, k8s_health_builder};
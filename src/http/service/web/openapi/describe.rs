@@ -0,0 +1,391 @@
+use super::{Operation, Parameter, Schema, ToSchema};
+use crate::http::{
+    self,
+    response::{Html, Redirect},
+    service::web::extract::{
+        Body, Bytes, Extension, Host, Json, Path, Query, State, Text, TypedHeader,
+    },
+    Method, Request, Response, StatusCode,
+};
+use crate::service::Context;
+use headers::Header;
+use std::{borrow::Cow, convert::Infallible, future::Future};
+
+/// An extractor which can describe what it extracts from a request,
+/// as part of an [`Operation`].
+///
+/// The default implementation describes nothing,
+/// which is what you want for extractors that do not
+/// extract anything from the request itself, e.g. [`State`].
+pub trait OperationInput {
+    /// Add the input this extractor expects to the given [`Operation`].
+    fn describe_input(_operation: &mut Operation) {}
+}
+
+/// A response type which can describe the response(s) it produces,
+/// as part of an [`Operation`].
+///
+/// The default implementation describes nothing.
+pub trait OperationOutput {
+    /// Add the response(s) this type produces to the given [`Operation`].
+    fn describe_output(_operation: &mut Operation) {}
+}
+
+/// An endpoint function which can describe itself as an [`Operation`],
+/// by combining the descriptions of its extractors ([`OperationInput`])
+/// and response ([`OperationOutput`]).
+///
+/// Implemented for all [`EndpointServiceFn`] of which
+/// the arguments and output implement these traits.
+///
+/// [`EndpointServiceFn`]: crate::http::service::web::EndpointServiceFn
+pub trait DescribeEndpoint<S, T> {
+    /// Add the description of the endpoint to the given [`Operation`].
+    fn describe_endpoint(operation: &mut Operation);
+}
+
+impl<F, R, O, S> DescribeEndpoint<S, (F, R, O)> for F
+where
+    F: Fn() -> R,
+    R: Future<Output = O>,
+    O: OperationOutput,
+{
+    fn describe_endpoint(operation: &mut Operation) {
+        O::describe_output(operation);
+    }
+}
+
+impl<F, R, O, S, I, M> DescribeEndpoint<S, (F, R, O, I, M)> for F
+where
+    F: Fn(I) -> R,
+    R: Future<Output = O>,
+    O: OperationOutput,
+    I: OperationInput,
+{
+    fn describe_endpoint(operation: &mut Operation) {
+        I::describe_input(operation);
+        O::describe_output(operation);
+    }
+}
+
+macro_rules! impl_describe_endpoint_tuple {
+    ($($ty:ident),+ $(,)?) => {
+        impl<F, R, O, S, $($ty),+, I, M> DescribeEndpoint<S, (F, R, O, $($ty),+, I, M)> for F
+            where
+                F: Fn($($ty),+, I) -> R,
+                R: Future<Output = O>,
+                O: OperationOutput,
+                $($ty: OperationInput),+,
+                I: OperationInput,
+        {
+            fn describe_endpoint(operation: &mut Operation) {
+                $($ty::describe_input(operation);)+
+                I::describe_input(operation);
+                O::describe_output(operation);
+            }
+        }
+    };
+}
+
+all_the_tuples_no_last_special_case!(impl_describe_endpoint_tuple);
+
+macro_rules! impl_describe_endpoint_tuple_with_context_and_request {
+    ($($ty:ident),+ $(,)?) => {
+        impl<F, R, O, S, $($ty),+> DescribeEndpoint<S, (F, R, O, (), (), (), (), (), (), (), (), (), (), (), (), $($ty),+, Context<S>, Request)> for F
+            where
+                F: Fn($($ty),+, Context<S>, Request) -> R,
+                R: Future<Output = O>,
+                O: OperationOutput,
+                $($ty: OperationInput),+,
+        {
+            fn describe_endpoint(operation: &mut Operation) {
+                $($ty::describe_input(operation);)+
+                O::describe_output(operation);
+            }
+        }
+    };
+}
+
+all_the_tuples_no_last_special_case!(impl_describe_endpoint_tuple_with_context_and_request);
+
+impl<T: ToSchema> OperationInput for Path<T> {
+    fn describe_input(operation: &mut Operation) {
+        // the params are captured by the PathMatcher under their lowercased name,
+        // and only those of the route pattern can be captured at all
+        for (name, schema, _) in T::schema().properties() {
+            let name = name.to_lowercase();
+            if operation.is_path_param(&name) {
+                operation.add_parameter(Parameter::path(name, schema));
+            }
+        }
+    }
+}
+
+impl<T: ToSchema> OperationInput for Query<T> {
+    fn describe_input(operation: &mut Operation) {
+        for (name, schema, required) in T::schema().properties() {
+            operation.add_parameter(Parameter::query(name, schema, required));
+        }
+    }
+}
+
+impl<H: Header> OperationInput for TypedHeader<H> {
+    fn describe_input(operation: &mut Operation) {
+        operation.add_parameter(Parameter::header(
+            H::name().as_str(),
+            Schema::string(),
+            true,
+        ));
+    }
+}
+
+impl<T: ToSchema> OperationInput for Json<T> {
+    fn describe_input(operation: &mut Operation) {
+        operation.add_request_body(mime::APPLICATION_JSON.as_ref(), T::schema());
+    }
+}
+
+impl OperationInput for Text {
+    fn describe_input(operation: &mut Operation) {
+        operation.add_request_body(mime::TEXT_PLAIN_UTF_8.as_ref(), Schema::string());
+    }
+}
+
+impl OperationInput for Bytes {
+    fn describe_input(operation: &mut Operation) {
+        operation.add_request_body(
+            mime::APPLICATION_OCTET_STREAM.as_ref(),
+            Schema::string().description("binary"),
+        );
+    }
+}
+
+impl OperationInput for Body {}
+impl OperationInput for Host {}
+impl OperationInput for Method {}
+impl OperationInput for Request {}
+impl<S> OperationInput for Context<S> {}
+impl<S> OperationInput for State<S> {}
+impl<T> OperationInput for Extension<T> {}
+
+impl<T: ToSchema> OperationOutput for Json<T> {
+    fn describe_output(operation: &mut Operation) {
+        operation.add_response(
+            Some(StatusCode::OK),
+            Some((mime::APPLICATION_JSON.as_ref(), T::schema())),
+        );
+    }
+}
+
+impl<T> OperationOutput for Html<T> {
+    fn describe_output(operation: &mut Operation) {
+        operation.add_response(
+            Some(StatusCode::OK),
+            Some((mime::TEXT_HTML_UTF_8.as_ref(), Schema::string())),
+        );
+    }
+}
+
+macro_rules! impl_operation_output_text {
+    ($($ty:ty),+ $(,)?) => {
+        $(
+            impl OperationOutput for $ty {
+                fn describe_output(operation: &mut Operation) {
+                    operation.add_response(
+                        Some(StatusCode::OK),
+                        Some((mime::TEXT_PLAIN_UTF_8.as_ref(), Schema::string())),
+                    );
+                }
+            }
+        )+
+    };
+}
+
+impl_operation_output_text!(&'static str, String, Box<str>, Cow<'static, str>);
+
+macro_rules! impl_operation_output_binary {
+    ($($ty:ty),+ $(,)?) => {
+        $(
+            impl OperationOutput for $ty {
+                fn describe_output(operation: &mut Operation) {
+                    operation.add_response(
+                        Some(StatusCode::OK),
+                        Some((
+                            mime::APPLICATION_OCTET_STREAM.as_ref(),
+                            Schema::string().description("binary"),
+                        )),
+                    );
+                }
+            }
+        )+
+    };
+}
+
+impl_operation_output_binary!(bytes::Bytes, Vec<u8>, &'static [u8]);
+
+impl OperationOutput for () {
+    fn describe_output(operation: &mut Operation) {
+        operation.add_response(Some(StatusCode::OK), None);
+    }
+}
+
+impl OperationOutput for Infallible {}
+
+macro_rules! impl_operation_output_default {
+    ($($ty:ty),+ $(,)?) => {
+        $(
+            impl OperationOutput for $ty {
+                fn describe_output(operation: &mut Operation) {
+                    operation.add_response(None, None);
+                }
+            }
+        )+
+    };
+}
+
+impl_operation_output_default!(StatusCode, Response, Redirect, http::Body);
+
+impl<T, E> OperationOutput for Result<T, E>
+where
+    T: OperationOutput,
+    E: OperationOutput,
+{
+    fn describe_output(operation: &mut Operation) {
+        T::describe_output(operation);
+        E::describe_output(operation);
+    }
+}
+
+impl<R: OperationOutput> OperationOutput for (StatusCode, R) {
+    fn describe_output(operation: &mut Operation) {
+        // the status code is only known at runtime,
+        // so the content is described as the default response
+        let mut inner = Operation::new();
+        R::describe_output(&mut inner);
+        operation.add_response(None, None);
+        for (content_type, schema) in inner.into_default_responses() {
+            operation.add_response(None, Some((&content_type, schema)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{headers::UserAgent, service::web::EndpointServiceFn};
+    use serde_json::json;
+
+    fn describe<F, T>(path: &str, _: F) -> serde_json::Value
+    where
+        F: EndpointServiceFn<(), T> + DescribeEndpoint<(), T>,
+    {
+        let mut operation = Operation::new();
+        operation.set_path_params(super::super::path_param_names(path));
+        F::describe_endpoint(&mut operation);
+        serde_json::to_value(operation).unwrap()
+    }
+
+    #[derive(Debug, serde::Deserialize)]
+    #[allow(dead_code)]
+    struct Params {
+        id: u64,
+        verbose: Option<bool>,
+    }
+
+    impl ToSchema for Params {
+        fn schema() -> Schema {
+            Schema::object()
+                .property("id", u64::schema(), true)
+                .property("verbose", bool::schema(), false)
+        }
+    }
+
+    #[test]
+    fn test_describe_endpoint_fn() {
+        let doc = describe(
+            "/items/:ID",
+            |_: TypedHeader<UserAgent>, _: Path<Params>, _: Json<Vec<String>>| async { Json(42u8) },
+        );
+        assert_eq!(
+            doc,
+            json!({
+                "parameters": [
+                    {
+                        "name": "user-agent",
+                        "in": "header",
+                        "required": true,
+                        "schema": { "type": "string" },
+                    },
+                    {
+                        "name": "id",
+                        "in": "path",
+                        "required": true,
+                        "schema": { "type": "integer", "format": "int64" },
+                    },
+                ],
+                "requestBody": {
+                    "required": true,
+                    "content": {
+                        "application/json": {
+                            "schema": { "type": "array", "items": { "type": "string" } },
+                        },
+                    },
+                },
+                "responses": {
+                    "200": {
+                        "description": "OK",
+                        "content": {
+                            "application/json": {
+                                "schema": { "type": "integer", "format": "int32" },
+                            },
+                        },
+                    },
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_describe_endpoint_fn_result() {
+        let doc = describe("/items", |_: Query<Params>| async {
+            Result::<String, (StatusCode, Json<bool>)>::Ok("hello".to_owned())
+        });
+        assert_eq!(
+            doc,
+            json!({
+                "parameters": [
+                    {
+                        "name": "id",
+                        "in": "query",
+                        "required": true,
+                        "schema": { "type": "integer", "format": "int64" },
+                    },
+                    {
+                        "name": "verbose",
+                        "in": "query",
+                        "required": false,
+                        "schema": { "type": "boolean" },
+                    },
+                ],
+                "responses": {
+                    "200": {
+                        "description": "OK",
+                        "content": {
+                            "text/plain; charset=utf-8": {
+                                "schema": { "type": "string" },
+                            },
+                        },
+                    },
+                    "default": {
+                        "description": "Default response",
+                        "content": {
+                            "application/json": {
+                                "schema": { "type": "boolean" },
+                            },
+                        },
+                    },
+                },
+            })
+        );
+    }
+}
@@ -0,0 +1,160 @@
+use super::Operation;
+use crate::http::{matcher::ParamSegment, response::Json, IntoResponse, Method, Response};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Serialize)]
+/// An OpenAPI 3.1 document, describing the operations of a [`WebService`].
+///
+/// Serializes to the JSON representation of the document,
+/// and can be served as-is as it implements [`IntoResponse`].
+///
+/// [`WebService`]: crate::http::service::web::WebService
+pub struct OpenApi {
+    openapi: &'static str,
+    info: Info,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    servers: Vec<Server>,
+    paths: BTreeMap<String, BTreeMap<String, Operation>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Info {
+    title: String,
+    version: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Server {
+    url: String,
+}
+
+impl OpenApi {
+    /// Create a new [`OpenApi`] document for the API with the given title and version.
+    pub fn new(title: impl Into<String>, version: impl Into<String>) -> Self {
+        Self {
+            openapi: "3.1.0",
+            info: Info {
+                title: title.into(),
+                version: version.into(),
+                description: None,
+            },
+            servers: Vec::new(),
+            paths: BTreeMap::new(),
+        }
+    }
+
+    /// Set the description of the API.
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.info.description = Some(description.into());
+        self
+    }
+
+    /// Add the URL of a server on which the API is available.
+    pub fn server(mut self, url: impl Into<String>) -> Self {
+        self.servers.push(Server { url: url.into() });
+        self
+    }
+
+    /// Add an [`Operation`] for the given method and path.
+    ///
+    /// The path is expected in the syntax used by a [`PathMatcher`] (e.g. `/users/:id`),
+    /// and is converted to the OpenAPI syntax (e.g. `/users/{id}`).
+    ///
+    /// [`PathMatcher`]: crate::http::matcher::PathMatcher
+    pub fn operation(mut self, method: &Method, path: &str, operation: Operation) -> Self {
+        self.add_operation(method, path, operation);
+        self
+    }
+
+    /// Add an [`Operation`] for the given method and path.
+    ///
+    /// See [`OpenApi::operation`] for more information.
+    pub fn add_operation(&mut self, method: &Method, path: &str, operation: Operation) {
+        self.paths
+            .entry(openapi_path(path))
+            .or_default()
+            .insert(method.as_str().to_lowercase(), operation);
+    }
+}
+
+impl IntoResponse for OpenApi {
+    fn into_response(self) -> Response {
+        Json(self).into_response()
+    }
+}
+
+/// Convert a path in the syntax of a [`PathMatcher`] to an OpenAPI path template.
+///
+/// Param constraints and optional markers cannot be expressed in such a template
+/// and are therefore dropped, while param suffixes are kept as literals.
+///
+/// [`PathMatcher`]: crate::http::matcher::PathMatcher
+fn openapi_path(path: &str) -> String {
+    let path = path.trim().trim_matches('/');
+    let mut result = String::with_capacity(path.len() + 1);
+    for segment in path.split('/').filter(|s| !s.is_empty()) {
+        result.push('/');
+        if segment.starts_with(':') {
+            let param = ParamSegment::parse(segment);
+            result.push('{');
+            result.push_str(&param.name.to_lowercase());
+            result.push('}');
+            if let Some(suffix) = param.suffix {
+                result.push_str(suffix);
+            }
+        } else {
+            result.push_str(segment);
+        }
+    }
+    if result.is_empty() {
+        result.push('/');
+    }
+    result
+}
+
+/// Get the lowercased names of the params of a path in the syntax of a [`PathMatcher`],
+/// as they are captured by it.
+///
+/// [`PathMatcher`]: crate::http::matcher::PathMatcher
+pub(crate) fn path_param_names(path: &str) -> Vec<String> {
+    path.split('/')
+        .filter(|segment| segment.starts_with(':'))
+        .map(|segment| ParamSegment::parse(segment).name.to_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_openapi_path() {
+        for (path, expected) in [
+            ("", "/"),
+            ("/", "/"),
+            ("/users", "/users"),
+            ("users/", "/users"),
+            ("/users/:id", "/users/{id}"),
+            ("/users/:id/posts/:post_id", "/users/{id}/posts/{post_id}"),
+            ("/assets/*", "/assets/*"),
+            ("/users/:id{int}", "/users/{id}"),
+            ("/blog/:slug{[a-z-]+}?", "/blog/{slug}"),
+            ("/reports/:{file}.json", "/reports/{file}.json"),
+            ("/users/:userId", "/users/{userid}"),
+        ] {
+            assert_eq!(openapi_path(path), expected, "path: {}", path);
+        }
+    }
+
+    #[test]
+    fn test_path_param_names() {
+        assert_eq!(
+            path_param_names("/users/:userId/posts/:id{int}?"),
+            vec!["userid".to_owned(), "id".to_owned()]
+        );
+        assert!(path_param_names("/users/*").is_empty());
+    }
+}
@@ -0,0 +1,67 @@
+//! OpenAPI document generation for the [`WebService`].
+//!
+//! Routes registered using [`WebService::documented_route`] are described by an [`Operation`],
+//! generated from the extractors ([`OperationInput`]) and response type ([`OperationOutput`])
+//! of the endpoint function, combined with the metadata you provide yourself.
+//!
+//! The resulting [`OpenApi`] document can be created using [`WebService::openapi`]
+//! or served directly from the same [`WebService`] using [`WebService::openapi_route`].
+//!
+//! Types used within [`Path`], [`Query`] and [`Json`] extractors
+//! and [`Json`] responses need to implement [`ToSchema`] for this purpose.
+//!
+//! # Example
+//!
+//! ```
+//! use rama::http::matcher::MethodMatcher;
+//! use rama::http::service::web::{
+//!     extract::{Json, Path},
+//!     openapi::{OpenApi, Operation, Schema, ToSchema},
+//!     WebService,
+//! };
+//!
+//! #[derive(Debug, serde::Deserialize)]
+//! struct UserParams {
+//!     id: u64,
+//! }
+//!
+//! impl ToSchema for UserParams {
+//!     fn schema() -> Schema {
+//!         Schema::object().property("id", u64::schema(), true)
+//!     }
+//! }
+//!
+//! let svc = WebService::<()>::default()
+//!     .documented_route(
+//!         MethodMatcher::GET,
+//!         "/users/:id",
+//!         Operation::new().summary("get the name of a user"),
+//!         |Path(params): Path<UserParams>| async move { Json(format!("user #{}", params.id)) },
+//!     )
+//!     .openapi_route("/openapi.json", OpenApi::new("users", "1.0.0"));
+//! ```
+//!
+//! [`WebService`]: crate::http::service::web::WebService
+//! [`WebService::documented_route`]: crate::http::service::web::WebService::documented_route
+//! [`WebService::openapi`]: crate::http::service::web::WebService::openapi
+//! [`WebService::openapi_route`]: crate::http::service::web::WebService::openapi_route
+//! [`Path`]: crate::http::service::web::extract::Path
+//! [`Query`]: crate::http::service::web::extract::Query
+//! [`Json`]: crate::http::service::web::extract::Json
+
+mod schema;
+#[doc(inline)]
+pub use schema::{Schema, ToSchema};
+
+mod operation;
+#[doc(inline)]
+pub use operation::{Operation, Parameter, ParameterLocation};
+
+mod describe;
+#[doc(inline)]
+pub use describe::{DescribeEndpoint, OperationInput, OperationOutput};
+
+mod document;
+pub(crate) use document::path_param_names;
+#[doc(inline)]
+pub use document::OpenApi;
@@ -0,0 +1,287 @@
+use super::Schema;
+use crate::http::{Method, StatusCode};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+/// Metadata describing a single API operation,
+/// which is a single method on a single path.
+///
+/// Most of this information is generated from the extractors and response type
+/// of the endpoint it is attached to, but it can also be set (or overwritten) manually.
+/// Manually specified metadata always takes precedence over generated metadata.
+pub struct Operation {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    operation_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    summary: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    deprecated: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    parameters: Vec<Parameter>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_body: Option<RequestBody>,
+    responses: BTreeMap<String, ResponseDoc>,
+    #[serde(skip)]
+    path_params: Option<Vec<String>>,
+}
+
+impl Operation {
+    /// Create a new [`Operation`] without any metadata.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the unique identifier of the operation,
+    /// used by client generators as the name of the generated function.
+    pub fn operation_id(mut self, id: impl Into<String>) -> Self {
+        self.operation_id = Some(id.into());
+        self
+    }
+
+    /// Set a short summary of what the operation does.
+    pub fn summary(mut self, summary: impl Into<String>) -> Self {
+        self.summary = Some(summary.into());
+        self
+    }
+
+    /// Set a verbose explanation of the operation behavior.
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    /// Add a tag, used to group operations together.
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.tags.push(tag.into());
+        self
+    }
+
+    /// Mark the operation as deprecated.
+    pub fn deprecated(mut self, deprecated: bool) -> Self {
+        self.deprecated = deprecated;
+        self
+    }
+
+    /// Add a [`Parameter`] to the operation.
+    pub fn parameter(mut self, parameter: Parameter) -> Self {
+        self.add_parameter(parameter);
+        self
+    }
+
+    /// Set the request body of the operation.
+    pub fn request_body(mut self, content_type: impl Into<String>, schema: Schema) -> Self {
+        self.request_body = Some(RequestBody::new(content_type, schema));
+        self
+    }
+
+    /// Add a response without content for the given status code.
+    pub fn response(mut self, status: StatusCode, description: impl Into<String>) -> Self {
+        self.responses.insert(
+            status.as_str().to_owned(),
+            ResponseDoc::new(description.into()),
+        );
+        self
+    }
+
+    /// Add a response with content of the given type for the given status code.
+    pub fn response_with_content(
+        mut self,
+        status: StatusCode,
+        description: impl Into<String>,
+        content_type: impl Into<String>,
+        schema: Schema,
+    ) -> Self {
+        let mut response = ResponseDoc::new(description.into());
+        response
+            .content
+            .insert(content_type.into(), MediaType { schema });
+        self.responses.insert(status.as_str().to_owned(), response);
+        self
+    }
+
+    /// Add a [`Parameter`] to the operation,
+    /// unless a parameter with the same name and location is already defined.
+    pub fn add_parameter(&mut self, parameter: Parameter) {
+        if !self
+            .parameters
+            .iter()
+            .any(|p| p.name == parameter.name && p.location == parameter.location)
+        {
+            self.parameters.push(parameter);
+        }
+    }
+
+    /// Set the (lowercased) names of the params of the path the operation is routed on,
+    /// such that only these are described as path parameters.
+    pub(crate) fn set_path_params(&mut self, names: Vec<String>) {
+        self.path_params = Some(names);
+    }
+
+    /// Suffix the identifier of the operation, if any, with the given method,
+    /// such that it stays unique when the operation is documented for multiple methods.
+    pub(crate) fn suffix_operation_id(&mut self, method: &Method) {
+        if let Some(id) = &mut self.operation_id {
+            id.push('_');
+            id.push_str(&method.as_str().to_ascii_lowercase());
+        }
+    }
+
+    /// Returns `true` if the given (lowercased) name is a param of the path
+    /// the operation is routed on, or if that path is not known.
+    pub(super) fn is_path_param(&self, name: &str) -> bool {
+        self.path_params
+            .as_ref()
+            .map_or(true, |names| names.iter().any(|n| n == name))
+    }
+
+    /// Set the request body of the operation, unless one is already defined.
+    pub fn add_request_body(&mut self, content_type: impl Into<String>, schema: Schema) {
+        if self.request_body.is_none() {
+            self.request_body = Some(RequestBody::new(content_type, schema));
+        }
+    }
+
+    /// Add a response for the given status code (or the default response if `None`),
+    /// unless a response is already defined for it.
+    ///
+    /// The content is only added in case it is given.
+    pub fn add_response(&mut self, status: Option<StatusCode>, content: Option<(&str, Schema)>) {
+        let (key, description) = match status {
+            Some(status) => (
+                status.as_str().to_owned(),
+                status.canonical_reason().unwrap_or_default().to_owned(),
+            ),
+            None => ("default".to_owned(), "Default response".to_owned()),
+        };
+        let response = self
+            .responses
+            .entry(key)
+            .or_insert_with(|| ResponseDoc::new(description));
+        if let Some((content_type, schema)) = content {
+            response
+                .content
+                .entry(content_type.to_owned())
+                .or_insert(MediaType { schema });
+        }
+    }
+
+    /// Move all generated responses into the default response,
+    /// used for responses of which the status code is only known at runtime.
+    pub(super) fn into_default_responses(self) -> impl Iterator<Item = (String, Schema)> {
+        self.responses.into_values().flat_map(|response| {
+            response
+                .content
+                .into_iter()
+                .map(|(content_type, media)| (content_type, media.schema))
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+/// The location of a [`Parameter`].
+pub enum ParameterLocation {
+    /// Parameter that is part of the path, e.g. `id` in `/users/:id`.
+    Path,
+    /// Parameter that is part of the query string.
+    Query,
+    /// Parameter passed as a request header.
+    Header,
+    /// Parameter passed as a cookie.
+    Cookie,
+}
+
+#[derive(Debug, Clone, Serialize)]
+/// A single parameter of an [`Operation`].
+pub struct Parameter {
+    name: String,
+    #[serde(rename = "in")]
+    location: ParameterLocation,
+    required: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    schema: Schema,
+}
+
+impl Parameter {
+    /// Create a new [`Parameter`].
+    pub fn new(
+        name: impl Into<String>,
+        location: ParameterLocation,
+        schema: Schema,
+        required: bool,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            location,
+            required,
+            description: None,
+            schema,
+        }
+    }
+
+    /// Create a new path [`Parameter`], which is always required.
+    pub fn path(name: impl Into<String>, schema: Schema) -> Self {
+        Self::new(name, ParameterLocation::Path, schema, true)
+    }
+
+    /// Create a new query [`Parameter`].
+    pub fn query(name: impl Into<String>, schema: Schema, required: bool) -> Self {
+        Self::new(name, ParameterLocation::Query, schema, required)
+    }
+
+    /// Create a new header [`Parameter`].
+    pub fn header(name: impl Into<String>, schema: Schema, required: bool) -> Self {
+        Self::new(name, ParameterLocation::Header, schema, required)
+    }
+
+    /// Set a description of the parameter.
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct RequestBody {
+    required: bool,
+    content: BTreeMap<String, MediaType>,
+}
+
+impl RequestBody {
+    fn new(content_type: impl Into<String>, schema: Schema) -> Self {
+        let mut content = BTreeMap::new();
+        content.insert(content_type.into(), MediaType { schema });
+        Self {
+            required: true,
+            content,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ResponseDoc {
+    description: String,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    content: BTreeMap<String, MediaType>,
+}
+
+impl ResponseDoc {
+    fn new(description: String) -> Self {
+        Self {
+            description,
+            content: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct MediaType {
+    schema: Schema,
+}
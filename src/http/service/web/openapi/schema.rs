@@ -0,0 +1,280 @@
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(transparent)]
+/// A JSON Schema, as used by OpenAPI 3.1 to describe
+/// parameters, request bodies and responses.
+pub struct Schema(Value);
+
+impl Schema {
+    /// Create a [`Schema`] from a raw JSON Schema value.
+    pub fn new(value: Value) -> Self {
+        Self(value)
+    }
+
+    /// Create a schema which accepts any value.
+    pub fn any() -> Self {
+        Self(Value::Object(Map::new()))
+    }
+
+    /// Create a schema for a string value.
+    pub fn string() -> Self {
+        Self(json!({ "type": "string" }))
+    }
+
+    /// Create a schema for an integer value, with the given format (e.g. `int64`).
+    pub fn integer(format: &'static str) -> Self {
+        Self(json!({ "type": "integer", "format": format }))
+    }
+
+    /// Create a schema for a number value, with the given format (e.g. `double`).
+    pub fn number(format: &'static str) -> Self {
+        Self(json!({ "type": "number", "format": format }))
+    }
+
+    /// Create a schema for a boolean value.
+    pub fn boolean() -> Self {
+        Self(json!({ "type": "boolean" }))
+    }
+
+    /// Create a schema for an array of which all items match the given schema.
+    pub fn array(items: Schema) -> Self {
+        Self(json!({ "type": "array", "items": items.0 }))
+    }
+
+    /// Create a schema for an object with no properties defined (yet).
+    ///
+    /// Use [`Schema::property`] to add properties to it.
+    pub fn object() -> Self {
+        Self(json!({ "type": "object", "properties": {} }))
+    }
+
+    /// Create a schema for an object which values all match the given schema.
+    pub fn map(values: Schema) -> Self {
+        Self(json!({ "type": "object", "additionalProperties": values.0 }))
+    }
+
+    /// Allow `null` as an alternative to the current schema.
+    pub fn nullable(self) -> Self {
+        Self(json!({ "oneOf": [self.0, { "type": "null" }] }))
+    }
+
+    /// Add a property to an object schema, marking it as required if desired.
+    ///
+    /// # Panics
+    ///
+    /// This function panics in case the schema is not created using [`Schema::object`].
+    pub fn property(mut self, name: impl Into<String>, schema: Schema, required: bool) -> Self {
+        let name = name.into();
+        let object = self
+            .0
+            .as_object_mut()
+            .expect("property can only be added to an object schema");
+        if required {
+            object
+                .entry("required")
+                .or_insert_with(|| Value::Array(Vec::new()))
+                .as_array_mut()
+                .expect("required to be an array")
+                .push(Value::String(name.clone()));
+        }
+        object
+            .get_mut("properties")
+            .and_then(Value::as_object_mut)
+            .expect("property can only be added to an object schema")
+            .insert(name, schema.0);
+        self
+    }
+
+    /// Add a description to the schema.
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        if let Some(object) = self.0.as_object_mut() {
+            object.insert("description".to_owned(), Value::String(description.into()));
+        }
+        self
+    }
+
+    /// Iterate over the properties of an object schema,
+    /// as `(name, schema, required)` tuples.
+    ///
+    /// Nothing is returned in case the schema is not an object schema.
+    pub fn properties(&self) -> impl Iterator<Item = (&str, Schema, bool)> {
+        let required: Vec<&str> = self
+            .0
+            .get("required")
+            .and_then(Value::as_array)
+            .map(|required| required.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default();
+        self.0
+            .get("properties")
+            .and_then(Value::as_object)
+            .into_iter()
+            .flat_map(|properties| properties.iter())
+            .map(move |(name, schema)| {
+                (
+                    name.as_str(),
+                    Schema(schema.clone()),
+                    required.contains(&name.as_str()),
+                )
+            })
+    }
+
+    /// Get a reference to the raw JSON Schema value.
+    pub fn as_value(&self) -> &Value {
+        &self.0
+    }
+}
+
+impl From<Value> for Schema {
+    fn from(value: Value) -> Self {
+        Self(value)
+    }
+}
+
+/// A type that can describe itself as a [`Schema`].
+///
+/// Implemented for the primitive and std types,
+/// and to be implemented for your own types that you (de)serialize
+/// in documented endpoints, e.g. as part of a [`Json`] body or [`Path`] parameters.
+///
+/// # Example
+///
+/// ```
+/// use rama::http::service::web::openapi::{Schema, ToSchema};
+///
+/// #[derive(Debug, serde::Deserialize)]
+/// struct Person {
+///     name: String,
+///     age: Option<u8>,
+/// }
+///
+/// impl ToSchema for Person {
+///     fn schema() -> Schema {
+///         Schema::object()
+///             .property("name", String::schema(), true)
+///             .property("age", u8::schema(), false)
+///     }
+/// }
+/// ```
+///
+/// [`Json`]: crate::http::service::web::extract::Json
+/// [`Path`]: crate::http::service::web::extract::Path
+pub trait ToSchema {
+    /// Describe the type as a [`Schema`].
+    fn schema() -> Schema;
+}
+
+macro_rules! impl_to_schema {
+    ($schema:expr => $($ty:ty),+ $(,)?) => {
+        $(
+            impl ToSchema for $ty {
+                fn schema() -> Schema {
+                    $schema
+                }
+            }
+        )+
+    };
+}
+
+impl_to_schema!(Schema::string() => String, str, char);
+impl_to_schema!(Schema::boolean() => bool);
+impl_to_schema!(Schema::integer("int32") => i8, i16, i32, u8, u16);
+impl_to_schema!(Schema::integer("int64") => i64, u32, u64, isize, usize);
+impl_to_schema!(Schema::number("float") => f32);
+impl_to_schema!(Schema::number("double") => f64);
+impl_to_schema!(Schema::any() => Value);
+
+impl<T: ToSchema + ?Sized> ToSchema for &T {
+    fn schema() -> Schema {
+        T::schema()
+    }
+}
+
+impl<T: ToSchema + ?Sized> ToSchema for Box<T> {
+    fn schema() -> Schema {
+        T::schema()
+    }
+}
+
+impl<T: ToSchema> ToSchema for Option<T> {
+    fn schema() -> Schema {
+        T::schema().nullable()
+    }
+}
+
+impl<T: ToSchema> ToSchema for Vec<T> {
+    fn schema() -> Schema {
+        Schema::array(T::schema())
+    }
+}
+
+impl<T: ToSchema> ToSchema for [T] {
+    fn schema() -> Schema {
+        Schema::array(T::schema())
+    }
+}
+
+impl<K, V: ToSchema, H> ToSchema for HashMap<K, V, H> {
+    fn schema() -> Schema {
+        Schema::map(V::schema())
+    }
+}
+
+impl<K, V: ToSchema> ToSchema for BTreeMap<K, V> {
+    fn schema() -> Schema {
+        Schema::map(V::schema())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_object_schema_properties() {
+        let schema = Schema::object()
+            .property("name", String::schema(), true)
+            .property("age", Option::<u8>::schema(), false);
+
+        assert_eq!(
+            schema.as_value(),
+            &json!({
+                "type": "object",
+                "properties": {
+                    "name": { "type": "string" },
+                    "age": {
+                        "oneOf": [
+                            { "type": "integer", "format": "int32" },
+                            { "type": "null" },
+                        ]
+                    },
+                },
+                "required": ["name"],
+            })
+        );
+
+        let mut properties: Vec<_> = schema
+            .properties()
+            .map(|(name, _, required)| (name, required))
+            .collect();
+        properties.sort();
+        assert_eq!(properties, vec![("age", false), ("name", true)]);
+    }
+
+    #[test]
+    fn test_collection_schema() {
+        assert_eq!(
+            Vec::<bool>::schema().as_value(),
+            &json!({ "type": "array", "items": { "type": "boolean" } })
+        );
+        assert_eq!(
+            HashMap::<String, f64>::schema().as_value(),
+            &json!({
+                "type": "object",
+                "additionalProperties": { "type": "number", "format": "double" },
+            })
+        );
+    }
+}
@@ -1,9 +1,14 @@
-use super::{endpoint::Endpoint, IntoEndpointService};
+use super::{
+    endpoint::{Endpoint, EndpointRoute},
+    openapi::{path_param_names, DescribeEndpoint, OpenApi, Operation},
+    EndpointServiceFn, IntoEndpointService,
+};
 use crate::{
     http::{
-        matcher::{HttpMatcher, UriParams},
+        header,
+        matcher::{HttpMatcher, MethodMatcher, PathMatcher, UriParams},
         service::fs::ServeDir,
-        IntoResponse, Request, Response, StatusCode, Uri,
+        Body, HeaderValue, IntoResponse, Method, Request, Response, StatusCode, Uri,
     },
     service::{context::Extensions, service_fn, BoxService, Context, Matcher, Service},
 };
@@ -16,15 +21,26 @@ use std::{convert::Infallible, future::Future, marker::PhantomData, sync::Arc};
 /// For those locations where you need do not desire the convenience over performance,
 /// you can instead use a tuple of `(M, S)` tuples, where M is a matcher and S is a service,
 /// e.g. `((MethodMatcher::GET, service_a), (MethodMatcher::POST, service_b), service_fallback)`.
+///
+/// In case no route matches, but the path of the request does match one or more
+/// routes registered for other methods, a `405 Method Not Allowed` response is returned,
+/// with the `Allow` header set to the methods that are allowed for that path.
+/// This is only possible for routes registered using [`WebService::route`]
+/// or one of its method-specific shortcuts such as [`WebService::get`].
 pub struct WebService<State> {
     endpoints: Vec<Arc<Endpoint<State>>>,
     not_found: Arc<BoxService<State, Request, Response, Infallible>>,
+    auto_head: bool,
+    auto_options: bool,
     _phantom: PhantomData<State>,
 }
 
 impl<State> std::fmt::Debug for WebService<State> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("WebService").finish()
+        f.debug_struct("WebService")
+            .field("auto_head", &self.auto_head)
+            .field("auto_options", &self.auto_options)
+            .finish()
     }
 }
 
@@ -33,6 +49,8 @@ impl<State> Clone for WebService<State> {
         Self {
             endpoints: self.endpoints.clone(),
             not_found: self.not_found.clone(),
+            auto_head: self.auto_head,
+            auto_options: self.auto_options,
             _phantom: PhantomData,
         }
     }
@@ -49,17 +67,127 @@ where
             not_found: Arc::new(
                 service_fn(|| async { Ok(StatusCode::NOT_FOUND.into_response()) }).boxed(),
             ),
+            auto_head: false,
+            auto_options: false,
             _phantom: PhantomData,
         }
     }
 
+    /// add a route to the web service for the given methods and path, using the given service.
+    ///
+    /// Routes added this way (directly or via the method-specific shortcuts
+    /// such as [`WebService::get`]) are taken into account to respond
+    /// with `405 Method Not Allowed` when the path matches but the method does not.
+    pub fn route<I, T>(self, methods: MethodMatcher, path: &str, service: I) -> Self
+    where
+        I: IntoEndpointService<State, T>,
+    {
+        self.add_route(methods, path, None, service)
+    }
+
+    /// add a route to the web service for the given methods and path,
+    /// documented by the given [`Operation`], using the given endpoint function.
+    ///
+    /// The operation is completed with the description of the extractors
+    /// and response type of the endpoint function, and is included in the
+    /// document created by [`WebService::openapi`].
+    ///
+    /// See the [`openapi`] module for more information.
+    ///
+    /// [`openapi`]: super::openapi
+    pub fn documented_route<F, T>(
+        self,
+        methods: MethodMatcher,
+        path: &str,
+        mut operation: Operation,
+        service: F,
+    ) -> Self
+    where
+        F: EndpointServiceFn<State, T> + DescribeEndpoint<State, T>,
+        T: Send + 'static,
+    {
+        operation.set_path_params(path_param_names(path));
+        F::describe_endpoint(&mut operation);
+        self.add_route(methods, path, Some(operation), service)
+    }
+
+    /// create an [`OpenApi`] document, adding the operations
+    /// of all routes registered using [`WebService::documented_route`] to the given document.
+    ///
+    /// Operations of nested web services are not included.
+    /// The `HEAD` and `OPTIONS` methods of a route are only documented if it has no other methods,
+    /// while the operation id is suffixed with the method for all but the first other method,
+    /// such that each operation id is unique.
+    pub fn openapi(&self, mut document: OpenApi) -> OpenApi {
+        for route in self.endpoints.iter().filter_map(|e| e.route.as_ref()) {
+            if let Some(operation) = &route.operation {
+                let methods: Vec<_> = route
+                    .methods
+                    .iter()
+                    .filter(|m| m != Method::CONNECT)
+                    .collect();
+                let explicit: Vec<_> = methods
+                    .iter()
+                    .filter(|m| *m != Method::HEAD && *m != Method::OPTIONS)
+                    .cloned()
+                    .collect();
+                let methods = if explicit.is_empty() {
+                    methods
+                } else {
+                    explicit
+                };
+                for (index, method) in methods.iter().enumerate() {
+                    let mut operation = operation.clone();
+                    if index > 0 {
+                        operation.suffix_operation_id(method);
+                    }
+                    document.add_operation(method, &route.pattern, operation);
+                }
+            }
+        }
+        document
+    }
+
+    /// serve the [`OpenApi`] document, as created by [`WebService::openapi`],
+    /// as JSON for `GET` requests on the given path.
+    ///
+    /// Only routes registered before this call are included in the document,
+    /// so it is best called as the last route of the web service.
+    pub fn openapi_route(self, path: &str, document: OpenApi) -> Self {
+        let document = self.openapi(document);
+        self.get(path, document)
+    }
+
+    fn add_route<I, T>(
+        mut self,
+        methods: MethodMatcher,
+        path: &str,
+        operation: Option<Operation>,
+        service: I,
+    ) -> Self
+    where
+        I: IntoEndpointService<State, T>,
+    {
+        let endpoint = Endpoint {
+            matcher: HttpMatcher::method(methods).and_path(path),
+            route: Some(EndpointRoute {
+                pattern: path.to_owned(),
+                path: PathMatcher::new(path),
+                methods,
+                operation,
+            }),
+            service: service.into_endpoint_service().boxed(),
+        };
+        self.endpoints.push(Arc::new(endpoint));
+        self
+    }
+
     /// add a GET route to the web service, using the given service.
     pub fn get<I, T>(self, path: &str, service: I) -> Self
     where
         I: IntoEndpointService<State, T>,
     {
-        let matcher = HttpMatcher::method_get().and_path(path);
-        self.on(matcher, service)
+        self.route(MethodMatcher::GET, path, service)
     }
 
     /// add a POST route to the web service, using the given service.
@@ -67,8 +195,7 @@ where
     where
         I: IntoEndpointService<State, T>,
     {
-        let matcher = HttpMatcher::method_post().and_path(path);
-        self.on(matcher, service)
+        self.route(MethodMatcher::POST, path, service)
     }
 
     /// add a PUT route to the web service, using the given service.
@@ -76,8 +203,7 @@ where
     where
         I: IntoEndpointService<State, T>,
     {
-        let matcher = HttpMatcher::method_put().and_path(path);
-        self.on(matcher, service)
+        self.route(MethodMatcher::PUT, path, service)
     }
 
     /// add a DELETE route to the web service, using the given service.
@@ -85,8 +211,7 @@ where
     where
         I: IntoEndpointService<State, T>,
     {
-        let matcher = HttpMatcher::method_delete().and_path(path);
-        self.on(matcher, service)
+        self.route(MethodMatcher::DELETE, path, service)
     }
 
     /// add a PATCH route to the web service, using the given service.
@@ -94,8 +219,7 @@ where
     where
         I: IntoEndpointService<State, T>,
     {
-        let matcher = HttpMatcher::method_patch().and_path(path);
-        self.on(matcher, service)
+        self.route(MethodMatcher::PATCH, path, service)
     }
 
     /// add a HEAD route to the web service, using the given service.
@@ -103,8 +227,7 @@ where
     where
         I: IntoEndpointService<State, T>,
     {
-        let matcher = HttpMatcher::method_head().and_path(path);
-        self.on(matcher, service)
+        self.route(MethodMatcher::HEAD, path, service)
     }
 
     /// add a OPTIONS route to the web service, using the given service.
@@ -112,8 +235,7 @@ where
     where
         I: IntoEndpointService<State, T>,
     {
-        let matcher = HttpMatcher::method_options().and_path(path);
-        self.on(matcher, service)
+        self.route(MethodMatcher::OPTIONS, path, service)
     }
 
     /// add a TRACE route to the web service, using the given service.
@@ -121,8 +243,7 @@ where
     where
         I: IntoEndpointService<State, T>,
     {
-        let matcher = HttpMatcher::method_trace().and_path(path);
-        self.on(matcher, service)
+        self.route(MethodMatcher::TRACE, path, service)
     }
 
     /// nest a web service under the given path.
@@ -151,6 +272,7 @@ where
     {
         let endpoint = Endpoint {
             matcher,
+            route: None,
             service: service.into_endpoint_service().boxed(),
         };
         self.endpoints.push(Arc::new(endpoint));
@@ -165,6 +287,64 @@ where
         self.not_found = Arc::new(service.into_endpoint_service().boxed());
         self
     }
+
+    /// answer `HEAD` requests using the `GET` route registered for the same path,
+    /// in case no explicit `HEAD` route matched the request.
+    ///
+    /// The response body returned by the `GET` route is dropped,
+    /// while its status and headers are kept as-is.
+    pub fn auto_head(mut self, enabled: bool) -> Self {
+        self.auto_head = enabled;
+        self
+    }
+
+    /// answer `OPTIONS` requests with a `204 No Content` response
+    /// containing the `Allow` header, in case no explicit `OPTIONS` route matched the request.
+    pub fn auto_options(mut self, enabled: bool) -> Self {
+        self.auto_options = enabled;
+        self
+    }
+
+    /// find the first endpoint matching the request,
+    /// returning it together with the extensions generated by its matcher.
+    fn match_endpoint(
+        &self,
+        ctx: &Context<State>,
+        req: &Request,
+    ) -> Option<(&Endpoint<State>, Extensions)> {
+        let mut ext = Extensions::new();
+        for endpoint in &self.endpoints {
+            if endpoint.matcher.matches(Some(&mut ext), ctx, req) {
+                return Some((endpoint, ext));
+            }
+            // clear the extensions for the next matcher
+            ext.clear();
+        }
+        None
+    }
+
+    /// all methods that are allowed for the given path,
+    /// empty in case no route matches the path at all.
+    fn allowed_methods(&self, path: &str) -> MethodMatcher {
+        let mut allowed = self
+            .endpoints
+            .iter()
+            .filter_map(|endpoint| endpoint.route.as_ref())
+            .filter(|route| route.path.matches_path(path).is_some())
+            .fold(MethodMatcher::empty(), |allowed, route| {
+                allowed.or(route.methods)
+            });
+        if allowed.is_empty() {
+            return allowed;
+        }
+        if self.auto_head && allowed.contains(MethodMatcher::GET) {
+            allowed = allowed.or(MethodMatcher::HEAD);
+        }
+        if self.auto_options {
+            allowed = allowed.or(MethodMatcher::OPTIONS);
+        }
+        allowed
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -225,19 +405,37 @@ where
     async fn serve(
         &self,
         mut ctx: Context<State>,
-        req: Request,
+        mut req: Request,
     ) -> Result<Self::Response, Self::Error> {
-        let mut ext = Extensions::new();
-        for endpoint in &self.endpoints {
-            if endpoint.matcher.matches(Some(&mut ext), &ctx, &req) {
-                // insert the extensions that might be generated by the matcher(s) into the context
+        if let Some((endpoint, ext)) = self.match_endpoint(&ctx, &req) {
+            // insert the extensions that might be generated by the matcher(s) into the context
+            ctx.extend(ext);
+            return endpoint.service.serve(ctx, req).await;
+        }
+
+        let allowed = self.allowed_methods(req.uri().path());
+        if allowed.is_empty() {
+            return self.not_found.serve(ctx, req).await;
+        }
+
+        if req.method() == Method::HEAD && allowed.contains(MethodMatcher::HEAD) {
+            *req.method_mut() = Method::GET;
+            if let Some((endpoint, ext)) = self.match_endpoint(&ctx, &req) {
                 ctx.extend(ext);
-                return endpoint.service.serve(ctx, req).await;
+                let (parts, _) = endpoint.service.serve(ctx, req).await?.into_parts();
+                return Ok(Response::from_parts(parts, Body::empty()));
             }
-            // clear the extensions for the next matcher
-            ext.clear();
+            *req.method_mut() = Method::HEAD;
         }
-        self.not_found.serve(ctx, req).await
+
+        let allow = HeaderValue::from_str(&allowed.to_string())
+            .expect("allowed methods to be a valid header value");
+        let status = if req.method() == Method::OPTIONS && self.auto_options {
+            StatusCode::NO_CONTENT
+        } else {
+            StatusCode::METHOD_NOT_ALLOWED
+        };
+        Ok(([(header::ALLOW, allow)], status).into_response())
     }
 }
 
@@ -407,12 +605,186 @@ mod test {
         assert_eq!(body, "world");
 
         let res = get_response(&svc, "https://www.test.io/world").await;
-        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(res.headers().get(header::ALLOW).unwrap(), "POST");
 
         let res = get_response(&svc, "https://www.test.io").await;
         assert_eq!(res.status(), StatusCode::NOT_FOUND);
     }
 
+    async fn method_response<S>(service: &S, method: Method, uri: &str) -> Response
+    where
+        S: Service<(), Request, Response = Response, Error = Infallible>,
+    {
+        let req = Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap();
+        service.serve(Context::default(), req).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_web_service_method_not_allowed() {
+        let svc = WebService::new()
+            .get("/hello", "hello")
+            .post("/hello", "world")
+            .route(
+                MethodMatcher::PUT.or(MethodMatcher::PATCH),
+                "/items/:id",
+                "item",
+            );
+
+        let res = method_response(&svc, Method::DELETE, "https://www.test.io/hello").await;
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(res.headers().get(header::ALLOW).unwrap(), "GET, POST");
+
+        let res = method_response(&svc, Method::GET, "https://www.test.io/items/42").await;
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(res.headers().get(header::ALLOW).unwrap(), "PATCH, PUT");
+
+        let res = method_response(&svc, Method::PATCH, "https://www.test.io/items/42").await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // HEAD and OPTIONS are not derived unless enabled
+        let res = method_response(&svc, Method::HEAD, "https://www.test.io/hello").await;
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+        let res = method_response(&svc, Method::OPTIONS, "https://www.test.io/hello").await;
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+        let res = method_response(&svc, Method::DELETE, "https://www.test.io/world").await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert!(res.headers().get(header::ALLOW).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_web_service_auto_head_and_options() {
+        let svc = WebService::new()
+            .get("/hello", ([(header::ETAG, "\"hello\"")], "hello"))
+            .post("/hello", "world")
+            .options("/custom", StatusCode::IM_A_TEAPOT)
+            .auto_head(true)
+            .auto_options(true);
+
+        let res = method_response(&svc, Method::HEAD, "https://www.test.io/hello").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get(header::ETAG).unwrap(), "\"hello\"");
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert!(body.is_empty());
+
+        let res = method_response(&svc, Method::OPTIONS, "https://www.test.io/hello").await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            res.headers().get(header::ALLOW).unwrap(),
+            "GET, HEAD, OPTIONS, POST"
+        );
+
+        let res = method_response(&svc, Method::OPTIONS, "https://www.test.io/custom").await;
+        assert_eq!(res.status(), StatusCode::IM_A_TEAPOT);
+
+        let res = method_response(&svc, Method::PUT, "https://www.test.io/hello").await;
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(
+            res.headers().get(header::ALLOW).unwrap(),
+            "GET, HEAD, OPTIONS, POST"
+        );
+
+        let res = method_response(&svc, Method::OPTIONS, "https://www.test.io/world").await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_web_service_path_constraints() {
+        let svc = WebService::new()
+            .get("/users/me", "me")
+            .get("/users/:id{int}", "by id")
+            .get("/users/:name{[a-z]+}", "by name");
+
+        let res = get_response(&svc, "https://www.test.io/users/me").await;
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "me");
+
+        let res = get_response(&svc, "https://www.test.io/users/42").await;
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "by id");
+
+        let res = get_response(&svc, "https://www.test.io/users/glen").await;
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "by name");
+
+        let res = get_response(&svc, "https://www.test.io/users/42-glen").await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        let res = post_response(&svc, "https://www.test.io/users/42").await;
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(res.headers().get(header::ALLOW).unwrap(), "GET");
+    }
+
+    #[tokio::test]
+    async fn test_web_service_openapi_route() {
+        use crate::http::service::web::{
+            extract::{Json, Path},
+            openapi::{Schema, ToSchema},
+        };
+
+        #[derive(Debug, serde::Deserialize)]
+        struct Params {
+            id: u64,
+        }
+
+        impl ToSchema for Params {
+            fn schema() -> Schema {
+                Schema::object().property("id", u64::schema(), true)
+            }
+        }
+
+        let svc = WebService::new()
+            .documented_route(
+                MethodMatcher::GET.or(MethodMatcher::HEAD),
+                "/items/:id",
+                Operation::new().operation_id("getItem"),
+                |Path(params): Path<Params>| async move { Json(params.id) },
+            )
+            .documented_route(
+                MethodMatcher::PATCH.or(MethodMatcher::PUT),
+                "/items/:id",
+                Operation::new().operation_id("updateItem"),
+                |Path(params): Path<Params>| async move { Json(params.id) },
+            )
+            .post("/items", StatusCode::CREATED)
+            .openapi_route("/openapi.json", OpenApi::new("items", "1.0.0"));
+
+        let res = get_response(&svc, "https://www.test.io/openapi.json").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        let doc: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(doc["openapi"], "3.1.0");
+        assert_eq!(doc["info"]["title"], "items");
+        assert_eq!(doc["paths"].as_object().unwrap().len(), 1);
+        let get = &doc["paths"]["/items/{id}"]["get"];
+        assert_eq!(get["operationId"], "getItem");
+        assert_eq!(get["parameters"][0]["name"], "id");
+        assert_eq!(get["parameters"][0]["in"], "path");
+        assert_eq!(
+            get["responses"]["200"]["content"]["application/json"]["schema"]["type"],
+            "integer"
+        );
+        assert!(doc["paths"]["/items/{id}"].get("head").is_none());
+        assert_eq!(
+            doc["paths"]["/items/{id}"]["patch"]["operationId"],
+            "updateItem"
+        );
+        assert_eq!(
+            doc["paths"]["/items/{id}"]["put"]["operationId"],
+            "updateItem_put"
+        );
+
+        let res = get_response(&svc, "https://www.test.io/items/42").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "42");
+    }
+
     #[tokio::test]
     async fn test_web_service_not_found() {
         let svc = WebService::new().not_found("not found");
@@ -443,7 +815,8 @@ mod test {
         assert_eq!(body, "world");
 
         let res = get_response(&svc, "https://www.test.io/api/world").await;
-        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(res.headers().get(header::ALLOW).unwrap(), "POST");
 
         let res = get_response(&svc, "https://www.test.io").await;
         assert_eq!(res.status(), StatusCode::NOT_FOUND);
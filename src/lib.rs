@@ -109,6 +109,8 @@ pub(crate) mod macros;
 #[cfg(test)]
 mod test_helpers;
 
+pub(crate) mod fs;
+
 pub mod graceful;
 pub mod latency;
 
@@ -121,6 +123,11 @@ pub mod stream;
 
 pub mod tcp;
 
+pub mod udp;
+
+#[cfg(unix)]
+pub mod unix;
+
 pub mod tls;
 
 pub mod http;
@@ -22,7 +22,9 @@ pub use username::{parse_username_config, UsernameConfig};
 
 pub mod pp;
 
-#[derive(Debug, Default, Clone, Deserialize, PartialEq)]
+pub mod quota;
+
+#[derive(Debug, Default, Clone, Deserialize, PartialEq, Eq, Hash)]
 /// Filter to select a specific kind of proxy.
 ///
 /// If the `id` is specified the other fields are used
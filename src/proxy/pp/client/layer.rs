@@ -0,0 +1,257 @@
+use crate::{
+    proxy::pp::{
+        protocol::{
+            v1,
+            v2::{self, Builder, Command, Protocol, Type, Version},
+        },
+        server::ProxyProtocolVersion,
+    },
+    service::{Context, Layer, Service},
+    stream::{SocketInfo, Stream},
+};
+use std::{io, net::SocketAddr, sync::Arc};
+use tokio::io::AsyncWriteExt;
+
+#[derive(Debug, Clone)]
+struct HeaderConfig {
+    version: ProxyProtocolVersion,
+    addresses: Option<(SocketAddr, SocketAddr)>,
+    tlvs: Vec<(u8, Vec<u8>)>,
+    unique_id: bool,
+}
+
+/// Layer to encode the HaProxy Protocol on outgoing connections.
+///
+/// The layer wraps a connector, a [`Service`] returning a connected [`Stream`],
+/// such as the [`TcpConnector`], and writes a PROXY protocol header
+/// on each stream it establishes, before returning it.
+///
+/// [`TcpConnector`]: crate::tcp::client::TcpConnector
+#[derive(Debug, Clone)]
+pub struct HaProxyClientLayer {
+    config: HeaderConfig,
+}
+
+impl HaProxyClientLayer {
+    /// Create a new [`HaProxyClientLayer`] for the given protocol version.
+    pub fn new(version: ProxyProtocolVersion) -> Self {
+        Self {
+            config: HeaderConfig {
+                version,
+                addresses: None,
+                tlvs: Vec::new(),
+                unique_id: false,
+            },
+        }
+    }
+
+    /// Create a new [`HaProxyClientLayer`] writing v1 (text) headers.
+    pub fn v1() -> Self {
+        Self::new(ProxyProtocolVersion::V1)
+    }
+
+    /// Create a new [`HaProxyClientLayer`] writing v2 (binary) headers.
+    pub fn v2() -> Self {
+        Self::new(ProxyProtocolVersion::V2)
+    }
+
+    /// Use the given source and destination address in the header.
+    ///
+    /// By default the addresses are derived from the [`SocketInfo`] in the [`Context`],
+    /// using the peer address of the incoming connection as the source and its
+    /// local address as the destination. If these are not known, the addresses
+    /// are sent as unknown (v1) or unspecified (v2).
+    pub fn addresses(mut self, source: SocketAddr, destination: SocketAddr) -> Self {
+        self.config.addresses = Some((source, destination));
+        self
+    }
+
+    /// Add a Type-Length-Value (TLV) payload to the header.
+    ///
+    /// TLVs are only supported by v2 headers, and ignored for v1 headers.
+    pub fn tlv(mut self, kind: impl Into<u8>, value: impl Into<Vec<u8>>) -> Self {
+        self.config.tlvs.push((kind.into(), value.into()));
+        self
+    }
+
+    /// Add the authority (e.g. the SNI hostname) as a TLV to the header.
+    ///
+    /// TLVs are only supported by v2 headers, and ignored for v1 headers.
+    pub fn authority(self, authority: impl Into<String>) -> Self {
+        self.tlv(Type::Authority, authority.into())
+    }
+
+    /// Add a unique ID, generated for each connection, as a TLV to the header.
+    ///
+    /// TLVs are only supported by v2 headers, and ignored for v1 headers.
+    pub fn unique_id(mut self, unique_id: bool) -> Self {
+        self.config.unique_id = unique_id;
+        self
+    }
+}
+
+impl<S> Layer<S> for HaProxyClientLayer {
+    type Service = HaProxyClientService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        HaProxyClientService {
+            inner,
+            config: Arc::new(self.config.clone()),
+        }
+    }
+}
+
+/// Service to encode the HaProxy Protocol on outgoing connections.
+///
+/// See [`HaProxyClientLayer`] for more information.
+#[derive(Debug, Clone)]
+pub struct HaProxyClientService<S> {
+    inner: S,
+    config: Arc<HeaderConfig>,
+}
+
+impl<S> HaProxyClientService<S> {
+    fn header<State>(&self, ctx: &Context<State>) -> io::Result<Vec<u8>> {
+        let addresses = self.config.addresses.or_else(|| {
+            ctx.get::<SocketInfo>().and_then(|info| {
+                info.local_addr()
+                    .map(|local_addr| (*info.peer_addr(), *local_addr))
+            })
+        });
+
+        match self.config.version {
+            ProxyProtocolVersion::V1 => {
+                let addresses = addresses.map(v1::Addresses::from).unwrap_or_default();
+                Ok(addresses.to_string().into_bytes())
+            }
+            ProxyProtocolVersion::V2 => {
+                let addresses = addresses
+                    .map(v2::Addresses::from)
+                    .unwrap_or(v2::Addresses::Unspecified);
+                let protocol = match addresses {
+                    v2::Addresses::Unspecified => Protocol::Unspecified,
+                    _ => Protocol::Stream,
+                };
+
+                let mut builder =
+                    Builder::with_addresses(Version::Two | Command::Proxy, protocol, addresses);
+                for (kind, value) in self.config.tlvs.iter() {
+                    builder = builder.write_tlv(*kind, value)?;
+                }
+                if self.config.unique_id {
+                    let id = uuid::Uuid::new_v4().to_string();
+                    builder = builder.write_tlv(Type::UniqueId, id.as_bytes())?;
+                }
+                builder.build()
+            }
+        }
+    }
+}
+
+impl<State, S, Request> Service<State, Request> for HaProxyClientService<S>
+where
+    State: Send + Sync + 'static,
+    Request: Send + 'static,
+    S: Service<State, Request>,
+    S::Response: Stream + Unpin,
+    S::Error: From<io::Error>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+
+    async fn serve(
+        &self,
+        ctx: Context<State>,
+        req: Request,
+    ) -> Result<Self::Response, Self::Error> {
+        let header = self.header(&ctx)?;
+
+        let mut stream = self.inner.serve(ctx, req).await?;
+        stream.write_all(&header).await?;
+        stream.flush().await?;
+
+        Ok(stream)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        proxy::pp::{protocol::HeaderResult, server::ProxyProtocolHeader},
+        service::service_fn,
+    };
+    use tokio::io::{AsyncReadExt, DuplexStream};
+
+    async fn connect(layer: HaProxyClientLayer, ctx: Context<()>) -> ProxyProtocolHeader {
+        let (client, mut server) = tokio::io::duplex(1024);
+        // the connected stream is passed as the request
+        let connector = layer.layer(service_fn(|_, stream: DuplexStream| async move {
+            Ok::<_, io::Error>(stream)
+        }));
+        let _client = connector.serve(ctx, client).await.unwrap();
+
+        let mut buffer = vec![0; 1024];
+        let n = server.read(&mut buffer).await.unwrap();
+        match HeaderResult::parse(&buffer[..n]) {
+            HeaderResult::V1(Ok(header)) => ProxyProtocolHeader::from(&header),
+            HeaderResult::V2(Ok(header)) => ProxyProtocolHeader::try_from(&header).unwrap(),
+            header => panic!("unexpected header: {header:?}"),
+        }
+    }
+
+    fn ctx() -> Context<()> {
+        let mut ctx = Context::default();
+        ctx.insert(SocketInfo::new(
+            Some("10.0.0.2:443".parse().unwrap()),
+            "10.0.0.1:1234".parse().unwrap(),
+        ));
+        ctx
+    }
+
+    #[tokio::test]
+    async fn test_haproxy_client_v1() {
+        let header = connect(HaProxyClientLayer::v1(), ctx()).await;
+        assert_eq!(header.version(), ProxyProtocolVersion::V1);
+        assert_eq!(header.source(), Some("10.0.0.1:1234".parse().unwrap()));
+        assert_eq!(header.destination(), Some("10.0.0.2:443".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_haproxy_client_v1_unknown() {
+        let header = connect(HaProxyClientLayer::v1(), Context::default()).await;
+        assert_eq!(header.source(), None);
+    }
+
+    #[tokio::test]
+    async fn test_haproxy_client_v2() {
+        let layer = HaProxyClientLayer::v2()
+            .addresses("[::1]:1234".parse().unwrap(), "[::2]:443".parse().unwrap())
+            .authority("example.com")
+            .unique_id(true);
+        let header = connect(layer, ctx()).await;
+
+        assert_eq!(header.version(), ProxyProtocolVersion::V2);
+        assert_eq!(header.command(), Command::Proxy);
+        assert_eq!(header.source(), Some("[::1]:1234".parse().unwrap()));
+        assert_eq!(header.destination(), Some("[::2]:443".parse().unwrap()));
+        assert_eq!(header.authority(), Some("example.com"));
+        assert_eq!(header.unique_id().unwrap().len(), 36);
+    }
+
+    #[tokio::test]
+    async fn test_haproxy_client_v2_unspecified() {
+        let header = connect(HaProxyClientLayer::v2(), Context::default()).await;
+        assert_eq!(header.protocol(), Protocol::Unspecified);
+        assert_eq!(header.source(), None);
+    }
+
+    #[tokio::test]
+    async fn test_haproxy_client_connect_error() {
+        let connector = HaProxyClientLayer::v2().layer(service_fn(|_, ()| async {
+            Err::<DuplexStream, _>(io::Error::from(io::ErrorKind::ConnectionRefused))
+        }));
+        let err = connector.serve(Context::default(), ()).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+    }
+}
@@ -0,0 +1,30 @@
+//! HaProxy Protocol Client support
+//!
+//! <https://www.haproxy.org/download/1.8/doc/proxy-protocol.txt>
+//!
+//! # Example
+//!
+//! ```no_run
+//! use rama::proxy::pp::client::HaProxyClientLayer;
+//! use rama::service::ServiceBuilder;
+//! use rama::tcp::{client::TcpConnector, server::TcpListener, service::Forwarder};
+//!
+//! #[tokio::main]
+//! async fn main() {
+//!     // forward all connections to a backend which expects a PROXY protocol v2 header,
+//!     // informing it about the actual peer of the forwarded connection
+//!     let connector = ServiceBuilder::new()
+//!         .layer(HaProxyClientLayer::v2().authority("example.com"))
+//!         .service(TcpConnector::new());
+//!
+//!     TcpListener::bind("127.0.0.1:9000")
+//!         .await
+//!         .expect("bind TCP Listener")
+//!         .serve(Forwarder::target("127.0.0.1:8080".parse().unwrap()).connector(connector))
+//!         .await;
+//! }
+//! ```
+
+mod layer;
+#[doc(inline)]
+pub use layer::{HaProxyClientLayer, HaProxyClientService};
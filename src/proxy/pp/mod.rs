@@ -2,5 +2,6 @@
 //!
 //! <https://www.haproxy.org/download/1.8/doc/proxy-protocol.txt>
 
+pub mod client;
 pub mod protocol;
 pub mod server;
@@ -0,0 +1,393 @@
+use crate::proxy::pp::protocol::{
+    v1,
+    v2::{self, Command, Protocol, Type, TypeLengthValue},
+};
+use std::net::SocketAddr;
+
+/// The custom TLV type used by AWS for the VPC endpoint ID.
+const PP2_TYPE_AWS: u8 = 0xEA;
+/// The AWS TLV subtype of the VPC endpoint ID.
+const PP2_SUBTYPE_AWS_VPCE_ID: u8 = 0x01;
+/// The custom TLV type used by Azure for the private endpoint link ID.
+const PP2_TYPE_AZURE: u8 = 0xEE;
+/// The Azure TLV subtype of the private endpoint link ID.
+const PP2_SUBTYPE_AZURE_PRIVATEENDPOINT_LINKID: u8 = 0x01;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The version of a PROXY protocol header.
+pub enum ProxyProtocolVersion {
+    /// The human-readable (text) version of the PROXY protocol.
+    V1,
+    /// The binary version of the PROXY protocol.
+    V2,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// The PROXY protocol header received for a connection,
+/// inserted in the [`Context`] by the [`HaProxyService`].
+///
+/// Contrary to the parsed [`v1::Header`] and [`v2::Header`] this is an owned type,
+/// exposing all information of the header, including the v2 Type-Length-Value (TLV) payloads.
+///
+/// [`Context`]: crate::service::Context
+/// [`HaProxyService`]: super::HaProxyService
+pub struct ProxyProtocolHeader {
+    version: ProxyProtocolVersion,
+    command: Command,
+    protocol: Protocol,
+    source: Option<SocketAddr>,
+    destination: Option<SocketAddr>,
+    tlvs: Vec<TypeLengthValue<'static>>,
+}
+
+impl ProxyProtocolHeader {
+    /// The version of the PROXY protocol used.
+    pub fn version(&self) -> ProxyProtocolVersion {
+        self.version
+    }
+
+    /// The command of the header, which is always [`Command::Proxy`] for v1 headers.
+    ///
+    /// [`Command::Local`] is used for connections established by the proxy itself
+    /// (e.g. health checks), in which case the addresses are to be ignored.
+    pub fn command(&self) -> Command {
+        self.command
+    }
+
+    /// The transport protocol of the proxied connection.
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    /// The address of the original client, if known.
+    pub fn source(&self) -> Option<SocketAddr> {
+        self.source
+    }
+
+    /// The address the original client connected to, if known.
+    pub fn destination(&self) -> Option<SocketAddr> {
+        self.destination
+    }
+
+    /// All Type-Length-Value (TLV) payloads of the header,
+    /// which is always empty for v1 headers.
+    pub fn tlvs(&self) -> &[TypeLengthValue<'static>] {
+        &self.tlvs
+    }
+
+    /// The value of the first TLV of the given type.
+    pub fn tlv(&self, kind: impl Into<u8>) -> Option<&[u8]> {
+        let kind = kind.into();
+        self.tlvs
+            .iter()
+            .find(|tlv| tlv.kind == kind)
+            .map(|tlv| tlv.value.as_ref())
+    }
+
+    /// The Application-Layer Protocol Negotiation (ALPN) protocol, e.g. `h2`.
+    pub fn alpn(&self) -> Option<&[u8]> {
+        self.tlv(Type::ALPN)
+    }
+
+    /// The authority (host name) of the connection, e.g. as passed in the TLS SNI extension.
+    pub fn authority(&self) -> Option<&str> {
+        self.tlv(Type::Authority)
+            .and_then(|value| std::str::from_utf8(value).ok())
+    }
+
+    /// The CRC32c checksum of the header.
+    pub fn crc32c(&self) -> Option<u32> {
+        self.tlv(Type::CRC32C)
+            .and_then(|value| value.try_into().ok())
+            .map(u32::from_be_bytes)
+    }
+
+    /// The unique ID of the connection, as assigned by the proxy.
+    pub fn unique_id(&self) -> Option<&[u8]> {
+        self.tlv(Type::UniqueId)
+    }
+
+    /// The TLS information of the connection, in case it was made over TLS.
+    pub fn ssl(&self) -> Option<SslInfo<'_>> {
+        self.tlv(Type::SSL).and_then(SslInfo::parse)
+    }
+
+    /// The name of the network namespace the connection was accepted in.
+    pub fn network_namespace(&self) -> Option<&str> {
+        self.tlv(Type::NetworkNamespace)
+            .and_then(|value| std::str::from_utf8(value).ok())
+    }
+
+    /// The ID of the AWS VPC endpoint the connection was received through.
+    pub fn aws_vpce_id(&self) -> Option<&str> {
+        self.subtype_tlv(PP2_TYPE_AWS, PP2_SUBTYPE_AWS_VPCE_ID)
+            .and_then(|value| std::str::from_utf8(value).ok())
+    }
+
+    /// The link ID of the Azure private endpoint the connection was received through.
+    pub fn azure_private_endpoint_link_id(&self) -> Option<u32> {
+        self.subtype_tlv(PP2_TYPE_AZURE, PP2_SUBTYPE_AZURE_PRIVATEENDPOINT_LINKID)
+            .and_then(|value| value.try_into().ok())
+            .map(u32::from_le_bytes)
+    }
+
+    /// The value of a custom TLV of which the first byte is the subtype.
+    fn subtype_tlv(&self, kind: u8, subtype: u8) -> Option<&[u8]> {
+        self.tlvs
+            .iter()
+            .filter(|tlv| tlv.kind == kind)
+            .find_map(|tlv| match tlv.value.split_first() {
+                Some((t, value)) if *t == subtype => Some(value),
+                _ => None,
+            })
+    }
+}
+
+impl From<&v1::Header<'_>> for ProxyProtocolHeader {
+    fn from(header: &v1::Header<'_>) -> Self {
+        let (protocol, source, destination) = match header.addresses {
+            v1::Addresses::Tcp4(info) => (
+                Protocol::Stream,
+                Some((info.source_address, info.source_port).into()),
+                Some((info.destination_address, info.destination_port).into()),
+            ),
+            v1::Addresses::Tcp6(info) => (
+                Protocol::Stream,
+                Some((info.source_address, info.source_port).into()),
+                Some((info.destination_address, info.destination_port).into()),
+            ),
+            v1::Addresses::Unknown => (Protocol::Unspecified, None, None),
+        };
+        Self {
+            version: ProxyProtocolVersion::V1,
+            command: Command::Proxy,
+            protocol,
+            source,
+            destination,
+            tlvs: Vec::new(),
+        }
+    }
+}
+
+impl TryFrom<&v2::Header<'_>> for ProxyProtocolHeader {
+    type Error = v2::ParseError;
+
+    fn try_from(header: &v2::Header<'_>) -> Result<Self, Self::Error> {
+        let (source, destination) = match header.addresses {
+            v2::Addresses::IPv4(info) => (
+                Some((info.source_address, info.source_port).into()),
+                Some((info.destination_address, info.destination_port).into()),
+            ),
+            v2::Addresses::IPv6(info) => (
+                Some((info.source_address, info.source_port).into()),
+                Some((info.destination_address, info.destination_port).into()),
+            ),
+            v2::Addresses::Unix(_) | v2::Addresses::Unspecified => (None, None),
+        };
+        let tlvs = header
+            .tlvs()
+            .map(|tlv| tlv.map(|tlv| tlv.to_owned()))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            version: ProxyProtocolVersion::V2,
+            command: header.command,
+            protocol: header.protocol,
+            source,
+            destination,
+            tlvs,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The TLS information of a PROXY protocol v2 header.
+pub struct SslInfo<'a> {
+    client: u8,
+    verify: u32,
+    tlvs: &'a [u8],
+}
+
+impl<'a> SslInfo<'a> {
+    const CLIENT_SSL: u8 = 0x01;
+    const CLIENT_CERT_CONN: u8 = 0x02;
+    const CLIENT_CERT_SESS: u8 = 0x04;
+
+    fn parse(value: &'a [u8]) -> Option<Self> {
+        if value.len() < 5 {
+            return None;
+        }
+        Some(Self {
+            client: value[0],
+            verify: u32::from_be_bytes([value[1], value[2], value[3], value[4]]),
+            tlvs: &value[5..],
+        })
+    }
+
+    /// Returns `true` if the client connected over TLS.
+    pub fn is_ssl(&self) -> bool {
+        self.client & Self::CLIENT_SSL != 0
+    }
+
+    /// Returns `true` if the client provided a certificate over the current connection.
+    pub fn client_cert_conn(&self) -> bool {
+        self.client & Self::CLIENT_CERT_CONN != 0
+    }
+
+    /// Returns `true` if the client provided a certificate at least once
+    /// over the TLS session this connection belongs to.
+    pub fn client_cert_sess(&self) -> bool {
+        self.client & Self::CLIENT_CERT_SESS != 0
+    }
+
+    /// Returns `true` if the client certificate was verified successfully,
+    /// or if no certificate was presented.
+    pub fn is_verified(&self) -> bool {
+        self.verify == 0
+    }
+
+    /// The TLS version used, e.g. `TLSv1.3`.
+    pub fn version(&self) -> Option<&'a str> {
+        self.sub_tlv_str(Type::SSLVersion)
+    }
+
+    /// The common name of the client certificate subject.
+    pub fn common_name(&self) -> Option<&'a str> {
+        self.sub_tlv_str(Type::SSLCommonName)
+    }
+
+    /// The name of the cipher used, e.g. `ECDHE-RSA-AES128-GCM-SHA256`.
+    pub fn cipher(&self) -> Option<&'a str> {
+        self.sub_tlv_str(Type::SSLCipher)
+    }
+
+    /// The name of the algorithm used to sign the certificate presented by the frontend.
+    pub fn signature_algorithm(&self) -> Option<&'a str> {
+        self.sub_tlv_str(Type::SSLSignatureAlgorithm)
+    }
+
+    /// The name of the algorithm used to generate the key of the certificate presented by the frontend.
+    pub fn key_algorithm(&self) -> Option<&'a str> {
+        self.sub_tlv_str(Type::SSLKeyAlgorithm)
+    }
+
+    fn sub_tlv_str(&self, kind: Type) -> Option<&'a str> {
+        let kind: u8 = kind.into();
+        v2::TypeLengthValues::from(self.tlvs)
+            .map_while(Result::ok)
+            .find(|tlv| tlv.kind == kind)
+            .and_then(|tlv| match tlv.value {
+                std::borrow::Cow::Borrowed(value) => std::str::from_utf8(value).ok(),
+                std::borrow::Cow::Owned(_) => None,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::pp::protocol::v2::{Builder, IPv4, Version};
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_from_v1_header() {
+        let header = v1::Header::try_from("PROXY TCP4 127.0.1.2 192.168.1.101 80 443\r\n").unwrap();
+        let header = ProxyProtocolHeader::from(&header);
+
+        assert_eq!(header.version(), ProxyProtocolVersion::V1);
+        assert_eq!(header.command(), Command::Proxy);
+        assert_eq!(header.protocol(), Protocol::Stream);
+        assert_eq!(header.source(), Some("127.0.1.2:80".parse().unwrap()));
+        assert_eq!(
+            header.destination(),
+            Some("192.168.1.101:443".parse().unwrap())
+        );
+        assert!(header.tlvs().is_empty());
+
+        let header = v1::Header::try_from("PROXY UNKNOWN\r\n").unwrap();
+        let header = ProxyProtocolHeader::from(&header);
+        assert_eq!(header.protocol(), Protocol::Unspecified);
+        assert_eq!(header.source(), None);
+        assert_eq!(header.destination(), None);
+    }
+
+    #[test]
+    fn test_from_v2_header() {
+        let mut ssl = vec![0x05, 0, 0, 0, 0];
+        ssl.extend([u8::from(Type::SSLVersion), 0, 7]);
+        ssl.extend(b"TLSv1.3");
+        ssl.extend([u8::from(Type::SSLCommonName), 0, 7]);
+        ssl.extend(b"example");
+
+        let bytes = Builder::with_addresses(
+            Version::Two | Command::Proxy,
+            Protocol::Stream,
+            IPv4::new(
+                Ipv4Addr::new(127, 0, 0, 1),
+                Ipv4Addr::new(192, 168, 1, 1),
+                80,
+                443,
+            ),
+        )
+        .write_tlv(Type::ALPN, b"h2")
+        .unwrap()
+        .write_tlv(Type::Authority, b"example.com")
+        .unwrap()
+        .write_tlv(Type::UniqueId, b"abc")
+        .unwrap()
+        .write_tlv(Type::SSL, &ssl)
+        .unwrap()
+        .write_tlv(PP2_TYPE_AWS, b"\x01vpce-08d2bf15fac5001c9")
+        .unwrap()
+        .write_tlv(PP2_TYPE_AZURE, &[0x01, 0x2a, 0, 0, 0])
+        .unwrap()
+        .build()
+        .unwrap();
+
+        let header = v2::Header::try_from(bytes.as_slice()).unwrap();
+        let header = ProxyProtocolHeader::try_from(&header).unwrap();
+
+        assert_eq!(header.version(), ProxyProtocolVersion::V2);
+        assert_eq!(header.command(), Command::Proxy);
+        assert_eq!(header.protocol(), Protocol::Stream);
+        assert_eq!(header.source(), Some("127.0.0.1:80".parse().unwrap()));
+        assert_eq!(
+            header.destination(),
+            Some("192.168.1.1:443".parse().unwrap())
+        );
+        assert_eq!(header.tlvs().len(), 6);
+        assert_eq!(header.alpn(), Some(&b"h2"[..]));
+        assert_eq!(header.authority(), Some("example.com"));
+        assert_eq!(header.unique_id(), Some(&b"abc"[..]));
+        assert_eq!(header.network_namespace(), None);
+        assert_eq!(header.aws_vpce_id(), Some("vpce-08d2bf15fac5001c9"));
+        assert_eq!(header.azure_private_endpoint_link_id(), Some(42));
+
+        let ssl = header.ssl().unwrap();
+        assert!(ssl.is_ssl());
+        assert!(!ssl.client_cert_conn());
+        assert!(ssl.client_cert_sess());
+        assert!(ssl.is_verified());
+        assert_eq!(ssl.version(), Some("TLSv1.3"));
+        assert_eq!(ssl.common_name(), Some("example"));
+        assert_eq!(ssl.cipher(), None);
+    }
+
+    #[test]
+    fn test_from_v2_header_invalid_tlv() {
+        let mut bytes = Builder::with_addresses(
+            Version::Two | Command::Local,
+            Protocol::Stream,
+            IPv4::new([127, 0, 0, 1], [127, 0, 0, 1], 80, 443),
+        )
+        .write_tlv(Type::NoOp, b"")
+        .unwrap()
+        .build()
+        .unwrap();
+        // truncate the TLV, keeping the header length consistent
+        bytes.pop();
+        bytes[15] -= 1;
+
+        let header = v2::Header::try_from(bytes.as_slice()).unwrap();
+        assert!(ProxyProtocolHeader::try_from(&header).is_err());
+    }
+}
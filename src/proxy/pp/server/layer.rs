@@ -1,50 +1,125 @@
-use std::net::SocketAddr;
-
+use super::ProxyProtocolHeader;
 use crate::{
     error::Error,
-    proxy::pp::protocol::{v1, v2, HeaderResult, PartialResult},
-    service::{Context, Layer, Service},
+    proxy::pp::protocol::{
+        v1,
+        v2::{self, Command},
+        HeaderResult, PartialResult,
+    },
+    service::{matcher::Always, Context, Layer, Matcher, Service},
     stream::{ChainReader, HeapReader, SocketInfo, Stream},
 };
+use std::{io, time::Duration};
 use tokio::io::AsyncReadExt;
 
+/// The initial size of the buffer used to read the header,
+/// which is large enough for any v1 header and most v2 headers.
+const INITIAL_BUFFER_SIZE: usize = 512;
+
 /// Layer to decode the HaProxy Protocol
-#[derive(Debug, Default, Clone)]
-#[non_exhaustive]
-pub struct HaProxyLayer;
+#[derive(Debug, Clone)]
+pub struct HaProxyLayer<M = Always> {
+    trusted: M,
+    optional: bool,
+    header_timeout: Option<Duration>,
+}
 
 impl HaProxyLayer {
     /// Create a new [`HaProxyLayer`].
+    ///
+    /// By default a header is required from all peers, without a read timeout.
     pub fn new() -> Self {
-        HaProxyLayer
+        HaProxyLayer {
+            trusted: Always::new(),
+            optional: false,
+            header_timeout: None,
+        }
+    }
+}
+
+impl Default for HaProxyLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M> HaProxyLayer<M> {
+    /// Only decode the header for streams matched by the given [`Matcher`],
+    /// e.g. a [`SocketMatcher`] matching the IP networks of the trusted proxies.
+    ///
+    /// Streams of untrusted peers are passed through as-is,
+    /// such that a header they send is never used.
+    ///
+    /// [`SocketMatcher`]: crate::stream::matcher::SocketMatcher
+    pub fn trusted<T>(self, matcher: T) -> HaProxyLayer<T> {
+        HaProxyLayer {
+            trusted: matcher,
+            optional: self.optional,
+            header_timeout: self.header_timeout,
+        }
+    }
+
+    /// Make the header optional, passing the stream through as-is
+    /// when it does not start with a v1 or v2 signature.
+    ///
+    /// Combine it with a [`Self::header_timeout`] for peers which might not send
+    /// anything at all, e.g. for protocols where the server speaks first,
+    /// in which case the stream is passed through as-is once the timeout expires
+    /// without having received a signature.
+    pub fn optional(mut self, optional: bool) -> Self {
+        self.optional = optional;
+        self
+    }
+
+    /// Fail if the header is not fully received within the given duration.
+    ///
+    /// See [`Self::optional`] for how it behaves when the header is optional.
+    pub fn header_timeout(mut self, timeout: Duration) -> Self {
+        self.header_timeout = Some(timeout);
+        self
     }
 }
 
-impl<S> Layer<S> for HaProxyLayer {
-    type Service = HaProxyService<S>;
+impl<S, M: Clone> Layer<S> for HaProxyLayer<M> {
+    type Service = HaProxyService<S, M>;
 
     fn layer(&self, inner: S) -> Self::Service {
-        HaProxyService { inner }
+        HaProxyService {
+            inner,
+            trusted: self.trusted.clone(),
+            optional: self.optional,
+            header_timeout: self.header_timeout,
+        }
     }
 }
 
 /// Service to decode the HaProxy Protocol
 ///
 /// This service will decode the HaProxy Protocol header and pass the decoded
-/// information to the inner service.
+/// information to the inner service, as a [`ProxyProtocolHeader`] in the [`Context`].
+///
+/// The [`SocketInfo`] in the [`Context`] is replaced by one using the source address
+/// of the header, unless it is a LOCAL command or the source address is unknown,
+/// in which case the actual peer address is kept.
+///
+/// See [`HaProxyLayer`] for the options to only trust some peers,
+/// make the header optional or limit the time to receive it.
 #[derive(Debug, Clone)]
-pub struct HaProxyService<S> {
+pub struct HaProxyService<S, M = Always> {
     inner: S,
+    trusted: M,
+    optional: bool,
+    header_timeout: Option<Duration>,
 }
 
 impl<S> HaProxyService<S> {
     /// Create a new [`HaProxyService`] with the given inner service.
     pub fn new(inner: S) -> Self {
-        HaProxyService { inner }
+        HaProxyLayer::new().layer(inner)
     }
 }
 
-impl<State, S, IO> Service<State, IO> for HaProxyService<S>
+impl<State, S, M, IO> Service<State, IO> for HaProxyService<S, M>
 where
     State: Send + Sync + 'static,
     S: Service<
@@ -52,6 +127,7 @@ where
         tokio::io::Join<ChainReader<HeapReader, tokio::io::ReadHalf<IO>>, tokio::io::WriteHalf<IO>>,
     >,
     S::Error: Into<Error>,
+    M: Matcher<State, IO>,
     IO: Stream + Unpin,
 {
     type Response = S::Response;
@@ -62,51 +138,123 @@ where
         mut ctx: Context<State>,
         mut stream: IO,
     ) -> Result<Self::Response, Self::Error> {
-        let mut buffer = [0; 512];
-        let mut read = 0;
+        let mut buffer = Vec::new();
+        let mut consumed = 0;
+
+        if self.trusted.matches(None, &ctx, &stream) {
+            let read = match self.header_timeout {
+                Some(timeout) => {
+                    match tokio::time::timeout(timeout, self.read_header(&mut stream, &mut buffer))
+                        .await
+                    {
+                        Ok(read) => read?,
+                        // e.g. a protocol where the server speaks first
+                        Err(_) if self.optional && signature(&buffer) != Signature::Present => {
+                            tracing::trace!(
+                                "no PROXY protocol signature received in time: pass through stream"
+                            );
+                            None
+                        }
+                        Err(_) => {
+                            return Err(io::Error::new(
+                                io::ErrorKind::TimedOut,
+                                "timed out reading PROXY protocol header",
+                            )
+                            .into())
+                        }
+                    }
+                }
+                None => self.read_header(&mut stream, &mut buffer).await?,
+            };
+
+            if let Some((read, header)) = read {
+                consumed = read;
+                // LOCAL connections are made by the proxy itself,
+                // in which case the actual peer is the one to keep
+                if header.command() == Command::Proxy {
+                    if let Some(peer_addr) = header.source() {
+                        let local_addr = ctx
+                            .get::<SocketInfo>()
+                            .and_then(|info| info.local_addr().copied());
+                        ctx.insert(SocketInfo::new(local_addr, peer_addr));
+                    }
+                }
+                ctx.insert(header);
+            }
+        } else {
+            tracing::trace!("untrusted peer: pass through stream without PROXY protocol header");
+        }
+
+        // put back the data that is read too much
+        let (r, w) = tokio::io::split(stream);
+        let mem: HeapReader = buffer[consumed..].into();
+        let r = ChainReader::new(mem, r);
+        let stream = tokio::io::join(r, w);
+
+        // read the rest of the data
+        match self.inner.serve(ctx, stream).await {
+            Ok(response) => Ok(response),
+            Err(error) => Err(error.into()),
+        }
+    }
+}
+
+impl<S, M> HaProxyService<S, M> {
+    /// Read the header from the stream, returning the number of bytes it consumed.
+    ///
+    /// All bytes read are kept in the buffer, such that they can be passed on,
+    /// and `None` is returned if the header is optional and not present.
+    async fn read_header<IO>(
+        &self,
+        stream: &mut IO,
+        buffer: &mut Vec<u8>,
+    ) -> Result<Option<(usize, ProxyProtocolHeader)>, Error>
+    where
+        IO: Stream + Unpin,
+    {
+        buffer.reserve(INITIAL_BUFFER_SIZE);
+
         let header = loop {
-            read += stream.read(&mut buffer[read..]).await?;
+            let n = stream.read_buf(buffer).await?;
+            if n == 0 {
+                if self.optional && signature(buffer) != Signature::Present {
+                    return Ok(None);
+                }
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "stream closed before receiving the PROXY protocol header",
+                )
+                .into());
+            }
 
-            let header = HeaderResult::parse(&buffer[..read]);
+            if self.optional {
+                match signature(buffer) {
+                    Signature::Absent => return Ok(None),
+                    Signature::Undecided => continue,
+                    Signature::Present => (),
+                }
+            }
+
+            let header = HeaderResult::parse(buffer);
             if header.is_complete() {
                 break header;
             }
 
-            tracing::debug!("Incomplete header. Read {} bytes so far.", read);
+            // v2 headers can have a large TLV section, up to 64KiB
+            if let HeaderResult::V2(Err(v2::ParseError::Partial(_, length))) = header {
+                let full_length = v2::MINIMUM_LENGTH + length;
+                buffer.reserve(full_length.saturating_sub(buffer.len()));
+            }
+
+            tracing::debug!("Incomplete header. Read {} bytes so far.", buffer.len());
         };
 
-        let consumed = match header {
+        let (consumed, header) = match header {
             HeaderResult::V1(Ok(header)) => {
-                match header.addresses {
-                    v1::Addresses::Tcp4(info) => {
-                        let peer_addr: SocketAddr = (info.source_address, info.source_port).into();
-                        let socket_info = SocketInfo::new(None, peer_addr);
-                        ctx.insert(socket_info);
-                    }
-                    v1::Addresses::Tcp6(info) => {
-                        let peer_addr: SocketAddr = (info.source_address, info.source_port).into();
-                        let socket_info = SocketInfo::new(None, peer_addr);
-                        ctx.insert(socket_info);
-                    }
-                    v1::Addresses::Unknown => (),
-                };
-                header.header.len()
+                (header.header.len(), ProxyProtocolHeader::from(&header))
             }
             HeaderResult::V2(Ok(header)) => {
-                match header.addresses {
-                    v2::Addresses::IPv4(info) => {
-                        let peer_addr: SocketAddr = (info.source_address, info.source_port).into();
-                        let socket_info = SocketInfo::new(None, peer_addr);
-                        ctx.insert(socket_info);
-                    }
-                    v2::Addresses::IPv6(info) => {
-                        let peer_addr: SocketAddr = (info.source_address, info.source_port).into();
-                        let socket_info = SocketInfo::new(None, peer_addr);
-                        ctx.insert(socket_info);
-                    }
-                    v2::Addresses::Unix(_) | v2::Addresses::Unspecified => (),
-                };
-                header.header.len()
+                (header.header.len(), ProxyProtocolHeader::try_from(&header)?)
             }
             HeaderResult::V1(Err(error)) => {
                 return Err(error.into());
@@ -116,16 +264,287 @@ where
             }
         };
 
-        // put back the data that is read too much
-        let (r, w) = tokio::io::split(stream);
-        let mem: HeapReader = buffer[consumed..read].into();
-        let r = ChainReader::new(mem, r);
-        let stream = tokio::io::join(r, w);
+        Ok(Some((consumed, header)))
+    }
+}
 
-        // read the rest of the data
-        match self.inner.serve(ctx, stream).await {
-            Ok(response) => Ok(response),
-            Err(error) => Err(error.into()),
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Signature {
+    Present,
+    Absent,
+    Undecided,
+}
+
+/// Detect whether the data starts with a v1 or v2 signature.
+fn signature(data: &[u8]) -> Signature {
+    for prefix in [v1::PROTOCOL_PREFIX.as_bytes(), v2::PROTOCOL_PREFIX] {
+        let n = data.len().min(prefix.len());
+        if data[..n] == prefix[..n] {
+            return if n == prefix.len() {
+                Signature::Present
+            } else {
+                Signature::Undecided
+            };
         }
     }
+    Signature::Absent
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::context::Extensions;
+    use crate::{
+        proxy::pp::protocol::v2::{self, Builder, IPv4, Protocol, Version},
+        service::service_fn,
+    };
+    use std::{convert::Infallible, net::SocketAddr};
+    use tokio::io::{AsyncWriteExt, DuplexStream, Join, ReadHalf, WriteHalf};
+    use tokio_test::io::Mock;
+
+    type TestStream = Join<ChainReader<HeapReader, ReadHalf<Mock>>, WriteHalf<Mock>>;
+
+    async fn serve(ctx: Context<()>, header: &[u8]) -> Context<()> {
+        serve_with(HaProxyLayer::new(), ctx, header, b"hello")
+            .await
+            .unwrap()
+    }
+
+    async fn serve_with<M>(
+        layer: HaProxyLayer<M>,
+        ctx: Context<()>,
+        header: &[u8],
+        expected: &[u8],
+    ) -> Result<Context<()>, Error>
+    where
+        M: Matcher<(), Mock> + Clone,
+    {
+        let mut builder = tokio_test::io::Builder::new();
+        if !header.is_empty() {
+            builder.read(header);
+        }
+        let stream = builder.read(b"hello").build();
+        let expected = expected.to_vec();
+        let service = layer.layer(service_fn(
+            move |ctx: Context<()>, mut stream: TestStream| {
+                let expected = expected.clone();
+                async move {
+                    let mut buf = Vec::new();
+                    stream.read_to_end(&mut buf).await.unwrap();
+                    assert_eq!(buf, expected);
+                    Ok::<_, Infallible>(ctx)
+                }
+            },
+        ));
+        service.serve(ctx, stream).await
+    }
+
+    #[derive(Debug, Clone)]
+    struct Untrusted;
+
+    impl Matcher<(), Mock> for Untrusted {
+        fn matches(&self, _: Option<&mut Extensions>, _: &Context<()>, _: &Mock) -> bool {
+            false
+        }
+    }
+
+    fn header(command: Command) -> Vec<u8> {
+        Builder::with_addresses(
+            Version::Two | command,
+            Protocol::Stream,
+            IPv4::new([10, 0, 0, 1], [10, 0, 0, 2], 1234, 443),
+        )
+        .write_tlv(v2::Type::Authority, b"example.com")
+        .unwrap()
+        .build()
+        .unwrap()
+    }
+
+    fn ctx() -> Context<()> {
+        let mut ctx = Context::default();
+        ctx.insert(SocketInfo::new(
+            Some("127.0.0.1:8080".parse().unwrap()),
+            "127.0.0.1:5000".parse().unwrap(),
+        ));
+        ctx
+    }
+
+    #[tokio::test]
+    async fn test_haproxy_service_proxy_command() {
+        let ctx = serve(ctx(), &header(Command::Proxy)).await;
+
+        let info = ctx.get::<SocketInfo>().unwrap();
+        assert_eq!(
+            info.peer_addr(),
+            &"10.0.0.1:1234".parse::<SocketAddr>().unwrap()
+        );
+        assert_eq!(
+            info.local_addr(),
+            Some(&"127.0.0.1:8080".parse::<SocketAddr>().unwrap())
+        );
+
+        let header = ctx.get::<ProxyProtocolHeader>().unwrap();
+        assert_eq!(header.destination(), Some("10.0.0.2:443".parse().unwrap()));
+        assert_eq!(header.authority(), Some("example.com"));
+    }
+
+    #[tokio::test]
+    async fn test_haproxy_service_local_command() {
+        let ctx = serve(ctx(), &header(Command::Local)).await;
+
+        let info = ctx.get::<SocketInfo>().unwrap();
+        assert_eq!(
+            info.peer_addr(),
+            &"127.0.0.1:5000".parse::<SocketAddr>().unwrap()
+        );
+        assert_eq!(
+            ctx.get::<ProxyProtocolHeader>().unwrap().command(),
+            Command::Local
+        );
+    }
+
+    #[tokio::test]
+    async fn test_haproxy_service_v1() {
+        let ctx = serve(ctx(), b"PROXY TCP4 10.0.0.1 10.0.0.2 1234 443\r\n").await;
+
+        let info = ctx.get::<SocketInfo>().unwrap();
+        assert_eq!(
+            info.peer_addr(),
+            &"10.0.0.1:1234".parse::<SocketAddr>().unwrap()
+        );
+        assert_eq!(
+            ctx.get::<ProxyProtocolHeader>().unwrap().destination(),
+            Some("10.0.0.2:443".parse().unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_haproxy_service_large_v2_header() {
+        let header = Builder::with_addresses(
+            Version::Two | Command::Proxy,
+            Protocol::Stream,
+            IPv4::new([10, 0, 0, 1], [10, 0, 0, 2], 1234, 443),
+        )
+        .write_tlv(v2::Type::NoOp, &[0; 2048])
+        .unwrap()
+        .write_tlv(v2::Type::Authority, b"example.com")
+        .unwrap()
+        .build()
+        .unwrap();
+        assert!(header.len() > INITIAL_BUFFER_SIZE);
+
+        let ctx = serve(ctx(), &header).await;
+        assert_eq!(
+            ctx.get::<ProxyProtocolHeader>().unwrap().authority(),
+            Some("example.com")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_haproxy_service_untrusted() {
+        let header = header(Command::Proxy);
+        let mut expected = header.clone();
+        expected.extend_from_slice(b"hello");
+
+        let ctx = serve_with(
+            HaProxyLayer::new().trusted(Untrusted),
+            ctx(),
+            &header,
+            &expected,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            ctx.get::<SocketInfo>().unwrap().peer_addr(),
+            &"127.0.0.1:5000".parse::<SocketAddr>().unwrap()
+        );
+        assert!(ctx.get::<ProxyProtocolHeader>().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_haproxy_service_optional() {
+        let layer = HaProxyLayer::new().optional(true);
+
+        let out = serve_with(layer.clone(), ctx(), b"", b"hello")
+            .await
+            .unwrap();
+        assert!(out.get::<ProxyProtocolHeader>().is_none());
+
+        // a prefix of the signature is not enough to decide
+        let out = serve_with(layer.clone(), ctx(), b"PRO", b"PROhello")
+            .await
+            .unwrap();
+        assert!(out.get::<ProxyProtocolHeader>().is_none());
+
+        let out = serve_with(layer, ctx(), &header(Command::Proxy), b"hello")
+            .await
+            .unwrap();
+        assert!(out.get::<ProxyProtocolHeader>().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_haproxy_service_required() {
+        let err = serve_with(HaProxyLayer::new(), ctx(), b"", b"hello")
+            .await
+            .unwrap_err();
+        assert!(err.downcast_ref::<v1::BinaryParseError>().is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_haproxy_service_header_timeout() {
+        let (mut client, stream) = tokio::io::duplex(64);
+        client.write_all(b"PROXY TCP4 ").await.unwrap();
+        let service = HaProxyLayer::new()
+            .header_timeout(Duration::from_secs(5))
+            .layer(service_fn(
+                |_, _: Join<ChainReader<HeapReader, ReadHalf<DuplexStream>>, _>| async {
+                    Ok::<_, Infallible>(())
+                },
+            ));
+
+        let err = service.serve(ctx(), stream).await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<io::Error>().unwrap().kind(),
+            io::ErrorKind::TimedOut
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_haproxy_service_optional_header_timeout() {
+        let service = HaProxyLayer::new()
+            .optional(true)
+            .header_timeout(Duration::from_secs(5))
+            .layer(service_fn(
+                |ctx: Context<()>,
+                 mut stream: Join<ChainReader<HeapReader, ReadHalf<DuplexStream>>, _>| async move {
+                    assert!(ctx.get::<ProxyProtocolHeader>().is_none());
+                    // the server speaks first
+                    stream.write_all(b"220 ready\r\n").await.unwrap();
+                    let mut buf = Vec::new();
+                    stream.read_to_end(&mut buf).await.unwrap();
+                    Ok::<_, Infallible>(buf)
+                },
+            ));
+
+        // a client which sends nothing until the server spoke
+        let (mut client, stream) = tokio::io::duplex(64);
+        let svc = service.clone();
+        let server = tokio::spawn(async move { svc.serve(ctx(), stream).await });
+        let mut greeting = [0; 11];
+        client.read_exact(&mut greeting).await.unwrap();
+        assert_eq!(&greeting, b"220 ready\r\n");
+        client.write_all(b"HELO").await.unwrap();
+        drop(client);
+        assert_eq!(server.await.unwrap().unwrap(), b"HELO");
+
+        // a partial signature is kept as well
+        let (mut client, stream) = tokio::io::duplex(64);
+        client.write_all(b"PRO").await.unwrap();
+        let server = tokio::spawn(async move { service.serve(ctx(), stream).await });
+        tokio::time::sleep(Duration::from_secs(6)).await;
+        client.write_all(b"TOCOL").await.unwrap();
+        drop(client);
+        assert_eq!(server.await.unwrap().unwrap(), b"PROTOCOL");
+    }
 }
@@ -5,3 +5,7 @@
 mod layer;
 #[doc(inline)]
 pub use layer::{HaProxyLayer, HaProxyService};
+
+mod header;
+#[doc(inline)]
+pub use header::{ProxyProtocolHeader, ProxyProtocolVersion, SslInfo};
@@ -0,0 +1,94 @@
+use super::{QuotaStatus, QuotaStore, QuotaUsage, Quotas};
+use crate::{
+    http::{headers::authorization::Basic, layer::proxy_auth::ProxyAuthoritySync},
+    service::context::Extensions,
+};
+
+/// A [`ProxyAuthoritySync`] which wraps another [`ProxyAuthoritySync`],
+/// rejecting users which exceeded the hard limits of their [`Quotas`],
+/// and counting a request for all other authorized users.
+///
+/// The user is identified by the username of the [`Basic`] credentials
+/// inserted by the inner authority. Users which cannot be
+/// identified are authorized as is.
+///
+/// See the [module docs](super) for an example.
+#[derive(Debug, Clone)]
+pub struct QuotaAuthority<A, S> {
+    inner: A,
+    quotas: Quotas<S>,
+}
+
+impl<A, S> QuotaAuthority<A, S> {
+    /// Create a new [`QuotaAuthority`], wrapping the given [`ProxyAuthoritySync`]
+    /// to enforce the given [`Quotas`].
+    pub fn new(inner: A, quotas: Quotas<S>) -> Self {
+        Self { inner, quotas }
+    }
+}
+
+impl<A, C, L, S> ProxyAuthoritySync<C, L> for QuotaAuthority<A, S>
+where
+    A: ProxyAuthoritySync<C, L>,
+    S: QuotaStore,
+{
+    fn authorized(&self, ext: &mut Extensions, credentials: &C) -> bool {
+        let mut inner_ext = Extensions::new();
+        if !self.inner.authorized(&mut inner_ext, credentials) {
+            return false;
+        }
+        if let Some(user) = inner_ext
+            .get::<Basic>()
+            .map(|basic| basic.username().to_owned())
+        {
+            if self.quotas.status(&user) == QuotaStatus::HardExceeded {
+                tracing::debug!(user, "proxy user rejected: quota exceeded");
+                return false;
+            }
+            self.quotas.count(&user, QuotaUsage::new(0, 1));
+        }
+        ext.extend(inner_ext);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        http::{layer::proxy_auth::ProxyAuthLayer, Body, Request, Response, StatusCode},
+        proxy::quota::{MemoryQuotaStore, QuotaLimits},
+        service::{service_fn, Context, Layer, Service},
+    };
+    use std::convert::Infallible;
+
+    #[tokio::test]
+    async fn test_quota_authority_max_requests() {
+        let quotas = Quotas::load(
+            MemoryQuotaStore::new(),
+            QuotaLimits::unlimited().max_requests(2),
+        )
+        .await
+        .unwrap();
+        let service =
+            ProxyAuthLayer::basic(QuotaAuthority::new(("john", "secret"), quotas.clone())).layer(
+                service_fn(|_req: Request| async move {
+                    Ok::<_, Infallible>(Response::new(Body::empty()))
+                }),
+            );
+
+        for status in [
+            StatusCode::OK,
+            StatusCode::OK,
+            StatusCode::PROXY_AUTHENTICATION_REQUIRED,
+        ] {
+            let req = Request::builder()
+                .header("proxy-authorization", "Basic am9objpzZWNyZXQ=")
+                .body(Body::empty())
+                .unwrap();
+            let res = service.serve(Context::default(), req).await.unwrap();
+            assert_eq!(res.status(), status);
+        }
+        assert_eq!(quotas.usage("john"), QuotaUsage::new(0, 2));
+    }
+}
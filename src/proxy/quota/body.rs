@@ -0,0 +1,66 @@
+use super::{layer::ResponseQuota, QuotaExceeded, QuotaStatus};
+use crate::error::BoxError;
+use bytes::Bytes;
+use http_body::{Body, Frame, SizeHint};
+use pin_project_lite::pin_project;
+use std::{
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+pin_project! {
+    /// A response body which counts the bytes transferred while it is polled,
+    /// and which fails once the user exceeds the hard limits of its quota.
+    pub(super) struct QuotaBody<B> {
+        #[pin]
+        inner: B,
+        quota: ResponseQuota,
+        exceeded: bool,
+    }
+}
+
+impl<B> QuotaBody<B> {
+    pub(super) fn new(inner: B, quota: ResponseQuota) -> Self {
+        Self {
+            inner,
+            quota,
+            exceeded: false,
+        }
+    }
+}
+
+impl<B> Body for QuotaBody<B>
+where
+    B: Body<Data = Bytes>,
+    B::Error: Into<BoxError>,
+{
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        if *this.exceeded {
+            return Poll::Ready(None);
+        }
+
+        let frame = ready!(this.inner.poll_frame(cx));
+        if this.quota.count() == QuotaStatus::HardExceeded && frame.is_some() {
+            *this.exceeded = true;
+            let user = this.quota.user().to_owned();
+            tracing::debug!(user, "cut off response body: quota exceeded");
+            return Poll::Ready(Some(Err(QuotaExceeded { user }.into())));
+        }
+        Poll::Ready(frame.map(|frame| frame.map_err(Into::into)))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.exceeded || self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
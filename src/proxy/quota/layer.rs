@@ -0,0 +1,415 @@
+use super::{body::QuotaBody, QuotaExceeded, QuotaStatus, QuotaStore, QuotaUsage, Quotas};
+use crate::{
+    error::BoxError,
+    http::{Body, Response},
+    service::{
+        layer::limit::policy::{KeyExtractor, ProxyUsernameKey},
+        Context, Layer, Service,
+    },
+    stream::layer::BytesRWTrackerHandle,
+};
+use std::{fmt, sync::Arc, time::Duration};
+
+/// The default interval at which the bytes of a served request are counted.
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A [`Service`] which counts the bytes read and written while serving a request
+/// against the [`Quotas`] of its user, created using the [`QuotaLayer`].
+///
+/// See [`QuotaLayer`] for more details.
+#[derive(Debug, Clone)]
+pub struct QuotaService<S, Q, K = ProxyUsernameKey> {
+    inner: S,
+    quotas: Quotas<Q>,
+    key: K,
+    interval: Duration,
+}
+
+impl<S, Q, K> QuotaService<S, Q, K> {
+    define_inner_service_accessors!();
+}
+
+impl<S, Q, K, State, Request> Service<State, Request> for QuotaService<S, Q, K>
+where
+    S: Service<State, Request>,
+    S::Response: QuotaResponse,
+    S::Error: Into<BoxError>,
+    Q: QuotaStore,
+    K: KeyExtractor<State, Request, Key = String>,
+    State: Send + Sync + 'static,
+    Request: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+
+    async fn serve(
+        &self,
+        ctx: Context<State>,
+        req: Request,
+    ) -> Result<Self::Response, Self::Error> {
+        let Some(user) = self.key.extract(&ctx, &req) else {
+            return self.inner.serve(ctx, req).await.map_err(Into::into);
+        };
+        if self.quotas.status(&user) == QuotaStatus::HardExceeded {
+            return Err(QuotaExceeded { user }.into());
+        }
+        let Some(tracker) = ctx.get::<BytesRWTrackerHandle>().cloned() else {
+            return self.inner.serve(ctx, req).await.map_err(Into::into);
+        };
+
+        // count the bytes transferred since the previous request over the same connection,
+        // such as the head of this request and the remainder of the previous response
+        let status = self.record(&user, &tracker).await;
+        if status == QuotaStatus::HardExceeded {
+            return Err(QuotaExceeded { user }.into());
+        }
+
+        let serve = self.inner.serve(ctx, req);
+        tokio::pin!(serve);
+        let mut interval = tokio::time::interval(self.interval);
+        interval.tick().await;
+
+        loop {
+            tokio::select! {
+                result = &mut serve => {
+                    self.record(&user, &tracker).await;
+                    let response = result.map_err(Into::into)?;
+                    let quotas = self.quotas.clone();
+                    return Ok(response.count_quota(ResponseQuota {
+                        user,
+                        tracker,
+                        count: Arc::new(move |user, usage| quotas.count(user, usage)),
+                    }));
+                }
+                _ = interval.tick() => {
+                    if self.record(&user, &tracker).await == QuotaStatus::HardExceeded {
+                        tracing::debug!(user, "cut off request: quota exceeded");
+                        return Err(QuotaExceeded { user }.into());
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<S, Q: QuotaStore, K> QuotaService<S, Q, K> {
+    async fn record(&self, user: &str, tracker: &BytesRWTrackerHandle) -> QuotaStatus {
+        self.quotas
+            .record(user, QuotaUsage::new(tracker.take(), 0))
+            .await
+    }
+}
+
+/// A response served by the [`QuotaService`], of which bytes can still be
+/// transferred after it is returned, such as the body of an HTTP [`Response`].
+pub trait QuotaResponse: Sized {
+    /// Count the bytes transferred for the response using the given [`ResponseQuota`],
+    /// for as long as the response is being transferred.
+    fn count_quota(self, quota: ResponseQuota) -> Self;
+}
+
+impl QuotaResponse for () {
+    fn count_quota(self, _quota: ResponseQuota) -> Self {}
+}
+
+impl QuotaResponse for Response {
+    fn count_quota(self, quota: ResponseQuota) -> Self {
+        self.map(|body| Body::new(QuotaBody::new(body, quota)))
+    }
+}
+
+/// Counts the bytes transferred for a response after it is returned
+/// by the inner service of the [`QuotaService`], see [`QuotaResponse`].
+///
+/// The bytes are counted once more when it is dropped, and the bytes transferred
+/// afterwards are counted once the connection is closed, unless they are counted
+/// by a next request over the same connection first.
+pub struct ResponseQuota {
+    user: String,
+    tracker: BytesRWTrackerHandle,
+    count: Arc<dyn Fn(&str, QuotaUsage) -> QuotaStatus + Send + Sync>,
+}
+
+impl ResponseQuota {
+    /// The user whose quota is counted against.
+    pub fn user(&self) -> &str {
+        &self.user
+    }
+
+    /// Count the bytes transferred since the previous count,
+    /// returning the [`QuotaStatus`] of the user afterwards.
+    pub fn count(&self) -> QuotaStatus {
+        (self.count)(&self.user, QuotaUsage::new(self.tracker.take(), 0))
+    }
+}
+
+impl fmt::Debug for ResponseQuota {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResponseQuota")
+            .field("user", &self.user)
+            .field("tracker", &self.tracker)
+            .finish()
+    }
+}
+
+impl Drop for ResponseQuota {
+    fn drop(&mut self) {
+        self.count();
+        // the remainder of the response might still be buffered to be written,
+        // which is counted with the next request, or once the connection is closed
+        let count = self.count.clone();
+        let user = std::mem::take(&mut self.user);
+        self.tracker.on_close(move |bytes| {
+            count(&user, QuotaUsage::new(bytes, 0));
+        });
+    }
+}
+
+/// A [`Layer`] which counts the bytes read and written while serving a request
+/// against the [`Quotas`] of its user.
+///
+/// The bytes are those tracked by the [`BytesRWTrackerHandle`] in the [`Context`],
+/// as inserted by the [`BytesTrackerLayer`], and are counted at a regular interval.
+/// Once the user exceeds the hard limits of its quota, the request is cut off
+/// by failing with a [`QuotaExceeded`] error, dropping the inner service's future.
+/// Requests of a user which already exceeded its quota fail immediately.
+///
+/// The bytes are tracked per connection, and each byte is counted exactly once.
+/// Bytes transferred in between the requests served over the same connection,
+/// such as the head of a request which is read before it is served, are counted
+/// with the next request. The body of an HTTP [`Response`] is counted while it is
+/// transferred, and cut off once the user exceeds its hard limits, see [`QuotaResponse`].
+/// Bytes of requests served concurrently over the same connection are counted
+/// for whichever request counts them first.
+///
+/// By default the user is identified by the [`ProxyUsernameKey`].
+/// Requests of which no user can be identified are served as is.
+///
+/// See the [module docs](super) for an example.
+///
+/// [`BytesTrackerLayer`]: crate::stream::layer::BytesTrackerLayer
+#[derive(Debug, Clone)]
+pub struct QuotaLayer<S, K = ProxyUsernameKey> {
+    quotas: Quotas<S>,
+    key: K,
+    interval: Duration,
+}
+
+impl<S> QuotaLayer<S> {
+    /// Create a new [`QuotaLayer`] enforcing the given [`Quotas`].
+    pub fn new(quotas: Quotas<S>) -> Self {
+        Self {
+            quotas,
+            key: ProxyUsernameKey,
+            interval: DEFAULT_INTERVAL,
+        }
+    }
+}
+
+impl<S, K> QuotaLayer<S, K> {
+    /// Identify the user of a request using the given [`KeyExtractor`].
+    pub fn key<K2>(self, key: K2) -> QuotaLayer<S, K2> {
+        QuotaLayer {
+            quotas: self.quotas,
+            key,
+            interval: self.interval,
+        }
+    }
+
+    /// Set the interval at which the bytes of a served request are counted,
+    /// by default every second.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+}
+
+impl<S, Q, K: Clone> Layer<S> for QuotaLayer<Q, K> {
+    type Service = QuotaService<S, Q, K>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        QuotaService {
+            inner,
+            quotas: self.quotas.clone(),
+            key: self.key.clone(),
+            interval: self.interval,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        http::{
+            dep::{
+                http_body::Frame,
+                http_body_util::{BodyExt, StreamBody},
+            },
+            headers::Authorization,
+            server::HttpServer,
+            Request, StatusCode,
+        },
+        proxy::quota::{MemoryQuotaStore, QuotaLimits},
+        service::service_fn,
+        stream::{
+            layer::{BytesRWTracker, BytesTrackerLayer},
+            Stream,
+        },
+    };
+    use bytes::Bytes;
+    use hyper_util::rt::TokioIo;
+    use std::convert::Infallible;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+    #[tokio::test(start_paused = true)]
+    async fn test_quota_layer_cut_off() {
+        let quotas = Quotas::load(
+            MemoryQuotaStore::new(),
+            QuotaLimits::unlimited().max_bytes(100),
+        )
+        .await
+        .unwrap();
+        async fn echo(mut stream: impl Stream + Unpin) -> Result<(), Infallible> {
+            let mut buf = [0; 16];
+            loop {
+                let n = stream.read(&mut buf).await.unwrap();
+                if n == 0 {
+                    return Ok(());
+                }
+                stream.write_all(&buf[..n]).await.unwrap();
+            }
+        }
+        let service =
+            BytesTrackerLayer::new().layer(QuotaLayer::new(quotas.clone()).layer(service_fn(echo)));
+
+        let mut ctx = Context::default();
+        ctx.insert(Authorization::basic("john", "secret").0);
+
+        let (mut client, server): (DuplexStream, DuplexStream) = tokio::io::duplex(1024);
+        let client = async move {
+            let mut buf = [0; 10];
+            for _ in 0..100 {
+                if client.write_all(&buf).await.is_err()
+                    || client.read_exact(&mut buf).await.is_err()
+                {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        };
+        let (result, ()) = tokio::join!(service.serve(ctx.clone(), server), client);
+        let err = result.unwrap_err();
+        assert_eq!(err.downcast_ref::<QuotaExceeded>().unwrap().user(), "john");
+        assert!(quotas.usage("john").bytes >= 100);
+
+        // once exceeded, the user is rejected immediately
+        let (_client, server) = tokio::io::duplex(1024);
+        assert!(service.serve(ctx, server).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_quota_layer_http_keep_alive() {
+        let quotas = Quotas::load(MemoryQuotaStore::new(), QuotaLimits::unlimited())
+            .await
+            .unwrap();
+        let service = QuotaLayer::new(quotas.clone()).layer(service_fn(|_req: Request| async {
+            Ok::<_, Infallible>(Response::new(Body::from(vec![b'x'; 1000])))
+        }));
+        let service = Arc::new(service);
+        let service = BytesTrackerLayer::new().layer(HttpServer::http1().service(service_fn(
+            move |ctx: Context<()>, req: Request| {
+                let service = service.clone();
+                async move {
+                    Ok::<_, Infallible>(service.serve(ctx, req).await.unwrap_or_else(|_| {
+                        Response::builder()
+                            .status(StatusCode::TOO_MANY_REQUESTS)
+                            .body(Body::empty())
+                            .unwrap()
+                    }))
+                }
+            },
+        )));
+
+        let mut ctx = Context::default();
+        ctx.insert(Authorization::basic("john", "secret").0);
+
+        let (client, server) = tokio::io::duplex(64);
+        let client = BytesRWTracker::new(client);
+        let client_bytes = client.handle();
+        let client = async move {
+            let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(client))
+                .await
+                .unwrap();
+            let conn = tokio::spawn(conn);
+            for _ in 0..2 {
+                let req = Request::builder()
+                    .uri("/")
+                    .header("host", "example.com")
+                    .body(Body::empty())
+                    .unwrap();
+                let res = sender.send_request(req).await.unwrap();
+                let body = res.into_body().collect().await.unwrap().to_bytes();
+                assert_eq!(body.len(), 1000);
+            }
+            drop(sender);
+            conn.await.unwrap().unwrap();
+        };
+        let (result, ()) = tokio::join!(service.serve(ctx, server), client);
+        result.unwrap();
+
+        // all bytes of the connection are counted, including the heads of
+        // the requests and the response bodies, transferred after being served
+        let total = (client_bytes.read() + client_bytes.written()) as u64;
+        assert!(total > 2000);
+        assert_eq!(quotas.usage("john").bytes, total);
+    }
+
+    #[tokio::test]
+    async fn test_quota_layer_http_body_cut_off() {
+        let quotas = Quotas::load(
+            MemoryQuotaStore::new(),
+            QuotaLimits::unlimited().max_bytes(1000),
+        )
+        .await
+        .unwrap();
+        let service = QuotaLayer::new(quotas.clone()).layer(service_fn(|_req: Request| async {
+            let chunks =
+                (0..100).map(|_| Ok::<_, Infallible>(Frame::data(Bytes::from(vec![b'x'; 100]))));
+            Ok::<_, Infallible>(Response::new(Body::new(StreamBody::new(
+                futures::stream::iter(chunks),
+            ))))
+        }));
+
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        let server = BytesRWTracker::new(server);
+        let mut ctx = Context::default();
+        ctx.insert(Authorization::basic("john", "secret").0);
+        ctx.insert(server.handle());
+
+        let res = service
+            .serve(ctx, Request::builder().body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        // emulate the transfer of the body over the tracked connection
+        let mut body = res.into_body();
+        let mut server = server;
+        let mut transferred = 0;
+        while let Some(frame) = body.frame().await {
+            match frame {
+                Ok(frame) => {
+                    let data = frame.into_data().unwrap();
+                    server.write_all(&data).await.unwrap();
+                    transferred += data.len();
+                }
+                Err(err) => {
+                    assert!(err.downcast_ref::<QuotaExceeded>().is_some());
+                    break;
+                }
+            }
+        }
+        assert!((1000..10_000).contains(&transferred));
+        let mut buf = vec![0; transferred];
+        client.read_exact(&mut buf).await.unwrap();
+    }
+}
@@ -0,0 +1,413 @@
+//! Per user bandwidth and request quotas, e.g. for proxy customers buying monthly traffic.
+//!
+//! The [`Quotas`] aggregate the [`QuotaUsage`] of each authenticated proxy user,
+//! persisting it in a [`QuotaStore`] such as the [`MemoryQuotaStore`] or [`FileQuotaStore`].
+//! The usage is checked against the [`QuotaLimits`] of a user in two places:
+//!
+//! - the [`QuotaAuthority`] wraps the [`ProxyAuthoritySync`] of a [`ProxyAuthLayer`],
+//!   counting each authorized request and rejecting users which exceeded their hard limits;
+//! - the [`QuotaLayer`] counts the bytes read and written, as tracked by the [`BytesTrackerLayer`],
+//!   cutting off the served request (e.g. a tunnel) once a user exceeds the hard limits mid-flight.
+//!
+//! Exceeding a soft limit is only logged, and can be inspected using [`Quotas::status`].
+//!
+//! # Example
+//!
+//! ```
+//! use rama::proxy::quota::{MemoryQuotaStore, QuotaAuthority, QuotaLayer, QuotaLimits, Quotas};
+//! use rama::http::layer::proxy_auth::ProxyAuthLayer;
+//! use rama::http::{Body, Request, Response};
+//! use rama::service::{Context, Service, ServiceBuilder, service_fn};
+//! use std::convert::Infallible;
+//!
+//! # #[tokio::main]
+//! # async fn main() {
+//! let quotas = Quotas::load(
+//!     MemoryQuotaStore::new(),
+//!     QuotaLimits::unlimited()
+//!         .max_bytes(10 * 1024 * 1024 * 1024)
+//!         .soft_max_bytes(8 * 1024 * 1024 * 1024)
+//!         .max_requests(1_000_000),
+//! )
+//! .await
+//! .unwrap();
+//!
+//! let service = ServiceBuilder::new()
+//!     .layer(ProxyAuthLayer::basic(QuotaAuthority::new(("john", "secret"), quotas.clone())))
+//!     .layer(QuotaLayer::new(quotas.clone()))
+//!     .service_fn(|_req: Request| async move {
+//!         Ok::<_, Infallible>(Response::new(Body::empty()))
+//!     });
+//!
+//! let req = Request::builder()
+//!     .header("proxy-authorization", "Basic am9objpzZWNyZXQ=")
+//!     .body(Body::empty())
+//!     .unwrap();
+//! service.serve(Context::default(), req).await.unwrap();
+//! assert_eq!(quotas.usage("john").requests, 1);
+//! # }
+//! ```
+//!
+//! [`ProxyAuthoritySync`]: crate::http::layer::proxy_auth::ProxyAuthoritySync
+//! [`ProxyAuthLayer`]: crate::http::layer::proxy_auth::ProxyAuthLayer
+//! [`BytesTrackerLayer`]: crate::stream::layer::BytesTrackerLayer
+
+use std::{
+    collections::HashMap,
+    fmt,
+    ops::AddAssign,
+    sync::{Arc, Mutex},
+};
+
+mod store;
+#[doc(inline)]
+pub use store::{FileQuotaStore, MemoryQuotaStore, QuotaStore};
+
+mod auth;
+#[doc(inline)]
+pub use auth::QuotaAuthority;
+
+mod layer;
+#[doc(inline)]
+pub use layer::{QuotaLayer, QuotaResponse, QuotaService, ResponseQuota};
+
+mod body;
+
+/// The bytes and requests used by a user.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QuotaUsage {
+    /// The number of bytes read and written.
+    pub bytes: u64,
+    /// The number of requests.
+    pub requests: u64,
+}
+
+impl QuotaUsage {
+    /// Create a new [`QuotaUsage`] of the given bytes and requests.
+    pub fn new(bytes: u64, requests: u64) -> Self {
+        Self { bytes, requests }
+    }
+}
+
+impl AddAssign for QuotaUsage {
+    fn add_assign(&mut self, rhs: Self) {
+        self.bytes = self.bytes.saturating_add(rhs.bytes);
+        self.requests = self.requests.saturating_add(rhs.requests);
+    }
+}
+
+/// The limits of the [`QuotaUsage`] of a user.
+///
+/// Hard limits are enforced, while exceeding soft limits is only reported.
+/// By default no limits apply.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QuotaLimits {
+    max_bytes: Option<u64>,
+    soft_max_bytes: Option<u64>,
+    max_requests: Option<u64>,
+    soft_max_requests: Option<u64>,
+}
+
+impl QuotaLimits {
+    /// Create new [`QuotaLimits`], without any limit.
+    pub fn unlimited() -> Self {
+        Self::default()
+    }
+
+    /// Set the hard limit of the number of bytes read and written.
+    pub fn max_bytes(mut self, bytes: u64) -> Self {
+        self.max_bytes = Some(bytes);
+        self
+    }
+
+    /// Set the soft limit of the number of bytes read and written.
+    pub fn soft_max_bytes(mut self, bytes: u64) -> Self {
+        self.soft_max_bytes = Some(bytes);
+        self
+    }
+
+    /// Set the hard limit of the number of requests.
+    pub fn max_requests(mut self, requests: u64) -> Self {
+        self.max_requests = Some(requests);
+        self
+    }
+
+    /// Set the soft limit of the number of requests.
+    pub fn soft_max_requests(mut self, requests: u64) -> Self {
+        self.soft_max_requests = Some(requests);
+        self
+    }
+
+    /// Get the [`QuotaStatus`] of the given [`QuotaUsage`] within these limits.
+    pub fn status(&self, usage: QuotaUsage) -> QuotaStatus {
+        let reached = |max: Option<u64>, used: u64| max.is_some_and(|max| used >= max);
+        if reached(self.max_bytes, usage.bytes) || reached(self.max_requests, usage.requests) {
+            QuotaStatus::HardExceeded
+        } else if reached(self.soft_max_bytes, usage.bytes)
+            || reached(self.soft_max_requests, usage.requests)
+        {
+            QuotaStatus::SoftExceeded
+        } else {
+            QuotaStatus::Within
+        }
+    }
+}
+
+/// The status of the [`QuotaUsage`] of a user within its [`QuotaLimits`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaStatus {
+    /// No limit is reached.
+    Within,
+    /// A soft limit is reached, but no hard limit.
+    SoftExceeded,
+    /// A hard limit is reached.
+    HardExceeded,
+}
+
+/// The error returned by the [`QuotaService`] in case
+/// the user exceeded the hard limits of its quota.
+#[derive(Debug)]
+pub struct QuotaExceeded {
+    user: String,
+}
+
+impl QuotaExceeded {
+    /// Get the user which exceeded its quota.
+    pub fn user(&self) -> &str {
+        &self.user
+    }
+}
+
+impl fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "quota exceeded for user '{}'", self.user)
+    }
+}
+
+impl std::error::Error for QuotaExceeded {}
+
+/// The quotas of all users, aggregating their [`QuotaUsage`] in memory
+/// and persisting it in a [`QuotaStore`].
+///
+/// Usage which is only counted, rather than recorded, is persisted
+/// once the user records usage again, or on [`Quotas::flush`],
+/// which is therefore best called periodically and on shutdown.
+///
+/// Cloning the [`Quotas`] shares the usage and limits.
+/// See the [module docs](self) for an example.
+pub struct Quotas<S = MemoryQuotaStore> {
+    inner: Arc<QuotasInner<S>>,
+}
+
+struct QuotasInner<S> {
+    store: S,
+    default_limits: QuotaLimits,
+    limits: Mutex<HashMap<String, QuotaLimits>>,
+    usage: Mutex<HashMap<String, QuotaUsage>>,
+    pending: Mutex<HashMap<String, QuotaUsage>>,
+}
+
+impl<S> Clone for Quotas<S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<S: fmt::Debug> fmt::Debug for Quotas<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Quotas")
+            .field("store", &self.inner.store)
+            .field("default_limits", &self.inner.default_limits)
+            .finish()
+    }
+}
+
+impl<S: QuotaStore> Quotas<S> {
+    /// Create new [`Quotas`], loading the usage of all users from the given [`QuotaStore`],
+    /// applying the given [`QuotaLimits`] to all users without limits of their own.
+    pub async fn load(store: S, default_limits: QuotaLimits) -> Result<Self, S::Error> {
+        let usage = store.load().await?;
+        Ok(Self {
+            inner: Arc::new(QuotasInner {
+                store,
+                default_limits,
+                limits: Mutex::new(HashMap::new()),
+                usage: Mutex::new(usage),
+                pending: Mutex::new(HashMap::new()),
+            }),
+        })
+    }
+
+    /// Get the [`QuotaStore`] in which the usage is persisted.
+    pub fn store(&self) -> &S {
+        &self.inner.store
+    }
+
+    /// Set the [`QuotaLimits`] of the given user, overwriting the default limits.
+    pub fn set_limits(&self, user: impl Into<String>, limits: QuotaLimits) {
+        self.inner
+            .limits
+            .lock()
+            .unwrap()
+            .insert(user.into(), limits);
+    }
+
+    /// Get the [`QuotaLimits`] which apply to the given user.
+    pub fn limits(&self, user: &str) -> QuotaLimits {
+        self.inner
+            .limits
+            .lock()
+            .unwrap()
+            .get(user)
+            .unwrap_or(&self.inner.default_limits)
+            .clone()
+    }
+
+    /// Get the [`QuotaUsage`] of the given user.
+    pub fn usage(&self, user: &str) -> QuotaUsage {
+        self.inner
+            .usage
+            .lock()
+            .unwrap()
+            .get(user)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Get the [`QuotaStatus`] of the given user.
+    pub fn status(&self, user: &str) -> QuotaStatus {
+        self.limits(user).status(self.usage(user))
+    }
+
+    /// Count the given [`QuotaUsage`] on top of the usage of the given user,
+    /// returning the [`QuotaStatus`] of the user afterwards.
+    ///
+    /// The usage is only persisted on the next [`Quotas::record`]
+    /// for the same user, or [`Quotas::flush`].
+    pub fn count(&self, user: &str, usage: QuotaUsage) -> QuotaStatus {
+        if usage == QuotaUsage::default() {
+            return self.status(user);
+        }
+        let total = {
+            let mut all = self.inner.usage.lock().unwrap();
+            let total = all.entry(user.to_owned()).or_default();
+            *total += usage;
+            *total
+        };
+        *self
+            .inner
+            .pending
+            .lock()
+            .unwrap()
+            .entry(user.to_owned())
+            .or_default() += usage;
+
+        let status = self.limits(user).status(total);
+        if status == QuotaStatus::SoftExceeded {
+            tracing::debug!(
+                user,
+                bytes = total.bytes,
+                requests = total.requests,
+                "soft quota exceeded"
+            );
+        }
+        status
+    }
+
+    /// Record the given [`QuotaUsage`] on top of the usage of the given user,
+    /// persisting it together with the usage counted before,
+    /// and returning the [`QuotaStatus`] of the user afterwards.
+    ///
+    /// The usage is counted even if it fails to be persisted,
+    /// in which case the error is logged and persisting is retried later on.
+    pub async fn record(&self, user: &str, usage: QuotaUsage) -> QuotaStatus {
+        let status = self.count(user, usage);
+        if let Err(err) = self.persist(user).await {
+            tracing::error!(error = %err, user, "failed to persist quota usage");
+        }
+        status
+    }
+
+    /// Persist the usage counted, but not yet persisted, of all users.
+    pub async fn flush(&self) -> Result<(), S::Error> {
+        let users: Vec<_> = self.inner.pending.lock().unwrap().keys().cloned().collect();
+        for user in users {
+            self.persist(&user).await?;
+        }
+        Ok(())
+    }
+
+    async fn persist(&self, user: &str) -> Result<(), S::Error> {
+        let Some(usage) = self.inner.pending.lock().unwrap().remove(user) else {
+            return Ok(());
+        };
+        if let Err(err) = self.inner.store.record(user, usage).await {
+            *self
+                .inner
+                .pending
+                .lock()
+                .unwrap()
+                .entry(user.to_owned())
+                .or_default() += usage;
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Reset the [`QuotaUsage`] of the given user, e.g. at the start of a new billing period.
+    pub async fn reset(&self, user: &str) -> Result<(), S::Error> {
+        self.inner.store.reset(user).await?;
+        self.inner.usage.lock().unwrap().remove(user);
+        self.inner.pending.lock().unwrap().remove(user);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_quotas() {
+        let store = MemoryQuotaStore::new();
+        store.record("john", QuotaUsage::new(90, 1)).await.unwrap();
+
+        let quotas = Quotas::load(
+            store.clone(),
+            QuotaLimits::unlimited().soft_max_bytes(80).max_bytes(100),
+        )
+        .await
+        .unwrap();
+        quotas.set_limits("jane", QuotaLimits::unlimited().max_requests(1));
+
+        assert_eq!(quotas.status("john"), QuotaStatus::SoftExceeded);
+        assert_eq!(
+            quotas.record("john", QuotaUsage::new(10, 0)).await,
+            QuotaStatus::HardExceeded
+        );
+        assert_eq!(store.load().await.unwrap()["john"], QuotaUsage::new(100, 1));
+
+        assert_eq!(quotas.status("jane"), QuotaStatus::Within);
+        assert_eq!(
+            quotas.record("jane", QuotaUsage::new(1000, 1)).await,
+            QuotaStatus::HardExceeded
+        );
+        quotas.count("jane", QuotaUsage::new(0, 1));
+        assert_eq!(
+            store.load().await.unwrap()["jane"],
+            QuotaUsage::new(1000, 1)
+        );
+        quotas.flush().await.unwrap();
+        assert_eq!(
+            store.load().await.unwrap()["jane"],
+            QuotaUsage::new(1000, 2)
+        );
+
+        quotas.reset("john").await.unwrap();
+        assert_eq!(quotas.usage("john"), QuotaUsage::default());
+        assert_eq!(quotas.status("john"), QuotaStatus::Within);
+        assert!(!store.load().await.unwrap().contains_key("john"));
+    }
+}
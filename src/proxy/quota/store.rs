@@ -0,0 +1,290 @@
+use super::QuotaUsage;
+use crate::fs::spawn_write_atomic;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    future::Future,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+};
+
+/// A store in which the [`QuotaUsage`] of each user is persisted,
+/// such that it survives the connections, and restarts, of the proxy.
+pub trait QuotaStore: Send + Sync + 'static {
+    /// The error returned in case the store fails.
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    /// Load the [`QuotaUsage`] of all users.
+    fn load(&self)
+        -> impl Future<Output = Result<HashMap<String, QuotaUsage>, Self::Error>> + Send;
+
+    /// Record the given [`QuotaUsage`] on top of the usage of the given user.
+    fn record(
+        &self,
+        user: &str,
+        usage: QuotaUsage,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send;
+
+    /// Reset the [`QuotaUsage`] of the given user, e.g. at the start of a new billing period.
+    fn reset(&self, user: &str) -> impl Future<Output = Result<(), Self::Error>> + Send;
+}
+
+/// A [`QuotaStore`] which keeps the [`QuotaUsage`] in memory,
+/// and thus does not survive restarts.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryQuotaStore {
+    usage: Arc<Mutex<HashMap<String, QuotaUsage>>>,
+}
+
+impl MemoryQuotaStore {
+    /// Create a new, empty, [`MemoryQuotaStore`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl QuotaStore for MemoryQuotaStore {
+    type Error = Infallible;
+
+    async fn load(&self) -> Result<HashMap<String, QuotaUsage>, Self::Error> {
+        Ok(self.usage.lock().unwrap().clone())
+    }
+
+    async fn record(&self, user: &str, usage: QuotaUsage) -> Result<(), Self::Error> {
+        *self
+            .usage
+            .lock()
+            .unwrap()
+            .entry(user.to_owned())
+            .or_default() += usage;
+        Ok(())
+    }
+
+    async fn reset(&self, user: &str) -> Result<(), Self::Error> {
+        self.usage.lock().unwrap().remove(user);
+        Ok(())
+    }
+}
+
+/// A [`QuotaStore`] which appends each recorded [`QuotaUsage`] to a file,
+/// replaying all records to load the usage.
+///
+/// The file consists of one record per line, either `add <bytes> <requests> <user>`
+/// or `reset <user>`, where control characters (such as newlines) and `%` in the
+/// user are percent-encoded. Use [`FileQuotaStore::compact`] to rewrite the file with
+/// a single record per user, as it otherwise keeps growing.
+#[derive(Debug, Clone)]
+pub struct FileQuotaStore {
+    path: PathBuf,
+    file: Arc<tokio::sync::Mutex<File>>,
+}
+
+impl FileQuotaStore {
+    /// Open the [`FileQuotaStore`] at the given path, creating the file if it does not exist yet.
+    pub async fn open(path: impl AsRef<Path>) -> Result<Self, io::Error> {
+        let path = path.as_ref().to_owned();
+        let file = open_append(&path).await?;
+        Ok(Self {
+            path,
+            file: Arc::new(tokio::sync::Mutex::new(file)),
+        })
+    }
+
+    /// Get the path of the file of this store.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Rewrite the file with a single record per user,
+    /// replacing it atomically once written.
+    pub async fn compact(&self) -> Result<(), io::Error> {
+        let mut file = self.file.lock().await;
+        let usage = read_records(&self.path).await?;
+        spawn_write_atomic(self.path.clone(), move |tmp| {
+            for (user, usage) in usage {
+                tmp.write_all(add_record(&user, usage).as_bytes())?;
+            }
+            Ok(())
+        })
+        .await?;
+        *file = open_append(&self.path).await?;
+        Ok(())
+    }
+
+    async fn append(&self, record: String) -> Result<(), io::Error> {
+        let mut file = self.file.lock().await;
+        file.write_all(record.as_bytes()).await?;
+        file.flush().await
+    }
+}
+
+async fn open_append(path: &Path) -> Result<File, io::Error> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+}
+
+/// The characters of a user which are percent-encoded in a record,
+/// such that a user can never span multiple lines.
+const USER_ENCODE_SET: &AsciiSet = &CONTROLS.add(b'%');
+
+fn encode_user(user: &str) -> impl std::fmt::Display + '_ {
+    utf8_percent_encode(user, USER_ENCODE_SET)
+}
+
+fn decode_user(user: &str, line: usize) -> Result<String, io::Error> {
+    percent_decode_str(user)
+        .decode_utf8()
+        .map(|user| user.into_owned())
+        .map_err(|_| invalid_record(line))
+}
+
+fn add_record(user: &str, usage: QuotaUsage) -> String {
+    format!(
+        "add {} {} {}\n",
+        usage.bytes,
+        usage.requests,
+        encode_user(user)
+    )
+}
+
+fn reset_record(user: &str) -> String {
+    format!("reset {}\n", encode_user(user))
+}
+
+fn invalid_record(line: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid quota record on line {line}"),
+    )
+}
+
+async fn read_records(path: &Path) -> Result<HashMap<String, QuotaUsage>, io::Error> {
+    let mut usage = HashMap::<String, QuotaUsage>::new();
+    let mut lines = BufReader::new(File::open(path).await?).lines();
+    let mut n = 0;
+    while let Some(line) = lines.next_line().await? {
+        n += 1;
+        if line.is_empty() {
+            continue;
+        }
+        match line.split_once(' ') {
+            Some(("add", record)) => {
+                let mut parts = record.splitn(3, ' ');
+                let (Some(bytes), Some(requests), Some(user)) =
+                    (parts.next(), parts.next(), parts.next())
+                else {
+                    return Err(invalid_record(n));
+                };
+                let record = QuotaUsage {
+                    bytes: bytes.parse().map_err(|_| invalid_record(n))?,
+                    requests: requests.parse().map_err(|_| invalid_record(n))?,
+                };
+                *usage.entry(decode_user(user, n)?).or_default() += record;
+            }
+            Some(("reset", user)) => {
+                usage.remove(&decode_user(user, n)?);
+            }
+            _ => return Err(invalid_record(n)),
+        }
+    }
+    Ok(usage)
+}
+
+impl QuotaStore for FileQuotaStore {
+    type Error = io::Error;
+
+    async fn load(&self) -> Result<HashMap<String, QuotaUsage>, Self::Error> {
+        let _file = self.file.lock().await;
+        read_records(&self.path).await
+    }
+
+    async fn record(&self, user: &str, usage: QuotaUsage) -> Result<(), Self::Error> {
+        self.append(add_record(user, usage)).await
+    }
+
+    async fn reset(&self, user: &str) -> Result<(), Self::Error> {
+        self.append(reset_record(user)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_file_quota_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("quota.log");
+
+        let store = FileQuotaStore::open(&path).await.unwrap();
+        store.record("john", QuotaUsage::new(10, 1)).await.unwrap();
+        store
+            .record("jane doe", QuotaUsage::new(5, 2))
+            .await
+            .unwrap();
+        store.record("john", QuotaUsage::new(20, 1)).await.unwrap();
+        store.reset("jane doe").await.unwrap();
+        store
+            .record("jane doe", QuotaUsage::new(1, 1))
+            .await
+            .unwrap();
+
+        // records survive reopening the store
+        let store = FileQuotaStore::open(&path).await.unwrap();
+        let usage = store.load().await.unwrap();
+        assert_eq!(usage.len(), 2);
+        assert_eq!(usage["john"], QuotaUsage::new(30, 2));
+        assert_eq!(usage["jane doe"], QuotaUsage::new(1, 1));
+
+        store.compact().await.unwrap();
+        let content = tokio::fs::read_to_string(&path).await.unwrap();
+        assert_eq!(content.lines().count(), 2);
+        store.record("john", QuotaUsage::new(1, 0)).await.unwrap();
+        let usage = store.load().await.unwrap();
+        assert_eq!(usage["john"], QuotaUsage::new(31, 2));
+        assert_eq!(usage["jane doe"], QuotaUsage::new(1, 1));
+    }
+
+    #[tokio::test]
+    async fn test_file_quota_store_escaped_user() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("quota.log");
+
+        let store = FileQuotaStore::open(&path).await.unwrap();
+        // a user which would otherwise inject a reset record of another user
+        store.record("john", QuotaUsage::new(10, 1)).await.unwrap();
+        store
+            .record("x\nreset john", QuotaUsage::new(1, 1))
+            .await
+            .unwrap();
+        store.record("50%", QuotaUsage::new(2, 1)).await.unwrap();
+        store.reset("x\nreset john").await.unwrap();
+
+        let content = tokio::fs::read_to_string(&path).await.unwrap();
+        assert_eq!(content.lines().count(), 4);
+        let usage = store.load().await.unwrap();
+        assert_eq!(usage.len(), 2);
+        assert_eq!(usage["john"], QuotaUsage::new(10, 1));
+        assert_eq!(usage["50%"], QuotaUsage::new(2, 1));
+    }
+
+    #[tokio::test]
+    async fn test_file_quota_store_invalid() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("quota.log");
+        tokio::fs::write(&path, "add 1 x john\n").await.unwrap();
+
+        let store = FileQuotaStore::open(&path).await.unwrap();
+        let err = store.load().await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,382 @@
+use super::CircuitOpen;
+use std::{
+    any::{Any, TypeId},
+    collections::{BTreeMap, HashMap, VecDeque},
+    hash::Hash,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::time::Instant;
+
+/// The number of buckets the rolling window is divided in.
+const WINDOW_BUCKETS: u32 = 10;
+
+#[derive(Debug, Clone)]
+pub(super) struct BreakerConfig {
+    pub(super) failure_rate: f64,
+    pub(super) minimum_requests: usize,
+    pub(super) consecutive_failures: Option<usize>,
+    pub(super) window: Duration,
+    pub(super) open_duration: Duration,
+    pub(super) half_open_requests: usize,
+    pub(super) capacity: usize,
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_rate: 0.5,
+            minimum_requests: 10,
+            consecutive_failures: None,
+            window: Duration::from_secs(60),
+            open_duration: Duration::from_secs(30),
+            half_open_requests: 1,
+            capacity: 10_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The state of a circuit breaker.
+pub enum CircuitState {
+    /// Requests are allowed through, while their outcome is tracked.
+    Closed,
+    /// Requests fail fast with [`CircuitOpen`], until the open duration has passed.
+    Open,
+    /// A limited number of probe requests is allowed through,
+    /// to test whether the inner service has recovered.
+    HalfOpen,
+}
+
+/// The breakers of a [`CircuitBreaker`] service, one per key.
+///
+/// The breakers are stored per type of key, as the key type is only known
+/// once a request is served.
+///
+/// [`CircuitBreaker`]: super::CircuitBreaker
+#[derive(Debug)]
+pub(super) struct Breakers {
+    config: BreakerConfig,
+    stores: Mutex<HashMap<TypeId, Box<dyn Any + Send>>>,
+}
+
+impl Breakers {
+    pub(super) fn new(config: BreakerConfig) -> Self {
+        Self {
+            config,
+            stores: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn with_store<Key, T>(&self, f: impl FnOnce(&mut BreakerStore<Key>) -> T) -> T
+    where
+        Key: Hash + Eq + Clone + Send + 'static,
+    {
+        let mut stores = self.stores.lock().unwrap();
+        let store = stores
+            .entry(TypeId::of::<Key>())
+            .or_insert_with(|| Box::new(BreakerStore::<Key>::default()))
+            .downcast_mut::<BreakerStore<Key>>()
+            .expect("breaker store matches the type of its key");
+        f(store)
+    }
+
+    pub(super) fn state<Key>(&self, key: &Key) -> CircuitState
+    where
+        Key: Hash + Eq + Clone + Send + 'static,
+    {
+        self.with_store(|store: &mut BreakerStore<Key>| {
+            store
+                .entries
+                .get(key)
+                .map_or(CircuitState::Closed, |entry| match entry.breaker.state {
+                    State::Closed => CircuitState::Closed,
+                    State::Open { .. } => CircuitState::Open,
+                    State::HalfOpen { .. } => CircuitState::HalfOpen,
+                })
+        })
+    }
+
+    pub(super) fn try_acquire<Key>(self: &Arc<Self>, key: Key) -> Result<Permit<Key>, CircuitOpen>
+    where
+        Key: Hash + Eq + Clone + Send + 'static,
+    {
+        let probe = self.with_store(|store| {
+            store
+                .get_or_insert(key.clone(), self.config.capacity)
+                .try_acquire(&self.config, Instant::now())
+        })?;
+        Ok(Permit {
+            breakers: self.clone(),
+            key: Some(key),
+            probe,
+        })
+    }
+}
+
+/// The breakers of a single key type, of which at most `capacity` are kept,
+/// evicting the least recently used breaker once exceeded.
+#[derive(Debug)]
+struct BreakerStore<Key> {
+    entries: HashMap<Key, BreakerEntry>,
+    /// the keys, ordered from least to most recently used
+    lru: BTreeMap<u64, Key>,
+    tick: u64,
+}
+
+impl<Key> Default for BreakerStore<Key> {
+    fn default() -> Self {
+        Self {
+            entries: HashMap::new(),
+            lru: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct BreakerEntry {
+    breaker: Breaker,
+    tick: u64,
+}
+
+impl<Key: Hash + Eq + Clone> BreakerStore<Key> {
+    fn get_or_insert(&mut self, key: Key, capacity: usize) -> &mut Breaker {
+        self.tick += 1;
+        let tick = self.tick;
+
+        if let Some(entry) = self.entries.get_mut(&key) {
+            self.lru.remove(&entry.tick);
+            entry.tick = tick;
+        } else {
+            while self.entries.len() >= capacity {
+                let Some((_, oldest)) = self.lru.pop_first() else {
+                    break;
+                };
+                self.entries.remove(&oldest);
+            }
+            self.entries.insert(
+                key.clone(),
+                BreakerEntry {
+                    breaker: Breaker {
+                        state: State::Closed,
+                        window: Window::default(),
+                        consecutive_failures: 0,
+                    },
+                    tick,
+                },
+            );
+        }
+        self.lru.insert(tick, key.clone());
+        &mut self.entries.get_mut(&key).unwrap().breaker
+    }
+
+    fn get_mut(&mut self, key: &Key) -> Option<&mut Breaker> {
+        self.entries.get_mut(key).map(|entry| &mut entry.breaker)
+    }
+}
+
+/// Permission to call the inner service, which reports its outcome to the breaker.
+///
+/// The outcome is not recorded in case the breaker was evicted in the meantime.
+pub(super) struct Permit<Key>
+where
+    Key: Hash + Eq + Clone + Send + 'static,
+{
+    breakers: Arc<Breakers>,
+    /// taken once the outcome is recorded
+    key: Option<Key>,
+    probe: bool,
+}
+
+impl<Key> Permit<Key>
+where
+    Key: Hash + Eq + Clone + Send + 'static,
+{
+    pub(super) fn record(mut self, failed: bool) {
+        let Some(key) = self.key.take() else {
+            return;
+        };
+        let config = &self.breakers.config;
+        self.breakers.with_store(|store| {
+            if let Some(breaker) = store.get_mut(&key) {
+                breaker.record(config, Instant::now(), self.probe, failed);
+            }
+        });
+    }
+}
+
+impl<Key> Drop for Permit<Key>
+where
+    Key: Hash + Eq + Clone + Send + 'static,
+{
+    fn drop(&mut self) {
+        let Some(key) = self.key.take() else {
+            return;
+        };
+        if !self.probe {
+            return;
+        }
+        // cancelled probe, give it back without recording an outcome
+        self.breakers.with_store(|store| {
+            if let Some(Breaker {
+                state: State::HalfOpen { in_flight, .. },
+                ..
+            }) = store.get_mut(&key)
+            {
+                *in_flight -= 1;
+            }
+        });
+    }
+}
+
+#[derive(Debug)]
+struct Breaker {
+    state: State,
+    window: Window,
+    consecutive_failures: usize,
+}
+
+#[derive(Debug)]
+enum State {
+    Closed,
+    Open { until: Instant },
+    HalfOpen { in_flight: usize, successes: usize },
+}
+
+impl Breaker {
+    /// Returns whether or not the request is a half-open probe.
+    fn try_acquire(&mut self, config: &BreakerConfig, now: Instant) -> Result<bool, CircuitOpen> {
+        match &mut self.state {
+            State::Closed => Ok(false),
+            State::Open { until } => {
+                if now < *until {
+                    return Err(CircuitOpen::new(*until - now));
+                }
+                self.state = State::HalfOpen {
+                    in_flight: 1,
+                    successes: 0,
+                };
+                Ok(true)
+            }
+            State::HalfOpen { in_flight, .. } => {
+                if *in_flight >= config.half_open_requests {
+                    return Err(CircuitOpen::new(Duration::ZERO));
+                }
+                *in_flight += 1;
+                Ok(true)
+            }
+        }
+    }
+
+    fn record(&mut self, config: &BreakerConfig, now: Instant, probe: bool, failed: bool) {
+        match &mut self.state {
+            State::HalfOpen {
+                in_flight,
+                successes,
+            } if probe => {
+                *in_flight -= 1;
+                if failed {
+                    self.open(config, now);
+                } else {
+                    *successes += 1;
+                    if *successes >= config.half_open_requests {
+                        self.close();
+                    }
+                }
+            }
+            State::Closed if !probe => {
+                self.window.record(config, now, failed);
+                if failed {
+                    self.consecutive_failures += 1;
+                } else {
+                    self.consecutive_failures = 0;
+                }
+                if self.should_trip(config, now) {
+                    self.open(config, now);
+                }
+            }
+            // outcome of a request admitted in a previous state
+            _ => (),
+        }
+    }
+
+    fn should_trip(&mut self, config: &BreakerConfig, now: Instant) -> bool {
+        if config
+            .consecutive_failures
+            .is_some_and(|threshold| self.consecutive_failures >= threshold)
+        {
+            return true;
+        }
+        let (total, failures) = self.window.totals(config, now);
+        total > 0
+            && total >= config.minimum_requests
+            && failures as f64 / total as f64 >= config.failure_rate
+    }
+
+    fn open(&mut self, config: &BreakerConfig, now: Instant) {
+        self.state = State::Open {
+            until: now + config.open_duration,
+        };
+    }
+
+    fn close(&mut self) {
+        self.state = State::Closed;
+        self.window = Window::default();
+        self.consecutive_failures = 0;
+    }
+}
+
+/// Rolling window of request outcomes, divided in time buckets.
+#[derive(Debug, Default)]
+struct Window {
+    buckets: VecDeque<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    start: Instant,
+    total: usize,
+    failures: usize,
+}
+
+impl Window {
+    fn record(&mut self, config: &BreakerConfig, now: Instant, failed: bool) {
+        self.expire(config, now);
+        let bucket_size = config.window / WINDOW_BUCKETS;
+        let bucket = match self.buckets.back_mut() {
+            Some(bucket) if now.duration_since(bucket.start) < bucket_size => bucket,
+            _ => {
+                self.buckets.push_back(Bucket {
+                    start: now,
+                    total: 0,
+                    failures: 0,
+                });
+                self.buckets.back_mut().unwrap()
+            }
+        };
+        bucket.total += 1;
+        if failed {
+            bucket.failures += 1;
+        }
+    }
+
+    fn totals(&mut self, config: &BreakerConfig, now: Instant) -> (usize, usize) {
+        self.expire(config, now);
+        self.buckets
+            .iter()
+            .fold((0, 0), |(total, failures), bucket| {
+                (total + bucket.total, failures + bucket.failures)
+            })
+    }
+
+    fn expire(&mut self, config: &BreakerConfig, now: Instant) {
+        while self
+            .buckets
+            .front()
+            .is_some_and(|bucket| now.duration_since(bucket.start) >= config.window)
+        {
+            self.buckets.pop_front();
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use crate::http::{
+    layer::classify::{ClassifiedResponse, ClassifyResponse, ServerErrorsAsFailures},
+    Response,
+};
+
+/// Classifies the outcome of a request as a success or failure,
+/// as tracked by a [`CircuitBreaker`] or reported by a [`Limit`] to its policy.
+///
+/// Implemented for closures of the form `Fn(&Result<Response, Error>) -> bool`,
+/// returning `true` for failures.
+///
+/// [`CircuitBreaker`]: super::CircuitBreaker
+/// [`Limit`]: crate::service::layer::limit::Limit
+pub trait ClassifyOutcome<Response, Error>: Send + Sync + 'static {
+    /// Returns `true` if the outcome is to be considered a failure.
+    fn is_failure(&self, result: &Result<Response, Error>) -> bool;
+}
+
+impl<F, Response, Error> ClassifyOutcome<Response, Error> for F
+where
+    F: Fn(&Result<Response, Error>) -> bool + Send + Sync + 'static,
+{
+    fn is_failure(&self, result: &Result<Response, Error>) -> bool {
+        (self)(result)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// The default [`ClassifyOutcome`], which considers
+/// all errors as failures and all responses as successes.
+pub struct ErrorsAsFailures;
+
+impl<Response, Error> ClassifyOutcome<Response, Error> for ErrorsAsFailures {
+    fn is_failure(&self, result: &Result<Response, Error>) -> bool {
+        result.is_err()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// A [`ClassifyOutcome`] for http services,
+/// which uses a [`ClassifyResponse`] to classify the responses.
+///
+/// All errors are considered failures. Responses which can only be classified
+/// at the end of their stream are considered successes, as the breaker does not wait
+/// for the body to be consumed.
+///
+/// By default [`ServerErrorsAsFailures`] is used.
+pub struct HttpClassifier<C = ServerErrorsAsFailures> {
+    classifier: C,
+}
+
+impl<C> HttpClassifier<C> {
+    /// Create a new [`HttpClassifier`] using the given [`ClassifyResponse`].
+    pub fn new(classifier: C) -> Self {
+        Self { classifier }
+    }
+}
+
+impl<C, Body, Error> ClassifyOutcome<Response<Body>, Error> for HttpClassifier<C>
+where
+    C: ClassifyResponse + Clone,
+{
+    fn is_failure(&self, result: &Result<Response<Body>, Error>) -> bool {
+        match result {
+            Ok(response) => matches!(
+                self.classifier.clone().classify_response(response),
+                ClassifiedResponse::Ready(Err(_))
+            ),
+            Err(_) => true,
+        }
+    }
+}
@@ -0,0 +1,30 @@
+//! Error type for the CircuitBreaker middleware.
+
+use std::{error, fmt, time::Duration};
+
+/// The circuit is open, and the request failed fast
+/// without being passed to the inner service.
+#[derive(Debug, Clone)]
+pub struct CircuitOpen(Duration);
+
+impl CircuitOpen {
+    /// Construct a new circuit open error
+    pub(crate) fn new(retry_after: Duration) -> Self {
+        Self(retry_after)
+    }
+
+    /// The time after which the circuit will allow a request through again.
+    ///
+    /// This is zero when the circuit is half-open and all probe requests are already in flight.
+    pub fn retry_after(&self) -> Duration {
+        self.0
+    }
+}
+
+impl fmt::Display for CircuitOpen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "circuit open, retry after {:?}", self.0)
+    }
+}
+
+impl error::Error for CircuitOpen {}
@@ -0,0 +1,175 @@
+use std::time::Duration;
+
+use crate::service::Layer;
+
+use super::{breaker::BreakerConfig, CircuitBreaker, ErrorsAsFailures, GlobalKey};
+
+/// Applies a [`CircuitBreaker`] to the inner service.
+///
+/// Each service created by this layer has its own breaker(s),
+/// shared by all clones of that service.
+#[derive(Debug)]
+pub struct CircuitBreakerLayer<C = ErrorsAsFailures, K = GlobalKey> {
+    config: BreakerConfig,
+    classifier: C,
+    extractor: K,
+}
+
+impl<C, K> Clone for CircuitBreakerLayer<C, K>
+where
+    C: Clone,
+    K: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            config: self.config.clone(),
+            classifier: self.classifier.clone(),
+            extractor: self.extractor.clone(),
+        }
+    }
+}
+
+impl CircuitBreakerLayer {
+    /// Create a new [`CircuitBreakerLayer`] with the default settings:
+    ///
+    /// - opens at a failure rate of `50%`, with a minimum of `10` requests,
+    ///   within a rolling window of `60` seconds;
+    /// - stays open for `30` seconds;
+    /// - closes again after a single successful probe request.
+    pub fn new() -> Self {
+        Self {
+            config: BreakerConfig::default(),
+            classifier: ErrorsAsFailures,
+            extractor: GlobalKey,
+        }
+    }
+}
+
+impl Default for CircuitBreakerLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C, K> CircuitBreakerLayer<C, K> {
+    /// Set the failure rate, between `0` and `1`, at which the circuit opens.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the rate is not within the `(0, 1]` range.
+    pub fn failure_rate(mut self, rate: f64) -> Self {
+        assert!(
+            rate > 0.0 && rate <= 1.0,
+            "circuit breaker failure rate must be within (0, 1]"
+        );
+        self.config.failure_rate = rate;
+        self
+    }
+
+    /// Set the minimum number of requests within the window
+    /// before the failure rate is considered.
+    pub fn minimum_requests(mut self, minimum: usize) -> Self {
+        self.config.minimum_requests = minimum;
+        self
+    }
+
+    /// Open the circuit after the given number of consecutive failures,
+    /// regardless of the failure rate.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the threshold is zero.
+    pub fn consecutive_failures(mut self, threshold: usize) -> Self {
+        assert!(
+            threshold > 0,
+            "circuit breaker consecutive failures cannot be zero"
+        );
+        self.config.consecutive_failures = Some(threshold);
+        self
+    }
+
+    /// Set the duration of the rolling window over which the failure rate is computed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the window is zero.
+    pub fn window(mut self, window: Duration) -> Self {
+        assert!(!window.is_zero(), "circuit breaker window cannot be zero");
+        self.config.window = window;
+        self
+    }
+
+    /// Set the duration the circuit stays open before allowing probe requests.
+    pub fn open_duration(mut self, duration: Duration) -> Self {
+        self.config.open_duration = duration;
+        self
+    }
+
+    /// Set the number of probe requests allowed while half-open,
+    /// all of which have to succeed to close the circuit again.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number is zero.
+    pub fn half_open_requests(mut self, requests: usize) -> Self {
+        assert!(
+            requests > 0,
+            "circuit breaker half-open requests cannot be zero"
+        );
+        self.config.half_open_requests = requests;
+        self
+    }
+
+    /// Set the maximum number of breakers kept per key type, which defaults to `10_000`,
+    /// evicting the least recently used breaker once exceeded.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the capacity is zero.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        assert!(capacity > 0, "circuit breaker capacity cannot be zero");
+        self.config.capacity = capacity;
+        self
+    }
+
+    /// Set the [`ClassifyOutcome`] used to classify the outcome of requests.
+    ///
+    /// [`ClassifyOutcome`]: super::ClassifyOutcome
+    pub fn classifier<T>(self, classifier: T) -> CircuitBreakerLayer<T, K> {
+        CircuitBreakerLayer {
+            config: self.config,
+            classifier,
+            extractor: self.extractor,
+        }
+    }
+
+    /// Keep a separate breaker for each key extracted by the given [`KeyExtractor`].
+    ///
+    /// Requests for which no key can be extracted bypass the circuit breaker.
+    ///
+    /// [`KeyExtractor`]: crate::service::layer::limit::policy::KeyExtractor
+    pub fn per_key<T>(self, extractor: T) -> CircuitBreakerLayer<C, T> {
+        CircuitBreakerLayer {
+            config: self.config,
+            classifier: self.classifier,
+            extractor,
+        }
+    }
+}
+
+impl<S, C, K> Layer<S> for CircuitBreakerLayer<C, K>
+where
+    C: Clone,
+    K: Clone,
+{
+    type Service = CircuitBreaker<S, C, K>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CircuitBreaker::with(
+            inner,
+            self.classifier.clone(),
+            self.extractor.clone(),
+            self.config.clone(),
+        )
+    }
+}
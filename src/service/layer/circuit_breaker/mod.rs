@@ -0,0 +1,369 @@
+//! Middleware that stops calling an inner service which keeps failing.
+//!
+//! A circuit breaker starts closed, allowing all requests through while
+//! tracking their outcome over a rolling window. Once the failure rate
+//! or the number of consecutive failures exceeds the configured threshold,
+//! the circuit opens and requests fail fast with [`CircuitOpen`].
+//! After the open duration has passed the circuit becomes half-open,
+//! allowing a limited number of probe requests through. If these succeed
+//! the circuit is closed again, otherwise it opens again.
+//!
+//! The outcome of a request is classified using a [`ClassifyOutcome`],
+//! which by default considers all errors as failures. Use [`HttpClassifier`]
+//! to reuse a [`ClassifyResponse`] for http services.
+//!
+//! By default a single breaker is used for all requests. Using [`CircuitBreakerLayer::per_key`]
+//! a separate breaker is kept for each key extracted using a [`KeyExtractor`],
+//! e.g. per upstream. To keep memory bounded at most [`CircuitBreakerLayer::capacity`]
+//! breakers are kept, evicting the least recently used one. As an evicted key starts
+//! with a closed circuit again, keys are best taken from a bounded set, rather than
+//! from what a client controls, such as the authority of a proxied request.
+//!
+//! # Example
+//!
+//! ```
+//! use rama::http::{layer::classify::ServerErrorsAsFailures, Body, Request, Response, StatusCode};
+//! use rama::service::{
+//!     layer::circuit_breaker::{CircuitBreakerLayer, CircuitOpen, HttpClassifier},
+//!     Context, Layer, Service, service_fn,
+//! };
+//! # use std::convert::Infallible;
+//! use std::time::Duration;
+//!
+//! # #[tokio::main]
+//! # async fn main() {
+//! let service = CircuitBreakerLayer::new()
+//!     .consecutive_failures(1)
+//!     .open_duration(Duration::from_secs(10))
+//!     .classifier(HttpClassifier::new(ServerErrorsAsFailures::new()))
+//!     // a breaker per known upstream, all other requests bypass the breaker
+//!     .per_key(|_: &Context<()>, req: &Request| match req.uri().host() {
+//!         Some(host @ ("example.com" | "example.org")) => Some(host.to_owned()),
+//!         _ => None,
+//!     })
+//!     .layer(service_fn(|_, _: Request| async {
+//!         Ok::<_, Infallible>(
+//!             Response::builder()
+//!                 .status(StatusCode::BAD_GATEWAY)
+//!                 .body(Body::empty())
+//!                 .unwrap(),
+//!         )
+//!     }));
+//!
+//! let request = || Request::builder().uri("http://example.com").body(Body::empty()).unwrap();
+//!
+//! let response = service.serve(Context::default(), request()).await.unwrap();
+//! assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+//!
+//! let err = service.serve(Context::default(), request()).await.unwrap_err();
+//! assert!(err.downcast_ref::<CircuitOpen>().is_some());
+//! # }
+//! ```
+//!
+//! [`ClassifyResponse`]: crate::http::layer::classify::ClassifyResponse
+//! [`KeyExtractor`]: crate::service::layer::limit::policy::KeyExtractor
+
+use crate::error::BoxError;
+use crate::service::{layer::limit::policy::KeyExtractor, Context, Service};
+use std::sync::Arc;
+
+mod breaker;
+#[doc(inline)]
+pub use breaker::CircuitState;
+use breaker::{BreakerConfig, Breakers};
+
+mod classify;
+#[doc(inline)]
+pub use classify::{ClassifyOutcome, ErrorsAsFailures, HttpClassifier};
+
+mod error;
+#[doc(inline)]
+pub use error::CircuitOpen;
+
+mod layer;
+#[doc(inline)]
+pub use layer::CircuitBreakerLayer;
+
+#[derive(Debug, Clone, Copy, Default)]
+/// The [`KeyExtractor`] used by a [`CircuitBreaker`] by default,
+/// using a single breaker for all requests.
+///
+/// [`KeyExtractor`]: crate::service::layer::limit::policy::KeyExtractor
+pub struct GlobalKey;
+
+impl<State, Request> KeyExtractor<State, Request> for GlobalKey {
+    type Key = ();
+
+    fn extract(&self, _ctx: &Context<State>, _req: &Request) -> Option<Self::Key> {
+        Some(())
+    }
+}
+
+/// Fails fast when the inner service keeps failing.
+///
+/// See the [module docs](self) for more information.
+#[derive(Debug)]
+pub struct CircuitBreaker<S, C, K> {
+    inner: S,
+    classifier: C,
+    extractor: K,
+    breakers: Arc<Breakers>,
+}
+
+impl<S, C, K> Clone for CircuitBreaker<S, C, K>
+where
+    S: Clone,
+    C: Clone,
+    K: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            classifier: self.classifier.clone(),
+            extractor: self.extractor.clone(),
+            breakers: self.breakers.clone(),
+        }
+    }
+}
+
+impl<S, C, K> CircuitBreaker<S, C, K> {
+    fn with(inner: S, classifier: C, extractor: K, config: BreakerConfig) -> Self {
+        Self {
+            inner,
+            classifier,
+            extractor,
+            breakers: Arc::new(Breakers::new(config)),
+        }
+    }
+
+    /// Returns the [`CircuitState`] of the breaker that applies to the given request.
+    ///
+    /// Requests for which no key can be extracted always pass, and are thus
+    /// reported as [`CircuitState::Closed`].
+    pub fn state<State, Request>(&self, ctx: &Context<State>, req: &Request) -> CircuitState
+    where
+        K: KeyExtractor<State, Request>,
+    {
+        match self.extractor.extract(ctx, req) {
+            Some(key) => self.breakers.state(&key),
+            None => CircuitState::Closed,
+        }
+    }
+}
+
+impl<S, C, K, State, Request> Service<State, Request> for CircuitBreaker<S, C, K>
+where
+    S: Service<State, Request>,
+    S::Error: Into<BoxError>,
+    C: ClassifyOutcome<S::Response, S::Error>,
+    K: KeyExtractor<State, Request>,
+    State: Send + Sync + 'static,
+    Request: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+
+    async fn serve(
+        &self,
+        ctx: Context<State>,
+        request: Request,
+    ) -> Result<Self::Response, Self::Error> {
+        let permit = match self.extractor.extract(&ctx, &request) {
+            Some(key) => Some(self.breakers.try_acquire(key)?),
+            None => None,
+        };
+
+        let result = self.inner.serve(ctx, request).await;
+        if let Some(permit) = permit {
+            permit.record(self.classifier.is_failure(&result));
+        }
+        result.map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::{service_fn, Layer};
+    use std::{convert::Infallible, time::Duration};
+
+    fn service(
+        layer: CircuitBreakerLayer,
+    ) -> impl Service<(), Result<(), ()>, Response = (), Error = BoxError> {
+        layer.layer(service_fn(|_, req: Result<(), ()>| async move {
+            req.map_err(|_| BoxError::from("failure"))
+        }))
+    }
+
+    fn is_open(result: Result<(), BoxError>) -> bool {
+        result
+            .err()
+            .and_then(|err| err.downcast_ref::<CircuitOpen>().cloned())
+            .is_some()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_circuit_breaker_failure_rate() {
+        let service = service(
+            CircuitBreakerLayer::new()
+                .failure_rate(0.5)
+                .minimum_requests(4)
+                .open_duration(Duration::from_secs(10)),
+        );
+
+        for req in [Ok(()), Err(()), Ok(())] {
+            assert!(!is_open(service.serve(Context::default(), req).await));
+        }
+        assert!(!is_open(service.serve(Context::default(), Err(())).await));
+        assert!(is_open(service.serve(Context::default(), Ok(())).await));
+
+        tokio::time::advance(Duration::from_secs(10)).await;
+        // half-open: a single successful probe closes the circuit again
+        assert!(service.serve(Context::default(), Ok(())).await.is_ok());
+        assert!(service.serve(Context::default(), Ok(())).await.is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_circuit_breaker_rolling_window() {
+        let service = service(
+            CircuitBreakerLayer::new()
+                .failure_rate(0.5)
+                .minimum_requests(2)
+                .window(Duration::from_secs(10)),
+        );
+
+        assert!(!is_open(service.serve(Context::default(), Err(())).await));
+        tokio::time::advance(Duration::from_secs(11)).await;
+        // the first failure is no longer part of the window
+        assert!(!is_open(service.serve(Context::default(), Ok(())).await));
+        assert!(!is_open(service.serve(Context::default(), Err(())).await));
+        assert!(is_open(service.serve(Context::default(), Ok(())).await));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_circuit_breaker_half_open_failure() {
+        let service = service(
+            CircuitBreakerLayer::new()
+                .consecutive_failures(2)
+                .open_duration(Duration::from_secs(5)),
+        );
+
+        assert!(!is_open(service.serve(Context::default(), Err(())).await));
+        assert!(!is_open(service.serve(Context::default(), Err(())).await));
+        let err = service.serve(Context::default(), Ok(())).await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<CircuitOpen>().unwrap().retry_after(),
+            Duration::from_secs(5)
+        );
+
+        tokio::time::advance(Duration::from_secs(5)).await;
+        // failed probe opens the circuit again
+        assert!(!is_open(service.serve(Context::default(), Err(())).await));
+        assert!(is_open(service.serve(Context::default(), Ok(())).await));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_circuit_breaker_half_open_probes() {
+        let layer = CircuitBreakerLayer::new()
+            .consecutive_failures(1)
+            .half_open_requests(1);
+        let service = layer.layer(service_fn(|_, req: Result<(), ()>| async move {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            req.map_err(|_| BoxError::from("failure"))
+        }));
+
+        assert!(service.serve(Context::default(), Err(())).await.is_err());
+        assert_eq!(service.state(&Context::default(), &()), CircuitState::Open);
+        tokio::time::advance(Duration::from_secs(30)).await;
+
+        let (probe, ()) = tokio::join!(service.serve(Context::default(), Ok(())), async {
+            assert_eq!(
+                service.state(&Context::default(), &()),
+                CircuitState::HalfOpen
+            );
+            // only a single probe is allowed at once
+            assert!(is_open(service.serve(Context::default(), Ok(())).await));
+        });
+        assert!(probe.is_ok());
+        assert_eq!(
+            service.state(&Context::default(), &()),
+            CircuitState::Closed
+        );
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker_per_key() {
+        let service = CircuitBreakerLayer::new()
+            .consecutive_failures(1)
+            .per_key(|_: &Context<()>, req: &(&'static str, bool)| Some(req.0))
+            .layer(service_fn(|_, (_, ok): (&'static str, bool)| async move {
+                if ok {
+                    Ok(())
+                } else {
+                    Err(BoxError::from("failure"))
+                }
+            }));
+
+        assert!(service
+            .serve(Context::default(), ("a", false))
+            .await
+            .is_err());
+        assert!(is_open(
+            service.serve(Context::default(), ("a", true)).await
+        ));
+        assert!(service.serve(Context::default(), ("b", true)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker_capacity() {
+        let service = CircuitBreakerLayer::new()
+            .consecutive_failures(1)
+            .capacity(1)
+            .per_key(|_: &Context<()>, req: &(&'static str, bool)| Some(req.0))
+            .layer(service_fn(|_, (_, ok): (&'static str, bool)| async move {
+                if ok {
+                    Ok(())
+                } else {
+                    Err(BoxError::from("failure"))
+                }
+            }));
+
+        assert!(service
+            .serve(Context::default(), ("a", false))
+            .await
+            .is_err());
+        assert_eq!(
+            service.state(&Context::default(), &("a", true)),
+            CircuitState::Open
+        );
+
+        // the breaker of "a" is evicted to make room for the one of "b"
+        assert!(service.serve(Context::default(), ("b", true)).await.is_ok());
+        assert_eq!(
+            service.state(&Context::default(), &("a", true)),
+            CircuitState::Closed
+        );
+        assert!(service.serve(Context::default(), ("a", true)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker_classifier() {
+        let service = CircuitBreakerLayer::new()
+            .consecutive_failures(1)
+            .classifier(
+                |result: &Result<u16, Infallible>| matches!(result, Ok(status) if *status >= 500),
+            )
+            .layer(service_fn(|_, status: u16| async move {
+                Ok::<_, Infallible>(status)
+            }));
+
+        assert!(service.serve(Context::default(), 404).await.is_ok());
+        assert!(service.serve(Context::default(), 503).await.is_ok());
+        assert!(service
+            .serve(Context::default(), 200)
+            .await
+            .unwrap_err()
+            .downcast_ref::<CircuitOpen>()
+            .is_some());
+    }
+}
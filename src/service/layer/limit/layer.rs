@@ -1,38 +1,56 @@
 use super::Limit;
-use crate::service::Layer;
+use crate::service::{layer::circuit_breaker::ErrorsAsFailures, Layer};
 
 /// Limit requests based on a policy
 #[derive(Debug)]
-pub struct LimitLayer<P> {
+pub struct LimitLayer<P, C = ErrorsAsFailures> {
     policy: P,
+    classifier: C,
 }
 
 impl<P> LimitLayer<P> {
     /// Creates a new [`LimitLayer`] from a [`crate::service::layer::limit::Policy`].
     pub fn new(policy: P) -> Self {
-        LimitLayer { policy }
+        LimitLayer {
+            policy,
+            classifier: ErrorsAsFailures,
+        }
+    }
+}
+
+impl<P, C> LimitLayer<P, C> {
+    /// Classify the outcome of the inner service using the given [`ClassifyOutcome`].
+    ///
+    /// [`ClassifyOutcome`]: crate::service::layer::circuit_breaker::ClassifyOutcome
+    pub fn classifier<C2>(self, classifier: C2) -> LimitLayer<P, C2> {
+        LimitLayer {
+            policy: self.policy,
+            classifier,
+        }
     }
 }
 
-impl<P> Clone for LimitLayer<P>
+impl<P, C> Clone for LimitLayer<P, C>
 where
     P: Clone,
+    C: Clone,
 {
     fn clone(&self) -> Self {
         Self {
             policy: self.policy.clone(),
+            classifier: self.classifier.clone(),
         }
     }
 }
 
-impl<T, P> Layer<T> for LimitLayer<P>
+impl<T, P, C> Layer<T> for LimitLayer<P, C>
 where
     P: Clone,
+    C: Clone,
 {
-    type Service = Limit<T, P>;
+    type Service = Limit<T, P, C>;
 
     fn layer(&self, service: T) -> Self::Service {
-        let policy = self.policy.clone();
-        Limit::new(service, policy)
+        Limit::new(service, self.policy.clone()).classifier(self.classifier.clone())
     }
 }
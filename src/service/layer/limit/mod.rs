@@ -3,7 +3,10 @@
 //! See [`Limit`].
 
 use crate::error::BoxError;
-use crate::service::{Context, Service};
+use crate::service::{
+    layer::circuit_breaker::{ClassifyOutcome, ErrorsAsFailures},
+    Context, Service,
+};
 
 pub mod policy;
 pub use policy::{Policy, PolicyOutput};
@@ -13,37 +16,62 @@ mod layer;
 pub use layer::LimitLayer;
 
 /// Limit requests based on a policy
+///
+/// The outcome of the inner service is classified using a [`ClassifyOutcome`],
+/// by default considering all errors as failures, and reported to policies
+/// which adapt to it, such as the [`AdaptivePolicy`].
+///
+/// [`AdaptivePolicy`]: policy::AdaptivePolicy
 #[derive(Debug)]
-pub struct Limit<T, P> {
+pub struct Limit<T, P, C = ErrorsAsFailures> {
     inner: T,
     policy: P,
+    classifier: C,
 }
 
 impl<T, P> Limit<T, P> {
     /// Creates a new [`Limit`] from a limit policy,
     /// wrapping the given service.
     pub fn new(inner: T, policy: P) -> Self {
-        Limit { inner, policy }
+        Limit {
+            inner,
+            policy,
+            classifier: ErrorsAsFailures,
+        }
     }
 }
 
-impl<T, P> Clone for Limit<T, P>
+impl<T, P, C> Limit<T, P, C> {
+    /// Classify the outcome of the inner service using the given [`ClassifyOutcome`].
+    pub fn classifier<C2>(self, classifier: C2) -> Limit<T, P, C2> {
+        Limit {
+            inner: self.inner,
+            policy: self.policy,
+            classifier,
+        }
+    }
+}
+
+impl<T, P, C> Clone for Limit<T, P, C>
 where
     T: Clone,
     P: Clone,
+    C: Clone,
 {
     fn clone(&self) -> Self {
         Limit {
             inner: self.inner.clone(),
             policy: self.policy.clone(),
+            classifier: self.classifier.clone(),
         }
     }
 }
 
-impl<T, P, State, Request> Service<State, Request> for Limit<T, P>
+impl<T, P, C, State, Request> Service<State, Request> for Limit<T, P, C>
 where
     T: Service<State, Request>,
     T::Error: Into<BoxError>,
+    C: ClassifyOutcome<T::Response, T::Error>,
     P: policy::Policy<State, Request>,
     P::Error: Into<BoxError>,
     Request: Send + Sync + 'static,
@@ -64,8 +92,13 @@ where
 
             match result.output {
                 policy::PolicyOutput::Ready(guard) => {
-                    let _ = guard;
-                    return self.inner.serve(ctx, request).await.map_err(Into::into);
+                    let outcome = ctx.get::<policy::AdaptiveOutcome>().cloned();
+                    let result = self.inner.serve(ctx, request).await;
+                    if let Some(outcome) = outcome {
+                        outcome.record(self.classifier.is_failure(&result));
+                    }
+                    drop(guard);
+                    return result.map_err(Into::into);
                 }
                 policy::PolicyOutput::Abort(err) => return Err(err.into()),
                 policy::PolicyOutput::Retry => (),
@@ -115,6 +148,35 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_limit_adaptive_outcome() {
+        use super::policy::{AdaptivePolicy, Aimd};
+
+        let policy = AdaptivePolicy::new(Aimd::new().decrease_factor(0.5), 2);
+        let service = Limit::new(
+            service_fn(|_, fail: bool| async move {
+                if fail {
+                    Err(BoxError::from("failure"))
+                } else {
+                    Ok(())
+                }
+            }),
+            policy.clone(),
+        );
+
+        service.serve(Context::default(), false).await.unwrap();
+        assert_eq!(policy.limit(), 3);
+        service.serve(Context::default(), true).await.unwrap_err();
+        assert_eq!(policy.limit(), 1);
+        service.serve(Context::default(), false).await.unwrap();
+        assert_eq!(policy.limit(), 2);
+
+        // classify successful responses as failures as well
+        let service = service.classifier(|_: &Result<(), BoxError>| true);
+        service.serve(Context::default(), false).await.unwrap();
+        assert_eq!(policy.limit(), 1);
+    }
+
     #[tokio::test]
     async fn test_zero_limit() {
         async fn handle_request<State, Request>(
@@ -0,0 +1,593 @@
+//! A policy that limits the number of concurrent requests,
+//! adapting the limit automatically based on the observed latency and failures.
+//!
+//! See [`AdaptivePolicy`].
+//!
+//! # Examples
+//!
+//! ```
+//! use rama::service::{
+//!     layer::limit::{Limit, policy::{AdaptiveOutcome, AdaptivePolicy, Gradient}},
+//!     Context, Service, service_fn,
+//! };
+//! # use std::convert::Infallible;
+//!
+//! # #[tokio::main]
+//! # async fn main() {
+//!
+//! let service = service_fn(|ctx: Context<()>, overloaded: bool| async move {
+//!     if overloaded {
+//!         // mark the request as failed, even though it returns successfully,
+//!         // such that the limit is lowered
+//!         ctx.get::<AdaptiveOutcome>().unwrap().failure();
+//!     }
+//!     Ok::<_, Infallible>(())
+//! });
+//! let service = Limit::new(service, AdaptivePolicy::new(Gradient::default(), 20));
+//!
+//! let response = service.serve(Context::default(), false).await;
+//! assert!(response.is_ok());
+//! # }
+//! ```
+
+use super::{LimitReached, Policy, PolicyOutput, PolicyResult};
+use crate::service::Context;
+use std::{
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::time::Instant;
+
+/// A policy that limits the number of concurrent requests,
+/// of which the limit is adjusted automatically using an [`AdaptiveAlgorithm`].
+///
+/// The latency of each request is measured from the moment the policy is passed
+/// until the [`AdaptiveGuard`] is dropped, which is after the inner service has completed.
+/// The outcome of the request is recorded by the [`Limit`] service, which classifies
+/// the result of the inner service using its [`ClassifyOutcome`], by default considering
+/// all errors as failures. The inner service can mark a request as failed as well,
+/// using the [`AdaptiveOutcome`] found in the [`Context`].
+///
+/// Requests which never complete, e.g. because they are cancelled,
+/// are not sampled and thus do not affect the limit.
+/// Place timeouts within the limit, such that requests which time out count as failures.
+///
+/// Requests that exceed the current limit are aborted with [`LimitReached`],
+/// shedding the excess load before the upstream collapses.
+///
+/// Clones of the policy share the same limit.
+///
+/// [`Limit`]: crate::service::layer::limit::Limit
+/// [`ClassifyOutcome`]: crate::service::layer::circuit_breaker::ClassifyOutcome
+#[derive(Debug)]
+pub struct AdaptivePolicy<A> {
+    state: Arc<Mutex<AdaptiveState<A>>>,
+}
+
+impl<A> Clone for AdaptivePolicy<A> {
+    fn clone(&self) -> Self {
+        Self {
+            state: self.state.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct AdaptiveState<A> {
+    algorithm: A,
+    limit: usize,
+    min_limit: usize,
+    max_limit: usize,
+    in_flight: usize,
+}
+
+impl<A> AdaptivePolicy<A> {
+    /// Create a new [`AdaptivePolicy`] using the given [`AdaptiveAlgorithm`]
+    /// and initial concurrency limit.
+    ///
+    /// The limit is kept between `1` and `1000` by default.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the `initial_limit` is zero.
+    pub fn new(algorithm: A, initial_limit: usize) -> Self {
+        assert!(initial_limit > 0, "adaptive initial limit cannot be zero");
+        Self {
+            state: Arc::new(Mutex::new(AdaptiveState {
+                algorithm,
+                limit: initial_limit,
+                min_limit: 1,
+                max_limit: initial_limit.max(1000),
+                in_flight: 0,
+            })),
+        }
+    }
+
+    /// Set the minimum concurrency limit, which defaults to `1`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the `min_limit` is zero.
+    pub fn min_limit(self, min_limit: usize) -> Self {
+        assert!(min_limit > 0, "adaptive min limit cannot be zero");
+        {
+            let mut state = self.state.lock().unwrap();
+            state.min_limit = min_limit;
+            state.limit = state.limit.max(min_limit);
+        }
+        self
+    }
+
+    /// Set the maximum concurrency limit, which defaults to `1000`
+    /// (or the initial limit if that is greater).
+    pub fn max_limit(self, max_limit: usize) -> Self {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.max_limit = max_limit.max(state.min_limit);
+            state.limit = state.limit.min(state.max_limit);
+        }
+        self
+    }
+
+    /// Returns the current concurrency limit.
+    pub fn limit(&self) -> usize {
+        self.state.lock().unwrap().limit
+    }
+
+    /// Returns the number of requests currently in flight.
+    pub fn in_flight(&self) -> usize {
+        self.state.lock().unwrap().in_flight
+    }
+}
+
+impl<A, State, Request> Policy<State, Request> for AdaptivePolicy<A>
+where
+    A: AdaptiveAlgorithm,
+    State: Send + Sync + 'static,
+    Request: Send + 'static,
+{
+    type Guard = AdaptiveGuard<A>;
+    type Error = LimitReached;
+
+    async fn check(
+        &self,
+        mut ctx: Context<State>,
+        request: Request,
+    ) -> PolicyResult<State, Request, Self::Guard, Self::Error> {
+        let output = {
+            let mut state = self.state.lock().unwrap();
+            if state.in_flight >= state.limit {
+                PolicyOutput::Abort(LimitReached)
+            } else {
+                state.in_flight += 1;
+                let outcome = AdaptiveOutcome {
+                    state: Arc::new(AtomicU8::new(AdaptiveOutcome::PENDING)),
+                };
+                ctx.insert(outcome.clone());
+                PolicyOutput::Ready(AdaptiveGuard {
+                    state: self.state.clone(),
+                    start: Instant::now(),
+                    in_flight: state.in_flight,
+                    outcome,
+                })
+            }
+        };
+
+        PolicyResult {
+            ctx,
+            request,
+            output,
+        }
+    }
+}
+
+/// The guard of an [`AdaptivePolicy`], which records
+/// the outcome of the request when dropped.
+#[derive(Debug)]
+pub struct AdaptiveGuard<A: AdaptiveAlgorithm> {
+    state: Arc<Mutex<AdaptiveState<A>>>,
+    start: Instant,
+    in_flight: usize,
+    outcome: AdaptiveOutcome,
+}
+
+impl<A: AdaptiveAlgorithm> Drop for AdaptiveGuard<A> {
+    fn drop(&mut self) {
+        let failed = match self.outcome.state.load(Ordering::Acquire) {
+            AdaptiveOutcome::SUCCESS => false,
+            AdaptiveOutcome::FAILURE => true,
+            // the request never completed, e.g. because it was cancelled
+            _ => {
+                self.state.lock().unwrap().in_flight -= 1;
+                return;
+            }
+        };
+        let sample = AdaptiveSample {
+            latency: self.start.elapsed(),
+            in_flight: self.in_flight,
+            failed,
+        };
+        let mut state = self.state.lock().unwrap();
+        state.in_flight -= 1;
+        let current = state.limit;
+        let limit = state.algorithm.update(current, &sample);
+        state.limit = limit.clamp(state.min_limit, state.max_limit);
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Handle inserted in the [`Context`] by the [`AdaptivePolicy`],
+/// used to record the outcome of the request.
+///
+/// The outcome is recorded by the [`Limit`] service once the inner service completed,
+/// but a request can be marked as failed by the inner service as well.
+/// A failure is never overwritten by a success.
+///
+/// [`Limit`]: crate::service::layer::limit::Limit
+pub struct AdaptiveOutcome {
+    state: Arc<AtomicU8>,
+}
+
+impl AdaptiveOutcome {
+    const PENDING: u8 = 0;
+    const SUCCESS: u8 = 1;
+    const FAILURE: u8 = 2;
+
+    /// Mark the request as failed, e.g. because the upstream
+    /// returned a response indicating it is overloaded.
+    pub fn failure(&self) {
+        self.state.store(Self::FAILURE, Ordering::Release);
+    }
+
+    /// Mark the request as successful, unless it is already marked as failed.
+    pub fn success(&self) {
+        let _ = self.state.compare_exchange(
+            Self::PENDING,
+            Self::SUCCESS,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        );
+    }
+
+    /// Record the outcome of the request.
+    pub(crate) fn record(&self, failed: bool) {
+        if failed {
+            self.failure();
+        } else {
+            self.success();
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// The outcome of a single request, as passed to an [`AdaptiveAlgorithm`].
+pub struct AdaptiveSample {
+    latency: Duration,
+    in_flight: usize,
+    failed: bool,
+}
+
+impl AdaptiveSample {
+    /// Create a new [`AdaptiveSample`].
+    pub fn new(latency: Duration, in_flight: usize, failed: bool) -> Self {
+        Self {
+            latency,
+            in_flight,
+            failed,
+        }
+    }
+
+    /// The latency of the request.
+    pub fn latency(&self) -> Duration {
+        self.latency
+    }
+
+    /// The number of requests in flight (including this one)
+    /// at the moment the request started.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight
+    }
+
+    /// Returns `true` if the request was marked as failed.
+    pub fn is_failure(&self) -> bool {
+        self.failed
+    }
+}
+
+/// The algorithm used by an [`AdaptivePolicy`] to adjust its concurrency limit.
+pub trait AdaptiveAlgorithm: Send + 'static {
+    /// Returns the new concurrency limit, given the current limit
+    /// and the sample of a completed request.
+    ///
+    /// The result is clamped by the policy within its min and max limit.
+    fn update(&mut self, limit: usize, sample: &AdaptiveSample) -> usize;
+}
+
+#[derive(Debug, Clone)]
+/// Additive-increase/multiplicative-decrease [`AdaptiveAlgorithm`].
+///
+/// The limit is increased by a fixed amount for each successful request,
+/// and multiplied by a factor smaller than one for each failed request
+/// or request which took longer than the (optional) latency threshold.
+pub struct Aimd {
+    increase: usize,
+    decrease_factor: f64,
+    latency_threshold: Option<Duration>,
+}
+
+impl Default for Aimd {
+    fn default() -> Self {
+        Self {
+            increase: 1,
+            decrease_factor: 0.9,
+            latency_threshold: None,
+        }
+    }
+}
+
+impl Aimd {
+    /// Create a new [`Aimd`] algorithm with the default settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the amount the limit is increased with for a successful request,
+    /// which defaults to `1`.
+    pub fn increase(mut self, increase: usize) -> Self {
+        self.increase = increase;
+        self
+    }
+
+    /// Set the factor the limit is multiplied with for a failed request,
+    /// which defaults to `0.9`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the factor is not within the `(0, 1)` range.
+    pub fn decrease_factor(mut self, factor: f64) -> Self {
+        assert!(
+            factor > 0.0 && factor < 1.0,
+            "aimd decrease factor must be within (0, 1)"
+        );
+        self.decrease_factor = factor;
+        self
+    }
+
+    /// Consider requests which take longer than the given duration as failed.
+    pub fn latency_threshold(mut self, threshold: Duration) -> Self {
+        self.latency_threshold = Some(threshold);
+        self
+    }
+}
+
+impl AdaptiveAlgorithm for Aimd {
+    fn update(&mut self, limit: usize, sample: &AdaptiveSample) -> usize {
+        let failed = sample.is_failure()
+            || self
+                .latency_threshold
+                .is_some_and(|threshold| sample.latency() > threshold);
+        if failed {
+            (limit as f64 * self.decrease_factor) as usize
+        } else if sample.in_flight() * 2 >= limit {
+            // only grow the limit when it is actually being used
+            limit + self.increase
+        } else {
+            limit
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Gradient (Vegas-like) [`AdaptiveAlgorithm`].
+///
+/// The latency of each request is compared with a long term average of the latency.
+/// When the latency rises above that average, queueing is assumed to happen upstream
+/// and the limit is lowered proportionally, while otherwise the limit
+/// is allowed to grow with a headroom of the square root of the limit.
+/// Failed requests halve the gradient.
+pub struct Gradient {
+    smoothing: f64,
+    long_window: f64,
+    long_latency: Option<f64>,
+    estimate: Option<f64>,
+}
+
+impl Default for Gradient {
+    fn default() -> Self {
+        Self {
+            smoothing: 0.2,
+            long_window: 600.0,
+            long_latency: None,
+            estimate: None,
+        }
+    }
+}
+
+impl Gradient {
+    /// Create a new [`Gradient`] algorithm with the default settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the smoothing factor used to move towards a new limit,
+    /// which defaults to `0.2`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the factor is not within the `(0, 1]` range.
+    pub fn smoothing(mut self, smoothing: f64) -> Self {
+        assert!(
+            smoothing > 0.0 && smoothing <= 1.0,
+            "gradient smoothing must be within (0, 1]"
+        );
+        self.smoothing = smoothing;
+        self
+    }
+
+    /// Set the number of samples over which the long term latency is averaged,
+    /// which defaults to `600`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the window is zero.
+    pub fn long_window(mut self, samples: usize) -> Self {
+        assert!(samples > 0, "gradient long window cannot be zero");
+        self.long_window = samples as f64;
+        self
+    }
+}
+
+impl AdaptiveAlgorithm for Gradient {
+    fn update(&mut self, limit: usize, sample: &AdaptiveSample) -> usize {
+        let latency = sample.latency().as_secs_f64();
+        let long_latency = match self.long_latency {
+            Some(long) => long + (latency - long) / self.long_window,
+            None => latency,
+        };
+        self.long_latency = Some(long_latency);
+
+        let estimate = self.estimate.unwrap_or(limit as f64);
+        let mut gradient = if latency > 0.0 {
+            (long_latency / latency).clamp(0.5, 1.0)
+        } else {
+            1.0
+        };
+        if sample.is_failure() {
+            gradient *= 0.5;
+        }
+
+        let target = if sample.in_flight() * 2 < limit && !sample.is_failure() {
+            // do not grow the limit when it is not being used
+            estimate
+        } else {
+            estimate.mul_add(gradient, estimate.sqrt())
+        };
+        let estimate = estimate
+            .mul_add(1.0 - self.smoothing, target * self.smoothing)
+            .max(1.0);
+        self.estimate = Some(estimate);
+        estimate as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_ready<S, R, G, E>(result: PolicyResult<S, R, G, E>) -> (Context<S>, G) {
+        match result.output {
+            PolicyOutput::Ready(guard) => (result.ctx, guard),
+            _ => panic!("unexpected output, expected ready"),
+        }
+    }
+
+    fn assert_abort<S, R, G, E>(result: PolicyResult<S, R, G, E>) {
+        match result.output {
+            PolicyOutput::Abort(_) => (),
+            _ => panic!("unexpected output, expected abort"),
+        }
+    }
+
+    #[tokio::test]
+    async fn adaptive_policy_sheds_load() {
+        let policy = AdaptivePolicy::new(Aimd::default(), 2);
+
+        let (ctx, guard_a) = assert_ready(policy.check(Context::default(), ()).await);
+        let (_, _guard_b) = assert_ready(policy.check(Context::default(), ()).await);
+        assert_abort(policy.check(Context::default(), ()).await);
+        assert_eq!(policy.in_flight(), 2);
+
+        ctx.get::<AdaptiveOutcome>().unwrap().success();
+        drop(guard_a);
+        assert_eq!(policy.in_flight(), 1);
+        assert_eq!(policy.limit(), 3);
+        assert_ready(policy.check(Context::default(), ()).await);
+    }
+
+    #[tokio::test]
+    async fn adaptive_policy_aimd_failure() {
+        let policy = AdaptivePolicy::new(Aimd::new().decrease_factor(0.5), 10).min_limit(2);
+
+        for expected in [5, 2, 2] {
+            let (ctx, guard) = assert_ready(policy.check(Context::default(), ()).await);
+            ctx.get::<AdaptiveOutcome>().unwrap().failure();
+            drop(guard);
+            assert_eq!(policy.limit(), expected);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn adaptive_policy_aimd_latency_threshold() {
+        let policy = AdaptivePolicy::new(
+            Aimd::new()
+                .decrease_factor(0.5)
+                .latency_threshold(Duration::from_millis(100)),
+            1,
+        )
+        .max_limit(2);
+
+        let (ctx, guard) = assert_ready(policy.check(Context::default(), ()).await);
+        ctx.get::<AdaptiveOutcome>().unwrap().success();
+        drop(guard);
+        assert_eq!(policy.limit(), 2);
+
+        let (ctx, guard) = assert_ready(policy.check(Context::default(), ()).await);
+        let (ctx2, guard2) = assert_ready(policy.check(Context::default(), ()).await);
+        ctx2.get::<AdaptiveOutcome>().unwrap().success();
+        drop(guard2);
+        assert_eq!(policy.limit(), 2);
+
+        tokio::time::advance(Duration::from_millis(200)).await;
+        ctx.get::<AdaptiveOutcome>().unwrap().success();
+        drop(guard);
+        assert_eq!(policy.limit(), 1);
+    }
+
+    #[tokio::test]
+    async fn adaptive_policy_unfinished_not_sampled() {
+        let policy = AdaptivePolicy::new(Aimd::default(), 2);
+
+        let (ctx, guard) = assert_ready(policy.check(Context::default(), ()).await);
+        drop(guard);
+        assert_eq!(policy.in_flight(), 0);
+        assert_eq!(policy.limit(), 2);
+
+        // a failure is never overwritten by a success
+        ctx.get::<AdaptiveOutcome>().unwrap().failure();
+        ctx.get::<AdaptiveOutcome>().unwrap().success();
+        let (ctx, guard) = assert_ready(policy.check(Context::default(), ()).await);
+        let outcome = ctx.get::<AdaptiveOutcome>().unwrap();
+        outcome.failure();
+        outcome.success();
+        drop(guard);
+        assert_eq!(policy.limit(), 1);
+    }
+
+    #[test]
+    fn gradient_algorithm() {
+        let mut gradient = Gradient::new().smoothing(1.0);
+
+        // steady latency at full usage grows the limit
+        let sample = AdaptiveSample::new(Duration::from_millis(10), 16, false);
+        assert_eq!(gradient.update(16, &sample), 20);
+
+        // latency spike lowers the limit
+        let sample = AdaptiveSample::new(Duration::from_millis(100), 20, false);
+        let limit = gradient.update(20, &sample);
+        assert!(limit < 20, "limit: {limit}");
+
+        // failures lower the limit
+        let sample = AdaptiveSample::new(Duration::from_millis(10), limit, true);
+        assert!(gradient.update(limit, &sample) < limit);
+    }
+
+    #[test]
+    fn gradient_algorithm_unused() {
+        let mut gradient = Gradient::new();
+        let sample = AdaptiveSample::new(Duration::from_millis(10), 1, false);
+        assert_eq!(gradient.update(50, &sample), 50);
+    }
+}
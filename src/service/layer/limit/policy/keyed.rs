@@ -0,0 +1,560 @@
+//! A policy that applies an independent sub-policy per key,
+//! e.g. to limit each client or tenant separately.
+//!
+//! See [`KeyedPolicy`].
+//!
+//! # Examples
+//!
+//! ```
+//! use rama::service::{
+//!     layer::limit::{Limit, policy::{ConcurrentPolicy, KeyedPolicy, PeerIpKey}},
+//!     Context, Service, service_fn,
+//! };
+//! use rama::stream::SocketInfo;
+//! # use std::convert::Infallible;
+//!
+//! # #[tokio::main]
+//! # async fn main() {
+//!
+//! let service = service_fn(|_, _| async {
+//!     Ok::<_, Infallible>(())
+//! });
+//! let service = Limit::new(
+//!     service,
+//!     KeyedPolicy::new(PeerIpKey, || ConcurrentPolicy::max(2)),
+//! );
+//!
+//! let mut ctx = Context::default();
+//! ctx.insert(SocketInfo::new(None, "127.0.0.1:8080".parse().unwrap()));
+//!
+//! let response = service.serve(ctx, ()).await;
+//! assert!(response.is_ok());
+//! # }
+//! ```
+
+use super::{Policy, PolicyOutput, PolicyResult};
+use crate::http::{
+    headers::authorization::Basic, layer::proxy_auth::ProxyUsernameLabels, HeaderName, Request,
+};
+use crate::proxy::ProxyFilter;
+use crate::service::Context;
+use crate::stream::SocketInfo;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    hash::Hash,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::time::Instant;
+
+/// Extracts the key used by a [`KeyedPolicy`] to select
+/// the sub-policy that applies to a request.
+///
+/// Implemented for closures of the form `Fn(&Context<State>, &Request) -> Option<Key>`.
+pub trait KeyExtractor<State, Request>: Send + Sync + 'static {
+    /// The key used to select the sub-policy.
+    type Key: Hash + Eq + Clone + Send + Sync + 'static;
+
+    /// Extract the key from the given [`Context`] and request,
+    /// returning `None` in case no key can be extracted.
+    fn extract(&self, ctx: &Context<State>, req: &Request) -> Option<Self::Key>;
+}
+
+impl<F, K, State, Request> KeyExtractor<State, Request> for F
+where
+    F: Fn(&Context<State>, &Request) -> Option<K> + Send + Sync + 'static,
+    K: Hash + Eq + Clone + Send + Sync + 'static,
+{
+    type Key = K;
+
+    fn extract(&self, ctx: &Context<State>, req: &Request) -> Option<Self::Key> {
+        (self)(ctx, req)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// A [`KeyExtractor`] using the IP address of the peer,
+/// as found in the [`SocketInfo`] of the [`Context`].
+pub struct PeerIpKey;
+
+impl<State, Request> KeyExtractor<State, Request> for PeerIpKey {
+    type Key = IpAddr;
+
+    fn extract(&self, ctx: &Context<State>, _req: &Request) -> Option<Self::Key> {
+        ctx.get::<SocketInfo>().map(|info| info.peer_addr().ip())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// A [`KeyExtractor`] using the username of the [`Basic`] credentials,
+/// as inserted in the [`Context`] by the [`ProxyAuthLayer`].
+///
+/// [`ProxyAuthLayer`]: crate::http::layer::proxy_auth::ProxyAuthLayer
+pub struct ProxyUsernameKey;
+
+impl<State, Request> KeyExtractor<State, Request> for ProxyUsernameKey {
+    type Key = String;
+
+    fn extract(&self, ctx: &Context<State>, _req: &Request) -> Option<Self::Key> {
+        ctx.get::<Basic>().map(|basic| basic.username().to_owned())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// A [`KeyExtractor`] using the [`ProxyUsernameLabels`] found in the [`Context`],
+/// e.g. to limit per session label.
+pub struct ProxyUsernameLabelsKey<const C: char = '-'>;
+
+impl<const C: char, State, Request> KeyExtractor<State, Request> for ProxyUsernameLabelsKey<C> {
+    type Key = Vec<String>;
+
+    fn extract(&self, ctx: &Context<State>, _req: &Request) -> Option<Self::Key> {
+        ctx.get::<ProxyUsernameLabels<C>>()
+            .map(|labels| labels.0.clone())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// A [`KeyExtractor`] using the [`ProxyFilter`] found in the [`Context`].
+pub struct ProxyFilterKey;
+
+impl<State, Request> KeyExtractor<State, Request> for ProxyFilterKey {
+    type Key = ProxyFilter;
+
+    fn extract(&self, ctx: &Context<State>, _req: &Request) -> Option<Self::Key> {
+        ctx.get::<ProxyFilter>().cloned()
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A [`KeyExtractor`] using the value of a header of the http [`Request`],
+/// e.g. an API key.
+pub struct HeaderKey {
+    name: HeaderName,
+}
+
+impl HeaderKey {
+    /// Create a new [`HeaderKey`] for the given header name.
+    pub fn new(name: HeaderName) -> Self {
+        Self { name }
+    }
+}
+
+impl<State, Body> KeyExtractor<State, Request<Body>> for HeaderKey
+where
+    Body: 'static,
+{
+    type Key = Vec<u8>;
+
+    fn extract(&self, _ctx: &Context<State>, req: &Request<Body>) -> Option<Self::Key> {
+        req.headers()
+            .get(&self.name)
+            .map(|value| value.as_bytes().to_vec())
+    }
+}
+
+/// A policy that applies an independent sub-policy per key,
+/// where the key is extracted from the [`Context`] and request using a [`KeyExtractor`].
+///
+/// The sub-policies are created on demand using the given factory,
+/// and are cloned for each request, such that policies which share their state
+/// across clones (e.g. [`ConcurrentPolicy`] and [`RatePolicy`]) keep track of
+/// the budget of each key separately.
+///
+/// Requests for which no key can be extracted are allowed to proceed.
+/// Combine with a policy map in case you want to enforce a different policy for these.
+///
+/// To keep memory bounded, at most `capacity` sub-policies are kept.
+/// When the capacity is reached the least recently used sub-policy is evicted,
+/// and optionally sub-policies which have not been used for a given idle timeout
+/// are evicted as well. Note that an evicted key starts with a fresh budget when seen again.
+///
+/// Sub-policies of which a guard is still alive are never evicted, such that a key
+/// cannot exceed its budget by being evicted. The capacity is therefore exceeded
+/// in case more keys than the capacity have requests in flight.
+///
+/// [`ConcurrentPolicy`]: super::ConcurrentPolicy
+/// [`RatePolicy`]: super::RatePolicy
+pub struct KeyedPolicy<K, F, P, Key> {
+    extractor: K,
+    factory: F,
+    store: Arc<Mutex<KeyedStore<Key, P>>>,
+}
+
+impl<K, F, P, Key> fmt::Debug for KeyedPolicy<K, F, P, Key>
+where
+    K: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let store = self.store.lock().unwrap();
+        f.debug_struct("KeyedPolicy")
+            .field("extractor", &self.extractor)
+            .field("keys", &store.entries.len())
+            .field("capacity", &store.capacity)
+            .field("idle_timeout", &store.idle_timeout)
+            .finish()
+    }
+}
+
+impl<K, F, P, Key> Clone for KeyedPolicy<K, F, P, Key>
+where
+    K: Clone,
+    F: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            extractor: self.extractor.clone(),
+            factory: self.factory.clone(),
+            store: self.store.clone(),
+        }
+    }
+}
+
+/// The default maximum number of keys tracked by a [`KeyedPolicy`].
+const DEFAULT_CAPACITY: usize = 10_000;
+
+impl<K, F, P, Key> KeyedPolicy<K, F, P, Key>
+where
+    F: Fn() -> P,
+{
+    /// Create a new [`KeyedPolicy`], using the given [`KeyExtractor`]
+    /// and the factory used to create the sub-policy for a new key.
+    pub fn new(extractor: K, factory: F) -> Self {
+        Self {
+            extractor,
+            factory,
+            store: Arc::new(Mutex::new(KeyedStore {
+                entries: HashMap::new(),
+                lru: BTreeMap::new(),
+                tick: 0,
+                capacity: DEFAULT_CAPACITY,
+                idle_timeout: None,
+            })),
+        }
+    }
+}
+
+impl<K, F, P, Key> KeyedPolicy<K, F, P, Key> {
+    /// Set the maximum number of keys for which a sub-policy is kept,
+    /// which defaults to `10_000`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the `capacity` is zero.
+    pub fn capacity(self, capacity: usize) -> Self {
+        assert!(capacity > 0, "keyed policy capacity cannot be zero");
+        self.store.lock().unwrap().capacity = capacity;
+        self
+    }
+
+    /// Evict the sub-policies of keys which have not been used for the given duration.
+    pub fn idle_timeout(self, timeout: Duration) -> Self {
+        self.store.lock().unwrap().idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Returns the number of keys for which a sub-policy is currently kept.
+    pub fn len(&self) -> usize {
+        self.store.lock().unwrap().entries.len()
+    }
+
+    /// Returns `true` if no sub-policy is currently kept.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<K, F, P, State, Request> Policy<State, Request> for KeyedPolicy<K, F, P, K::Key>
+where
+    K: KeyExtractor<State, Request>,
+    F: Fn() -> P + Send + Sync + 'static,
+    P: Policy<State, Request> + Clone,
+    State: Send + Sync + 'static,
+    Request: Send + 'static,
+{
+    type Guard = Option<KeyedGuard<P::Guard>>;
+    type Error = P::Error;
+
+    async fn check(
+        &self,
+        ctx: Context<State>,
+        request: Request,
+    ) -> PolicyResult<State, Request, Self::Guard, Self::Error> {
+        let (policy, in_use) = match self.extractor.extract(&ctx, &request) {
+            Some(key) => self
+                .store
+                .lock()
+                .unwrap()
+                .get_or_insert_with(key, &self.factory),
+            None => {
+                return PolicyResult {
+                    ctx,
+                    request,
+                    output: PolicyOutput::Ready(None),
+                }
+            }
+        };
+
+        let result = policy.check(ctx, request).await;
+        PolicyResult {
+            ctx: result.ctx,
+            request: result.request,
+            output: match result.output {
+                PolicyOutput::Ready(guard) => PolicyOutput::Ready(Some(KeyedGuard {
+                    guard,
+                    _in_use: in_use,
+                })),
+                PolicyOutput::Abort(err) => PolicyOutput::Abort(err),
+                PolicyOutput::Retry => PolicyOutput::Retry,
+            },
+        }
+    }
+}
+
+/// The guard of a [`KeyedPolicy`], wrapping the guard of the sub-policy of the key,
+/// which keeps that sub-policy from being evicted for as long as it is alive.
+#[derive(Debug)]
+pub struct KeyedGuard<G> {
+    guard: G,
+    _in_use: Arc<()>,
+}
+
+impl<G> KeyedGuard<G> {
+    /// Returns a reference to the guard of the sub-policy.
+    pub fn get_ref(&self) -> &G {
+        &self.guard
+    }
+}
+
+#[derive(Debug)]
+struct KeyedStore<Key, P> {
+    entries: HashMap<Key, KeyedEntry<P>>,
+    /// the keys, ordered from least to most recently used
+    lru: BTreeMap<u64, Key>,
+    tick: u64,
+    capacity: usize,
+    idle_timeout: Option<Duration>,
+}
+
+#[derive(Debug)]
+struct KeyedEntry<P> {
+    policy: P,
+    last_used: Instant,
+    tick: u64,
+    /// shared with the guards of the entry, and the checks in progress
+    in_use: Arc<()>,
+}
+
+impl<P> KeyedEntry<P> {
+    fn in_use(&self) -> bool {
+        Arc::strong_count(&self.in_use) > 1
+    }
+
+    fn is_idle(&self, now: Instant, idle_timeout: Option<Duration>) -> bool {
+        idle_timeout.is_some_and(|timeout| now.duration_since(self.last_used) >= timeout)
+    }
+}
+
+impl<Key, P> KeyedStore<Key, P>
+where
+    Key: Hash + Eq + Clone,
+    P: Clone,
+{
+    fn get_or_insert_with(&mut self, key: Key, factory: impl Fn() -> P) -> (P, Arc<()>) {
+        let now = Instant::now();
+        self.tick += 1;
+        let tick = self.tick;
+
+        if let Some(entry) = self.entries.get_mut(&key) {
+            if !entry.is_idle(now, self.idle_timeout) || entry.in_use() {
+                self.lru.remove(&entry.tick);
+                self.lru.insert(tick, key);
+                entry.tick = tick;
+                entry.last_used = now;
+                return (entry.policy.clone(), entry.in_use.clone());
+            }
+            self.remove(&key);
+        }
+
+        self.evict(now);
+
+        let policy = factory();
+        let in_use = Arc::new(());
+        self.lru.insert(tick, key.clone());
+        self.entries.insert(
+            key,
+            KeyedEntry {
+                policy: policy.clone(),
+                last_used: now,
+                tick,
+                in_use: in_use.clone(),
+            },
+        );
+        (policy, in_use)
+    }
+
+    fn remove(&mut self, key: &Key) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.lru.remove(&entry.tick);
+        }
+    }
+
+    /// Evict the idle entries, and the least recently used ones while at capacity,
+    /// skipping the entries which are still in use.
+    fn evict(&mut self, now: Instant) {
+        let mut len = self.entries.len();
+        let mut evicted = Vec::new();
+        for key in self.lru.values() {
+            let entry = &self.entries[key];
+            if len < self.capacity && !entry.is_idle(now, self.idle_timeout) {
+                break;
+            }
+            if !entry.in_use() {
+                evicted.push(key.clone());
+                len -= 1;
+            }
+        }
+        for key in evicted {
+            self.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::layer::limit::policy::{ConcurrentPolicy, LimitReached, RatePolicy};
+
+    fn assert_ready<S, R, G, E>(result: PolicyResult<S, R, G, E>) -> G {
+        match result.output {
+            PolicyOutput::Ready(guard) => guard,
+            _ => panic!("unexpected output, expected ready"),
+        }
+    }
+
+    fn assert_abort<S, R, G, E>(result: PolicyResult<S, R, G, E>) -> E {
+        match result.output {
+            PolicyOutput::Abort(err) => err,
+            _ => panic!("unexpected output, expected abort"),
+        }
+    }
+
+    fn ctx_for_ip(ip: &str) -> Context<()> {
+        let mut ctx = Context::default();
+        ctx.insert(SocketInfo::new(None, format!("{ip}:1234").parse().unwrap()));
+        ctx
+    }
+
+    #[tokio::test]
+    async fn keyed_policy_per_key() {
+        let policy = KeyedPolicy::new(PeerIpKey, || ConcurrentPolicy::max(1));
+
+        let guard_a = assert_ready(policy.check(ctx_for_ip("10.0.0.1"), ()).await);
+        let _: LimitReached = assert_abort(policy.check(ctx_for_ip("10.0.0.1"), ()).await);
+        let _guard_b = assert_ready(policy.check(ctx_for_ip("10.0.0.2"), ()).await);
+        assert_eq!(policy.len(), 2);
+
+        drop(guard_a);
+        assert_ready(policy.check(ctx_for_ip("10.0.0.1"), ()).await);
+    }
+
+    #[tokio::test]
+    async fn keyed_policy_no_key() {
+        let policy = KeyedPolicy::new(PeerIpKey, || ConcurrentPolicy::max(0));
+
+        let guard = assert_ready(policy.check(Context::default(), ()).await);
+        assert!(guard.is_none());
+        assert!(policy.is_empty());
+    }
+
+    #[tokio::test]
+    async fn keyed_policy_header_key() {
+        let policy = KeyedPolicy::new(HeaderKey::new(HeaderName::from_static("x-api-key")), || {
+            RatePolicy::per_minute(1)
+        });
+        let request = |key: &str| {
+            Request::builder()
+                .header("x-api-key", key)
+                .body(())
+                .unwrap()
+        };
+
+        assert_ready(policy.check(Context::default(), request("a")).await);
+        assert_abort(policy.check(Context::default(), request("a")).await);
+        assert_ready(policy.check(Context::default(), request("b")).await);
+    }
+
+    #[tokio::test]
+    async fn keyed_policy_fn_key() {
+        let policy = KeyedPolicy::new(
+            |_: &Context<()>, req: &u8| Some(*req % 2),
+            || ConcurrentPolicy::max(1),
+        );
+
+        let _guard = assert_ready(policy.check(Context::default(), 1).await);
+        assert_abort(policy.check(Context::default(), 3).await);
+        assert_ready(policy.check(Context::default(), 2).await);
+    }
+
+    #[tokio::test]
+    async fn keyed_policy_lru_eviction() {
+        let policy = KeyedPolicy::new(PeerIpKey, || RatePolicy::per_minute(1)).capacity(2);
+
+        assert_ready(policy.check(ctx_for_ip("10.0.0.1"), ()).await);
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        assert_ready(policy.check(ctx_for_ip("10.0.0.2"), ()).await);
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        assert_ready(policy.check(ctx_for_ip("10.0.0.3"), ()).await);
+        assert_eq!(policy.len(), 2);
+
+        // 10.0.0.1 was evicted and starts with a fresh budget,
+        // while 10.0.0.3 is still tracked
+        assert_ready(policy.check(ctx_for_ip("10.0.0.1"), ()).await);
+        assert_abort(policy.check(ctx_for_ip("10.0.0.3"), ()).await);
+    }
+
+    #[tokio::test]
+    async fn keyed_policy_lru_eviction_skips_in_use() {
+        let policy = KeyedPolicy::new(PeerIpKey, || ConcurrentPolicy::max(1)).capacity(1);
+
+        let _guard = assert_ready(policy.check(ctx_for_ip("10.0.0.1"), ()).await);
+        let guard_b = assert_ready(policy.check(ctx_for_ip("10.0.0.2"), ()).await);
+        assert_eq!(policy.len(), 2);
+
+        // 10.0.0.1 is still in use, so it keeps its budget
+        let _: LimitReached = assert_abort(policy.check(ctx_for_ip("10.0.0.1"), ()).await);
+
+        drop(guard_b);
+        assert_ready(policy.check(ctx_for_ip("10.0.0.3"), ()).await);
+        assert_eq!(policy.len(), 2);
+        let _: LimitReached = assert_abort(policy.check(ctx_for_ip("10.0.0.1"), ()).await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn keyed_policy_idle_eviction_skips_in_use() {
+        let policy = KeyedPolicy::new(PeerIpKey, || ConcurrentPolicy::max(1))
+            .idle_timeout(Duration::from_secs(60));
+
+        let guard = assert_ready(policy.check(ctx_for_ip("10.0.0.1"), ()).await);
+        tokio::time::advance(Duration::from_secs(61)).await;
+        assert_ready(policy.check(ctx_for_ip("10.0.0.2"), ()).await);
+        let _: LimitReached = assert_abort(policy.check(ctx_for_ip("10.0.0.1"), ()).await);
+
+        drop(guard);
+        assert_ready(policy.check(ctx_for_ip("10.0.0.1"), ()).await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn keyed_policy_idle_eviction() {
+        let policy = KeyedPolicy::new(PeerIpKey, || RatePolicy::per_hour(1))
+            .idle_timeout(Duration::from_secs(60));
+
+        assert_ready(policy.check(ctx_for_ip("10.0.0.1"), ()).await);
+        assert_abort(policy.check(ctx_for_ip("10.0.0.1"), ()).await);
+
+        tokio::time::advance(Duration::from_secs(61)).await;
+        assert_ready(policy.check(ctx_for_ip("10.0.0.1"), ()).await);
+    }
+}
@@ -36,6 +36,28 @@ mod concurrent;
 #[doc(inline)]
 pub use concurrent::{ConcurrentCounter, ConcurrentPolicy, ConcurrentTracker, LimitReached};
 
+mod rate;
+#[doc(inline)]
+pub use rate::{RateLimitReached, RateLimitStatus, RatePolicy};
+
+mod keyed;
+#[doc(inline)]
+pub use keyed::{
+    HeaderKey, KeyExtractor, KeyedGuard, KeyedPolicy, PeerIpKey, ProxyFilterKey, ProxyUsernameKey,
+    ProxyUsernameLabelsKey,
+};
+
+mod queue;
+#[doc(inline)]
+pub use queue::{Fifo, QueueError, QueueGuard, QueuePolicy, QueueStatus};
+
+mod adaptive;
+#[doc(inline)]
+pub use adaptive::{
+    AdaptiveAlgorithm, AdaptiveGuard, AdaptiveOutcome, AdaptivePolicy, AdaptiveSample, Aimd,
+    Gradient,
+};
+
 mod matcher;
 
 #[derive(Debug)]
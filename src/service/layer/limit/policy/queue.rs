@@ -0,0 +1,515 @@
+//! A policy that limits the number of concurrent requests,
+//! queueing the requests that exceed the limit.
+//!
+//! See [`QueuePolicy`].
+//!
+//! # Examples
+//!
+//! ```
+//! use rama::service::{
+//!     layer::limit::{Limit, policy::QueuePolicy},
+//!     Context, Service, service_fn,
+//! };
+//! # use std::convert::Infallible;
+//! use std::time::Duration;
+//!
+//! # #[tokio::main]
+//! # async fn main() {
+//!
+//! let service = service_fn(|_, _| async {
+//!     Ok::<_, Infallible>(())
+//! });
+//! let service = Limit::new(
+//!     service,
+//!     QueuePolicy::new(2, 100).max_wait(Duration::from_secs(5)),
+//! );
+//!
+//! let response = service.serve(Context::default(), ()).await;
+//! assert!(response.is_ok());
+//! # }
+//! ```
+
+use super::{KeyExtractor, Policy, PolicyOutput, PolicyResult};
+use crate::service::Context;
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, VecDeque},
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{sync::oneshot, time::Instant};
+
+/// A policy that limits the number of concurrent requests,
+/// parking the requests that exceed the limit in a bounded queue
+/// until a slot is released or the maximum wait time is reached.
+///
+/// Contrary to a [`ConcurrentPolicy`] with a [`Backoff`], waiting requests do not poll,
+/// but are woken up in order when a slot is released, by dropping the [`QueueGuard`].
+///
+/// By default the queue is FIFO. Using [`QueuePolicy::fair`] the waiting requests are
+/// grouped per key, extracted using a [`KeyExtractor`], and the groups are served
+/// in a round-robin fashion, such that a single client cannot starve the others.
+///
+/// Requests that are allowed to proceed get a [`QueueStatus`] inserted in the [`Context`],
+/// while requests that cannot be queued or waited too long are aborted with a [`QueueError`].
+///
+/// Clones of the policy share the same slots and queue.
+///
+/// [`ConcurrentPolicy`]: super::ConcurrentPolicy
+/// [`Backoff`]: crate::service::util::backoff::Backoff
+#[derive(Debug)]
+pub struct QueuePolicy<K = Fifo> {
+    extractor: K,
+    max_wait: Option<Duration>,
+    state: Arc<Mutex<QueueState>>,
+}
+
+impl<K> Clone for QueuePolicy<K>
+where
+    K: Clone,
+{
+    fn clone(&self) -> Self {
+        QueuePolicy {
+            extractor: self.extractor.clone(),
+            max_wait: self.max_wait,
+            state: self.state.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// The [`KeyExtractor`] used by a [`QueuePolicy`] by default,
+/// putting all requests in a single FIFO queue.
+pub struct Fifo;
+
+impl<State, Request> KeyExtractor<State, Request> for Fifo {
+    type Key = ();
+
+    fn extract(&self, _ctx: &Context<State>, _req: &Request) -> Option<Self::Key> {
+        Some(())
+    }
+}
+
+impl QueuePolicy {
+    /// Create a new FIFO [`QueuePolicy`], allowing `max_concurrent` requests at once,
+    /// and queueing at most `max_queued` requests waiting for a slot.
+    pub fn new(max_concurrent: usize, max_queued: usize) -> Self {
+        QueuePolicy {
+            extractor: Fifo,
+            max_wait: None,
+            state: Arc::new(Mutex::new(QueueState {
+                active: 0,
+                max_concurrent,
+                queued: 0,
+                max_queued,
+                next_id: 0,
+                queues: HashMap::new(),
+                order: VecDeque::new(),
+            })),
+        }
+    }
+}
+
+impl<K> QueuePolicy<K> {
+    /// Set the maximum time a request waits in the queue,
+    /// after which it is aborted with [`QueueError::Timeout`].
+    ///
+    /// By default requests wait until a slot is released.
+    pub fn max_wait(mut self, max_wait: Duration) -> Self {
+        self.max_wait = Some(max_wait);
+        self
+    }
+
+    /// Serve the waiting requests fairly, in a round-robin fashion
+    /// over the keys extracted using the given [`KeyExtractor`].
+    ///
+    /// Requests for which no key can be extracted share a single queue.
+    pub fn fair<T>(self, extractor: T) -> QueuePolicy<T> {
+        QueuePolicy {
+            extractor,
+            max_wait: self.max_wait,
+            state: self.state,
+        }
+    }
+
+    /// Returns the number of requests currently waiting in the queue.
+    pub fn queued(&self) -> usize {
+        self.state.lock().unwrap().queued
+    }
+
+    /// Returns the number of requests currently holding a slot.
+    pub fn active(&self) -> usize {
+        self.state.lock().unwrap().active
+    }
+}
+
+impl<K, State, Request> Policy<State, Request> for QueuePolicy<K>
+where
+    K: KeyExtractor<State, Request>,
+    State: Send + Sync + 'static,
+    Request: Send + 'static,
+{
+    type Guard = QueueGuard;
+    type Error = QueueError;
+
+    async fn check(
+        &self,
+        mut ctx: Context<State>,
+        request: Request,
+    ) -> PolicyResult<State, Request, Self::Guard, Self::Error> {
+        let key = self.extractor.extract(&ctx, &request).map(|key| {
+            let mut hasher = DefaultHasher::new();
+            key.hash(&mut hasher);
+            hasher.finish()
+        });
+
+        let output = match self.acquire(key.unwrap_or_default()).await {
+            Ok((guard, status)) => {
+                ctx.insert(status);
+                PolicyOutput::Ready(guard)
+            }
+            Err(err) => PolicyOutput::Abort(err),
+        };
+
+        PolicyResult {
+            ctx,
+            request,
+            output,
+        }
+    }
+}
+
+impl<K> QueuePolicy<K> {
+    async fn acquire(&self, key: u64) -> Result<(QueueGuard, QueueStatus), QueueError> {
+        let start = Instant::now();
+        let (id, depth, rx) = {
+            let mut state = self.state.lock().unwrap();
+            if state.active < state.max_concurrent && state.queued == 0 {
+                state.active += 1;
+                return Ok((
+                    QueueGuard {
+                        state: self.state.clone(),
+                    },
+                    QueueStatus {
+                        depth: 0,
+                        wait: Duration::ZERO,
+                    },
+                ));
+            }
+            if state.queued >= state.max_queued {
+                return Err(QueueError::Full);
+            }
+            let depth = state.queued;
+            let (id, rx) = state.enqueue(key);
+            (id, depth, rx)
+        };
+
+        let mut waiter = Waiter {
+            state: &self.state,
+            key,
+            id,
+            rx: Some(rx),
+        };
+        let received = match self.max_wait {
+            Some(max_wait) => tokio::time::timeout(max_wait, waiter.recv())
+                .await
+                .unwrap_or(false),
+            None => waiter.recv().await,
+        };
+        // in case of a timeout the slot might still have been handed over in the meantime
+        if received || waiter.cancel() {
+            Ok((
+                QueueGuard {
+                    state: self.state.clone(),
+                },
+                QueueStatus {
+                    depth,
+                    wait: start.elapsed(),
+                },
+            ))
+        } else {
+            Err(QueueError::Timeout(start.elapsed()))
+        }
+    }
+}
+
+#[derive(Debug)]
+struct QueueState {
+    active: usize,
+    max_concurrent: usize,
+    queued: usize,
+    max_queued: usize,
+    next_id: u64,
+    /// waiters per key
+    queues: HashMap<u64, VecDeque<(u64, oneshot::Sender<()>)>>,
+    /// round-robin order of the keys with waiters
+    order: VecDeque<u64>,
+}
+
+impl QueueState {
+    fn enqueue(&mut self, key: u64) -> (u64, oneshot::Receiver<()>) {
+        let (tx, rx) = oneshot::channel();
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        let queue = self.queues.entry(key).or_default();
+        if queue.is_empty() {
+            self.order.push_back(key);
+        }
+        queue.push_back((id, tx));
+        self.queued += 1;
+        (id, rx)
+    }
+
+    /// Remove the waiter from the queue, returning `false` if it was already dequeued.
+    fn remove(&mut self, key: u64, id: u64) -> bool {
+        let Some(queue) = self.queues.get_mut(&key) else {
+            return false;
+        };
+        let Some(index) = queue.iter().position(|(waiter, _)| *waiter == id) else {
+            return false;
+        };
+        queue.remove(index);
+        self.queued -= 1;
+        if queue.is_empty() {
+            self.queues.remove(&key);
+            self.order.retain(|k| *k != key);
+        }
+        true
+    }
+
+    /// Hand over the released slot to the next waiter, if any.
+    fn release(&mut self) {
+        while let Some(key) = self.order.pop_front() {
+            let queue = self.queues.get_mut(&key).expect("queue for key in order");
+            let (_, tx) = queue.pop_front().expect("non-empty queue for key in order");
+            if queue.is_empty() {
+                self.queues.remove(&key);
+            } else {
+                self.order.push_back(key);
+            }
+            self.queued -= 1;
+            if tx.send(()).is_ok() {
+                return;
+            }
+        }
+        self.active -= 1;
+    }
+}
+
+/// A waiter parked in the queue, which gives back
+/// a handed over slot in case it is dropped before it could take it.
+struct Waiter<'a> {
+    state: &'a Arc<Mutex<QueueState>>,
+    key: u64,
+    id: u64,
+    rx: Option<oneshot::Receiver<()>>,
+}
+
+impl Waiter<'_> {
+    async fn recv(&mut self) -> bool {
+        let rx = self.rx.as_mut().expect("receiver");
+        let received = rx.await.is_ok();
+        if received {
+            self.rx = None;
+        }
+        received
+    }
+
+    /// Stop waiting, returning `true` if the slot was handed over regardless.
+    fn cancel(&mut self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.remove(self.key, self.id) {
+            self.rx = None;
+            return false;
+        }
+        // already dequeued, so the slot is either handed over already or never will be
+        match self.rx.take() {
+            Some(mut rx) => rx.try_recv().is_ok(),
+            None => false,
+        }
+    }
+}
+
+impl Drop for Waiter<'_> {
+    fn drop(&mut self) {
+        if self.rx.is_some() && self.cancel() {
+            self.state.lock().unwrap().release();
+        }
+    }
+}
+
+/// The guard of a [`QueuePolicy`], releasing the slot
+/// to the next waiting request when dropped.
+#[derive(Debug)]
+pub struct QueueGuard {
+    state: Arc<Mutex<QueueState>>,
+}
+
+impl Drop for QueueGuard {
+    fn drop(&mut self) {
+        self.state.lock().unwrap().release();
+    }
+}
+
+#[derive(Debug, Clone)]
+/// The status of a request which passed a [`QueuePolicy`],
+/// inserted in the [`Context`] for observability.
+pub struct QueueStatus {
+    depth: usize,
+    wait: Duration,
+}
+
+impl QueueStatus {
+    /// The number of requests that were already waiting in the queue
+    /// when this request was queued, `0` if it was not queued at all.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// The time this request waited in the queue.
+    pub fn wait(&self) -> Duration {
+        self.wait
+    }
+}
+
+#[derive(Debug, Clone)]
+/// The error that indicates the request is aborted by a [`QueuePolicy`].
+pub enum QueueError {
+    /// The queue is full.
+    Full,
+    /// The request waited too long, for the contained duration.
+    Timeout(Duration),
+}
+
+impl std::fmt::Display for QueueError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QueueError::Full => write!(f, "QueueError: queue is full"),
+            QueueError::Timeout(wait) => write!(f, "QueueError: timeout after {:?}", wait),
+        }
+    }
+}
+
+impl std::error::Error for QueueError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_ready<S, R, G, E>(result: PolicyResult<S, R, G, E>) -> (Context<S>, G) {
+        match result.output {
+            PolicyOutput::Ready(guard) => (result.ctx, guard),
+            _ => panic!("unexpected output, expected ready"),
+        }
+    }
+
+    fn assert_abort<S, R, G, E>(result: PolicyResult<S, R, G, E>) -> E {
+        match result.output {
+            PolicyOutput::Abort(err) => err,
+            _ => panic!("unexpected output, expected abort"),
+        }
+    }
+
+    #[tokio::test]
+    async fn queue_policy_full() {
+        let policy = QueuePolicy::new(1, 0);
+
+        let (ctx, _guard) = assert_ready(policy.check(Context::default(), ()).await);
+        assert_eq!(ctx.get::<QueueStatus>().unwrap().depth(), 0);
+        assert!(matches!(
+            assert_abort(policy.check(Context::default(), ()).await),
+            QueueError::Full
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn queue_policy_timeout() {
+        let policy = QueuePolicy::new(1, 1).max_wait(Duration::from_secs(1));
+
+        let (_, _guard) = assert_ready(policy.check(Context::default(), ()).await);
+        match assert_abort(policy.check(Context::default(), ()).await) {
+            QueueError::Timeout(wait) => assert_eq!(wait, Duration::from_secs(1)),
+            QueueError::Full => panic!("unexpected error: queue is full"),
+        }
+        assert_eq!(policy.queued(), 0);
+        assert_eq!(policy.active(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn queue_policy_wakes_in_order() {
+        let policy = QueuePolicy::new(1, 10);
+        let (_, guard) = assert_ready(policy.check(Context::default(), ()).await);
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        for i in 0..3 {
+            let policy = policy.clone();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let (ctx, guard) = assert_ready(policy.check(Context::default(), ()).await);
+                tx.send((i, ctx.get::<QueueStatus>().unwrap().depth()))
+                    .unwrap();
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                drop(guard);
+            });
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(policy.queued(), 3);
+
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        drop(guard);
+        for expected in [(0, 0), (1, 1), (2, 2)] {
+            assert_eq!(rx.recv().await.unwrap(), expected);
+        }
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        assert_eq!(policy.active(), 0);
+        assert_eq!(policy.queued(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn queue_policy_fair() {
+        let policy = QueuePolicy::new(1, 10).fair(|_: &Context<()>, req: &&'static str| Some(*req));
+        let (_, guard) = assert_ready(policy.check(Context::default(), "a").await);
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        for key in ["a", "a", "a", "b"] {
+            let policy = policy.clone();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let (_, guard) = assert_ready(policy.check(Context::default(), key).await);
+                tx.send(key).unwrap();
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                drop(guard);
+            });
+            tokio::task::yield_now().await;
+        }
+
+        drop(guard);
+        let mut order = Vec::new();
+        for _ in 0..4 {
+            order.push(rx.recv().await.unwrap());
+        }
+        assert_eq!(order, ["a", "b", "a", "a"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn queue_policy_cancelled_waiter() {
+        let policy = QueuePolicy::new(1, 10);
+        let (_, guard) = assert_ready(policy.check(Context::default(), ()).await);
+
+        let handle = tokio::spawn({
+            let policy = policy.clone();
+            async move {
+                let _ = policy.check(Context::default(), ()).await;
+            }
+        });
+        tokio::task::yield_now().await;
+        assert_eq!(policy.queued(), 1);
+        handle.abort();
+        let _ = handle.await;
+
+        drop(guard);
+        assert_eq!(policy.active(), 0);
+        assert_eq!(policy.queued(), 0);
+        assert_ready(policy.check(Context::default(), ()).await);
+    }
+}
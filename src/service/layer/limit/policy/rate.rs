@@ -0,0 +1,570 @@
+//! A policy that limits the rate of requests.
+//!
+//! See [`RatePolicy`].
+//!
+//! # Examples
+//!
+//! ```
+//! use rama::service::{
+//!     layer::limit::{Limit, policy::RatePolicy},
+//!     Context, Service, service_fn,
+//! };
+//! # use std::convert::Infallible;
+//!
+//! # #[tokio::main]
+//! # async fn main() {
+//!
+//! let service = service_fn(|_, _| async {
+//!     Ok::<_, Infallible>(())
+//! });
+//! let service = Limit::new(service, RatePolicy::per_minute(100));
+//!
+//! let response = service.serve(Context::default(), ()).await;
+//! assert!(response.is_ok());
+//! # }
+//! ```
+
+use super::{Policy, PolicyOutput, PolicyResult};
+use crate::http::{header, HeaderValue, IntoResponse, Response, StatusCode};
+use crate::service::{util::backoff::Backoff, Context};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::time::Instant;
+
+/// A policy that limits the rate of requests,
+/// e.g. 100 requests per minute.
+///
+/// It is implemented using the [Generic Cell Rate Algorithm] (GCRA),
+/// which is equivalent to a token bucket which is refilled continuously,
+/// with the burst being the size of the bucket. By default the burst is equal to the limit,
+/// meaning that the full limit can be consumed at once, after which the requests
+/// are spread evenly over the period.
+///
+/// Clones of the policy share the same state, and thus the same limit.
+///
+/// When the limit is reached the request is aborted with a [`RateLimitReached`] error,
+/// unless a [`Backoff`] is used, in which case the request is retried as long as the backoff allows.
+/// For requests that are allowed to proceed, the [`RateLimitStatus`] is inserted in the [`Context`].
+///
+/// [Generic Cell Rate Algorithm]: https://en.wikipedia.org/wiki/Generic_cell_rate_algorithm
+#[derive(Debug)]
+pub struct RatePolicy<B> {
+    limiter: RateLimiter,
+    backoff: B,
+}
+
+impl<B> Clone for RatePolicy<B>
+where
+    B: Clone,
+{
+    fn clone(&self) -> Self {
+        RatePolicy {
+            limiter: self.limiter.clone(),
+            backoff: self.backoff.clone(),
+        }
+    }
+}
+
+impl RatePolicy<()> {
+    /// Create a new [`RatePolicy`], allowing `limit` requests per `period`.
+    ///
+    /// Rates of more than one request per nanosecond are clamped to that rate.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the `limit` or `period` is zero.
+    pub fn new(limit: u64, period: Duration) -> Self {
+        RatePolicy {
+            limiter: RateLimiter::new(limit, period),
+            backoff: (),
+        }
+    }
+
+    /// Create a new [`RatePolicy`], allowing `limit` requests per second.
+    pub fn per_second(limit: u64) -> Self {
+        Self::new(limit, Duration::from_secs(1))
+    }
+
+    /// Create a new [`RatePolicy`], allowing `limit` requests per minute.
+    pub fn per_minute(limit: u64) -> Self {
+        Self::new(limit, Duration::from_secs(60))
+    }
+
+    /// Create a new [`RatePolicy`], allowing `limit` requests per hour.
+    pub fn per_hour(limit: u64) -> Self {
+        Self::new(limit, Duration::from_secs(3600))
+    }
+}
+
+impl<B> RatePolicy<B> {
+    /// Set the maximum number of requests that can be made at once,
+    /// which defaults to the limit itself.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the `burst` is zero.
+    pub fn burst(mut self, burst: u64) -> Self {
+        assert!(burst > 0, "rate limit burst cannot be zero");
+        self.limiter = RateLimiter::with_burst(self.limiter.limit, self.limiter.period, burst);
+        self
+    }
+
+    /// Use the given [`Backoff`] policy to retry
+    /// when the rate limit is reached, instead of aborting immediately.
+    pub fn with_backoff<T>(self, backoff: T) -> RatePolicy<T> {
+        RatePolicy {
+            limiter: self.limiter,
+            backoff,
+        }
+    }
+}
+
+impl<B, State, Request> Policy<State, Request> for RatePolicy<B>
+where
+    B: Backoff,
+    State: Send + Sync + 'static,
+    Request: Send + 'static,
+{
+    type Guard = ();
+    type Error = RateLimitReached;
+
+    async fn check(
+        &self,
+        mut ctx: Context<State>,
+        request: Request,
+    ) -> PolicyResult<State, Request, Self::Guard, Self::Error> {
+        let err = match self.limiter.try_acquire() {
+            Ok(status) => {
+                ctx.insert(status);
+                return PolicyResult {
+                    ctx,
+                    request,
+                    output: PolicyOutput::Ready(()),
+                };
+            }
+            Err(err) => err,
+        };
+
+        let output = if !self.backoff.next_backoff().await {
+            PolicyOutput::Abort(err)
+        } else {
+            PolicyOutput::Retry
+        };
+
+        PolicyResult {
+            ctx,
+            request,
+            output,
+        }
+    }
+}
+
+impl<B, State, Request> Policy<State, Request> for RatePolicy<Option<B>>
+where
+    B: Backoff,
+    State: Send + Sync + 'static,
+    Request: Send + 'static,
+{
+    type Guard = ();
+    type Error = RateLimitReached;
+
+    async fn check(
+        &self,
+        mut ctx: Context<State>,
+        request: Request,
+    ) -> PolicyResult<State, Request, Self::Guard, Self::Error> {
+        let err = match self.limiter.try_acquire() {
+            Ok(status) => {
+                ctx.insert(status);
+                return PolicyResult {
+                    ctx,
+                    request,
+                    output: PolicyOutput::Ready(()),
+                };
+            }
+            Err(err) => err,
+        };
+        let output = match &self.backoff {
+            Some(backoff) => {
+                if !backoff.next_backoff().await {
+                    PolicyOutput::Abort(err)
+                } else {
+                    PolicyOutput::Retry
+                }
+            }
+            None => PolicyOutput::Abort(err),
+        };
+        PolicyResult {
+            ctx,
+            request,
+            output,
+        }
+    }
+}
+
+impl<State, Request> Policy<State, Request> for RatePolicy<()>
+where
+    State: Send + Sync + 'static,
+    Request: Send + 'static,
+{
+    type Guard = ();
+    type Error = RateLimitReached;
+
+    async fn check(
+        &self,
+        mut ctx: Context<State>,
+        request: Request,
+    ) -> PolicyResult<State, Request, Self::Guard, Self::Error> {
+        let output = match self.limiter.try_acquire() {
+            Ok(status) => {
+                ctx.insert(status);
+                PolicyOutput::Ready(())
+            }
+            Err(err) => PolicyOutput::Abort(err),
+        };
+        PolicyResult {
+            ctx,
+            request,
+            output,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct RateLimiter {
+    limit: u64,
+    period: Duration,
+    /// the time it takes to replenish a single request, in nanoseconds
+    emission_interval: u128,
+    /// the time it takes to replenish the full burst, in nanoseconds
+    tolerance: u128,
+    /// the theoretical arrival time of the next request
+    tat: Arc<Mutex<Option<Instant>>>,
+}
+
+impl RateLimiter {
+    fn new(limit: u64, period: Duration) -> Self {
+        Self::with_burst(limit, period, limit)
+    }
+
+    fn with_burst(limit: u64, period: Duration, burst: u64) -> Self {
+        assert!(limit > 0, "rate limit cannot be zero");
+        assert!(!period.is_zero(), "rate limit period cannot be zero");
+        // limits of more than one request per nanosecond are clamped to that rate
+        let emission_interval = (period.as_nanos() / u128::from(limit)).clamp(1, MAX_NANOS);
+        Self {
+            limit,
+            period,
+            emission_interval,
+            tolerance: emission_interval
+                .saturating_mul(u128::from(burst))
+                .min(MAX_NANOS),
+            tat: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    fn try_acquire(&self) -> Result<RateLimitStatus, RateLimitReached> {
+        let now = Instant::now();
+        let mut tat = self.tat.lock().unwrap();
+
+        // the time until the theoretical arrival time, before and after this request
+        let backlog = tat.map_or(0, |tat| tat.saturating_duration_since(now).as_nanos());
+        let new_backlog = backlog + self.emission_interval;
+        if new_backlog > self.tolerance {
+            return Err(RateLimitReached {
+                limit: self.limit,
+                period: self.period,
+                retry_after: duration_from_nanos(new_backlog - self.tolerance),
+                reset: duration_from_nanos(backlog),
+            });
+        }
+
+        let reset = duration_from_nanos(new_backlog);
+        if let Some(new_tat) = now.checked_add(reset) {
+            *tat = Some(new_tat);
+        }
+        let remaining = (self.tolerance - new_backlog) / self.emission_interval;
+        Ok(RateLimitStatus {
+            limit: self.limit,
+            period: self.period,
+            remaining: u64::try_from(remaining).unwrap_or(u64::MAX),
+            reset,
+        })
+    }
+}
+
+/// The maximum number of nanoseconds the limiter keeps track of, about 584 years.
+const MAX_NANOS: u128 = u64::MAX as u128;
+
+fn duration_from_nanos(nanos: u128) -> Duration {
+    Duration::from_nanos(u64::try_from(nanos).unwrap_or(u64::MAX))
+}
+
+#[derive(Debug, Clone)]
+/// The status of a [`RatePolicy`], inserted in the [`Context`]
+/// for requests that are allowed to proceed.
+pub struct RateLimitStatus {
+    limit: u64,
+    period: Duration,
+    remaining: u64,
+    reset: Duration,
+}
+
+impl RateLimitStatus {
+    /// The maximum number of requests allowed per period.
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// The period over which the limit applies.
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// The number of requests that can still be made right away.
+    pub fn remaining(&self) -> u64 {
+        self.remaining
+    }
+
+    /// The time until the limit is fully replenished.
+    pub fn reset(&self) -> Duration {
+        self.reset
+    }
+}
+
+/// The error that indicates the request is aborted,
+/// because the rate limit is reached.
+///
+/// For HTTP services it can be turned into a `429 Too Many Requests` response,
+/// with the `Retry-After` and `RateLimit-*` headers set, using its [`IntoResponse`] implementation.
+///
+/// # Example
+///
+/// ```
+/// use rama::{
+///     error::BoxError,
+///     http::{IntoResponse, Response, StatusCode},
+///     service::{
+///         layer::{limit::policy::{RateLimitReached, RatePolicy}, LimitLayer},
+///         ServiceBuilder,
+///     },
+/// };
+///
+/// let builder = ServiceBuilder::new()
+///     .map_result(|result: Result<Response, BoxError>| match result {
+///         Ok(response) => Ok::<_, BoxError>(response),
+///         Err(err) => match err.downcast::<RateLimitReached>() {
+///             Ok(err) => Ok(err.into_response()),
+///             Err(err) => Err(err),
+///         },
+///     })
+///     .layer(LimitLayer::new(RatePolicy::per_second(10)));
+/// ```
+#[derive(Debug, Clone)]
+pub struct RateLimitReached {
+    limit: u64,
+    period: Duration,
+    retry_after: Duration,
+    reset: Duration,
+}
+
+impl RateLimitReached {
+    /// The maximum number of requests allowed per period.
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// The period over which the limit applies.
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// The time after which the request can be retried.
+    pub fn retry_after(&self) -> Duration {
+        self.retry_after
+    }
+
+    /// The time until the limit is fully replenished.
+    pub fn reset(&self) -> Duration {
+        self.reset
+    }
+}
+
+impl std::fmt::Display for RateLimitReached {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "RateLimitReached (retry after {:?})", self.retry_after)
+    }
+}
+
+impl std::error::Error for RateLimitReached {}
+
+impl IntoResponse for RateLimitReached {
+    fn into_response(self) -> Response {
+        (
+            StatusCode::TOO_MANY_REQUESTS,
+            [
+                (header::RETRY_AFTER, secs_header_value(self.retry_after)),
+                (
+                    header::HeaderName::from_static("ratelimit-limit"),
+                    HeaderValue::from(self.limit),
+                ),
+                (
+                    header::HeaderName::from_static("ratelimit-remaining"),
+                    HeaderValue::from_static("0"),
+                ),
+                (
+                    header::HeaderName::from_static("ratelimit-reset"),
+                    secs_header_value(self.reset),
+                ),
+                (
+                    header::HeaderName::from_static("ratelimit-policy"),
+                    HeaderValue::from_str(&format!(
+                        "{};w={}",
+                        self.limit,
+                        self.period.as_secs().max(1)
+                    ))
+                    .expect("valid header value"),
+                ),
+            ],
+        )
+            .into_response()
+    }
+}
+
+/// Duration as whole seconds, rounded up, as used by the rate limit headers.
+fn secs_header_value(duration: Duration) -> HeaderValue {
+    let secs = duration.as_secs() + u64::from(duration.subsec_nanos() > 0);
+    HeaderValue::from(secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::util::backoff::ExponentialBackoff;
+
+    fn assert_ready<S, R, G, E>(result: PolicyResult<S, R, G, E>) -> Context<S> {
+        match result.output {
+            PolicyOutput::Ready(_) => result.ctx,
+            _ => panic!("unexpected output, expected ready"),
+        }
+    }
+
+    fn assert_abort<S, R, G, E>(result: PolicyResult<S, R, G, E>) -> E {
+        match result.output {
+            PolicyOutput::Abort(err) => err,
+            _ => panic!("unexpected output, expected abort"),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_policy() {
+        let policy = RatePolicy::new(2, Duration::from_secs(10));
+
+        let ctx = assert_ready(policy.check(Context::default(), ()).await);
+        assert_eq!(ctx.get::<RateLimitStatus>().unwrap().remaining(), 1);
+        let ctx = assert_ready(policy.check(Context::default(), ()).await);
+        let status = ctx.get::<RateLimitStatus>().unwrap();
+        assert_eq!(status.remaining(), 0);
+        assert_eq!(status.reset(), Duration::from_secs(10));
+
+        let err = assert_abort(policy.check(Context::default(), ()).await);
+        assert_eq!(err.retry_after(), Duration::from_secs(5));
+        assert_eq!(err.reset(), Duration::from_secs(10));
+
+        tokio::time::advance(Duration::from_secs(4)).await;
+        let err = assert_abort(policy.check(Context::default(), ()).await);
+        assert_eq!(err.retry_after(), Duration::from_secs(1));
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        let ctx = assert_ready(policy.check(Context::default(), ()).await);
+        assert_eq!(ctx.get::<RateLimitStatus>().unwrap().remaining(), 0);
+        assert_abort(policy.check(Context::default(), ()).await);
+
+        tokio::time::advance(Duration::from_secs(10)).await;
+        let ctx = assert_ready(policy.check(Context::default(), ()).await);
+        assert_eq!(ctx.get::<RateLimitStatus>().unwrap().remaining(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_policy_burst() {
+        let policy = RatePolicy::per_second(10).burst(1);
+
+        assert_ready(policy.check(Context::default(), ()).await);
+        let err = assert_abort(policy.check(Context::default(), ()).await);
+        assert_eq!(err.retry_after(), Duration::from_millis(100));
+
+        tokio::time::advance(Duration::from_millis(100)).await;
+        assert_ready(policy.check(Context::default(), ()).await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_policy_huge_limit() {
+        let policy = RatePolicy::per_second(2_000_000_000);
+        let ctx = assert_ready(policy.check(Context::default(), ()).await);
+        assert_eq!(
+            ctx.get::<RateLimitStatus>().unwrap().remaining(),
+            1_999_999_999
+        );
+
+        let policy = RatePolicy::new(1 << 32, Duration::from_secs(1));
+        assert_ready(policy.check(Context::default(), ()).await);
+
+        let policy = RatePolicy::new(u64::MAX, Duration::from_secs(3600));
+        let ctx = assert_ready(policy.check(Context::default(), ()).await);
+        assert!(ctx.get::<RateLimitStatus>().unwrap().remaining() > 1_000_000_000);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_policy_huge_burst() {
+        let policy = RatePolicy::per_hour(u64::MAX).burst(u64::MAX);
+        let ctx = assert_ready(policy.check(Context::default(), ()).await);
+        assert!(ctx.get::<RateLimitStatus>().unwrap().remaining() > 1_000_000_000);
+
+        let policy = RatePolicy::new(1, Duration::from_secs(u64::MAX)).burst(u64::MAX);
+        let ctx = assert_ready(policy.check(Context::default(), ()).await);
+        let status = ctx.get::<RateLimitStatus>().unwrap();
+        assert_eq!(status.remaining(), 0);
+        let err = assert_abort(policy.check(Context::default(), ()).await);
+        assert_eq!(err.reset(), status.reset());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_policy_clone() {
+        let policy = RatePolicy::per_minute(1);
+        let policy_clone = policy.clone();
+
+        assert_ready(policy.check(Context::default(), ()).await);
+        assert_abort(policy_clone.check(Context::default(), ()).await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_policy_backoff() {
+        let policy = RatePolicy::per_second(1).with_backoff(ExponentialBackoff::default());
+
+        assert_ready(policy.check(Context::default(), ()).await);
+        match policy.check(Context::default(), ()).await.output {
+            PolicyOutput::Retry => (),
+            _ => panic!("unexpected output, expected retry"),
+        }
+    }
+
+    #[test]
+    fn rate_limit_reached_into_response() {
+        let err = RateLimitReached {
+            limit: 100,
+            period: Duration::from_secs(60),
+            retry_after: Duration::from_millis(1500),
+            reset: Duration::from_secs(30),
+        };
+        let resp = err.into_response();
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        let headers = resp.headers();
+        assert_eq!(headers.get(header::RETRY_AFTER).unwrap(), "2");
+        assert_eq!(headers.get("ratelimit-limit").unwrap(), "100");
+        assert_eq!(headers.get("ratelimit-remaining").unwrap(), "0");
+        assert_eq!(headers.get("ratelimit-reset").unwrap(), "30");
+        assert_eq!(headers.get("ratelimit-policy").unwrap(), "100;w=60");
+    }
+}
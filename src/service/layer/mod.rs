@@ -73,6 +73,10 @@ pub mod limit;
 #[doc(inline)]
 pub use limit::{Limit, LimitLayer};
 
+pub mod circuit_breaker;
+#[doc(inline)]
+pub use circuit_breaker::{CircuitBreaker, CircuitBreakerLayer};
+
 pub mod add_extension;
 #[doc(inline)]
 pub use add_extension::{AddExtension, AddExtensionLayer};
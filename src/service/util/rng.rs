@@ -6,7 +6,7 @@
 //! [PRNG]: https://en.wikipedia.org/wiki/Pseudorandom_number_generator
 
 use std::{
-    collections::hash_map::RandomState,
+    collections::hash_map::{DefaultHasher, RandomState},
     hash::{BuildHasher, Hasher},
     ops::Range,
 };
@@ -86,6 +86,17 @@ impl Default for HasherRng {
     }
 }
 
+impl HasherRng<SeededState> {
+    /// Create a new [`HasherRng`] which generates the same sequence
+    /// of values for the same seed, e.g. to make tests reproducible.
+    ///
+    /// The sequence is only guaranteed to be reproducible
+    /// for binaries built with the same Rust version.
+    pub fn with_seed(seed: u64) -> Self {
+        HasherRng::with_hasher(SeededState { seed })
+    }
+}
+
 impl<H> HasherRng<H> {
     /// Create a new [`HasherRng`] with the provided hasher.
     pub fn with_hasher(hasher: H) -> Self {
@@ -105,11 +116,40 @@ where
     }
 }
 
+/// A [`BuildHasher`] creating [`DefaultHasher`]s which are initialized with a seed,
+/// as used by [`HasherRng::with_seed`].
+#[derive(Debug, Clone, Copy)]
+pub struct SeededState {
+    seed: u64,
+}
+
+impl BuildHasher for SeededState {
+    type Hasher = DefaultHasher;
+
+    fn build_hasher(&self) -> Self::Hasher {
+        let mut hasher = DefaultHasher::new();
+        hasher.write_u64(self.seed);
+        hasher
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use quickcheck::*;
 
+    #[test]
+    fn with_seed() {
+        let mut a = HasherRng::with_seed(42);
+        let mut b = HasherRng::with_seed(42);
+        let mut c = HasherRng::with_seed(43);
+        for _ in 0..10 {
+            let n = a.next_u64();
+            assert_eq!(n, b.next_u64());
+            assert_ne!(n, c.next_u64());
+        }
+    }
+
     quickcheck! {
         fn next_f64(counter: u64) -> TestResult {
             let mut rng = HasherRng {
@@ -0,0 +1,284 @@
+//! Middleware that emulates the conditions of a bad network on a [`Stream`],
+//! e.g. to test how clients cope with them when using rama as a distortion proxy.
+//!
+//! The [`NetworkConditions`] are emulated per connection, optionally only for the
+//! connections accepted by a [`Matcher`] such as the [`SocketMatcher`].
+//! Random conditions are reproducible when a seed is given to the [`NetworkEmulationLayer`].
+//!
+//! # Example
+//!
+//! ```
+//! use rama::stream::{
+//!     layer::{NetworkConditions, NetworkEmulationLayer},
+//!     matcher::SocketMatcher,
+//! };
+//! use std::time::Duration;
+//!
+//! // a flaky mobile network, for all but local connections
+//! let layer = NetworkEmulationLayer::new(
+//!     NetworkConditions::new()
+//!         .latency(Duration::from_millis(150))
+//!         .jitter(Duration::from_millis(50))
+//!         .bandwidth(64 * 1024)
+//!         .stall(0.01, Duration::from_secs(2))
+//!         .reset(0.001),
+//! )
+//! .matcher(SocketMatcher::loopback().negate())
+//! .seed(42);
+//! ```
+//!
+//! [`SocketMatcher`]: crate::stream::matcher::SocketMatcher
+
+use crate::{
+    service::{
+        matcher::Always,
+        util::rng::{HasherRng, Rng},
+        Context, Layer, Matcher, Service,
+    },
+    stream::Stream,
+};
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+mod stream;
+#[doc(inline)]
+pub use stream::EmulatedStream;
+
+/// The conditions of a (bad) network, emulated by an [`EmulatedStream`].
+///
+/// Random conditions apply to each read and write separately.
+/// By default no conditions are emulated.
+#[derive(Debug, Clone, Default)]
+pub struct NetworkConditions {
+    latency: Duration,
+    jitter: Duration,
+    bandwidth: Option<u64>,
+    stall: Option<(f64, Duration)>,
+    truncate_after: Option<u64>,
+    reset: f64,
+}
+
+impl NetworkConditions {
+    /// Create new [`NetworkConditions`], emulating a perfect network.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Delay each read and write with the given latency.
+    pub fn latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Add a random delay of up to the given jitter to each read and write.
+    pub fn jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Limit both the bytes read and written to the given number of bytes per second.
+    ///
+    /// See [`BandwidthLimit`] for more details.
+    ///
+    /// [`BandwidthLimit`]: crate::stream::layer::BandwidthLimit
+    pub fn bandwidth(mut self, bytes_per_second: u64) -> Self {
+        self.bandwidth = Some(bytes_per_second);
+        self
+    }
+
+    /// Stall a read or write for the given duration, with the given probability.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the probability is not within `[0, 1]`.
+    pub fn stall(mut self, probability: f64, duration: Duration) -> Self {
+        assert_probability(probability);
+        self.stall = Some((probability, duration));
+        self
+    }
+
+    /// Truncate the connection once the given number of bytes is read, or written.
+    ///
+    /// Once truncated, reads return EOF, while the stream is shut down for writing.
+    pub fn truncate_after(mut self, bytes: u64) -> Self {
+        self.truncate_after = Some(bytes);
+        self
+    }
+
+    /// Reset the connection on a read or write, with the given probability.
+    ///
+    /// Once reset, all reads and writes fail with an [`std::io::ErrorKind::ConnectionReset`] error.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the probability is not within `[0, 1]`.
+    pub fn reset(mut self, probability: f64) -> Self {
+        assert_probability(probability);
+        self.reset = probability;
+        self
+    }
+}
+
+fn assert_probability(probability: f64) {
+    assert!(
+        (0.0..=1.0).contains(&probability),
+        "probability must be within [0, 1]"
+    );
+}
+
+/// A [`Service`] which wraps the [`Stream`] in an [`EmulatedStream`],
+/// created using the [`NetworkEmulationLayer`].
+#[derive(Debug, Clone)]
+pub struct NetworkEmulationService<S, M = Always> {
+    inner: S,
+    conditions: NetworkConditions,
+    matcher: M,
+    seed: Option<Seed>,
+}
+
+/// The seed of a layer, from which the seed of each connection is derived.
+#[derive(Debug, Clone)]
+struct Seed {
+    seed: u64,
+    connections: Arc<AtomicU64>,
+}
+
+impl<State, S, M, IO> Service<State, IO> for NetworkEmulationService<S, M>
+where
+    State: Send + Sync + 'static,
+    S: Service<State, EmulatedStream<IO>>,
+    M: Matcher<State, IO>,
+    IO: Stream,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+
+    fn serve(
+        &self,
+        ctx: Context<State>,
+        stream: IO,
+    ) -> impl Future<Output = Result<Self::Response, Self::Error>> + Send + '_ {
+        let conditions = self
+            .matcher
+            .matches(None, &ctx, &stream)
+            .then(|| self.conditions.clone());
+        let rng: Box<dyn Rng> = match &self.seed {
+            Some(seed) => {
+                let connection = seed.connections.fetch_add(1, Ordering::Relaxed);
+                Box::new(HasherRng::with_seed(seed.seed.wrapping_add(connection)))
+            }
+            None => Box::new(HasherRng::new()),
+        };
+        self.inner
+            .serve(ctx, EmulatedStream::new(stream, conditions, rng))
+    }
+}
+
+/// A [`Layer`] that emulates the given [`NetworkConditions`] on a [`Stream`].
+///
+/// See the [module docs](self) for an example.
+#[derive(Debug, Clone)]
+pub struct NetworkEmulationLayer<M = Always> {
+    conditions: NetworkConditions,
+    matcher: M,
+    seed: Option<Seed>,
+}
+
+impl NetworkEmulationLayer {
+    /// Create a new [`NetworkEmulationLayer`], emulating
+    /// the given [`NetworkConditions`] for all connections.
+    pub fn new(conditions: NetworkConditions) -> Self {
+        Self {
+            conditions,
+            matcher: Always::new(),
+            seed: None,
+        }
+    }
+}
+
+impl<M> NetworkEmulationLayer<M> {
+    /// Only emulate the [`NetworkConditions`] for the connections
+    /// accepted by the given [`Matcher`], passing through all other connections as is.
+    pub fn matcher<T>(self, matcher: T) -> NetworkEmulationLayer<T> {
+        NetworkEmulationLayer {
+            conditions: self.conditions,
+            matcher,
+            seed: self.seed,
+        }
+    }
+
+    /// Make the random conditions reproducible, given the same seed
+    /// and the same order of connections.
+    ///
+    /// Each connection uses its own [`Rng`], seeded with
+    /// the given seed plus the number of connections served before it.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(Seed {
+            seed,
+            connections: Arc::new(AtomicU64::new(0)),
+        });
+        self
+    }
+}
+
+impl<S, M> Layer<S> for NetworkEmulationLayer<M>
+where
+    M: Clone,
+{
+    type Service = NetworkEmulationService<S, M>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        NetworkEmulationService {
+            inner,
+            conditions: self.conditions.clone(),
+            matcher: self.matcher.clone(),
+            seed: self.seed.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{service::service_fn, stream::matcher::SocketMatcher, tcp::server::TcpListener};
+    use std::convert::Infallible;
+    use tokio::net::TcpStream;
+
+    #[tokio::test]
+    async fn test_network_emulation_matcher() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            listener.serve_fn(|_stream: TcpStream| async move { Ok::<_, Infallible>(()) }),
+        );
+
+        let service = NetworkEmulationLayer::new(NetworkConditions::new().reset(1.0))
+            .matcher(SocketMatcher::port(addr.port()))
+            .layer(service_fn(|stream: EmulatedStream<TcpStream>| async move {
+                Ok::<_, Infallible>(stream.conditions().is_some())
+            }));
+
+        let emulated = service
+            .serve(Context::default(), TcpStream::connect(addr).await.unwrap())
+            .await
+            .unwrap();
+        assert!(emulated);
+
+        let other = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let other_addr = other.local_addr().unwrap();
+        let emulated = service
+            .serve(
+                Context::default(),
+                TcpStream::connect(other_addr).await.unwrap(),
+            )
+            .await
+            .unwrap();
+        assert!(!emulated);
+    }
+}
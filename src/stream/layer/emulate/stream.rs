@@ -0,0 +1,470 @@
+//! Provides [`EmulatedStream`] which wraps a [`AsyncRead`] and/or [`AsyncWrite`]
+//! in order to emulate the [`NetworkConditions`] of a bad network.
+//!
+//! [`AsyncRead`]: crate::stream::AsyncRead
+//! [`AsyncWrite`]: crate::stream::AsyncWrite
+
+use super::NetworkConditions;
+use crate::{
+    service::util::rng::Rng,
+    stream::layer::{BandwidthLimit, ThrottledStream},
+};
+use pin_project_lite::pin_project;
+use std::{
+    collections::VecDeque,
+    fmt,
+    future::Future,
+    io,
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::{Instant, Sleep},
+};
+
+/// The maximum number of bytes held back for the latency, per direction.
+const BUFFER_SIZE: usize = 8 * 1024;
+
+pin_project! {
+    /// A wrapper around a [`AsyncRead`] and/or [`AsyncWrite`] that emulates
+    /// the [`NetworkConditions`] of a bad network.
+    ///
+    /// Received data is held back for the latency before it can be read,
+    /// while data to be sent is held back for the latency before it is written,
+    /// as part of a later write or flush. Each chunk of data is held back
+    /// from the moment it is received or written, such that the latency of
+    /// consecutive chunks overlaps, as it would on an actual network.
+    ///
+    /// [`AsyncRead`]: crate::stream::AsyncRead
+    /// [`AsyncWrite`]: crate::stream::AsyncWrite
+    pub struct EmulatedStream<S> {
+        #[pin]
+        stream: ThrottledStream<S>,
+        conditions: Option<NetworkConditions>,
+        rng: Box<dyn Rng>,
+        reset: bool,
+        read: ReadState,
+        write: WriteState,
+    }
+}
+
+/// A chunk of data, held back until its deadline.
+#[derive(Debug)]
+struct Chunk {
+    deadline: Instant,
+    data: Vec<u8>,
+    pos: usize,
+}
+
+/// The chunks held back in a single direction, in the order they are released.
+#[derive(Debug, Default)]
+struct Queue {
+    chunks: VecDeque<Chunk>,
+    queued: usize,
+    last_deadline: Option<Instant>,
+    delay: Option<Pin<Box<Sleep>>>,
+    total: u64,
+}
+
+impl Queue {
+    /// Hold back the data for the given delay,
+    /// but never release it before the data queued earlier.
+    fn push(&mut self, data: Vec<u8>, delay: Duration) {
+        let deadline = Instant::now() + delay;
+        let deadline = self
+            .last_deadline
+            .map_or(deadline, |last| deadline.max(last));
+        self.last_deadline = Some(deadline);
+        self.queued += data.len();
+        self.total += data.len() as u64;
+        self.chunks.push_back(Chunk {
+            deadline,
+            data,
+            pos: 0,
+        });
+    }
+
+    /// Wait until the first chunk is released, returning it, if any.
+    fn poll_front(&mut self, cx: &mut Context<'_>) -> Poll<Option<&mut Chunk>> {
+        let Some(chunk) = self.chunks.front_mut() else {
+            return Poll::Ready(None);
+        };
+        if chunk.deadline > Instant::now() {
+            match self.delay.as_mut() {
+                Some(sleep) => sleep.as_mut().reset(chunk.deadline),
+                None => self.delay = Some(Box::pin(tokio::time::sleep_until(chunk.deadline))),
+            }
+            ready!(self.delay.as_mut().unwrap().as_mut().poll(cx));
+        }
+        Poll::Ready(self.chunks.front_mut())
+    }
+
+    /// Mark `n` bytes of the first chunk as consumed.
+    fn consume(&mut self, n: usize) {
+        if let Some(chunk) = self.chunks.front_mut() {
+            chunk.pos += n;
+            self.queued -= n;
+            if chunk.pos == chunk.data.len() {
+                self.chunks.pop_front();
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct ReadState {
+    queue: Queue,
+    eof: bool,
+}
+
+#[derive(Debug, Default)]
+struct WriteState {
+    queue: Queue,
+}
+
+impl<S: fmt::Debug> fmt::Debug for EmulatedStream<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EmulatedStream")
+            .field("stream", &self.stream)
+            .field("conditions", &self.conditions)
+            .field("reset", &self.reset)
+            .finish()
+    }
+}
+
+impl<S> EmulatedStream<S> {
+    /// Create a new [`EmulatedStream`] that wraps the given [`AsyncRead`] and/or [`AsyncWrite`],
+    /// emulating the given [`NetworkConditions`] using the given [`Rng`], if any.
+    ///
+    /// [`AsyncRead`]: crate::stream::AsyncRead
+    /// [`AsyncWrite`]: crate::stream::AsyncWrite
+    pub fn new(stream: S, conditions: Option<NetworkConditions>, rng: impl Rng) -> Self {
+        let limit = match conditions.as_ref().and_then(|c| c.bandwidth) {
+            Some(bytes_per_second) => BandwidthLimit::new(bytes_per_second),
+            None => BandwidthLimit::unlimited(),
+        };
+        Self {
+            stream: ThrottledStream::new(stream, limit),
+            conditions,
+            rng: Box::new(rng),
+            reset: false,
+            read: ReadState::default(),
+            write: WriteState::default(),
+        }
+    }
+
+    /// Get the [`NetworkConditions`] emulated by this stream,
+    /// `None` if the stream is passed through as is.
+    pub fn conditions(&self) -> Option<&NetworkConditions> {
+        self.conditions.as_ref()
+    }
+
+    /// Get the inner [`AsyncRead`] and/or [`AsyncWrite`] stream,
+    /// dropping any data which is still held back.
+    ///
+    /// [`AsyncRead`]: crate::stream::AsyncRead
+    /// [`AsyncWrite`]: crate::stream::AsyncWrite
+    pub fn into_inner(self) -> S {
+        self.stream.into_inner()
+    }
+}
+
+impl NetworkConditions {
+    /// Roll the dice for a single read or write, returning the delay to apply,
+    /// or an error in case the connection is to be reset.
+    fn roll(&self, rng: &mut dyn Rng) -> Result<Duration, io::Error> {
+        if self.reset > 0.0 && rng.next_f64() < self.reset {
+            return Err(reset_error());
+        }
+        let mut delay = self.latency;
+        if !self.jitter.is_zero() {
+            delay += self.jitter.mul_f64(rng.next_f64());
+        }
+        if let Some((probability, stall)) = self.stall {
+            if rng.next_f64() < probability {
+                delay += stall;
+            }
+        }
+        Ok(delay)
+    }
+
+    /// The number of bytes which can still be read or written,
+    /// given the number of bytes read or written so far.
+    fn remaining(&self, total: u64) -> usize {
+        self.truncate_after
+            .map_or(u64::MAX, |max| max.saturating_sub(total))
+            .try_into()
+            .unwrap_or(usize::MAX)
+    }
+}
+
+fn reset_error() -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionReset, "emulated connection reset")
+}
+
+impl<S> AsyncRead for EmulatedStream<S>
+where
+    S: AsyncRead,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let mut this = self.project();
+        let Some(conditions) = this.conditions.as_ref() else {
+            return this.stream.poll_read(cx, buf);
+        };
+        if *this.reset {
+            return Poll::Ready(Err(reset_error()));
+        }
+        let read = this.read;
+
+        // receive all data that is available, each chunk held back for its own delay
+        while !read.eof && read.queue.queued < BUFFER_SIZE {
+            let size =
+                (BUFFER_SIZE - read.queue.queued).min(conditions.remaining(read.queue.total));
+            if size == 0 {
+                // emulate a connection closed by the peer
+                read.eof = true;
+                break;
+            }
+
+            let mut data = vec![0; size];
+            let mut inner_buf = ReadBuf::new(&mut data);
+            match this.stream.as_mut().poll_read(cx, &mut inner_buf) {
+                Poll::Pending => break,
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Ready(Ok(())) => (),
+            }
+            let n = inner_buf.filled().len();
+            if n == 0 {
+                read.eof = true;
+                break;
+            }
+            data.truncate(n);
+
+            match conditions.roll(this.rng.as_mut()) {
+                Ok(delay) => read.queue.push(data, delay),
+                Err(err) => {
+                    *this.reset = true;
+                    return Poll::Ready(Err(err));
+                }
+            }
+        }
+
+        match ready!(read.queue.poll_front(cx)) {
+            Some(chunk) => {
+                let n = (chunk.data.len() - chunk.pos).min(buf.remaining());
+                buf.put_slice(&chunk.data[chunk.pos..chunk.pos + n]);
+                read.queue.consume(n);
+                Poll::Ready(Ok(()))
+            }
+            // EOF, once all data received before it is read
+            None if read.eof => Poll::Ready(Ok(())),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Write all data held back of which the delay has passed,
+/// ready once all data is written.
+fn poll_send<S: AsyncWrite>(
+    mut stream: Pin<&mut ThrottledStream<S>>,
+    queue: &mut Queue,
+    cx: &mut Context<'_>,
+) -> Poll<io::Result<()>> {
+    while let Some(chunk) = ready!(queue.poll_front(cx)) {
+        let n = ready!(stream.as_mut().poll_write(cx, &chunk.data[chunk.pos..]))?;
+        if n == 0 {
+            return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+        }
+        queue.consume(n);
+    }
+    Poll::Ready(Ok(()))
+}
+
+impl<S> AsyncWrite for EmulatedStream<S>
+where
+    S: AsyncWrite,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        let mut this = self.project();
+        let Some(conditions) = this.conditions.as_ref() else {
+            return this.stream.poll_write(cx, buf);
+        };
+        if *this.reset {
+            return Poll::Ready(Err(reset_error()));
+        }
+        let queue = &mut this.write.queue;
+
+        // make room by sending the data that is due
+        let sent = poll_send(this.stream.as_mut(), queue, cx)?;
+
+        let remaining = conditions.remaining(queue.total);
+        if remaining == 0 {
+            // emulate a connection closed halfway, as seen by the peer
+            ready!(sent);
+            ready!(this.stream.as_mut().poll_shutdown(cx))?;
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "emulated truncated connection",
+            )));
+        }
+        if queue.queued >= BUFFER_SIZE {
+            return Poll::Pending;
+        }
+
+        let size = buf.len().min(BUFFER_SIZE - queue.queued).min(remaining);
+        match conditions.roll(this.rng.as_mut()) {
+            Ok(delay) => queue.push(buf[..size].to_vec(), delay),
+            Err(err) => {
+                *this.reset = true;
+                return Poll::Ready(Err(err));
+            }
+        }
+        Poll::Ready(Ok(size))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        if self.reset {
+            return Poll::Ready(Err(reset_error()));
+        }
+        let mut this = self.project();
+        ready!(poll_send(this.stream.as_mut(), &mut this.write.queue, cx))?;
+        this.stream.poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        let mut this = self.project();
+        if !*this.reset {
+            ready!(poll_send(this.stream.as_mut(), &mut this.write.queue, cx))?;
+        }
+        this.stream.poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::util::rng::HasherRng;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        time::Instant,
+    };
+
+    #[tokio::test(start_paused = true)]
+    async fn test_latency() {
+        let (mut client, server) = tokio::io::duplex(64);
+        let conditions = NetworkConditions::new().latency(Duration::from_millis(100));
+        let mut stream = EmulatedStream::new(server, Some(conditions), HasherRng::with_seed(0));
+
+        let start = Instant::now();
+        client.write_all(b"ping").await.unwrap();
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(start.elapsed(), Duration::from_millis(100));
+
+        stream.write_all(b"pong").await.unwrap();
+        stream.flush().await.unwrap();
+        assert_eq!(start.elapsed(), Duration::from_millis(200));
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"pong");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_latency_pipelined() {
+        let (mut client, server) = tokio::io::duplex(64);
+        let conditions = NetworkConditions::new().latency(Duration::from_millis(100));
+        let mut stream = EmulatedStream::new(server, Some(conditions), HasherRng::with_seed(0));
+
+        // consecutive chunks are each held back from the moment they are received
+        let start = Instant::now();
+        let reader = tokio::spawn(async move {
+            let mut buf = [0; 4];
+            stream.read_exact(&mut buf).await.unwrap();
+            assert_eq!(start.elapsed(), Duration::from_millis(100));
+            stream.read_exact(&mut buf).await.unwrap();
+            assert_eq!(start.elapsed(), Duration::from_millis(150));
+            stream
+        });
+        client.write_all(b"ping").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        client.write_all(b"ping").await.unwrap();
+        let mut stream = reader.await.unwrap();
+
+        // the same goes for chunks written
+        let start = Instant::now();
+        stream.write_all(b"pong").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        stream.write_all(b"pong").await.unwrap();
+        stream.flush().await.unwrap();
+        assert_eq!(start.elapsed(), Duration::from_millis(150));
+        let mut buf = [0; 8];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"pongpong");
+    }
+
+    #[tokio::test]
+    async fn test_truncate() {
+        let (mut client, server) = tokio::io::duplex(64);
+        let conditions = NetworkConditions::new().truncate_after(3);
+        let mut stream = EmulatedStream::new(server, Some(conditions), HasherRng::with_seed(0));
+
+        client.write_all(b"hello").await.unwrap();
+        let mut buf = Vec::new();
+        stream.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, b"hel");
+
+        let err = stream.write_all(b"world").await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+        let mut buf = Vec::new();
+        client.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, b"wor");
+    }
+
+    #[tokio::test]
+    async fn test_reset_reproducible() {
+        async fn ops_until_reset(seed: u64) -> usize {
+            let (mut client, server) = tokio::io::duplex(1024);
+            let conditions = NetworkConditions::new().reset(0.1);
+            let mut stream =
+                EmulatedStream::new(server, Some(conditions), HasherRng::with_seed(seed));
+            for i in 0.. {
+                let result = match stream.write_all(b"x").await {
+                    Ok(()) => stream.flush().await,
+                    Err(err) => Err(err),
+                };
+                if let Err(err) = result {
+                    assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+                    // the connection remains reset
+                    client.write_all(b"x").await.unwrap();
+                    assert!(stream.read(&mut [0; 1]).await.is_err());
+                    return i;
+                }
+                client.read_exact(&mut [0; 1]).await.unwrap();
+            }
+            unreachable!()
+        }
+
+        assert_eq!(ops_until_reset(7).await, ops_until_reset(7).await);
+    }
+
+    #[tokio::test]
+    async fn test_passthrough() {
+        let (mut client, server) = tokio::io::duplex(64);
+        let mut stream = EmulatedStream::new(server, None, HasherRng::with_seed(0));
+        assert!(stream.conditions().is_none());
+
+        client.write_all(b"ping").await.unwrap();
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+    }
+}
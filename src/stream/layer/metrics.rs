@@ -0,0 +1,271 @@
+//! Middleware that records [`prometheus`] metrics of the [`Stream`]s served.
+//!
+//! The [`StreamMetricsLayer`] records the following metrics:
+//!
+//! - `stream_connections_accepted_total`: the number of connections accepted;
+//! - `stream_connections_active`: the number of connections being served;
+//! - `stream_bytes_read_total`: the number of bytes read, once a connection is closed;
+//! - `stream_bytes_written_total`: the number of bytes written, once a connection is closed;
+//! - `stream_tls_handshake_failures_total`: the number of failed TLS handshakes.
+//!
+//! The bytes are tracked by a [`BytesRWTrackerHandle`], inserted into the [`Context`],
+//! while the TLS handshake failures are recorded by a [`TlsAcceptorService`]
+//! served within the [`StreamMetricsService`].
+//!
+//! [`prometheus`]: https://crates.io/crates/prometheus
+//! [`TlsAcceptorService`]: crate::tls::rustls::server::TlsAcceptorService
+//!
+//! # Example
+//!
+//! ```
+//! use rama::stream::{layer::StreamMetricsLayer, Stream};
+//! use rama::service::{Context, Layer, Service, service_fn};
+//! use prometheus::Registry;
+//! use std::convert::Infallible;
+//! use tokio::io::AsyncWriteExt;
+//!
+//! async fn hello(mut stream: impl Stream + Unpin) -> Result<(), Infallible> {
+//!     stream.write_all(b"hello").await.unwrap();
+//!     Ok(())
+//! }
+//!
+//! # #[tokio::main]
+//! # async fn main() {
+//! let registry = Registry::new();
+//! let service = StreamMetricsLayer::with_registry(&registry)
+//!     .unwrap()
+//!     .layer(service_fn(hello));
+//!
+//! let (_client, server) = tokio::io::duplex(64);
+//! service.serve(Context::default(), server).await.unwrap();
+//! # }
+//! ```
+
+use super::{tracker::BytesRWTracker, BytesRWTrackerHandle};
+use crate::{
+    service::{Context, Layer, Service},
+    stream::Stream,
+};
+use prometheus::{IntCounter, IntGauge, Registry};
+use std::{future::Future, sync::OnceLock};
+
+/// The [`prometheus`] metrics recorded by the [`StreamMetricsLayer`].
+///
+/// See the [module docs](self) for the recorded metrics.
+///
+/// [`prometheus`]: https://crates.io/crates/prometheus
+#[derive(Debug, Clone)]
+pub struct StreamMetrics {
+    accepted: IntCounter,
+    active: IntGauge,
+    bytes_read: IntCounter,
+    bytes_written: IntCounter,
+    tls_handshake_failures: IntCounter,
+}
+
+impl StreamMetrics {
+    /// Create the [`StreamMetrics`] and register them in the given [`Registry`].
+    pub fn register(registry: &Registry) -> Result<Self, prometheus::Error> {
+        let metrics = Self {
+            accepted: IntCounter::new(
+                "stream_connections_accepted_total",
+                "Number of connections accepted.",
+            )?,
+            active: IntGauge::new(
+                "stream_connections_active",
+                "Number of connections being served.",
+            )?,
+            bytes_read: IntCounter::new(
+                "stream_bytes_read_total",
+                "Number of bytes read from closed connections.",
+            )?,
+            bytes_written: IntCounter::new(
+                "stream_bytes_written_total",
+                "Number of bytes written to closed connections.",
+            )?,
+            tls_handshake_failures: IntCounter::new(
+                "stream_tls_handshake_failures_total",
+                "Number of failed TLS handshakes.",
+            )?,
+        };
+        registry.register(Box::new(metrics.accepted.clone()))?;
+        registry.register(Box::new(metrics.active.clone()))?;
+        registry.register(Box::new(metrics.bytes_read.clone()))?;
+        registry.register(Box::new(metrics.bytes_written.clone()))?;
+        registry.register(Box::new(metrics.tls_handshake_failures.clone()))?;
+        Ok(metrics)
+    }
+
+    /// Get the [`StreamMetrics`] registered in the default [`Registry`],
+    /// registering them on first use.
+    ///
+    /// # Panics
+    ///
+    /// Panics if metrics with the same names are already registered in the default registry.
+    pub fn global() -> Self {
+        static METRICS: OnceLock<StreamMetrics> = OnceLock::new();
+        METRICS
+            .get_or_init(|| {
+                Self::register(prometheus::default_registry())
+                    .expect("register stream metrics in default registry")
+            })
+            .clone()
+    }
+
+    /// Record a failed TLS handshake.
+    pub fn record_tls_handshake_failure(&self) {
+        self.tls_handshake_failures.inc();
+    }
+}
+
+/// Records the end of a connection once it is served, or cancelled.
+struct ConnectionGuard {
+    metrics: StreamMetrics,
+    tracker: BytesRWTrackerHandle,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.metrics.active.dec();
+        self.metrics.bytes_read.inc_by(self.tracker.read() as u64);
+        self.metrics
+            .bytes_written
+            .inc_by(self.tracker.written() as u64);
+    }
+}
+
+/// A [`Service`] which records [`StreamMetrics`] of the [`Stream`]s it serves,
+/// created using the [`StreamMetricsLayer`].
+///
+/// The [`StreamMetrics`] are inserted into the [`Context`],
+/// such that inner services can record to them as well.
+#[derive(Debug, Clone)]
+pub struct StreamMetricsService<S> {
+    inner: S,
+    metrics: StreamMetrics,
+}
+
+impl<S> StreamMetricsService<S> {
+    define_inner_service_accessors!();
+}
+
+impl<State, S, IO> Service<State, IO> for StreamMetricsService<S>
+where
+    State: Send + Sync + 'static,
+    S: Service<State, BytesRWTracker<IO>>,
+    IO: Stream,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+
+    fn serve(
+        &self,
+        mut ctx: Context<State>,
+        stream: IO,
+    ) -> impl Future<Output = Result<Self::Response, Self::Error>> + Send + '_ {
+        let stream = BytesRWTracker::new(stream);
+        let tracker = stream.handle();
+        ctx.insert(tracker.clone());
+        ctx.insert(self.metrics.clone());
+
+        self.metrics.accepted.inc();
+        self.metrics.active.inc();
+        let guard = ConnectionGuard {
+            metrics: self.metrics.clone(),
+            tracker,
+        };
+
+        let future = self.inner.serve(ctx, stream);
+        async move {
+            let result = future.await;
+            drop(guard);
+            result
+        }
+    }
+}
+
+/// A [`Layer`] that records [`StreamMetrics`] of [`Stream`]s.
+///
+/// See the [module docs](self) for more details.
+#[derive(Debug, Clone)]
+pub struct StreamMetricsLayer {
+    metrics: StreamMetrics,
+}
+
+impl StreamMetricsLayer {
+    /// Create a new [`StreamMetricsLayer`], recording the [`StreamMetrics`]
+    /// registered in the default [`Registry`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if metrics with the same names are already registered in the default registry,
+    /// other than by this layer.
+    pub fn new() -> Self {
+        Self::with_metrics(StreamMetrics::global())
+    }
+
+    /// Create a new [`StreamMetricsLayer`], recording [`StreamMetrics`]
+    /// registered in the given [`Registry`].
+    pub fn with_registry(registry: &Registry) -> Result<Self, prometheus::Error> {
+        Ok(Self::with_metrics(StreamMetrics::register(registry)?))
+    }
+
+    /// Create a new [`StreamMetricsLayer`], recording the given [`StreamMetrics`],
+    /// e.g. to share them between multiple layers.
+    pub fn with_metrics(metrics: StreamMetrics) -> Self {
+        Self { metrics }
+    }
+}
+
+impl Default for StreamMetricsLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> Layer<S> for StreamMetricsLayer {
+    type Service = StreamMetricsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        StreamMetricsService {
+            inner,
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::service_fn;
+    use std::convert::Infallible;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_stream_metrics() {
+        let registry = Registry::new();
+        let metrics = StreamMetrics::register(&registry).unwrap();
+        let service = StreamMetricsLayer::with_metrics(metrics.clone()).layer(service_fn(
+            |ctx: Context<()>, mut stream: BytesRWTracker<tokio::io::DuplexStream>| async move {
+                let metrics = ctx.get::<StreamMetrics>().unwrap();
+                assert_eq!(metrics.active.get(), 1);
+                metrics.record_tls_handshake_failure();
+
+                let mut buf = [0; 4];
+                stream.read_exact(&mut buf).await.unwrap();
+                stream.write_all(b"pong!").await.unwrap();
+                Ok::<_, Infallible>(())
+            },
+        ));
+
+        let (mut client, server) = tokio::io::duplex(64);
+        client.write_all(b"ping").await.unwrap();
+        service.serve(Context::default(), server).await.unwrap();
+
+        assert_eq!(metrics.accepted.get(), 1);
+        assert_eq!(metrics.active.get(), 0);
+        assert_eq!(metrics.bytes_read.get(), 4);
+        assert_eq!(metrics.bytes_written.get(), 5);
+        assert_eq!(metrics.tls_handshake_failures.get(), 1);
+    }
+}
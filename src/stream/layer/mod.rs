@@ -3,7 +3,33 @@
 //! Examples are services that can operate directly on a `TCP`, `TLS` or `UDP` stream.
 
 mod tracker;
+#[cfg(test)]
+pub(crate) use tracker::BytesRWTracker;
 #[doc(inline)]
 pub use tracker::{BytesRWTrackerHandle, BytesTrackerLayer, BytesTrackerService};
 
+mod timeout;
+#[doc(inline)]
+pub use timeout::{
+    StreamTimeoutHandle, StreamTimeoutLayer, StreamTimeoutReason, StreamTimeoutService,
+    TimeoutStream,
+};
+
+mod throttle;
+#[doc(inline)]
+pub use throttle::{
+    BandwidthLimit, BandwidthLimits, KeyedBandwidthLimits, ThrottleLayer, ThrottleService,
+    ThrottledStream,
+};
+
+mod emulate;
+#[doc(inline)]
+pub use emulate::{
+    EmulatedStream, NetworkConditions, NetworkEmulationLayer, NetworkEmulationService,
+};
+
+mod metrics;
+#[doc(inline)]
+pub use metrics::{StreamMetrics, StreamMetricsLayer, StreamMetricsService};
+
 pub mod http;
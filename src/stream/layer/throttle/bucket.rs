@@ -0,0 +1,120 @@
+use std::{sync::Mutex, time::Duration};
+use tokio::time::Instant;
+
+/// A token bucket, refilled continuously at the rate in bytes per second,
+/// with room for one second worth of bytes.
+///
+/// Bytes only become available in chunks of at least a tenth of the rate,
+/// such that a stream is not throttled down to tiny reads and writes.
+///
+/// Tokens can be consumed beyond what is available, in which case
+/// the debt has to be paid off before any more tokens become available.
+#[derive(Debug)]
+pub(super) struct TokenBucket {
+    state: Mutex<BucketState>,
+}
+
+#[derive(Debug)]
+struct BucketState {
+    rate: Option<u64>,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl BucketState {
+    fn refill(&mut self, rate: u64) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = elapsed.mul_add(rate as f64, self.tokens).min(rate as f64);
+        self.last_refill = now;
+    }
+}
+
+impl TokenBucket {
+    /// Create a new full bucket, unlimited if no rate is given.
+    pub(super) fn new(rate: Option<u64>) -> Self {
+        Self {
+            state: Mutex::new(BucketState {
+                rate,
+                tokens: rate.unwrap_or_default() as f64,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    pub(super) fn rate(&self) -> Option<u64> {
+        self.state.lock().unwrap().rate
+    }
+
+    pub(super) fn set_rate(&self, rate: Option<u64>) {
+        let mut state = self.state.lock().unwrap();
+        match (state.rate, rate) {
+            (Some(current), Some(rate)) => {
+                state.refill(current);
+                state.tokens = state.tokens.min(rate as f64);
+            }
+            (None, Some(rate)) => {
+                state.tokens = rate as f64;
+                state.last_refill = Instant::now();
+            }
+            (_, None) => (),
+        }
+        state.rate = rate;
+    }
+
+    /// Returns the number of bytes which can be consumed,
+    /// or the duration to wait until a chunk of bytes can be consumed.
+    pub(super) fn available(&self) -> Result<usize, Duration> {
+        let mut state = self.state.lock().unwrap();
+        let Some(rate) = state.rate else {
+            return Ok(usize::MAX);
+        };
+        state.refill(rate);
+        let chunk = (rate / 10).max(1) as f64;
+        if state.tokens >= chunk {
+            Ok(state.tokens as usize)
+        } else {
+            // at least the resolution of the timer, which would otherwise not advance
+            Err(
+                Duration::from_secs_f64((chunk - state.tokens) / rate as f64)
+                    .max(Duration::from_millis(1)),
+            )
+        }
+    }
+
+    pub(super) fn consume(&self, bytes: usize) {
+        let mut state = self.state.lock().unwrap();
+        if state.rate.is_some() {
+            state.tokens -= bytes as f64;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_token_bucket() {
+        let bucket = TokenBucket::new(Some(100));
+        assert_eq!(bucket.available(), Ok(100));
+
+        // consuming beyond the available tokens results in debt
+        bucket.consume(150);
+        let wait = bucket.available().unwrap_err();
+        assert!(wait > Duration::from_millis(599) && wait < Duration::from_millis(601));
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(bucket.available(), Ok(50));
+
+        // the bucket never holds more than a second worth of bytes
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        assert_eq!(bucket.available(), Ok(100));
+
+        bucket.set_rate(Some(10));
+        assert_eq!(bucket.available(), Ok(10));
+        bucket.set_rate(None);
+        bucket.consume(1000);
+        assert_eq!(bucket.available(), Ok(usize::MAX));
+    }
+}
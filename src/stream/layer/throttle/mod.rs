@@ -0,0 +1,404 @@
+//! Middleware that limits the bandwidth of a [`Stream`].
+//!
+//! The read and write throughput of a stream is limited using token buckets,
+//! held by a [`BandwidthLimit`]. Streams which share a [`BandwidthLimit`] share
+//! its bandwidth, e.g. all connections of the same proxy user when using
+//! [`KeyedBandwidthLimits`]. The rates of a [`BandwidthLimit`] can be adjusted at runtime.
+//!
+//! # Example
+//!
+//! ```
+//! use rama::service::{layer::limit::policy::ProxyUsernameLabelsKey, Layer};
+//! use rama::stream::layer::{BandwidthLimit, KeyedBandwidthLimits, ThrottleLayer};
+//!
+//! // 1 MiB/s in both directions, shared by all connections of the same user labels
+//! let limits = KeyedBandwidthLimits::new(
+//!     ProxyUsernameLabelsKey::<'-'>,
+//!     |_labels: &Vec<String>| BandwidthLimit::new(1024 * 1024),
+//! );
+//! let layer = ThrottleLayer::new(limits.clone());
+//!
+//! // ... and upgrade a user at runtime
+//! let labels = vec!["john".to_owned(), "premium".to_owned()];
+//! if let Some(limit) = limits.get(&labels) {
+//!     limit.set_read_rate(Some(10 * 1024 * 1024));
+//!     limit.set_write_rate(Some(10 * 1024 * 1024));
+//! }
+//! ```
+
+use crate::{
+    service::{layer::limit::policy::KeyExtractor, Context, Layer, Service},
+    stream::Stream,
+};
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    hash::Hash,
+    sync::{Arc, Mutex, Weak},
+};
+
+mod bucket;
+use bucket::TokenBucket;
+
+mod stream;
+#[doc(inline)]
+pub use stream::ThrottledStream;
+
+/// The read and write bandwidth available to the [`ThrottledStream`]s sharing it,
+/// in bytes per second.
+///
+/// Cloning a [`BandwidthLimit`] shares its bandwidth.
+/// Each direction can burst up to one second worth of bytes.
+#[derive(Debug, Clone)]
+pub struct BandwidthLimit {
+    inner: Arc<Buckets>,
+}
+
+#[derive(Debug)]
+struct Buckets {
+    read: TokenBucket,
+    write: TokenBucket,
+}
+
+impl BandwidthLimit {
+    /// Create a new [`BandwidthLimit`], limiting both reads
+    /// and writes to the given number of bytes per second.
+    pub fn new(bytes_per_second: u64) -> Self {
+        Self::unlimited()
+            .read_rate(bytes_per_second)
+            .write_rate(bytes_per_second)
+    }
+
+    /// Create a new [`BandwidthLimit`] which does not limit reads nor writes.
+    pub fn unlimited() -> Self {
+        Self {
+            inner: Arc::new(Buckets {
+                read: TokenBucket::new(None),
+                write: TokenBucket::new(None),
+            }),
+        }
+    }
+
+    /// Limit the bytes read per second, i.e. the bytes received from the peer.
+    pub fn read_rate(self, bytes_per_second: u64) -> Self {
+        self.set_read_rate(Some(bytes_per_second));
+        self
+    }
+
+    /// Limit the bytes written per second, i.e. the bytes sent to the peer.
+    pub fn write_rate(self, bytes_per_second: u64) -> Self {
+        self.set_write_rate(Some(bytes_per_second));
+        self
+    }
+
+    /// Set or lift the limit of bytes read per second,
+    /// affecting all streams sharing this [`BandwidthLimit`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the given rate is `0`.
+    pub fn set_read_rate(&self, bytes_per_second: Option<u64>) {
+        assert_ne!(bytes_per_second, Some(0), "read rate cannot be zero");
+        self.inner.read.set_rate(bytes_per_second);
+    }
+
+    /// Set or lift the limit of bytes written per second,
+    /// affecting all streams sharing this [`BandwidthLimit`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the given rate is `0`.
+    pub fn set_write_rate(&self, bytes_per_second: Option<u64>) {
+        assert_ne!(bytes_per_second, Some(0), "write rate cannot be zero");
+        self.inner.write.set_rate(bytes_per_second);
+    }
+
+    /// Get the current limit of bytes read per second, if any.
+    pub fn get_read_rate(&self) -> Option<u64> {
+        self.inner.read.rate()
+    }
+
+    /// Get the current limit of bytes written per second, if any.
+    pub fn get_write_rate(&self) -> Option<u64> {
+        self.inner.write.rate()
+    }
+}
+
+/// Selects the [`BandwidthLimit`] of a stream served by a [`ThrottleService`].
+pub trait BandwidthLimits<State, IO>: Send + Sync + 'static {
+    /// Get the [`BandwidthLimit`] for the given [`Context`] and stream,
+    /// returning `None` in case the stream is not to be throttled.
+    fn limit(&self, ctx: &Context<State>, stream: &IO) -> Option<BandwidthLimit>;
+}
+
+/// A single [`BandwidthLimit`] is shared by all streams.
+impl<State, IO> BandwidthLimits<State, IO> for BandwidthLimit {
+    fn limit(&self, _ctx: &Context<State>, _stream: &IO) -> Option<BandwidthLimit> {
+        Some(self.clone())
+    }
+}
+
+/// [`BandwidthLimits`] which share a [`BandwidthLimit`] between
+/// all streams of the same key, extracted using a [`KeyExtractor`].
+///
+/// The [`BandwidthLimit`] of a new key is created using the given factory,
+/// and is kept as long as a stream of that key is being served.
+/// Streams for which no key can be extracted are not throttled.
+///
+/// Cloning [`KeyedBandwidthLimits`] shares the limits, such that a clone
+/// can be used to adjust the [`BandwidthLimit`] of a key at runtime.
+pub struct KeyedBandwidthLimits<K, F, Key> {
+    inner: Arc<KeyedInner<K, F, Key>>,
+}
+
+struct KeyedInner<K, F, Key> {
+    extractor: K,
+    factory: F,
+    limits: Mutex<KeyedLimits<Key>>,
+}
+
+struct KeyedLimits<Key> {
+    entries: HashMap<Key, Weak<Buckets>>,
+    prune_at: usize,
+}
+
+/// The minimum number of keys before pruning the limits of keys no longer in use.
+const MIN_PRUNE_AT: usize = 64;
+
+impl<K, F, Key> KeyedBandwidthLimits<K, F, Key>
+where
+    Key: Hash + Eq,
+    F: Fn(&Key) -> BandwidthLimit,
+{
+    /// Create new [`KeyedBandwidthLimits`], using the given [`KeyExtractor`]
+    /// and the factory used to create the [`BandwidthLimit`] of a new key.
+    pub fn new(extractor: K, factory: F) -> Self {
+        Self {
+            inner: Arc::new(KeyedInner {
+                extractor,
+                factory,
+                limits: Mutex::new(KeyedLimits {
+                    entries: HashMap::new(),
+                    prune_at: MIN_PRUNE_AT,
+                }),
+            }),
+        }
+    }
+
+    /// Get the [`BandwidthLimit`] of the given key,
+    /// if a stream of that key is currently being served.
+    pub fn get(&self, key: &Key) -> Option<BandwidthLimit> {
+        self.inner
+            .limits
+            .lock()
+            .unwrap()
+            .entries
+            .get(key)
+            .and_then(Weak::upgrade)
+            .map(|inner| BandwidthLimit { inner })
+    }
+
+    fn get_or_insert(&self, key: Key) -> BandwidthLimit {
+        let mut limits = self.inner.limits.lock().unwrap();
+        if let Some(inner) = limits.entries.get(&key).and_then(Weak::upgrade) {
+            return BandwidthLimit { inner };
+        }
+
+        if limits.entries.len() >= limits.prune_at {
+            limits.entries.retain(|_, limit| limit.strong_count() > 0);
+            limits.prune_at = (limits.entries.len() * 2).max(MIN_PRUNE_AT);
+        }
+
+        let limit = (self.inner.factory)(&key);
+        limits.entries.insert(key, Arc::downgrade(&limit.inner));
+        limit
+    }
+}
+
+impl<K, F, Key> Clone for KeyedBandwidthLimits<K, F, Key> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<K, F, Key> fmt::Debug for KeyedBandwidthLimits<K, F, Key>
+where
+    K: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyedBandwidthLimits")
+            .field("extractor", &self.inner.extractor)
+            .field("keys", &self.inner.limits.lock().unwrap().entries.len())
+            .finish()
+    }
+}
+
+impl<K, F, State, IO> BandwidthLimits<State, IO> for KeyedBandwidthLimits<K, F, K::Key>
+where
+    K: KeyExtractor<State, IO>,
+    F: Fn(&K::Key) -> BandwidthLimit + Send + Sync + 'static,
+{
+    fn limit(&self, ctx: &Context<State>, stream: &IO) -> Option<BandwidthLimit> {
+        let key = self.inner.extractor.extract(ctx, stream)?;
+        Some(self.get_or_insert(key))
+    }
+}
+
+/// A [`Service`] which wraps the [`Stream`] in a [`ThrottledStream`],
+/// created using the [`ThrottleLayer`].
+///
+/// The [`BandwidthLimit`] of the stream is inserted in the [`Context`].
+pub struct ThrottleService<S, L> {
+    inner: S,
+    limits: Arc<L>,
+}
+
+impl<S, L> fmt::Debug for ThrottleService<S, L>
+where
+    S: fmt::Debug,
+    L: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ThrottleService")
+            .field("inner", &self.inner)
+            .field("limits", &self.limits)
+            .finish()
+    }
+}
+
+impl<S, L> Clone for ThrottleService<S, L>
+where
+    S: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            limits: self.limits.clone(),
+        }
+    }
+}
+
+impl<State, S, L, IO> Service<State, IO> for ThrottleService<S, L>
+where
+    State: Send + Sync + 'static,
+    S: Service<State, ThrottledStream<IO>>,
+    L: BandwidthLimits<State, IO>,
+    IO: Stream,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+
+    fn serve(
+        &self,
+        mut ctx: Context<State>,
+        stream: IO,
+    ) -> impl Future<Output = Result<Self::Response, Self::Error>> + Send + '_ {
+        let limit = self
+            .limits
+            .limit(&ctx, &stream)
+            .unwrap_or_else(BandwidthLimit::unlimited);
+        ctx.insert(limit.clone());
+        self.inner.serve(ctx, ThrottledStream::new(stream, limit))
+    }
+}
+
+/// A [`Layer`] that limits the bandwidth of a [`Stream`],
+/// using the [`BandwidthLimit`] selected by the given [`BandwidthLimits`].
+///
+/// See the [module docs](self) for an example.
+pub struct ThrottleLayer<L> {
+    limits: Arc<L>,
+}
+
+impl<L> ThrottleLayer<L> {
+    /// Create a new [`ThrottleLayer`] using the given [`BandwidthLimits`],
+    /// such as a single [`BandwidthLimit`] shared by all streams
+    /// or [`KeyedBandwidthLimits`].
+    pub fn new(limits: L) -> Self {
+        Self {
+            limits: Arc::new(limits),
+        }
+    }
+}
+
+impl<L> fmt::Debug for ThrottleLayer<L>
+where
+    L: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ThrottleLayer")
+            .field("limits", &self.limits)
+            .finish()
+    }
+}
+
+impl<L> Clone for ThrottleLayer<L> {
+    fn clone(&self) -> Self {
+        Self {
+            limits: self.limits.clone(),
+        }
+    }
+}
+
+impl<S, L> Layer<S> for ThrottleLayer<L> {
+    type Service = ThrottleService<S, L>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ThrottleService {
+            inner,
+            limits: self.limits.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::service_fn;
+    use std::convert::Infallible;
+    use tokio::io::DuplexStream;
+
+    #[tokio::test]
+    async fn test_keyed_bandwidth_limits() {
+        let limits = KeyedBandwidthLimits::new(
+            |ctx: &Context<()>, _: &DuplexStream| ctx.get::<&'static str>().copied(),
+            |user: &&'static str| match *user {
+                "premium" => BandwidthLimit::new(1000),
+                _ => BandwidthLimit::new(100),
+            },
+        );
+        let service = ThrottleLayer::new(limits.clone()).layer(service_fn(
+            |ctx: Context<()>, stream: ThrottledStream<DuplexStream>| async move {
+                assert!(ctx.get::<BandwidthLimit>().is_some());
+                Ok::<_, Infallible>(stream)
+            },
+        ));
+
+        let serve = |user: Option<&'static str>| {
+            let mut ctx = Context::default();
+            if let Some(user) = user {
+                ctx.insert(user);
+            }
+            service.serve(ctx, tokio::io::duplex(64).0)
+        };
+
+        let a = serve(Some("free")).await.unwrap();
+        let b = serve(Some("free")).await.unwrap();
+        let c = serve(Some("premium")).await.unwrap();
+        let d = serve(None).await.unwrap();
+        assert!(Arc::ptr_eq(&a.limit().inner, &b.limit().inner));
+        assert_eq!(c.limit().get_write_rate(), Some(1000));
+        assert_eq!(d.limit().get_write_rate(), None);
+
+        // adjust the limit of a key at runtime
+        limits.get(&"free").unwrap().set_write_rate(Some(500));
+        assert_eq!(b.limit().get_write_rate(), Some(500));
+
+        // the limit of a key is dropped once none of its streams remain
+        drop((a, b));
+        assert!(limits.get(&"free").is_none());
+    }
+}
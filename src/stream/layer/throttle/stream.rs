@@ -0,0 +1,182 @@
+//! Provides [`ThrottledStream`] which wraps a [`AsyncRead`] and/or [`AsyncWrite`]
+//! in order to limit the number of bytes read and/or written per second.
+//!
+//! [`AsyncRead`]: crate::stream::AsyncRead
+//! [`AsyncWrite`]: crate::stream::AsyncWrite
+
+use super::{bucket::TokenBucket, BandwidthLimit};
+use pin_project_lite::pin_project;
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::Sleep,
+};
+
+pin_project! {
+    /// A wrapper around a [`AsyncRead`] and/or [`AsyncWrite`] that limits
+    /// the number of bytes read and/or written per second to its [`BandwidthLimit`].
+    ///
+    /// [`AsyncRead`]: crate::stream::AsyncRead
+    /// [`AsyncWrite`]: crate::stream::AsyncWrite
+    #[derive(Debug)]
+    pub struct ThrottledStream<S> {
+        #[pin]
+        stream: S,
+        limit: BandwidthLimit,
+        read_delay: Option<Pin<Box<Sleep>>>,
+        write_delay: Option<Pin<Box<Sleep>>>,
+    }
+}
+
+impl<S> ThrottledStream<S> {
+    /// Create a new [`ThrottledStream`] that wraps the given [`AsyncRead`] and/or [`AsyncWrite`],
+    /// consuming the bandwidth of the given [`BandwidthLimit`].
+    ///
+    /// [`AsyncRead`]: crate::stream::AsyncRead
+    /// [`AsyncWrite`]: crate::stream::AsyncWrite
+    pub fn new(stream: S, limit: BandwidthLimit) -> Self {
+        Self {
+            stream,
+            limit,
+            read_delay: None,
+            write_delay: None,
+        }
+    }
+
+    /// Get the [`BandwidthLimit`] of this stream.
+    pub fn limit(&self) -> &BandwidthLimit {
+        &self.limit
+    }
+
+    /// Get the inner [`AsyncRead`] and/or [`AsyncWrite`] stream,
+    /// no longer limiting its bandwidth.
+    ///
+    /// [`AsyncRead`]: crate::stream::AsyncRead
+    /// [`AsyncWrite`]: crate::stream::AsyncWrite
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+/// Wait until the bucket has bytes available, returning how many.
+fn poll_available(
+    bucket: &TokenBucket,
+    delay: &mut Option<Pin<Box<Sleep>>>,
+    cx: &mut Context<'_>,
+) -> Poll<usize> {
+    loop {
+        if let Some(sleep) = delay.as_mut() {
+            ready!(sleep.as_mut().poll(cx));
+            *delay = None;
+        }
+        match bucket.available() {
+            Ok(available) => return Poll::Ready(available),
+            Err(wait) => *delay = Some(Box::pin(tokio::time::sleep(wait))),
+        }
+    }
+}
+
+impl<S> AsyncRead for ThrottledStream<S>
+where
+    S: AsyncRead,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.project();
+        let bucket = &this.limit.inner.read;
+        ready!(poll_available(bucket, this.read_delay, cx));
+
+        // the bytes are only known once read, possibly going into debt
+        let size = buf.filled().len();
+        ready!(this.stream.poll_read(cx, buf))?;
+        bucket.consume(buf.filled().len().saturating_sub(size));
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S> AsyncWrite for ThrottledStream<S>
+where
+    S: AsyncWrite,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        let this = self.project();
+        let bucket = &this.limit.inner.write;
+        let available = ready!(poll_available(bucket, this.write_delay, cx));
+
+        let n = ready!(this.stream.poll_write(cx, &buf[..buf.len().min(available)]))?;
+        bucket.consume(n);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        self.project().stream.poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        self.project().stream.poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        time::Instant,
+    };
+
+    #[tokio::test(start_paused = true)]
+    async fn test_throttled_write() {
+        let (mut client, server) = tokio::io::duplex(1024);
+        let mut stream = ThrottledStream::new(server, BandwidthLimit::unlimited().write_rate(100));
+
+        let start = Instant::now();
+        let (result, ()) = tokio::join!(stream.write_all(&[0; 300]), async {
+            let mut buf = [0; 300];
+            client.read_exact(&mut buf).await.unwrap();
+        });
+        result.unwrap();
+        // the first 100 bytes are sent immediately, as the bucket starts full
+        let elapsed = start.elapsed();
+        assert!(elapsed > Duration::from_millis(1950) && elapsed < Duration::from_millis(2050));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_throttled_read_shared() {
+        let limit = BandwidthLimit::unlimited().read_rate(100);
+        let (mut client_a, server_a) = tokio::io::duplex(1024);
+        let (mut client_b, server_b) = tokio::io::duplex(1024);
+        let mut stream_a = ThrottledStream::new(server_a, limit.clone());
+        let mut stream_b = ThrottledStream::new(server_b, limit.clone());
+
+        client_a.write_all(&[0; 150]).await.unwrap();
+        client_b.write_all(&[0; 150]).await.unwrap();
+
+        let start = Instant::now();
+        let mut buf = [0; 150];
+        stream_a.read_exact(&mut buf).await.unwrap();
+        stream_b.read_exact(&mut buf).await.unwrap();
+        // both streams share the same budget
+        assert!(start.elapsed() >= Duration::from_millis(500));
+
+        // which can be lifted at runtime
+        limit.set_read_rate(None);
+        client_a.write_all(&[0; 150]).await.unwrap();
+        let start = Instant::now();
+        stream_a.read_exact(&mut buf).await.unwrap();
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+}
@@ -0,0 +1,229 @@
+//! Middleware that enforces idle, read and write timeouts
+//! as well as a minimum data rate on a [`Stream`].
+//!
+//! Unlike the [`TimeoutLayer`], which bounds an entire [`Service::serve`] call,
+//! the [`StreamTimeoutLayer`] only bounds the time a connection may go silent,
+//! which makes it suitable for tunnels and other long-lived connections
+//! such as the ones served by a [`Forwarder`].
+//!
+//! [`TimeoutLayer`]: crate::service::layer::TimeoutLayer
+//! [`Forwarder`]: crate::tcp::service::Forwarder
+
+use crate::{
+    service::{Context, Layer, Service},
+    stream::Stream,
+};
+use std::{
+    fmt,
+    future::Future,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+
+mod stream;
+#[doc(inline)]
+pub use stream::TimeoutStream;
+use stream::{MinDataRate, TimeoutConfig};
+
+/// The reason why a [`TimeoutStream`] timed out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamTimeoutReason {
+    /// No bytes were read or written within the idle timeout.
+    Idle,
+    /// A read did not complete within the read timeout.
+    Read,
+    /// A write did not complete within the write timeout.
+    Write,
+    /// The peer sent data slower than the minimum data rate.
+    MinDataRate,
+}
+
+impl fmt::Display for StreamTimeoutReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Idle => write!(f, "idle"),
+            Self::Read => write!(f, "read"),
+            Self::Write => write!(f, "write"),
+            Self::MinDataRate => write!(f, "min data rate"),
+        }
+    }
+}
+
+/// A handle to a [`TimeoutStream`] that can be used to know if and why
+/// the stream timed out, even though the stream is consumed by a protocol consumer.
+///
+/// The [`StreamTimeoutService`] inserts this handle in the [`Context`].
+#[derive(Debug, Clone)]
+pub struct StreamTimeoutHandle {
+    reason: Arc<OnceLock<StreamTimeoutReason>>,
+}
+
+impl StreamTimeoutHandle {
+    /// Get the reason why the stream timed out, if it did (so far).
+    pub fn reason(&self) -> Option<StreamTimeoutReason> {
+        self.reason.get().copied()
+    }
+}
+
+/// A [`Service`] which wraps the [`Stream`] in a [`TimeoutStream`],
+/// created using the [`StreamTimeoutLayer`].
+#[derive(Debug)]
+pub struct StreamTimeoutService<S> {
+    inner: S,
+    config: TimeoutConfig,
+}
+
+impl<S> Clone for StreamTimeoutService<S>
+where
+    S: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            config: self.config,
+        }
+    }
+}
+
+impl<State, S, IO> Service<State, IO> for StreamTimeoutService<S>
+where
+    State: Send + Sync + 'static,
+    S: Service<State, TimeoutStream<IO>>,
+    IO: Stream,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+
+    fn serve(
+        &self,
+        mut ctx: Context<State>,
+        stream: IO,
+    ) -> impl Future<Output = Result<Self::Response, Self::Error>> + Send + '_ {
+        let stream = TimeoutStream::new(stream, self.config);
+        ctx.insert(stream.handle());
+        self.inner.serve(ctx, stream)
+    }
+}
+
+/// A [`Layer`] that enforces idle, read and write timeouts
+/// as well as a minimum data rate on a [`Stream`].
+///
+/// No timeout is enforced by default.
+///
+/// # Example
+///
+/// ```
+/// use rama::stream::layer::StreamTimeoutLayer;
+/// use std::time::Duration;
+///
+/// let layer = StreamTimeoutLayer::new()
+///     .idle_timeout(Duration::from_secs(60))
+///     .write_timeout(Duration::from_secs(10))
+///     .min_data_rate(240, Duration::from_secs(5));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct StreamTimeoutLayer {
+    config: TimeoutConfig,
+}
+
+impl StreamTimeoutLayer {
+    /// Create a new [`StreamTimeoutLayer`], enforcing no timeouts.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Close the connection once no bytes were read or written for the given duration.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.config.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Close the connection once a read waited for data longer than the given duration.
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.config.read_timeout = Some(timeout);
+        self
+    }
+
+    /// Close the connection once a write was blocked longer than the given duration,
+    /// e.g. because the peer stopped reading.
+    pub fn write_timeout(mut self, timeout: Duration) -> Self {
+        self.config.write_timeout = Some(timeout);
+        self
+    }
+
+    /// Close the connection once the peer sends data slower than the given rate,
+    /// as a defence against slowloris attacks.
+    ///
+    /// The rate is measured over the time spent waiting for data,
+    /// and is only enforced once that time exceeds the grace period.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the given rate is `0`.
+    pub fn min_data_rate(mut self, bytes_per_second: u64, grace_period: Duration) -> Self {
+        assert!(
+            bytes_per_second > 0,
+            "min data rate must be greater than zero"
+        );
+        self.config.min_data_rate = Some(MinDataRate {
+            bytes_per_second,
+            grace_period,
+        });
+        self
+    }
+}
+
+impl<S> Layer<S> for StreamTimeoutLayer {
+    type Service = StreamTimeoutService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        StreamTimeoutService {
+            inner,
+            config: self.config,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{service::service_fn, tcp::server::TcpListener, tcp::service::Forwarder};
+    use std::convert::Infallible;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_stream_timeout_layer_forwarder() {
+        let echo = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let echo_addr = echo.local_addr().unwrap();
+        tokio::spawn(
+            echo.serve_fn(|mut stream: tokio::net::TcpStream| async move {
+                let (mut r, mut w) = stream.split();
+                tokio::io::copy(&mut r, &mut w).await
+            }),
+        );
+
+        let service = StreamTimeoutLayer::new()
+            .idle_timeout(Duration::from_millis(50))
+            .layer(service_fn(
+                move |ctx: Context<()>, stream: TimeoutStream<tokio::io::DuplexStream>| async move {
+                    let handle = ctx.get::<StreamTimeoutHandle>().unwrap().clone();
+                    let result = Forwarder::target(echo_addr).serve(ctx, stream).await;
+                    Ok::<_, Infallible>((result, handle.reason()))
+                },
+            ));
+
+        let (mut client, server) = tokio::io::duplex(64);
+        let (result, ()) = tokio::join!(service.serve(Context::default(), server), async {
+            client.write_all(b"ping").await.unwrap();
+            let mut buf = [0; 4];
+            client.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"ping");
+            // keep the client connected, but silent
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        });
+
+        let (result, reason) = result.unwrap();
+        assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::TimedOut);
+        assert_eq!(reason, Some(StreamTimeoutReason::Idle));
+    }
+}
@@ -0,0 +1,424 @@
+//! Provides [`TimeoutStream`] which wraps a [`AsyncRead`] and/or [`AsyncWrite`]
+//! in order to enforce idle, read and write timeouts as well as a minimum data rate.
+//!
+//! [`AsyncRead`]: crate::stream::AsyncRead
+//! [`AsyncWrite`]: crate::stream::AsyncWrite
+
+use super::{StreamTimeoutHandle, StreamTimeoutReason};
+use pin_project_lite::pin_project;
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    sync::{Arc, OnceLock},
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::{Instant, Sleep},
+};
+
+/// The timeouts enforced by a [`TimeoutStream`].
+#[derive(Debug, Clone, Copy, Default)]
+pub(super) struct TimeoutConfig {
+    pub(super) idle_timeout: Option<Duration>,
+    pub(super) read_timeout: Option<Duration>,
+    pub(super) write_timeout: Option<Duration>,
+    pub(super) min_data_rate: Option<MinDataRate>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(super) struct MinDataRate {
+    pub(super) bytes_per_second: u64,
+    pub(super) grace_period: Duration,
+}
+
+/// Keeps track of the time spent waiting for data and the data received meanwhile.
+#[derive(Debug, Default)]
+struct ReadRate {
+    bytes: u64,
+    waited: Duration,
+    waiting_since: Option<Instant>,
+}
+
+pin_project! {
+    /// A wrapper around a [`AsyncRead`] and/or [`AsyncWrite`] that enforces
+    /// idle, read and write timeouts as well as a minimum data rate for reads.
+    ///
+    /// Once a timeout is reached, all subsequent operations fail
+    /// with an [`io::ErrorKind::TimedOut`] error, such that the consumer
+    /// of the stream closes the connection. Use [`TimeoutStream::handle`] to get a
+    /// [`StreamTimeoutHandle`] in order to know why the stream timed out.
+    ///
+    /// [`AsyncRead`]: crate::stream::AsyncRead
+    /// [`AsyncWrite`]: crate::stream::AsyncWrite
+    #[derive(Debug)]
+    pub struct TimeoutStream<S> {
+        #[pin]
+        stream: S,
+        config: TimeoutConfig,
+        reason: Arc<OnceLock<StreamTimeoutReason>>,
+        idle: Option<Pin<Box<Sleep>>>,
+        read: Option<Pin<Box<Sleep>>>,
+        write: Option<Pin<Box<Sleep>>>,
+        rate: Option<Pin<Box<Sleep>>>,
+        read_rate: ReadRate,
+    }
+}
+
+impl<S> TimeoutStream<S> {
+    pub(super) fn new(stream: S, config: TimeoutConfig) -> Self {
+        Self {
+            stream,
+            config,
+            reason: Arc::new(OnceLock::new()),
+            idle: None,
+            read: None,
+            write: None,
+            rate: None,
+            read_rate: ReadRate::default(),
+        }
+    }
+
+    /// Get a [`StreamTimeoutHandle`] that can be used to know if and why
+    /// the stream timed out, even though the stream is consumed by a protocol
+    /// consumer in a later stage.
+    pub fn handle(&self) -> StreamTimeoutHandle {
+        StreamTimeoutHandle {
+            reason: self.reason.clone(),
+        }
+    }
+
+    /// Get a reference to the inner [`AsyncRead`] and/or [`AsyncWrite`] stream.
+    ///
+    /// [`AsyncRead`]: crate::stream::AsyncRead
+    /// [`AsyncWrite`]: crate::stream::AsyncWrite
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    /// Get the inner [`AsyncRead`] and/or [`AsyncWrite`] stream,
+    /// no longer enforcing any of the timeouts.
+    ///
+    /// [`AsyncRead`]: crate::stream::AsyncRead
+    /// [`AsyncWrite`]: crate::stream::AsyncWrite
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+impl<S> TimeoutStream<S>
+where
+    S: AsyncWrite,
+{
+    /// Poll a write operation of the inner stream, enforcing the write and idle timeouts.
+    fn poll_write_op<T>(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        op: impl FnOnce(Pin<&mut S>, &mut Context<'_>) -> Poll<io::Result<T>>,
+        written: impl FnOnce(&T) -> usize,
+    ) -> Poll<io::Result<T>> {
+        let this = self.project();
+        if let Some(reason) = this.reason.get() {
+            return Poll::Ready(Err(timeout_error(*reason)));
+        }
+
+        match op(this.stream, cx) {
+            Poll::Ready(result) => {
+                *this.write = None;
+                if let Ok(value) = &result {
+                    if written(value) > 0 {
+                        reset_idle(this.idle, this.config.idle_timeout);
+                    }
+                }
+                Poll::Ready(result)
+            }
+            Poll::Pending => {
+                if let Some(timeout) = this.config.write_timeout {
+                    let write = this
+                        .write
+                        .get_or_insert_with(|| Box::pin(tokio::time::sleep(timeout)));
+                    if write.as_mut().poll(cx).is_ready() {
+                        return Poll::Ready(Err(timed_out(
+                            this.reason,
+                            StreamTimeoutReason::Write,
+                        )));
+                    }
+                }
+                if poll_idle(this.idle, this.config.idle_timeout, cx) {
+                    return Poll::Ready(Err(timed_out(this.reason, StreamTimeoutReason::Idle)));
+                }
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<S> AsyncRead for TimeoutStream<S>
+where
+    S: AsyncRead,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.project();
+        if let Some(reason) = this.reason.get() {
+            return Poll::Ready(Err(timeout_error(*reason)));
+        }
+
+        let size = buf.filled().len();
+        match this.stream.poll_read(cx, buf) {
+            Poll::Ready(result) => {
+                *this.read = None;
+                *this.rate = None;
+                let bytes_read = buf.filled().len().saturating_sub(size);
+                if let Some(since) = this.read_rate.waiting_since.take() {
+                    this.read_rate.waited += since.elapsed();
+                }
+                this.read_rate.bytes += bytes_read as u64;
+                if bytes_read > 0 {
+                    reset_idle(this.idle, this.config.idle_timeout);
+                }
+                Poll::Ready(result)
+            }
+            Poll::Pending => {
+                if let Some(timeout) = this.config.read_timeout {
+                    let read = this
+                        .read
+                        .get_or_insert_with(|| Box::pin(tokio::time::sleep(timeout)));
+                    if read.as_mut().poll(cx).is_ready() {
+                        return Poll::Ready(Err(timed_out(this.reason, StreamTimeoutReason::Read)));
+                    }
+                }
+                if let Some(min_rate) = this.config.min_data_rate {
+                    let read_rate = this.read_rate;
+                    let rate = this.rate.get_or_insert_with(|| {
+                        let now = Instant::now();
+                        read_rate.waiting_since = Some(now);
+                        // the time allowed to wait for data, given the data received so far
+                        let allowed = Duration::from_secs_f64(
+                            read_rate.bytes as f64 / min_rate.bytes_per_second as f64,
+                        )
+                        .max(min_rate.grace_period);
+                        Box::pin(tokio::time::sleep_until(
+                            now + allowed.saturating_sub(read_rate.waited),
+                        ))
+                    });
+                    if rate.as_mut().poll(cx).is_ready() {
+                        return Poll::Ready(Err(timed_out(
+                            this.reason,
+                            StreamTimeoutReason::MinDataRate,
+                        )));
+                    }
+                }
+                if poll_idle(this.idle, this.config.idle_timeout, cx) {
+                    return Poll::Ready(Err(timed_out(this.reason, StreamTimeoutReason::Idle)));
+                }
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<S> AsyncWrite for TimeoutStream<S>
+where
+    S: AsyncWrite,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        self.poll_write_op(cx, |stream, cx| stream.poll_write(cx, buf), |n| *n)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        self.poll_write_op(cx, |stream, cx| stream.poll_flush(cx), |_| 0)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        self.project().stream.poll_shutdown(cx)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<Result<usize, io::Error>> {
+        self.poll_write_op(
+            cx,
+            |stream, cx| stream.poll_write_vectored(cx, bufs),
+            |n| *n,
+        )
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.stream.is_write_vectored()
+    }
+}
+
+/// Poll the idle timer, starting it if not yet done,
+/// returning true if the stream has been idle for too long.
+fn poll_idle(
+    idle: &mut Option<Pin<Box<Sleep>>>,
+    timeout: Option<Duration>,
+    cx: &mut Context<'_>,
+) -> bool {
+    match timeout {
+        Some(timeout) => idle
+            .get_or_insert_with(|| Box::pin(tokio::time::sleep(timeout)))
+            .as_mut()
+            .poll(cx)
+            .is_ready(),
+        None => false,
+    }
+}
+
+fn reset_idle(idle: &mut Option<Pin<Box<Sleep>>>, timeout: Option<Duration>) {
+    if let (Some(idle), Some(timeout)) = (idle, timeout) {
+        idle.as_mut().reset(Instant::now() + timeout);
+    }
+}
+
+fn timed_out(reason: &OnceLock<StreamTimeoutReason>, timeout: StreamTimeoutReason) -> io::Error {
+    let reason = *reason.get_or_init(|| timeout);
+    tracing::debug!(%reason, "stream timed out: close connection");
+    timeout_error(reason)
+}
+
+fn timeout_error(reason: StreamTimeoutReason) -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, format!("stream {reason} timeout"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn config() -> TimeoutConfig {
+        TimeoutConfig::default()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_idle_timeout() {
+        let (mut client, server) = tokio::io::duplex(64);
+        let mut stream = TimeoutStream::new(
+            server,
+            TimeoutConfig {
+                idle_timeout: Some(Duration::from_secs(10)),
+                ..config()
+            },
+        );
+        let handle = stream.handle();
+
+        let mut buf = [0; 4];
+        for _ in 0..3 {
+            let (result, ()) = tokio::join!(stream.read_exact(&mut buf), async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                client.write_all(b"ping").await.unwrap();
+            });
+            result.unwrap();
+        }
+        assert!(handle.reason().is_none());
+
+        let start = Instant::now();
+        let err = stream.read(&mut buf).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(start.elapsed(), Duration::from_secs(10));
+        assert_eq!(handle.reason(), Some(StreamTimeoutReason::Idle));
+
+        // the stream remains closed
+        client.write_all(b"ping").await.unwrap();
+        let err = stream.read(&mut buf).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_read_timeout() {
+        let (_client, server) = tokio::io::duplex(64);
+        let mut stream = TimeoutStream::new(
+            server,
+            TimeoutConfig {
+                idle_timeout: Some(Duration::from_secs(10)),
+                read_timeout: Some(Duration::from_secs(1)),
+                ..config()
+            },
+        );
+
+        let err = stream.read(&mut [0; 4]).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(stream.handle().reason(), Some(StreamTimeoutReason::Read));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_write_timeout() {
+        let (_client, server) = tokio::io::duplex(4);
+        let mut stream = TimeoutStream::new(
+            server,
+            TimeoutConfig {
+                write_timeout: Some(Duration::from_secs(1)),
+                ..config()
+            },
+        );
+
+        // the peer never reads, so the write blocks once the buffer is full
+        let err = stream.write_all(b"hello world").await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(stream.handle().reason(), Some(StreamTimeoutReason::Write));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_min_data_rate() {
+        let (mut client, server) = tokio::io::duplex(64);
+        let mut stream = TimeoutStream::new(
+            server,
+            TimeoutConfig {
+                min_data_rate: Some(MinDataRate {
+                    bytes_per_second: 2,
+                    grace_period: Duration::from_secs(5),
+                }),
+                ..config()
+            },
+        );
+        let handle = stream.handle();
+
+        // a byte per second is within the grace period
+        let mut buf = [0; 1];
+        for _ in 0..4 {
+            let (result, ()) = tokio::join!(stream.read_exact(&mut buf), async {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                client.write_all(b"x").await.unwrap();
+            });
+            result.unwrap();
+        }
+        assert!(handle.reason().is_none());
+
+        // but too slow once the grace period is over
+        let start = Instant::now();
+        let writer = async {
+            loop {
+                tokio::time::sleep(Duration::from_secs(2)).await;
+                if client.write_all(b"x").await.is_err() {
+                    break;
+                }
+            }
+        };
+        let reader = async {
+            loop {
+                if let Err(err) = stream.read_exact(&mut buf).await {
+                    return err;
+                }
+            }
+        };
+        let err = tokio::select! {
+            err = reader => err,
+            _ = writer => unreachable!(),
+        };
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+        assert_eq!(handle.reason(), Some(StreamTimeoutReason::MinDataRate));
+    }
+}
@@ -10,11 +10,11 @@
 //! [`AsyncWrite`]: crate::stream::AsyncWrite
 
 use std::{
-    io,
+    fmt, io,
     pin::Pin,
     sync::{
-        atomic::{AtomicUsize, Ordering},
-        Arc,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
     },
     task::{Context, Poll},
 };
@@ -37,6 +37,8 @@ pin_project! {
     pub struct BytesRWTracker<S> {
         read: Arc<AtomicUsize>,
         written: Arc<AtomicUsize>,
+        taken: Arc<AtomicU64>,
+        on_close: CloseGuard,
         #[pin]
         stream: S,
     }
@@ -48,9 +50,22 @@ impl<S> BytesRWTracker<S> {
     /// [`AsyncRead`]: crate::stream::AsyncRead
     /// [`AsyncWrite`]: crate::stream::AsyncWrite
     pub fn new(stream: S) -> Self {
+        let read = Arc::new(AtomicUsize::new(0));
+        let written = Arc::new(AtomicUsize::new(0));
+        let taken = Arc::new(AtomicU64::new(0));
+        let on_close = CloseGuard {
+            handle: BytesRWTrackerHandle {
+                read: read.clone(),
+                written: written.clone(),
+                taken: taken.clone(),
+                on_close: Default::default(),
+            },
+        };
         Self {
-            read: Arc::new(AtomicUsize::new(0)),
-            written: Arc::new(AtomicUsize::new(0)),
+            read,
+            written,
+            taken,
+            on_close,
             stream,
         }
     }
@@ -72,6 +87,8 @@ impl<S> BytesRWTracker<S> {
         BytesRWTrackerHandle {
             read: self.read.clone(),
             written: self.written.clone(),
+            taken: self.taken.clone(),
+            on_close: self.on_close.handle.on_close.clone(),
         }
     }
 
@@ -164,13 +181,48 @@ where
     }
 }
 
+type CloseFn = Box<dyn FnOnce(u64) + Send + 'static>;
+
+/// Calls the function registered using [`BytesRWTrackerHandle::on_close`]
+/// once the tracker is dropped.
+struct CloseGuard {
+    handle: BytesRWTrackerHandle,
+}
+
+impl fmt::Debug for CloseGuard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CloseGuard").finish()
+    }
+}
+
+impl Drop for CloseGuard {
+    fn drop(&mut self) {
+        let on_close = self.handle.on_close.lock().unwrap().take();
+        if let Some(on_close) = on_close {
+            on_close(self.handle.take());
+        }
+    }
+}
+
 /// A handle to a tracker that can be used to get the number of bytes
 /// read and/or written even though the tracker is consumed by a protocol
 /// consumer.
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct BytesRWTrackerHandle {
     read: Arc<AtomicUsize>,
     written: Arc<AtomicUsize>,
+    taken: Arc<AtomicU64>,
+    on_close: Arc<Mutex<Option<CloseFn>>>,
+}
+
+impl fmt::Debug for BytesRWTrackerHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BytesRWTrackerHandle")
+            .field("read", &self.read)
+            .field("written", &self.written)
+            .field("taken", &self.taken)
+            .finish()
+    }
 }
 
 impl BytesRWTrackerHandle {
@@ -183,6 +235,25 @@ impl BytesRWTrackerHandle {
     pub fn written(&self) -> usize {
         self.written.load(Ordering::SeqCst)
     }
+
+    /// Take the number of bytes read and written since the previous take,
+    /// using this or any other handle of the same tracker.
+    ///
+    /// This can be used to account for all bytes of a stream in parts,
+    /// e.g. per request served over it, counting each byte exactly once.
+    pub fn take(&self) -> u64 {
+        let total = (self.read() as u64).saturating_add(self.written() as u64);
+        let taken = self.taken.fetch_max(total, Ordering::SeqCst);
+        total.saturating_sub(taken)
+    }
+
+    /// Call the given function once the tracker is dropped, e.g. when the connection
+    /// is closed, with the number of bytes which are not yet [taken](Self::take).
+    ///
+    /// Only a single function is kept, replacing any function registered before.
+    pub fn on_close(&self, f: impl FnOnce(u64) + Send + 'static) {
+        *self.on_close.lock().unwrap() = Some(Box::new(f));
+    }
 }
 
 #[cfg(test)]
@@ -402,4 +473,40 @@ mod tests {
 
         futures::future::join_all(vec![task_1, task_2]).await;
     }
+
+    #[tokio::test]
+    async fn test_handle_take() {
+        let stream = Builder::new().read(b"foo").write(b"barbaz").build();
+        let mut tracker = BytesRWTracker::new(stream);
+        let handle = tracker.handle();
+        let other = handle.clone();
+        assert_eq!(handle.take(), 0);
+
+        let mut buf = [0u8; 3];
+        tracker.read_exact(&mut buf).await.unwrap();
+        assert_eq!(handle.take(), 3);
+        assert_eq!(other.take(), 0);
+
+        tracker.write_all(b"barbaz").await.unwrap();
+        assert_eq!(other.take(), 6);
+        assert_eq!(tracker.handle().take(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_handle_on_close() {
+        let stream = Builder::new().read(b"foo").read(b"bar").build();
+        let mut tracker = BytesRWTracker::new(stream);
+        let handle = tracker.handle();
+
+        let mut buf = [0u8; 3];
+        tracker.read_exact(&mut buf).await.unwrap();
+        assert_eq!(handle.take(), 3);
+        tracker.read_exact(&mut buf).await.unwrap();
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        handle.on_close(move |bytes| tx.send(bytes).unwrap());
+        drop(tracker);
+        assert_eq!(rx.recv().unwrap(), 3);
+        assert_eq!(handle.take(), 0);
+    }
 }
@@ -5,7 +5,7 @@ use crate::{
 use std::future::Future;
 
 mod bytes;
-use bytes::BytesRWTracker;
+pub(crate) use bytes::BytesRWTracker;
 #[doc(inline)]
 pub use bytes::BytesRWTrackerHandle;
 
@@ -26,6 +26,10 @@ mod ip;
 #[doc(inline)]
 pub use ip::IpNetMatcher;
 
+mod peer_cred;
+#[doc(inline)]
+pub use peer_cred::PeerCredentialsMatcher;
+
 use crate::{
     http::Request,
     service::{context::Extensions, matcher::IteratorMatcherExt, Context},
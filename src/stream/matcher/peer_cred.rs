@@ -0,0 +1,126 @@
+use http::Request;
+
+use crate::{
+    service::{context::Extensions, Context},
+    stream::PeerCredentials,
+};
+
+#[derive(Debug, Clone)]
+/// Matcher based on the [`PeerCredentials`] of the peer,
+/// available for Unix domain socket connections.
+pub struct PeerCredentialsMatcher {
+    kind: PeerCredentialsKind,
+    optional: bool,
+}
+
+#[derive(Debug, Clone)]
+enum PeerCredentialsKind {
+    Uid(u32),
+    Gid(u32),
+    Pid(i32),
+}
+
+impl PeerCredentialsMatcher {
+    /// create a new peer credentials matcher to match on the user ID of the peer.
+    ///
+    /// This matcher will not match in case the peer credentials could not be found,
+    /// if you want to match in case the peer credentials could not be found,
+    /// use the [`PeerCredentialsMatcher::optional`] method.
+    pub fn uid(uid: u32) -> Self {
+        Self {
+            kind: PeerCredentialsKind::Uid(uid),
+            optional: false,
+        }
+    }
+
+    /// create a new peer credentials matcher to match on the group ID of the peer.
+    ///
+    /// See [`PeerCredentialsMatcher::uid`] for more information.
+    pub fn gid(gid: u32) -> Self {
+        Self {
+            kind: PeerCredentialsKind::Gid(gid),
+            optional: false,
+        }
+    }
+
+    /// create a new peer credentials matcher to match on the process ID of the peer.
+    ///
+    /// This matcher does not match if the process ID is not known on this platform,
+    /// even if it is [optional](PeerCredentialsMatcher::optional).
+    ///
+    /// See [`PeerCredentialsMatcher::uid`] for more information.
+    pub fn pid(pid: i32) -> Self {
+        Self {
+            kind: PeerCredentialsKind::Pid(pid),
+            optional: false,
+        }
+    }
+
+    /// Make this matcher match in case the peer credentials could not be found.
+    pub fn optional(mut self) -> Self {
+        self.optional = true;
+        self
+    }
+
+    fn matches_credentials(&self, cred: Option<&PeerCredentials>) -> bool {
+        match cred {
+            Some(cred) => match self.kind {
+                PeerCredentialsKind::Uid(uid) => cred.uid() == uid,
+                PeerCredentialsKind::Gid(gid) => cred.gid() == gid,
+                PeerCredentialsKind::Pid(pid) => cred.pid() == Some(pid),
+            },
+            None => self.optional,
+        }
+    }
+}
+
+impl<State, Body> crate::service::Matcher<State, Request<Body>> for PeerCredentialsMatcher {
+    fn matches(
+        &self,
+        _ext: Option<&mut Extensions>,
+        ctx: &Context<State>,
+        _req: &Request<Body>,
+    ) -> bool {
+        self.matches_credentials(ctx.get::<PeerCredentials>())
+    }
+}
+
+#[cfg(unix)]
+impl<State> crate::service::Matcher<State, tokio::net::UnixStream> for PeerCredentialsMatcher {
+    fn matches(
+        &self,
+        _ext: Option<&mut Extensions>,
+        _ctx: &Context<State>,
+        stream: &tokio::net::UnixStream,
+    ) -> bool {
+        let cred = stream.peer_cred().ok().map(PeerCredentials::from);
+        self.matches_credentials(cred.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::service::Matcher;
+
+    fn request() -> Request<()> {
+        Request::builder().body(()).unwrap()
+    }
+
+    #[test]
+    fn test_peer_credentials_matcher() {
+        let mut ctx = Context::default();
+
+        let matcher = PeerCredentialsMatcher::uid(1000);
+        assert!(!matcher.matches(None, &ctx, &request()));
+        assert!(matcher.clone().optional().matches(None, &ctx, &request()));
+
+        ctx.insert(PeerCredentials::new(1000, 100, None));
+        assert!(matcher.matches(None, &ctx, &request()));
+        assert!(!PeerCredentialsMatcher::uid(0).matches(None, &ctx, &request()));
+        assert!(PeerCredentialsMatcher::gid(100).matches(None, &ctx, &request()));
+        assert!(!PeerCredentialsMatcher::pid(1)
+            .optional()
+            .matches(None, &ctx, &request()));
+    }
+}
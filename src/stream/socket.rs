@@ -87,3 +87,41 @@ impl SocketInfo {
         &self.peer_addr
     }
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Credentials of the process on the other end of a connected socket,
+/// as reported by the operating system for Unix domain sockets.
+pub struct PeerCredentials {
+    uid: u32,
+    gid: u32,
+    pid: Option<i32>,
+}
+
+impl PeerCredentials {
+    /// Create new `PeerCredentials`.
+    pub fn new(uid: u32, gid: u32, pid: Option<i32>) -> Self {
+        Self { uid, gid, pid }
+    }
+
+    /// Get the user ID of the peer process.
+    pub fn uid(&self) -> u32 {
+        self.uid
+    }
+
+    /// Get the group ID of the peer process.
+    pub fn gid(&self) -> u32 {
+        self.gid
+    }
+
+    /// Get the process ID of the peer process, if known on this platform.
+    pub fn pid(&self) -> Option<i32> {
+        self.pid
+    }
+}
+
+#[cfg(unix)]
+impl From<tokio::net::unix::UCred> for PeerCredentials {
+    fn from(cred: tokio::net::unix::UCred) -> Self {
+        Self::new(cred.uid(), cred.gid(), cred.pid())
+    }
+}
@@ -0,0 +1,150 @@
+use crate::service::{Context, Service};
+use socket2::{SockRef, TcpKeepalive};
+use std::{io, net::SocketAddr};
+use tokio::net::{TcpSocket, TcpStream, ToSocketAddrs};
+
+/// A connector which establishes a [`TcpStream`] to the target address.
+///
+/// The target can be anything that resolves to one or more socket addresses,
+/// such as a [`SocketAddr`] or a `host:port` string, in which case each resolved
+/// address is tried until a connection is established.
+#[derive(Debug, Clone, Default)]
+pub struct TcpConnector {
+    bind_addr: Option<SocketAddr>,
+    nodelay: Option<bool>,
+    keepalive: Option<TcpKeepalive>,
+}
+
+impl TcpConnector {
+    /// Create a new [`TcpConnector`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bind the local end of each connection to the given address,
+    /// e.g. to select the outgoing interface on a multi-homed host.
+    ///
+    /// Use port `0` to let the OS assign a port. Resolved target addresses
+    /// of a different IP version than the bind address are skipped.
+    pub fn bind_addr(mut self, addr: SocketAddr) -> Self {
+        self.bind_addr = Some(addr);
+        self
+    }
+
+    /// Sets the value for the `TCP_NODELAY` option on each connection.
+    pub fn nodelay(mut self, nodelay: bool) -> Self {
+        self.nodelay = Some(nodelay);
+        self
+    }
+
+    /// Enables TCP keepalive on each connection, using the given parameters.
+    pub fn keepalive(mut self, keepalive: TcpKeepalive) -> Self {
+        self.keepalive = Some(keepalive);
+        self
+    }
+
+    async fn connect_addr(&self, addr: SocketAddr) -> io::Result<TcpStream> {
+        let socket = if addr.is_ipv4() {
+            TcpSocket::new_v4()?
+        } else {
+            TcpSocket::new_v6()?
+        };
+        if let Some(bind_addr) = self.bind_addr {
+            socket.bind(bind_addr)?;
+        }
+        if let Some(keepalive) = &self.keepalive {
+            SockRef::from(&socket).set_tcp_keepalive(keepalive)?;
+        }
+
+        let stream = socket.connect(addr).await?;
+        if let Some(nodelay) = self.nodelay {
+            stream.set_nodelay(nodelay)?;
+        }
+        Ok(stream)
+    }
+}
+
+impl<State, Target> Service<State, Target> for TcpConnector
+where
+    State: Send + Sync + 'static,
+    Target: ToSocketAddrs + Send + 'static,
+{
+    type Response = TcpStream;
+    type Error = io::Error;
+
+    async fn serve(
+        &self,
+        _ctx: Context<State>,
+        target: Target,
+    ) -> Result<Self::Response, Self::Error> {
+        let mut last_err = None;
+        for addr in tokio::net::lookup_host(target).await? {
+            if self
+                .bind_addr
+                .is_some_and(|bind_addr| bind_addr.is_ipv4() != addr.is_ipv4())
+            {
+                continue;
+            }
+            match self.connect_addr(addr).await {
+                Ok(stream) => return Ok(stream),
+                Err(err) => last_err = Some(err),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "could not resolve to any address",
+            )
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tcp::server::TcpListener;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_tcp_connector_options() {
+        let listener = TcpListener::build()
+            .nodelay(true)
+            .backlog(16)
+            .bind("127.0.0.1:0")
+            .await
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+        // report the accepted stream's option back, as a failed assertion
+        // within the spawned connection task would go unnoticed
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(listener.serve_fn(move |stream: TcpStream| {
+            let tx = tx.clone();
+            async move {
+                tx.send(stream.nodelay()).unwrap();
+                Ok::<_, io::Error>(())
+            }
+        }));
+
+        let stream = TcpConnector::new()
+            .bind_addr("127.0.0.1:0".parse().unwrap())
+            .nodelay(true)
+            .keepalive(TcpKeepalive::new().with_time(Duration::from_secs(30)))
+            .serve(Context::<()>::default(), addr)
+            .await
+            .unwrap();
+        assert!(stream.nodelay().unwrap());
+        assert!(SockRef::from(&stream).keepalive().unwrap());
+        assert_eq!(stream.peer_addr().unwrap(), addr);
+        assert!(rx.recv().await.unwrap().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_tcp_connector_bind_addr_family_mismatch() {
+        let err = TcpConnector::new()
+            .bind_addr("127.0.0.1:0".parse().unwrap())
+            .serve(Context::<()>::default(), "[::1]:1")
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
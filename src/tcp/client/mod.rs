@@ -0,0 +1,16 @@
+//! TCP client module for Rama.
+//!
+//! A connector is a [`Service`] which establishes a connection to a target address,
+//! received as a `host:port` [`String`], returning the connected [`Stream`]. The [`TcpConnector`] is the default connector
+//! used by the [`Forwarder`] and [`HttpClient`], and can be wrapped by layers which operate
+//! on the connected stream, such as the [`HaProxyClientLayer`].
+//!
+//! [`Service`]: crate::service::Service
+//! [`Stream`]: crate::stream::Stream
+//! [`Forwarder`]: crate::tcp::service::Forwarder
+//! [`HttpClient`]: crate::http::client::HttpClient
+//! [`HaProxyClientLayer`]: crate::proxy::pp::client::HaProxyClientLayer
+
+mod connector;
+#[doc(inline)]
+pub use connector::TcpConnector;
@@ -1,5 +1,23 @@
 //! TCP module for Rama.
 
+pub mod client;
 pub mod server;
 pub mod service;
 pub mod utils;
+
+pub mod dep {
+    //! Dependencies for rama tcp modules.
+    //!
+    //! Exported for your convenience.
+
+    pub mod socket2 {
+        //! Re-export of the [`socket2`] crate.
+        //!
+        //! Used to configure socket options, such as [`TcpKeepalive`].
+        //!
+        //! [`socket2`]: https://docs.rs/socket2
+        //! [`TcpKeepalive`]: socket2::TcpKeepalive
+
+        pub use socket2::*;
+    }
+}
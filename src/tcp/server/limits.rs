@@ -0,0 +1,215 @@
+//! Accept-time limits of a [`TcpListener`].
+//!
+//! [`TcpListener`]: super::TcpListener
+
+use std::{
+    collections::HashMap,
+    io,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::{Interval, MissedTickBehavior},
+};
+
+/// The initial delay after a failed accept.
+const MIN_ACCEPT_BACKOFF: Duration = Duration::from_millis(5);
+/// The maximum delay after consecutive failed accepts.
+const MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, Default)]
+pub(super) struct LimitsConfig {
+    pub(super) max_connections: Option<usize>,
+    pub(super) max_accept_rate: Option<u32>,
+    pub(super) max_connections_per_ip: Option<usize>,
+}
+
+/// The limits of a single listener, shared by all its connections.
+#[derive(Debug)]
+pub(super) struct ConnectionLimits {
+    connections: Option<Arc<Semaphore>>,
+    accept_rate: Option<u32>,
+    per_ip: Option<Arc<PeerConnections>>,
+}
+
+impl ConnectionLimits {
+    pub(super) fn new(config: LimitsConfig) -> Self {
+        Self {
+            connections: config
+                .max_connections
+                .map(|max| Arc::new(Semaphore::new(max))),
+            accept_rate: config.max_accept_rate,
+            per_ip: config.max_connections_per_ip.map(|max| {
+                Arc::new(PeerConnections {
+                    max,
+                    counts: Mutex::new(HashMap::new()),
+                })
+            }),
+        }
+    }
+
+    /// Create the pacer used to limit the rate of accepted connections, if any.
+    pub(super) fn accept_pacer(&self) -> Option<Interval> {
+        self.accept_rate.map(|rate| {
+            let mut interval = tokio::time::interval(Duration::from_secs(1) / rate);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            interval
+        })
+    }
+
+    /// Wait until a connection can be accepted within the max concurrent connections.
+    pub(super) async fn acquire(&self) -> Option<OwnedSemaphorePermit> {
+        match &self.connections {
+            Some(semaphore) => Some(
+                semaphore
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .expect("connection semaphore is never closed"),
+            ),
+            None => None,
+        }
+    }
+
+    /// Register a connection of the given peer, failing if the peer
+    /// has reached its maximum number of concurrent connections.
+    pub(super) fn acquire_peer(&self, ip: IpAddr) -> Result<Option<PeerConnectionGuard>, ()> {
+        let Some(per_ip) = &self.per_ip else {
+            return Ok(None);
+        };
+        let mut counts = per_ip.counts.lock().unwrap();
+        let count = counts.entry(ip).or_default();
+        if *count >= per_ip.max {
+            return Err(());
+        }
+        *count += 1;
+        Ok(Some(PeerConnectionGuard {
+            connections: per_ip.clone(),
+            ip,
+        }))
+    }
+}
+
+#[derive(Debug)]
+struct PeerConnections {
+    max: usize,
+    counts: Mutex<HashMap<IpAddr, usize>>,
+}
+
+/// Releases the connection of a peer once dropped.
+#[derive(Debug)]
+pub(super) struct PeerConnectionGuard {
+    connections: Arc<PeerConnections>,
+    ip: IpAddr,
+}
+
+impl Drop for PeerConnectionGuard {
+    fn drop(&mut self) {
+        let mut counts = self.connections.counts.lock().unwrap();
+        if let Some(count) = counts.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// Exponential backoff for failing accepts, such as when the process
+/// ran out of file descriptors, logging only the first error of a streak.
+#[derive(Debug, Default)]
+pub(super) struct AcceptBackoff {
+    delay: Option<Duration>,
+}
+
+impl AcceptBackoff {
+    pub(super) fn reset(&mut self) {
+        if self.delay.take().is_some() {
+            tracing::info!("TCP accept recovered");
+        }
+    }
+
+    pub(super) async fn on_error(&mut self, err: io::Error) {
+        if crate::tcp::utils::is_connection_error(&err) {
+            tracing::trace!(
+                error = &err as &dyn std::error::Error,
+                "TCP accept error: connect error"
+            );
+            return;
+        }
+
+        let delay = match self.delay {
+            Some(delay) => {
+                tracing::debug!(error = &err as &dyn std::error::Error, "TCP accept error");
+                (delay * 2).min(MAX_ACCEPT_BACKOFF)
+            }
+            None if is_resource_exhaustion(&err) => {
+                tracing::error!(
+                    error = &err as &dyn std::error::Error,
+                    "TCP accept error: out of resources (e.g. max open files reached), backing off"
+                );
+                MIN_ACCEPT_BACKOFF
+            }
+            None => {
+                tracing::error!(error = &err as &dyn std::error::Error, "TCP accept error");
+                MIN_ACCEPT_BACKOFF
+            }
+        };
+        self.delay = Some(delay);
+        tokio::time::sleep(delay).await;
+    }
+}
+
+#[cfg(unix)]
+fn is_resource_exhaustion(err: &io::Error) -> bool {
+    matches!(
+        err.raw_os_error(),
+        Some(libc::EMFILE | libc::ENFILE | libc::ENOBUFS | libc::ENOMEM)
+    )
+}
+
+#[cfg(not(unix))]
+fn is_resource_exhaustion(_err: &io::Error) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_per_ip_limit() {
+        let limits = ConnectionLimits::new(LimitsConfig {
+            max_connections_per_ip: Some(1),
+            ..Default::default()
+        });
+        let (a, b): (IpAddr, IpAddr) = ("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap());
+
+        let guard = limits.acquire_peer(a).unwrap();
+        assert!(limits.acquire_peer(a).is_err());
+        assert!(limits.acquire_peer(b).is_ok());
+        drop(guard);
+        assert!(limits.acquire_peer(a).is_ok());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test(start_paused = true)]
+    async fn test_accept_backoff() {
+        let mut backoff = AcceptBackoff::default();
+        let start = tokio::time::Instant::now();
+        for _ in 0..3 {
+            backoff
+                .on_error(io::Error::from_raw_os_error(libc::EMFILE))
+                .await;
+        }
+        assert_eq!(start.elapsed(), Duration::from_millis(5 + 10 + 20));
+
+        backoff.reset();
+        backoff
+            .on_error(io::Error::from(io::ErrorKind::ConnectionAborted))
+            .await;
+        assert_eq!(start.elapsed(), Duration::from_millis(35));
+    }
+}
@@ -1,9 +1,11 @@
+use super::limits::{AcceptBackoff, ConnectionLimits, LimitsConfig};
 use crate::graceful::ShutdownGuard;
 use crate::rt::Executor;
 use crate::service::handler::{Factory, FromContextRequest};
 use crate::service::Context;
 use crate::service::Service;
 use crate::stream::SocketInfo;
+use socket2::{Domain, Protocol, SockRef, Socket, TcpKeepalive, Type};
 use std::future::Future;
 use std::pin::pin;
 use std::sync::Arc;
@@ -14,16 +16,23 @@ use tokio::net::{TcpListener as TokioTcpListener, TcpStream, ToSocketAddrs};
 #[derive(Debug)]
 pub struct TcpListenerBuilder<S> {
     ttl: Option<u32>,
+    reuse_address: Option<bool>,
+    reuse_port: bool,
+    backlog: u32,
+    only_v6: Option<bool>,
+    nodelay: Option<bool>,
+    keepalive: Option<TcpKeepalive>,
+    bind_device: Option<String>,
+    transparent: bool,
+    freebind: bool,
+    limits: LimitsConfig,
     state: Arc<S>,
 }
 
 impl TcpListenerBuilder<()> {
     /// Create a new `TcpListenerBuilder` without a state.
     pub fn new() -> Self {
-        Self {
-            ttl: None,
-            state: Arc::new(()),
-        }
+        Self::with_state_arc(Arc::new(()))
     }
 }
 
@@ -37,12 +46,39 @@ impl<S> Clone for TcpListenerBuilder<S> {
     fn clone(&self) -> Self {
         Self {
             ttl: self.ttl,
+            reuse_address: self.reuse_address,
+            reuse_port: self.reuse_port,
+            backlog: self.backlog,
+            only_v6: self.only_v6,
+            nodelay: self.nodelay,
+            keepalive: self.keepalive.clone(),
+            bind_device: self.bind_device.clone(),
+            transparent: self.transparent,
+            freebind: self.freebind,
+            limits: self.limits,
             state: self.state.clone(),
         }
     }
 }
 
 impl<S> TcpListenerBuilder<S> {
+    fn with_state_arc(state: Arc<S>) -> Self {
+        Self {
+            ttl: None,
+            reuse_address: None,
+            reuse_port: false,
+            backlog: 1024,
+            only_v6: None,
+            nodelay: None,
+            keepalive: None,
+            bind_device: None,
+            transparent: false,
+            freebind: false,
+            limits: LimitsConfig::default(),
+            state,
+        }
+    }
+
     /// Sets the value for the `IP_TTL` option on this socket.
     ///
     /// This value sets the time-to-live field that is used in every packet sent
@@ -51,6 +87,150 @@ impl<S> TcpListenerBuilder<S> {
         self.ttl = Some(ttl);
         self
     }
+
+    /// Sets the value for the `SO_REUSEADDR` option on this socket.
+    ///
+    /// Enabled by default on unix platforms, such that the address can be bound
+    /// again while connections of a previous listener are in the `TIME_WAIT` state.
+    pub fn reuse_address(&mut self, reuse: bool) -> &mut Self {
+        self.reuse_address = Some(reuse);
+        self
+    }
+
+    /// Sets the value for the `SO_REUSEPORT` option on this socket,
+    /// allowing multiple listeners to bind to the same address,
+    /// with the kernel balancing incoming connections over them.
+    #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+    pub fn reuse_port(&mut self, reuse: bool) -> &mut Self {
+        self.reuse_port = reuse;
+        self
+    }
+
+    /// Sets the maximum number of pending connections, defaults to `1024`.
+    pub fn backlog(&mut self, backlog: u32) -> &mut Self {
+        self.backlog = backlog;
+        self
+    }
+
+    /// Sets the value for the `IPV6_V6ONLY` option on this socket,
+    /// which only has an effect when binding to an IPv6 address.
+    ///
+    /// If enabled the socket only accepts IPv6 connections, otherwise
+    /// IPv4 connections are accepted as well, as IPv4-mapped IPv6 addresses.
+    pub fn only_v6(&mut self, only_v6: bool) -> &mut Self {
+        self.only_v6 = Some(only_v6);
+        self
+    }
+
+    /// Sets the value for the `TCP_NODELAY` option on all accepted streams.
+    pub fn nodelay(&mut self, nodelay: bool) -> &mut Self {
+        self.nodelay = Some(nodelay);
+        self
+    }
+
+    /// Enables TCP keepalive on all accepted streams, using the given parameters.
+    pub fn keepalive(&mut self, keepalive: TcpKeepalive) -> &mut Self {
+        self.keepalive = Some(keepalive);
+        self
+    }
+
+    /// Sets the value for the `SO_BINDTODEVICE` option on this socket,
+    /// only accepting connections received on the given interface (e.g. `eth0`).
+    #[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+    pub fn bind_device(&mut self, interface: impl Into<String>) -> &mut Self {
+        self.bind_device = Some(interface.into());
+        self
+    }
+
+    /// Sets the value for the `IP_TRANSPARENT` option on this socket,
+    /// allowing to accept connections for non-local addresses,
+    /// as used for transparent proxying (e.g. with `TPROXY`).
+    ///
+    /// This requires the `CAP_NET_ADMIN` capability.
+    #[cfg(target_os = "linux")]
+    pub fn transparent(&mut self, transparent: bool) -> &mut Self {
+        self.transparent = transparent;
+        self
+    }
+
+    /// Sets the value for the `IP_FREEBIND` option on this socket,
+    /// allowing to bind to an address which is not (yet) assigned to an interface.
+    #[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+    pub fn freebind(&mut self, freebind: bool) -> &mut Self {
+        self.freebind = freebind;
+        self
+    }
+
+    /// Sets the maximum number of concurrently served connections.
+    ///
+    /// Once reached, no new connections are accepted until a served connection closes,
+    /// leaving pending connections in the backlog of the socket.
+    pub fn max_connections(&mut self, max: usize) -> &mut Self {
+        self.limits.max_connections = Some(max);
+        self
+    }
+
+    /// Sets the maximum number of connections accepted per second,
+    /// spreading the accepts evenly over each second.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the given rate is `0`.
+    pub fn max_accept_rate(&mut self, rate: u32) -> &mut Self {
+        assert!(rate > 0, "max accept rate must be greater than zero");
+        self.limits.max_accept_rate = Some(rate);
+        self
+    }
+
+    /// Sets the maximum number of concurrently served connections per peer IP address.
+    ///
+    /// Connections of a peer which reached this limit are closed immediately after being accepted.
+    pub fn max_connections_per_ip(&mut self, max: usize) -> &mut Self {
+        self.limits.max_connections_per_ip = Some(max);
+        self
+    }
+
+    fn bind_socket(&self, addr: SocketAddr) -> io::Result<TokioTcpListener> {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+
+        if let Some(reuse) = self.reuse_address.or(cfg!(unix).then_some(true)) {
+            socket.set_reuse_address(reuse)?;
+        }
+        #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+        if self.reuse_port {
+            socket.set_reuse_port(true)?;
+        }
+        if let Some(only_v6) = self.only_v6 {
+            if addr.is_ipv6() {
+                socket.set_only_v6(only_v6)?;
+            }
+        }
+        if let Some(ttl) = self.ttl {
+            socket.set_ttl(ttl)?;
+        }
+        #[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+        {
+            if let Some(interface) = &self.bind_device {
+                socket.bind_device(Some(interface.as_bytes()))?;
+            }
+            if self.freebind {
+                if addr.is_ipv6() {
+                    socket.set_freebind_ipv6(true)?;
+                } else {
+                    socket.set_freebind(true)?;
+                }
+            }
+        }
+        #[cfg(target_os = "linux")]
+        if self.transparent {
+            socket.set_ip_transparent(true)?;
+        }
+
+        socket.bind(&addr.into())?;
+        socket.listen(self.backlog.try_into().unwrap_or(i32::MAX))?;
+        socket.set_nonblocking(true)?;
+        TokioTcpListener::from_std(socket.into())
+    }
 }
 
 impl<S> TcpListenerBuilder<S>
@@ -59,10 +239,7 @@ where
 {
     /// Create a new `TcpListenerBuilder` with the given state.
     pub fn with_state(state: S) -> Self {
-        Self {
-            ttl: None,
-            state: Arc::new(state),
-        }
+        Self::with_state_arc(Arc::new(state))
     }
 
     /// Creates a new TcpListener, which will be bound to the specified address.
@@ -72,17 +249,83 @@ where
     /// Binding with a port number of 0 will request that the OS assigns a port
     /// to this listener. The port allocated can be queried via the `local_addr`
     /// method.
+    ///
+    /// If the address resolves to multiple addresses, each is tried
+    /// until one of them could be bound.
     pub async fn bind<A: ToSocketAddrs>(&self, addr: A) -> io::Result<TcpListener<S>> {
-        let inner = TokioTcpListener::bind(addr).await?;
+        let mut last_err = None;
+        for addr in tokio::net::lookup_host(addr).await? {
+            match self.bind_socket(addr) {
+                Ok(inner) => return Ok(self.listener(inner)),
+                Err(err) => last_err = Some(err),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "could not resolve to any address",
+            )
+        }))
+    }
 
+    /// Creates a new TcpListener from an already bound and listening [`std::net::TcpListener`],
+    /// e.g. one created by another library or inherited from a parent process.
+    ///
+    /// Only the options which apply to accepted streams, such as [`nodelay`] and [`keepalive`],
+    /// and the TTL are applied, as the socket is already bound.
+    ///
+    /// [`nodelay`]: TcpListenerBuilder::nodelay
+    /// [`keepalive`]: TcpListenerBuilder::keepalive
+    pub fn from_std(&self, listener: std::net::TcpListener) -> io::Result<TcpListener<S>> {
+        listener.set_nonblocking(true)?;
+        let inner = TokioTcpListener::from_std(listener)?;
         if let Some(ttl) = self.ttl {
             inner.set_ttl(ttl)?;
         }
+        Ok(self.listener(inner))
+    }
+
+    /// Creates a new TcpListener from the file descriptor of a bound and listening socket,
+    /// e.g. one inherited using systemd socket activation.
+    ///
+    /// See [`TcpListenerBuilder::from_std`] for more details.
+    #[cfg(unix)]
+    pub fn from_fd(&self, fd: std::os::fd::OwnedFd) -> io::Result<TcpListener<S>> {
+        self.from_std(std::net::TcpListener::from(fd))
+    }
 
-        Ok(TcpListener {
+    fn listener(&self, inner: TokioTcpListener) -> TcpListener<S> {
+        TcpListener {
             inner,
+            stream_config: Arc::new(StreamConfig {
+                nodelay: self.nodelay,
+                keepalive: self.keepalive.clone(),
+            }),
+            limits: ConnectionLimits::new(self.limits),
             state: self.state.clone(),
-        })
+        }
+    }
+}
+
+/// The options applied to each accepted stream.
+#[derive(Debug, Default)]
+struct StreamConfig {
+    nodelay: Option<bool>,
+    keepalive: Option<TcpKeepalive>,
+}
+
+impl StreamConfig {
+    fn apply(&self, stream: &TcpStream) {
+        if let Some(nodelay) = self.nodelay {
+            if let Err(err) = stream.set_nodelay(nodelay) {
+                tracing::debug!(error = &err as &dyn std::error::Error, "set TCP_NODELAY");
+            }
+        }
+        if let Some(keepalive) = &self.keepalive {
+            if let Err(err) = SockRef::from(stream).set_tcp_keepalive(keepalive) {
+                tracing::debug!(error = &err as &dyn std::error::Error, "set TCP keepalive");
+            }
+        }
     }
 }
 
@@ -91,6 +334,8 @@ where
 #[derive(Debug)]
 pub struct TcpListener<S> {
     inner: TokioTcpListener,
+    stream_config: Arc<StreamConfig>,
+    limits: ConnectionLimits,
     state: Arc<S>,
 }
 
@@ -120,6 +365,13 @@ impl TcpListener<()> {
     pub async fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
         TcpListenerBuilder::default().bind(addr).await
     }
+
+    /// Creates a new TcpListener from an already bound and listening [`std::net::TcpListener`].
+    ///
+    /// See [`TcpListenerBuilder::from_std`] for more details.
+    pub fn from_std(listener: std::net::TcpListener) -> io::Result<Self> {
+        TcpListenerBuilder::default().from_std(listener)
+    }
 }
 
 impl<S> TcpListener<S> {
@@ -158,28 +410,9 @@ where
     where
         S: Service<State, TcpStream>,
     {
-        let ctx = Context::new(self.state, Executor::new());
-        let service = Arc::new(service);
-
-        loop {
-            let (socket, peer_addr) = match self.inner.accept().await {
-                Ok(stream) => stream,
-                Err(err) => {
-                    handle_accept_err(err).await;
-                    continue;
-                }
-            };
-
-            let service = service.clone();
-            let mut ctx = ctx.clone();
-
-            tokio::spawn(async move {
-                let local_addr = socket.local_addr().ok();
-                ctx.insert(SocketInfo::new(local_addr, peer_addr));
-
-                let _ = service.serve(ctx, socket).await;
-            });
-        }
+        let ctx = Context::new(self.state.clone(), Executor::new());
+        self.serve_connections(ctx, service, std::future::pending())
+            .await
     }
 
     /// Serve connections from this listener with the given service function.
@@ -206,36 +439,9 @@ where
     where
         S: Service<State, TcpStream>,
     {
-        let ctx: Context<State> = Context::new(self.state, Executor::graceful(guard.clone()));
-        let service = Arc::new(service);
-        let mut cancelled_fut = pin!(guard.cancelled());
-
-        loop {
-            tokio::select! {
-                _ = cancelled_fut.as_mut() => {
-                    tracing::trace!("signal received: initiate graceful shutdown");
-                    break;
-                }
-                result = self.inner.accept() => {
-                    match result {
-                        Ok((socket, peer_addr)) => {
-                            let service = service.clone();
-                            let mut ctx = ctx.clone();
-
-                            guard.spawn_task(async move {
-                                let local_addr = socket.local_addr().ok();
-                                ctx.insert(SocketInfo::new(local_addr, peer_addr));
-
-                                let _ = service.serve(ctx, socket).await;
-                            });
-                        }
-                        Err(err) => {
-                            handle_accept_err(err).await;
-                        }
-                    }
-                }
-            }
-        }
+        let ctx = Context::new(self.state.clone(), Executor::graceful(guard.clone()));
+        self.serve_connections(ctx, service, guard.cancelled())
+            .await
     }
 
     /// Serve gracefully connections from this listener with the given service function.
@@ -252,27 +458,151 @@ where
         let service = crate::service::service_fn(service);
         self.serve_graceful(guard, service).await
     }
+
+    async fn serve_connections<S>(
+        self,
+        ctx: Context<State>,
+        service: S,
+        cancelled: impl Future<Output = ()>,
+    ) where
+        S: Service<State, TcpStream>,
+    {
+        let service = Arc::new(service);
+        let mut pacer = self.limits.accept_pacer();
+        let mut backoff = AcceptBackoff::default();
+        let mut cancelled = pin!(cancelled);
+
+        loop {
+            // the permit is acquired prior to accepting, such that pending
+            // connections remain in the backlog while at max capacity
+            let (permit, result) = tokio::select! {
+                _ = cancelled.as_mut() => {
+                    tracing::trace!("signal received: initiate graceful shutdown");
+                    break;
+                }
+                accepted = async {
+                    let permit = self.limits.acquire().await;
+                    if let Some(pacer) = pacer.as_mut() {
+                        pacer.tick().await;
+                    }
+                    (permit, self.inner.accept().await)
+                } => accepted,
+            };
+
+            let (socket, peer_addr) = match result {
+                Ok(accepted) => {
+                    backoff.reset();
+                    accepted
+                }
+                Err(err) => {
+                    backoff.on_error(err).await;
+                    continue;
+                }
+            };
+
+            let Ok(peer_guard) = self.limits.acquire_peer(peer_addr.ip()) else {
+                tracing::trace!(%peer_addr, "TCP connection limit per IP reached: drop connection");
+                continue;
+            };
+
+            let service = service.clone();
+            let mut ctx = ctx.clone();
+            self.stream_config.apply(&socket);
+            ctx.insert(SocketInfo::new(socket.local_addr().ok(), peer_addr));
+
+            ctx.clone().spawn(async move {
+                let _ = service.serve(ctx, socket).await;
+                drop((permit, peer_guard));
+            });
+        }
+    }
 }
 
-async fn handle_accept_err(err: io::Error) {
-    if crate::tcp::utils::is_connection_error(&err) {
-        tracing::trace!(
-            error = &err as &dyn std::error::Error,
-            "TCP accept error: connect error"
-        );
-    } else {
-        // [From `hyper::Server` in 0.14](https://github.com/hyperium/hyper/blob/v0.14.27/src/server/tcp.rs#L186)
-        //
-        // > A possible scenario is that the process has hit the max open files
-        // > allowed, and so trying to accept a new connection will fail with
-        // > `EMFILE`. In some cases, it's preferable to just wait for some time, if
-        // > the application will likely close some files (or connections), and try
-        // > to accept the connection again. If this option is `true`, the error
-        // > will be logged at the `error` level, since it is still a big deal,
-        // > and then the listener will sleep for 1 second.
-        //
-        // hyper allowed customizing this but axum does not.
-        tracing::error!(error = &err as &dyn std::error::Error, "TCP accept error");
-        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    async fn serve_hold_open(builder: &TcpListenerBuilder<()>) -> SocketAddr {
+        let listener = builder.bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(listener.serve_fn(|mut stream: TcpStream| async move {
+            stream.write_all(b"hi").await.unwrap();
+            // hold the connection open until the peer closes it
+            let mut buf = [0; 1];
+            let _ = stream.read(&mut buf).await;
+            Ok::<_, Infallible>(())
+        }));
+        addr
+    }
+
+    async fn is_served(stream: &mut TcpStream) -> bool {
+        let mut buf = [0; 2];
+        matches!(
+            tokio::time::timeout(Duration::from_millis(100), stream.read(&mut buf)).await,
+            Ok(Ok(2))
+        )
+    }
+
+    #[tokio::test]
+    async fn test_tcp_listener_max_connections() {
+        let mut builder = TcpListener::build();
+        builder.max_connections(1);
+        let addr = serve_hold_open(&builder).await;
+
+        let mut first = TcpStream::connect(addr).await.unwrap();
+        assert!(is_served(&mut first).await);
+
+        // the second connection remains pending in the backlog
+        let mut second = TcpStream::connect(addr).await.unwrap();
+        assert!(!is_served(&mut second).await);
+
+        drop(first);
+        assert!(is_served(&mut second).await);
+    }
+
+    #[tokio::test]
+    async fn test_tcp_listener_max_connections_per_ip() {
+        let mut builder = TcpListener::build();
+        builder.max_connections_per_ip(1);
+        let addr = serve_hold_open(&builder).await;
+
+        let mut first = TcpStream::connect(addr).await.unwrap();
+        assert!(is_served(&mut first).await);
+
+        // the second connection of the same peer is closed immediately
+        let mut second = TcpStream::connect(addr).await.unwrap();
+        let mut buf = [0; 2];
+        assert!(matches!(second.read(&mut buf).await, Ok(0) | Err(_)));
+
+        drop(first);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let mut third = TcpStream::connect(addr).await.unwrap();
+        assert!(is_served(&mut third).await);
+    }
+
+    #[tokio::test]
+    async fn test_tcp_listener_from_std() {
+        let std_listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = std_listener.local_addr().unwrap();
+        let listener = TcpListener::from_std(std_listener).unwrap();
+        assert_eq!(listener.local_addr().unwrap(), addr);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_tcp_listener_reuse_port() {
+        let mut builder = TcpListener::build();
+        builder.reuse_port(true);
+
+        let first = builder.bind("127.0.0.1:0").await.unwrap();
+        let addr = first.local_addr().unwrap();
+        let second = builder.bind(addr).await.unwrap();
+        assert_eq!(second.local_addr().unwrap(), addr);
+
+        let err = TcpListener::bind(addr).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
     }
 }
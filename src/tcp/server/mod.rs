@@ -37,6 +37,7 @@
 //! }
 //! ```
 
+mod limits;
 mod listener;
 #[doc(inline)]
 pub use listener::{TcpListener, TcpListenerBuilder};
@@ -1,11 +1,9 @@
 use std::net::SocketAddr;
 
-use tokio::net::TcpStream;
-
 use crate::{
     service::{Context, Service},
     stream::Stream,
-    tcp::utils::is_connection_error,
+    tcp::{client::TcpConnector, utils::is_connection_error},
 };
 
 /// [`Forwarder`] using [`Forwarder::dynamic`] requires this struct
@@ -35,9 +33,13 @@ enum ForwarderKind {
 }
 
 /// A TCP forwarder.
+///
+/// The connection to the target is established using a connector,
+/// which is a [`TcpConnector`] by default.
 #[derive(Debug, Clone)]
-pub struct Forwarder {
+pub struct Forwarder<C = TcpConnector> {
     kind: ForwarderKind,
+    connector: C,
 }
 
 impl Forwarder {
@@ -50,6 +52,7 @@ impl Forwarder {
     pub fn target(target: SocketAddr) -> Self {
         Self {
             kind: ForwarderKind::Static(target),
+            connector: TcpConnector::new(),
         }
     }
 
@@ -61,6 +64,24 @@ impl Forwarder {
     pub fn dynamic() -> Self {
         Self {
             kind: ForwarderKind::Dynamic,
+            connector: TcpConnector::new(),
+        }
+    }
+}
+
+impl<C> Forwarder<C> {
+    /// Use the given connector to establish the connection to the target,
+    /// e.g. a [`TcpConnector`] wrapped in a [`HaProxyClientLayer`].
+    ///
+    /// The connector receives the target address as a `host:port` [`String`],
+    /// the same as for the [`HttpClient`].
+    ///
+    /// [`HttpClient`]: crate::http::client::HttpClient
+    /// [`HaProxyClientLayer`]: crate::proxy::pp::client::HaProxyClientLayer
+    pub fn connector<T>(self, connector: T) -> Forwarder<T> {
+        Forwarder {
+            kind: self.kind,
+            connector,
         }
     }
 }
@@ -71,22 +92,30 @@ impl Default for Forwarder {
     }
 }
 
-impl<S, T> Service<S, T> for Forwarder
+impl<S, T, C> Service<S, T> for Forwarder<C>
 where
     S: Send + Sync + 'static,
     T: Stream + Unpin,
+    C: Service<S, String>,
+    C::Response: Stream + Unpin,
+    C::Error: Into<std::io::Error>,
 {
     type Response = ();
     type Error = std::io::Error;
 
     async fn serve(&self, ctx: Context<S>, mut source: T) -> Result<Self::Response, Self::Error> {
-        let mut target = match &self.kind {
-            ForwarderKind::Static(target) => TcpStream::connect(target).await?,
+        let target = match &self.kind {
+            ForwarderKind::Static(target) => target.to_string(),
             ForwarderKind::Dynamic => {
                 let addr: &ForwardAddress = ctx.get().unwrap();
-                TcpStream::connect(addr.target).await?
+                addr.target.to_string()
             }
         };
+        let mut target = self
+            .connector
+            .serve(ctx, target)
+            .await
+            .map_err(Into::into)?;
 
         match tokio::io::copy_bidirectional(&mut source, &mut target).await {
             Ok(_) => Ok(()),
@@ -1,10 +1,10 @@
 use crate::{
     service::{Context, Service},
-    stream::Stream,
+    stream::{layer::StreamMetrics, Stream},
     tls::rustls::dep::tokio_rustls::{server::TlsStream, TlsAcceptor},
     tls::rustls::dep::{rustls::server::Acceptor, tokio_rustls::LazyConfigAcceptor},
 };
-use rustls::ServerConfig;
+use rustls::{ProtocolVersion, ServerConfig};
 use std::sync::Arc;
 
 use super::{client_config::IncomingClientHello, ServerConfigProvider, TlsClientConfigHandler};
@@ -57,13 +57,14 @@ where
     type Response = S::Response;
     type Error = TlsAcceptorError<S::Error>;
 
-    async fn serve(&self, ctx: Context<T>, stream: IO) -> Result<Self::Response, Self::Error> {
+    async fn serve(&self, mut ctx: Context<T>, stream: IO) -> Result<Self::Response, Self::Error> {
         let acceptor = TlsAcceptor::from(self.config.clone());
 
         let stream = acceptor
             .accept(stream)
             .await
-            .map_err(TlsAcceptorError::Accept)?;
+            .map_err(|err| accept_error(&ctx, err))?;
+        ctx.insert(NegotiatedTlsParameters::from_stream(&stream));
 
         self.inner
             .serve(ctx, stream)
@@ -84,7 +85,7 @@ where
     async fn serve(&self, mut ctx: Context<T>, stream: IO) -> Result<Self::Response, Self::Error> {
         let acceptor = LazyConfigAcceptor::new(Acceptor::default(), stream);
 
-        let start = acceptor.await.map_err(TlsAcceptorError::Accept)?;
+        let start = acceptor.await.map_err(|err| accept_error(&ctx, err))?;
 
         if self.client_config_handler.store_client_hello {
             let accepted_client_hello = IncomingClientHello::from(start.client_hello());
@@ -94,7 +95,8 @@ where
         let stream = start
             .into_stream(self.config.clone())
             .await
-            .map_err(TlsAcceptorError::Accept)?;
+            .map_err(|err| accept_error(&ctx, err))?;
+        ctx.insert(NegotiatedTlsParameters::from_stream(&stream));
 
         self.inner
             .serve(ctx, stream)
@@ -116,7 +118,7 @@ where
     async fn serve(&self, mut ctx: Context<T>, stream: IO) -> Result<Self::Response, Self::Error> {
         let acceptor = LazyConfigAcceptor::new(Acceptor::default(), stream);
 
-        let start = acceptor.await.map_err(TlsAcceptorError::Accept)?;
+        let start = acceptor.await.map_err(|err| accept_error(&ctx, err))?;
 
         let accepted_client_hello = IncomingClientHello::from(start.client_hello());
 
@@ -129,13 +131,14 @@ where
             .server_config_provider
             .get_server_config(accepted_client_hello)
             .await
-            .map_err(TlsAcceptorError::Accept)?
+            .map_err(|err| accept_error(&ctx, err))?
             .unwrap_or_else(|| self.config.clone());
 
         let stream = start
             .into_stream(config)
             .await
-            .map_err(TlsAcceptorError::Accept)?;
+            .map_err(|err| accept_error(&ctx, err))?;
+        ctx.insert(NegotiatedTlsParameters::from_stream(&stream));
 
         self.inner
             .serve(ctx, stream)
@@ -144,6 +147,44 @@ where
     }
 }
 
+/// The parameters negotiated during the handshake of a TLS connection,
+/// inserted into the [`Context`] by the [`TlsAcceptorService`] once accepted.
+#[derive(Debug, Clone)]
+pub struct NegotiatedTlsParameters {
+    /// The server name indicator.
+    ///
+    /// `None` if the client did not supply a SNI.
+    pub server_name: Option<String>,
+
+    /// The negotiated protocol version.
+    pub protocol_version: Option<ProtocolVersion>,
+
+    /// The negotiated ALPN protocol identifier.
+    ///
+    /// `None` if no protocol was agreed upon.
+    pub alpn_protocol: Option<Vec<u8>>,
+}
+
+impl NegotiatedTlsParameters {
+    fn from_stream<IO>(stream: &TlsStream<IO>) -> Self {
+        let (_, conn) = stream.get_ref();
+        Self {
+            server_name: conn.server_name().map(ToOwned::to_owned),
+            protocol_version: conn.protocol_version(),
+            alpn_protocol: conn.alpn_protocol().map(ToOwned::to_owned),
+        }
+    }
+}
+
+/// Create an accept error, recording the failed handshake
+/// in the [`StreamMetrics`] found in the [`Context`], if any.
+fn accept_error<T, E>(ctx: &Context<T>, err: std::io::Error) -> TlsAcceptorError<E> {
+    if let Some(metrics) = ctx.get::<StreamMetrics>() {
+        metrics.record_tls_handshake_failure();
+    }
+    TlsAcceptorError::Accept(err)
+}
+
 /// Errors that can happen when using [`TlsAcceptorService`].
 #[derive(Debug)]
 pub enum TlsAcceptorError<E> {
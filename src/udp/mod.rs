@@ -0,0 +1,7 @@
+//! UDP module for Rama.
+
+pub mod server;
+pub mod service;
+
+/// The maximum size of a UDP datagram payload.
+pub(crate) const MAX_DATAGRAM_SIZE: usize = 65_535;
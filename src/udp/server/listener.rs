@@ -0,0 +1,664 @@
+use crate::graceful::ShutdownGuard;
+use crate::rt::Executor;
+use crate::service::handler::{Factory, FromContextRequest};
+use crate::service::Context;
+use crate::service::Service;
+use crate::stream::SocketInfo;
+use crate::udp::MAX_DATAGRAM_SIZE;
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use std::{io, net::SocketAddr};
+use tokio::net::{ToSocketAddrs, UdpSocket};
+use tokio::sync::mpsc;
+
+/// The default maximum number of concurrent flows.
+const DEFAULT_MAX_FLOWS: usize = 1024;
+
+/// The initial delay after a failed receive.
+const MIN_RECV_BACKOFF: Duration = Duration::from_millis(5);
+/// The maximum delay after consecutive failed receives.
+const MAX_RECV_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Builder for `UdpListener`.
+#[derive(Debug)]
+pub struct UdpListenerBuilder<S> {
+    ttl: Option<u32>,
+    flow_buffer: usize,
+    max_flows: usize,
+    state: Arc<S>,
+}
+
+impl UdpListenerBuilder<()> {
+    /// Create a new `UdpListenerBuilder` without a state.
+    pub fn new() -> Self {
+        Self {
+            ttl: None,
+            flow_buffer: 64,
+            max_flows: DEFAULT_MAX_FLOWS,
+            state: Arc::new(()),
+        }
+    }
+}
+
+impl Default for UdpListenerBuilder<()> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> Clone for UdpListenerBuilder<S> {
+    fn clone(&self) -> Self {
+        Self {
+            ttl: self.ttl,
+            flow_buffer: self.flow_buffer,
+            max_flows: self.max_flows,
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<S> UdpListenerBuilder<S> {
+    /// Sets the value for the `IP_TTL` option on this socket.
+    ///
+    /// This value sets the time-to-live field that is used in every packet sent
+    /// from this socket.
+    pub fn ttl(&mut self, ttl: u32) -> &mut Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Sets the number of datagrams that can be buffered for a single [`UdpFlow`],
+    /// before additional datagrams of that peer are dropped.
+    ///
+    /// Defaults to `64`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the buffer is zero.
+    pub fn flow_buffer(&mut self, buffer: usize) -> &mut Self {
+        assert!(buffer > 0, "UDP flow buffer cannot be zero");
+        self.flow_buffer = buffer;
+        self
+    }
+
+    /// Sets the maximum number of concurrent [`UdpFlow`]s,
+    /// after which datagrams of new peers are dropped until a flow ends.
+    ///
+    /// Defaults to `1024`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the maximum is zero.
+    pub fn max_flows(&mut self, max: usize) -> &mut Self {
+        assert!(max > 0, "UDP max flows cannot be zero");
+        self.max_flows = max;
+        self
+    }
+}
+
+impl<S> UdpListenerBuilder<S>
+where
+    S: Send + Sync + 'static,
+{
+    /// Create a new `UdpListenerBuilder` with the given state.
+    pub fn with_state(state: S) -> Self {
+        Self {
+            ttl: None,
+            flow_buffer: 64,
+            max_flows: DEFAULT_MAX_FLOWS,
+            state: Arc::new(state),
+        }
+    }
+
+    /// Creates a new UdpListener, which will be bound to the specified address.
+    ///
+    /// The returned listener is ready for receiving datagrams.
+    ///
+    /// Binding with a port number of 0 will request that the OS assigns a port
+    /// to this listener. The port allocated can be queried via the `local_addr`
+    /// method.
+    pub async fn bind<A: ToSocketAddrs>(&self, addr: A) -> io::Result<UdpListener<S>> {
+        let inner = UdpSocket::bind(addr).await?;
+
+        if let Some(ttl) = self.ttl {
+            inner.set_ttl(ttl)?;
+        }
+
+        Ok(UdpListener {
+            inner: Arc::new(inner),
+            flow_buffer: self.flow_buffer,
+            max_flows: self.max_flows,
+            state: self.state.clone(),
+        })
+    }
+}
+
+/// A UDP socket server, receiving incoming datagrams once served
+/// using one of the `serve` methods such as [`UdpListener::serve`].
+#[derive(Debug)]
+pub struct UdpListener<S> {
+    inner: Arc<UdpSocket>,
+    flow_buffer: usize,
+    max_flows: usize,
+    state: Arc<S>,
+}
+
+impl UdpListener<()> {
+    /// Create a new `UdpListenerBuilder` without a state,
+    /// which can be used to configure a `UdpListener`.
+    pub fn build() -> UdpListenerBuilder<()> {
+        UdpListenerBuilder::new()
+    }
+
+    /// Create a new `UdpListenerBuilder` with the given state,
+    /// which can be used to configure a `UdpListener`.
+    pub fn build_with_state<S>(state: S) -> UdpListenerBuilder<S>
+    where
+        S: Send + Sync + 'static,
+    {
+        UdpListenerBuilder::with_state(state)
+    }
+
+    /// Creates a new UdpListener, which will be bound to the specified address.
+    ///
+    /// The returned listener is ready for receiving datagrams.
+    ///
+    /// Binding with a port number of 0 will request that the OS assigns a port
+    /// to this listener. The port allocated can be queried via the `local_addr`
+    /// method.
+    pub async fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        UdpListenerBuilder::default().bind(addr).await
+    }
+}
+
+impl<S> UdpListener<S> {
+    /// Returns the local address that this listener is bound to.
+    ///
+    /// This can be useful, for example, when binding to port 0 to figure out
+    /// which port was actually bound.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    /// Gets the value of the `IP_TTL` option for this socket.
+    ///
+    /// For more information about this option, see [`set_ttl`].
+    ///
+    /// [`set_ttl`]: UdpListenerBuilder::ttl
+    pub fn ttl(&self) -> io::Result<u32> {
+        self.inner.ttl()
+    }
+
+    /// Gets a reference to the listener's state.
+    pub fn state(&self) -> &S {
+        &self.state
+    }
+}
+
+impl<State> UdpListener<State>
+where
+    State: Send + Sync + 'static,
+{
+    /// Serve datagrams from this listener with the given service.
+    ///
+    /// Each datagram is served in its own task, as a [`Datagram`]
+    /// which can be used to reply to the peer.
+    pub async fn serve<S>(self, service: S)
+    where
+        S: Service<State, Datagram>,
+    {
+        let ctx = Context::new(self.state.clone(), Executor::new());
+        self.serve_datagrams(ctx, service, std::future::pending())
+            .await
+    }
+
+    /// Serve datagrams from this listener with the given service function.
+    ///
+    /// See [`Self::serve`] for more details.
+    pub async fn serve_fn<F, T, R, O, E>(self, f: F)
+    where
+        F: Factory<T, R, O, E>,
+        R: Future<Output = Result<O, E>> + Send + Sync + 'static,
+        O: Send + Sync + 'static,
+        E: Send + Sync + 'static,
+        T: FromContextRequest<State, Datagram>,
+    {
+        let service = crate::service::service_fn(f);
+        self.serve(service).await
+    }
+
+    /// Serve gracefully datagrams from this listener with the given service.
+    ///
+    /// This method does the same as [`Self::serve`] but it
+    /// will respect the given [`crate::graceful::ShutdownGuard`], and also pass
+    /// it to the service.
+    pub async fn serve_graceful<S>(self, guard: ShutdownGuard, service: S)
+    where
+        S: Service<State, Datagram>,
+    {
+        let ctx = Context::new(self.state.clone(), Executor::graceful(guard.clone()));
+        self.serve_datagrams(ctx, service, guard.cancelled()).await
+    }
+
+    /// Serve gracefully datagrams from this listener with the given service function.
+    ///
+    /// See [`Self::serve_graceful`] for more details.
+    pub async fn serve_fn_graceful<F, T, R, O, E>(self, guard: ShutdownGuard, service: F)
+    where
+        F: Factory<T, R, O, E>,
+        R: Future<Output = Result<O, E>> + Send + Sync + 'static,
+        O: Send + Sync + 'static,
+        E: Send + Sync + 'static,
+        T: FromContextRequest<State, Datagram>,
+    {
+        let service = crate::service::service_fn(service);
+        self.serve_graceful(guard, service).await
+    }
+
+    /// Serve flows from this listener with the given service.
+    ///
+    /// All datagrams received from the same peer are grouped in a [`UdpFlow`],
+    /// which is served in its own task. The flow lives for as long as the service
+    /// is serving it, and a new flow is started for the next datagram of that peer
+    /// once the service is finished. It is up to the service to end a flow,
+    /// e.g. once it has been idle for a while.
+    ///
+    /// Once the listener stops, [`UdpFlow::recv`] returns `None` for all flows.
+    pub async fn serve_flows<S>(self, service: S)
+    where
+        S: Service<State, UdpFlow>,
+    {
+        let ctx = Context::new(self.state.clone(), Executor::new());
+        self.serve_peer_flows(ctx, service, std::future::pending())
+            .await
+    }
+
+    /// Serve gracefully flows from this listener with the given service.
+    ///
+    /// This method does the same as [`Self::serve_flows`] but it
+    /// will respect the given [`crate::graceful::ShutdownGuard`], and also pass
+    /// it to the service.
+    pub async fn serve_flows_graceful<S>(self, guard: ShutdownGuard, service: S)
+    where
+        S: Service<State, UdpFlow>,
+    {
+        let ctx = Context::new(self.state.clone(), Executor::graceful(guard.clone()));
+        self.serve_peer_flows(ctx, service, guard.cancelled()).await
+    }
+
+    async fn serve_datagrams<S>(
+        self,
+        ctx: Context<State>,
+        service: S,
+        cancelled: impl Future<Output = ()>,
+    ) where
+        S: Service<State, Datagram>,
+    {
+        let service = Arc::new(service);
+        let local_addr = self.inner.local_addr().ok();
+        let mut cancelled = pin!(cancelled);
+        let mut buffer = vec![0; MAX_DATAGRAM_SIZE];
+        let mut backoff = RecvBackoff::default();
+
+        loop {
+            let (n, peer_addr) = tokio::select! {
+                _ = cancelled.as_mut() => {
+                    tracing::trace!("signal received: initiate graceful shutdown");
+                    break;
+                }
+                result = self.inner.recv_from(&mut buffer) => match result {
+                    Ok(received) => received,
+                    Err(err) => {
+                        backoff.on_error(err).await;
+                        continue;
+                    }
+                }
+            };
+            backoff.reset();
+
+            let datagram = Datagram {
+                payload: Bytes::copy_from_slice(&buffer[..n]),
+                peer_addr,
+                socket: self.inner.clone(),
+            };
+
+            let service = service.clone();
+            let mut ctx = ctx.clone();
+            ctx.insert(SocketInfo::new(local_addr, peer_addr));
+
+            ctx.clone().spawn(async move {
+                let _ = service.serve(ctx, datagram).await;
+            });
+        }
+    }
+
+    async fn serve_peer_flows<S>(
+        self,
+        ctx: Context<State>,
+        service: S,
+        cancelled: impl Future<Output = ()>,
+    ) where
+        S: Service<State, UdpFlow>,
+    {
+        let service = Arc::new(service);
+        // the flow tasks only keep the id of their flow, such that all senders
+        // are dropped with the map once the listener stops
+        let flows: Arc<Mutex<HashMap<SocketAddr, (u64, mpsc::Sender<Bytes>)>>> = Default::default();
+        let mut next_flow_id = 0;
+        let local_addr = self.inner.local_addr().ok();
+        let mut cancelled = pin!(cancelled);
+        let mut buffer = vec![0; MAX_DATAGRAM_SIZE];
+        let mut backoff = RecvBackoff::default();
+
+        loop {
+            let (n, peer_addr) = tokio::select! {
+                _ = cancelled.as_mut() => {
+                    tracing::trace!("signal received: initiate graceful shutdown");
+                    break;
+                }
+                result = self.inner.recv_from(&mut buffer) => match result {
+                    Ok(received) => received,
+                    Err(err) => {
+                        backoff.on_error(err).await;
+                        continue;
+                    }
+                }
+            };
+            backoff.reset();
+            let mut payload = Bytes::copy_from_slice(&buffer[..n]);
+
+            let mut active_flows = flows.lock().unwrap();
+            if let Some((_, sender)) = active_flows.get(&peer_addr) {
+                match sender.try_send(payload) {
+                    Ok(()) => continue,
+                    Err(mpsc::error::TrySendError::Full(_)) => {
+                        tracing::trace!(%peer_addr, "UDP flow buffer full: drop datagram");
+                        continue;
+                    }
+                    // the flow has ended, start a new one
+                    Err(mpsc::error::TrySendError::Closed(returned)) => {
+                        active_flows.remove(&peer_addr);
+                        payload = returned;
+                    }
+                }
+            }
+
+            if active_flows.len() >= self.max_flows {
+                tracing::trace!(%peer_addr, "UDP max flows reached: drop datagram");
+                continue;
+            }
+
+            let (sender, receiver) = mpsc::channel(self.flow_buffer);
+            let _ = sender.try_send(payload);
+            let flow_id = next_flow_id;
+            next_flow_id += 1;
+            active_flows.insert(peer_addr, (flow_id, sender));
+            drop(active_flows);
+
+            let flow = UdpFlow {
+                receiver,
+                peer_addr,
+                socket: self.inner.clone(),
+            };
+
+            let service = service.clone();
+            let flows = flows.clone();
+            let mut ctx = ctx.clone();
+            ctx.insert(SocketInfo::new(local_addr, peer_addr));
+
+            ctx.clone().spawn(async move {
+                let _ = service.serve(ctx, flow).await;
+
+                let mut active_flows = flows.lock().unwrap();
+                if active_flows
+                    .get(&peer_addr)
+                    .is_some_and(|(id, _)| *id == flow_id)
+                {
+                    active_flows.remove(&peer_addr);
+                }
+            });
+        }
+
+        // drop all senders, ending the flows which are still being served
+        flows.lock().unwrap().clear();
+    }
+}
+
+/// Exponential backoff for failing receives, logging only the first error of a streak.
+#[derive(Debug, Default)]
+struct RecvBackoff {
+    delay: Option<Duration>,
+}
+
+impl RecvBackoff {
+    fn reset(&mut self) {
+        if self.delay.take().is_some() {
+            tracing::info!("UDP recv recovered");
+        }
+    }
+
+    async fn on_error(&mut self, err: io::Error) {
+        if crate::tcp::utils::is_connection_error(&err) {
+            // e.g. an ICMP port unreachable reported for a previously sent datagram
+            tracing::trace!(
+                error = &err as &dyn std::error::Error,
+                "UDP recv error: connect error"
+            );
+            return;
+        }
+
+        let delay = match self.delay {
+            Some(delay) => {
+                tracing::debug!(error = &err as &dyn std::error::Error, "UDP recv error");
+                (delay * 2).min(MAX_RECV_BACKOFF)
+            }
+            None => {
+                tracing::error!(error = &err as &dyn std::error::Error, "UDP recv error");
+                MIN_RECV_BACKOFF
+            }
+        };
+        self.delay = Some(delay);
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// A single datagram received by a [`UdpListener`].
+#[derive(Debug, Clone)]
+pub struct Datagram {
+    payload: Bytes,
+    peer_addr: SocketAddr,
+    socket: Arc<UdpSocket>,
+}
+
+impl Datagram {
+    /// The payload of the datagram.
+    pub fn payload(&self) -> &Bytes {
+        &self.payload
+    }
+
+    /// Consume the datagram, returning its payload.
+    pub fn into_payload(self) -> Bytes {
+        self.payload
+    }
+
+    /// The address of the peer that sent the datagram.
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
+    /// Send a datagram back to the peer, from the socket of the listener.
+    pub async fn reply(&self, payload: &[u8]) -> io::Result<usize> {
+        self.socket.send_to(payload, self.peer_addr).await
+    }
+}
+
+/// All datagrams received by a [`UdpListener`] from a single peer,
+/// for as long as the flow is being served.
+#[derive(Debug)]
+pub struct UdpFlow {
+    receiver: mpsc::Receiver<Bytes>,
+    peer_addr: SocketAddr,
+    socket: Arc<UdpSocket>,
+}
+
+impl UdpFlow {
+    /// The address of the peer of this flow.
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
+    /// Receive the payload of the next datagram of the peer.
+    ///
+    /// Returns `None` once the listener has stopped.
+    pub async fn recv(&mut self) -> Option<Bytes> {
+        self.receiver.recv().await
+    }
+
+    /// Send a datagram to the peer, from the socket of the listener.
+    pub async fn send(&self, payload: &[u8]) -> io::Result<usize> {
+        self.socket.send_to(payload, self.peer_addr).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::service_fn;
+    use std::convert::Infallible;
+
+    #[tokio::test]
+    async fn test_udp_listener_datagrams() {
+        let listener = UdpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            listener.serve_fn(|ctx: Context<()>, datagram: Datagram| async move {
+                let info = ctx.get::<SocketInfo>().unwrap();
+                assert_eq!(info.peer_addr(), &datagram.peer_addr());
+                datagram.reply(datagram.payload()).await
+            }),
+        );
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(addr).await.unwrap();
+        let mut buffer = [0; 16];
+        for payload in [&b"hello"[..], b"world"] {
+            client.send(payload).await.unwrap();
+            let n = client.recv(&mut buffer).await.unwrap();
+            assert_eq!(&buffer[..n], payload);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_udp_listener_flows() {
+        let listener = UdpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        // each flow replies with a counter of the datagrams received from the peer,
+        // and ends after two datagrams
+        tokio::spawn(
+            listener.serve_flows(service_fn(|mut flow: UdpFlow| async move {
+                for i in 0..2u8 {
+                    flow.recv().await.unwrap();
+                    flow.send(&[i]).await.unwrap();
+                }
+                Ok::<_, Infallible>(())
+            })),
+        );
+
+        let (a, b) = (
+            UdpSocket::bind("127.0.0.1:0").await.unwrap(),
+            UdpSocket::bind("127.0.0.1:0").await.unwrap(),
+        );
+        a.connect(addr).await.unwrap();
+        b.connect(addr).await.unwrap();
+
+        let mut buffer = [0; 1];
+        for (client, expected) in [(&a, 0), (&b, 0), (&a, 1), (&b, 1)] {
+            client.send(b"ping").await.unwrap();
+            client.recv(&mut buffer).await.unwrap();
+            assert_eq!(buffer[0], expected);
+        }
+
+        // give the finished flow the time to be removed, a new flow is started for the peer
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        a.send(b"ping").await.unwrap();
+        a.recv(&mut buffer).await.unwrap();
+        assert_eq!(buffer[0], 0);
+    }
+
+    #[tokio::test]
+    async fn test_udp_listener_flows_shutdown() {
+        let listener = UdpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (ended_tx, mut ended_rx) = mpsc::channel(1);
+        let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+        let ctx = Context::new(listener.state.clone(), Executor::new());
+        let server = tokio::spawn(listener.serve_peer_flows(
+            ctx,
+            service_fn(move |mut flow: UdpFlow| {
+                let ended_tx = ended_tx.clone();
+                async move {
+                    while let Some(payload) = flow.recv().await {
+                        flow.send(&payload).await.unwrap();
+                    }
+                    ended_tx.send(()).await.unwrap();
+                    Ok::<_, Infallible>(())
+                }
+            }),
+            async {
+                let _ = shutdown_rx.await;
+            },
+        ));
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(addr).await.unwrap();
+        let mut buffer = [0; 4];
+        client.send(b"ping").await.unwrap();
+        client.recv(&mut buffer).await.unwrap();
+
+        shutdown_tx.send(()).unwrap();
+        server.await.unwrap();
+        tokio::time::timeout(std::time::Duration::from_secs(1), ended_rx.recv())
+            .await
+            .expect("flow ended after shutdown")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_udp_listener_max_flows() {
+        let listener = UdpListener::build()
+            .max_flows(1)
+            .bind("127.0.0.1:0")
+            .await
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            listener.serve_flows(service_fn(|mut flow: UdpFlow| async move {
+                while let Some(payload) = flow.recv().await {
+                    flow.send(&payload).await.unwrap();
+                }
+                Ok::<_, Infallible>(())
+            })),
+        );
+
+        let (a, b) = (
+            UdpSocket::bind("127.0.0.1:0").await.unwrap(),
+            UdpSocket::bind("127.0.0.1:0").await.unwrap(),
+        );
+        a.connect(addr).await.unwrap();
+        b.connect(addr).await.unwrap();
+
+        let mut buffer = [0; 4];
+        a.send(b"ping").await.unwrap();
+        a.recv(&mut buffer).await.unwrap();
+
+        // the datagram of the second peer is dropped
+        b.send(b"ping").await.unwrap();
+        let result =
+            tokio::time::timeout(std::time::Duration::from_millis(100), b.recv(&mut buffer)).await;
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,27 @@
+//! UDP server module for Rama.
+//!
+//! The UDP server is used to create a [`UdpListener`] and receive incoming datagrams,
+//! which are either served one by one as a [`Datagram`], or grouped per peer as a [`UdpFlow`].
+//!
+//! # Example
+//!
+//! ```no_run
+//! use rama::udp::server::{Datagram, UdpListener};
+//!
+//! #[tokio::main]
+//! async fn main() {
+//!     UdpListener::bind("127.0.0.1:9000")
+//!         .await
+//!         .expect("bind UDP Listener")
+//!         .serve_fn(|datagram: Datagram| async move {
+//!             // echo the datagram back to the peer
+//!             datagram.reply(datagram.payload()).await?;
+//!             Ok::<_, std::io::Error>(())
+//!         })
+//!         .await;
+//! }
+//! ```
+
+mod listener;
+#[doc(inline)]
+pub use listener::{Datagram, UdpFlow, UdpListener, UdpListenerBuilder};
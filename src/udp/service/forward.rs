@@ -0,0 +1,144 @@
+use std::{io, net::SocketAddr, time::Duration};
+
+use tokio::{net::UdpSocket, time::Instant};
+
+use crate::{
+    service::{Context, Service},
+    tcp::utils::is_connection_error,
+    udp::{server::UdpFlow, MAX_DATAGRAM_SIZE},
+};
+
+/// A UDP forwarder.
+///
+/// Each [`UdpFlow`] is forwarded using its own upstream socket, bound to an
+/// ephemeral port, such that replies of the target can be mapped back to the peer
+/// of the flow, similar to a NAT session. The session expires, and its flow ends,
+/// once no datagram has been forwarded in either direction for the idle timeout.
+///
+/// Use [`UdpListener::serve_flows`] to serve a [`UdpForwarder`].
+///
+/// [`UdpListener::serve_flows`]: crate::udp::server::UdpListener::serve_flows
+#[derive(Debug, Clone)]
+pub struct UdpForwarder {
+    target: SocketAddr,
+    idle_timeout: Duration,
+}
+
+impl UdpForwarder {
+    /// Create a new forwarder for the given target [`SocketAddr`],
+    /// with an idle timeout of `30` seconds.
+    pub fn target(target: SocketAddr) -> Self {
+        Self {
+            target,
+            idle_timeout: Duration::from_secs(30),
+        }
+    }
+
+    /// Set the duration after which an idle session expires.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+}
+
+impl<S> Service<S, UdpFlow> for UdpForwarder
+where
+    S: Send + Sync + 'static,
+{
+    type Response = ();
+    type Error = io::Error;
+
+    async fn serve(
+        &self,
+        _ctx: Context<S>,
+        mut flow: UdpFlow,
+    ) -> Result<Self::Response, Self::Error> {
+        let bind_addr: SocketAddr = if self.target.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let upstream = UdpSocket::bind(bind_addr).await?;
+        upstream.connect(self.target).await?;
+
+        let idle = tokio::time::sleep(self.idle_timeout);
+        tokio::pin!(idle);
+        let mut buffer = vec![0; MAX_DATAGRAM_SIZE];
+
+        loop {
+            tokio::select! {
+                payload = flow.recv() => match payload {
+                    Some(payload) => {
+                        upstream.send(&payload).await?;
+                    }
+                    None => return Ok(()),
+                },
+                result = upstream.recv(&mut buffer) => match result {
+                    Ok(n) => {
+                        flow.send(&buffer[..n]).await?;
+                    }
+                    // e.g. the target is (temporarily) not listening
+                    Err(err) if is_connection_error(&err) => continue,
+                    Err(err) => return Err(err),
+                },
+                _ = &mut idle => {
+                    tracing::trace!(peer_addr = %flow.peer_addr(), "UDP session idle: expire");
+                    return Ok(());
+                }
+            }
+            idle.as_mut().reset(Instant::now() + self.idle_timeout);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::udp::server::{Datagram, UdpListener};
+
+    #[tokio::test]
+    async fn test_udp_forwarder() {
+        let echo = UdpListener::bind("127.0.0.1:0").await.unwrap();
+        let echo_addr = echo.local_addr().unwrap();
+        tokio::spawn(echo.serve_fn(|datagram: Datagram| async move {
+            datagram.reply(datagram.payload()).await
+        }));
+
+        let listener = UdpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(listener.serve_flows(UdpForwarder::target(echo_addr)));
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(addr).await.unwrap();
+        let mut buffer = [0; 16];
+        for payload in [&b"hello"[..], b"world"] {
+            client.send(payload).await.unwrap();
+            let n = client.recv(&mut buffer).await.unwrap();
+            assert_eq!(&buffer[..n], payload);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_udp_forwarder_idle_timeout() {
+        let upstream = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let forwarder = UdpForwarder::target(upstream.local_addr().unwrap())
+            .idle_timeout(Duration::from_millis(50));
+
+        let listener = UdpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(listener.serve_flows(forwarder));
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(addr).await.unwrap();
+        let mut buffer = [0; 16];
+
+        client.send(b"one").await.unwrap();
+        let (_, first) = upstream.recv_from(&mut buffer).await.unwrap();
+
+        // a new session, using a new upstream socket, is started once the first expired
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        client.send(b"two").await.unwrap();
+        let (_, second) = upstream.recv_from(&mut buffer).await.unwrap();
+        assert_ne!(first, second);
+    }
+}
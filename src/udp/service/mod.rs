@@ -0,0 +1,5 @@
+//! UDP services for Rama.
+
+mod forward;
+#[doc(inline)]
+pub use forward::UdpForwarder;
@@ -0,0 +1,44 @@
+use crate::service::{Context, Service};
+use std::path::PathBuf;
+use tokio::net::UnixStream;
+
+/// A connector which establishes a [`UnixStream`] to a fixed path.
+///
+/// The target address passed to the connector, e.g. by the [`HttpClient`]
+/// or [`Forwarder`], is ignored, as all connections go to the same socket.
+///
+/// [`HttpClient`]: crate::http::client::HttpClient
+/// [`Forwarder`]: crate::tcp::service::Forwarder
+#[derive(Debug, Clone)]
+pub struct UnixConnector {
+    path: PathBuf,
+}
+
+impl UnixConnector {
+    /// Create a new [`UnixConnector`] connecting to the socket at the given path.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// The path of the socket this connector connects to.
+    pub fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+}
+
+impl<State, Target> Service<State, Target> for UnixConnector
+where
+    State: Send + Sync + 'static,
+    Target: Send + 'static,
+{
+    type Response = UnixStream;
+    type Error = std::io::Error;
+
+    async fn serve(
+        &self,
+        _ctx: Context<State>,
+        _target: Target,
+    ) -> Result<Self::Response, Self::Error> {
+        UnixStream::connect(&self.path).await
+    }
+}
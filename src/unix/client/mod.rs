@@ -0,0 +1,9 @@
+//! Unix domain socket client module for Rama.
+//!
+//! See the [`tcp::client`] module for more information about connectors.
+//!
+//! [`tcp::client`]: crate::tcp::client
+
+mod connector;
+#[doc(inline)]
+pub use connector::UnixConnector;
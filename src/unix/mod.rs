@@ -0,0 +1,40 @@
+//! Unix domain socket module for Rama.
+//!
+//! Only available on unix platforms.
+
+use std::path::{Path, PathBuf};
+
+pub mod client;
+pub mod server;
+
+#[derive(Debug, Clone)]
+/// Connected Unix domain socket information,
+/// the [`SocketInfo`] equivalent for Unix domain sockets.
+///
+/// [`SocketInfo`]: crate::stream::SocketInfo
+pub struct UnixSocketInfo {
+    local_path: Option<PathBuf>,
+    peer_path: Option<PathBuf>,
+}
+
+impl UnixSocketInfo {
+    /// Create a new `UnixSocketInfo`.
+    pub fn new(local_path: Option<PathBuf>, peer_path: Option<PathBuf>) -> Self {
+        Self {
+            local_path,
+            peer_path,
+        }
+    }
+
+    /// Get the path the local socket is bound to, if any.
+    pub fn local_path(&self) -> Option<&Path> {
+        self.local_path.as_deref()
+    }
+
+    /// Get the path the peer socket is bound to, if any.
+    ///
+    /// Client sockets are usually unnamed, in which case this is `None`.
+    pub fn peer_path(&self) -> Option<&Path> {
+        self.peer_path.as_deref()
+    }
+}
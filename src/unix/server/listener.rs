@@ -0,0 +1,302 @@
+use crate::graceful::ShutdownGuard;
+use crate::rt::Executor;
+use crate::service::handler::{Factory, FromContextRequest};
+use crate::service::Context;
+use crate::service::Service;
+use crate::stream::PeerCredentials;
+use crate::unix::UnixSocketInfo;
+use std::future::Future;
+use std::io;
+use std::path::Path;
+use std::pin::pin;
+use std::sync::Arc;
+use tokio::net::{unix::SocketAddr, UnixListener as TokioUnixListener, UnixStream};
+
+/// Builder for `UnixListener`.
+#[derive(Debug)]
+pub struct UnixListenerBuilder<S> {
+    state: Arc<S>,
+}
+
+impl UnixListenerBuilder<()> {
+    /// Create a new `UnixListenerBuilder` without a state.
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(()),
+        }
+    }
+}
+
+impl Default for UnixListenerBuilder<()> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> Clone for UnixListenerBuilder<S> {
+    fn clone(&self) -> Self {
+        Self {
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<S> UnixListenerBuilder<S>
+where
+    S: Send + Sync + 'static,
+{
+    /// Create a new `UnixListenerBuilder` with the given state.
+    pub fn with_state(state: S) -> Self {
+        Self {
+            state: Arc::new(state),
+        }
+    }
+
+    /// Creates a new UnixListener, which will be bound to the specified path.
+    ///
+    /// The returned listener is ready for accepting connections.
+    ///
+    /// Binding fails if a file already exists at the given path,
+    /// such as the socket file of a previous listener.
+    pub fn bind(&self, path: impl AsRef<Path>) -> io::Result<UnixListener<S>> {
+        let inner = TokioUnixListener::bind(path)?;
+
+        Ok(UnixListener {
+            inner,
+            state: self.state.clone(),
+        })
+    }
+}
+
+/// A Unix domain socket server, listening for incoming connections once served
+/// using one of the `serve` methods such as [`UnixListener::serve`].
+#[derive(Debug)]
+pub struct UnixListener<S> {
+    inner: TokioUnixListener,
+    state: Arc<S>,
+}
+
+impl UnixListener<()> {
+    /// Create a new `UnixListenerBuilder` without a state,
+    /// which can be used to configure a `UnixListener`.
+    pub fn build() -> UnixListenerBuilder<()> {
+        UnixListenerBuilder::new()
+    }
+
+    /// Create a new `UnixListenerBuilder` with the given state,
+    /// which can be used to configure a `UnixListener`.
+    pub fn build_with_state<S>(state: S) -> UnixListenerBuilder<S>
+    where
+        S: Send + Sync + 'static,
+    {
+        UnixListenerBuilder::with_state(state)
+    }
+
+    /// Creates a new UnixListener, which will be bound to the specified path.
+    ///
+    /// See [`UnixListenerBuilder::bind`] for more details.
+    pub fn bind(path: impl AsRef<Path>) -> io::Result<Self> {
+        UnixListenerBuilder::default().bind(path)
+    }
+}
+
+impl<S> UnixListener<S> {
+    /// Returns the local address that this listener is bound to.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    /// Gets a reference to the listener's state.
+    pub fn state(&self) -> &S {
+        &self.state
+    }
+}
+
+impl<State> UnixListener<State>
+where
+    State: Send + Sync + 'static,
+{
+    /// Serve connections from this listener with the given service.
+    ///
+    /// This method will block the current listener for each incoming connection,
+    /// the underlying service can choose to spawn a task to handle the accepted stream.
+    pub async fn serve<S>(self, service: S)
+    where
+        S: Service<State, UnixStream>,
+    {
+        let ctx = Context::new(self.state.clone(), Executor::new());
+        self.serve_connections(ctx, service, std::future::pending())
+            .await
+    }
+
+    /// Serve connections from this listener with the given service function.
+    ///
+    /// See [`Self::serve`] for more details.
+    pub async fn serve_fn<F, T, R, O, E>(self, f: F)
+    where
+        F: Factory<T, R, O, E>,
+        R: Future<Output = Result<O, E>> + Send + Sync + 'static,
+        O: Send + Sync + 'static,
+        E: Send + Sync + 'static,
+        T: FromContextRequest<State, UnixStream>,
+    {
+        let service = crate::service::service_fn(f);
+        self.serve(service).await
+    }
+
+    /// Serve gracefully connections from this listener with the given service.
+    ///
+    /// This method does the same as [`Self::serve`] but it
+    /// will respect the given [`crate::graceful::ShutdownGuard`], and also pass
+    /// it to the service.
+    pub async fn serve_graceful<S>(self, guard: ShutdownGuard, service: S)
+    where
+        S: Service<State, UnixStream>,
+    {
+        let ctx = Context::new(self.state.clone(), Executor::graceful(guard.clone()));
+        self.serve_connections(ctx, service, guard.cancelled())
+            .await
+    }
+
+    /// Serve gracefully connections from this listener with the given service function.
+    ///
+    /// See [`Self::serve_graceful`] for more details.
+    pub async fn serve_fn_graceful<F, T, R, O, E>(self, guard: ShutdownGuard, service: F)
+    where
+        F: Factory<T, R, O, E>,
+        R: Future<Output = Result<O, E>> + Send + Sync + 'static,
+        O: Send + Sync + 'static,
+        E: Send + Sync + 'static,
+        T: FromContextRequest<State, UnixStream>,
+    {
+        let service = crate::service::service_fn(service);
+        self.serve_graceful(guard, service).await
+    }
+
+    async fn serve_connections<S>(
+        self,
+        ctx: Context<State>,
+        service: S,
+        cancelled: impl Future<Output = ()>,
+    ) where
+        S: Service<State, UnixStream>,
+    {
+        let service = Arc::new(service);
+        let local_path = self
+            .inner
+            .local_addr()
+            .ok()
+            .and_then(|addr| addr.as_pathname().map(Path::to_path_buf));
+        let mut cancelled = pin!(cancelled);
+
+        loop {
+            let (socket, peer_addr) = tokio::select! {
+                _ = cancelled.as_mut() => {
+                    tracing::trace!("signal received: initiate graceful shutdown");
+                    break;
+                }
+                result = self.inner.accept() => match result {
+                    Ok(accepted) => accepted,
+                    Err(err) => {
+                        handle_accept_err(err).await;
+                        continue;
+                    }
+                }
+            };
+
+            let service = service.clone();
+            let mut ctx = ctx.clone();
+
+            ctx.insert(UnixSocketInfo::new(
+                local_path.clone(),
+                peer_addr.as_pathname().map(Path::to_path_buf),
+            ));
+            if let Ok(cred) = socket.peer_cred() {
+                ctx.insert(PeerCredentials::from(cred));
+            }
+
+            ctx.clone().spawn(async move {
+                let _ = service.serve(ctx, socket).await;
+            });
+        }
+    }
+}
+
+async fn handle_accept_err(err: io::Error) {
+    if crate::tcp::utils::is_connection_error(&err) {
+        tracing::trace!(
+            error = &err as &dyn std::error::Error,
+            "Unix accept error: connect error"
+        );
+    } else {
+        // see the TCP listener, e.g. the process might have hit the max open files
+        tracing::error!(error = &err as &dyn std::error::Error, "Unix accept error");
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        service::Matcher, stream::matcher::PeerCredentialsMatcher, tcp::service::Forwarder,
+        unix::client::UnixConnector,
+    };
+    use std::convert::Infallible;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_unix_listener() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rama.sock");
+
+        let listener = UnixListener::bind(&path).unwrap();
+        tokio::spawn(
+            listener.serve_fn(|ctx: Context<()>, mut stream: UnixStream| async move {
+                let cred = ctx.get::<PeerCredentials>().unwrap();
+                assert_eq!(cred.pid(), Some(std::process::id() as i32));
+                assert!(PeerCredentialsMatcher::uid(cred.uid()).matches(None, &ctx, &stream));
+
+                let info = ctx.get::<UnixSocketInfo>().unwrap();
+                assert!(info.local_path().unwrap().ends_with("rama.sock"));
+                assert!(info.peer_path().is_none());
+
+                stream.write_all(b"hello").await.unwrap();
+                Ok::<_, Infallible>(())
+            }),
+        );
+
+        let mut stream = UnixConnector::new(&path)
+            .serve(Context::<()>::default(), ())
+            .await
+            .unwrap();
+        let mut buf = Vec::new();
+        stream.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_unix_forwarder() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("echo.sock");
+
+        let listener = UnixListener::bind(&path).unwrap();
+        tokio::spawn(listener.serve_fn(|mut stream: UnixStream| async move {
+            let (mut r, mut w) = stream.split();
+            tokio::io::copy(&mut r, &mut w).await
+        }));
+
+        // forward a tcp connection to the unix socket
+        let forwarder =
+            Forwarder::target("127.0.0.1:1".parse().unwrap()).connector(UnixConnector::new(&path));
+        let (mut client, server) = tokio::io::duplex(64);
+        let (result, ()) = tokio::join!(forwarder.serve(Context::default(), server), async {
+            client.write_all(b"ping").await.unwrap();
+            let mut buf = [0; 4];
+            client.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"ping");
+            drop(client);
+        });
+        result.unwrap();
+    }
+}
@@ -0,0 +1,35 @@
+//! Unix domain socket server module for Rama.
+//!
+//! The Unix server is used to create a [`UnixListener`] and accept incoming connections.
+//! The [`Context`] of each connection contains the [`UnixSocketInfo`]
+//! and, if available, the [`PeerCredentials`] of the peer.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use rama::unix::server::UnixListener;
+//! use tokio::{io::AsyncWriteExt, net::UnixStream};
+//!
+//! #[tokio::main]
+//! async fn main() {
+//!     UnixListener::bind("/tmp/rama.sock")
+//!         .expect("bind Unix Listener")
+//!         .serve_fn(|mut stream: UnixStream| async move {
+//!             stream
+//!                 .write_all(b"hello")
+//!                 .await
+//!                 .expect("write to stream");
+//!
+//!             Ok::<_, std::convert::Infallible>(())
+//!         })
+//!         .await;
+//! }
+//! ```
+//!
+//! [`Context`]: crate::service::Context
+//! [`UnixSocketInfo`]: crate::unix::UnixSocketInfo
+//! [`PeerCredentials`]: crate::stream::PeerCredentials
+
+mod listener;
+#[doc(inline)]
+pub use listener::{UnixListener, UnixListenerBuilder};